@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zoog::header::CommentHeader as _;
+use zoog::opus::CommentHeader;
+
+fuzz_target!(|header: CommentHeader| {
+    let mut data = Vec::new();
+    header.serialize_into(&mut data).expect("Failed to serialize comment header");
+    let parsed = CommentHeader::try_parse(&data).expect("Failed to parse serialized comment header");
+    assert_eq!(parsed, header);
+});
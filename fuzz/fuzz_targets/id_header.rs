@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zoog::header::IdHeader as _;
+use zoog::opus::IdHeader;
+
+fuzz_target!(|header: IdHeader| {
+    let mapping = header.channel_mapping().expect("Generated header has an invalid channel mapping");
+    let parsed = IdHeader::try_parse(&header.clone().into_vec())
+        .expect("Failed to parse serialized identification header")
+        .expect("Serialized identification header was not recognised");
+    assert_eq!(parsed.channel_mapping().ok(), Some(mapping));
+    assert_eq!(parsed, header);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use zoog::ogg_page::Page;
+
+fuzz_target!(|page: Page| {
+    let mut data = Vec::new();
+    page.write(&mut data).expect("Failed to write page");
+    let parsed = Page::read(&mut Cursor::new(&data)).expect("Failed to read page back");
+    assert_eq!(parsed, Some(page));
+});
@@ -0,0 +1,221 @@
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use ogg::PacketReader;
+use opus::{Channels, Decoder};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rayon::ThreadPool;
+use serde::Serialize;
+use zoog::comment_rewrite::{CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig};
+use zoog::header::IdHeader as _;
+use zoog::header_rewriter::rewrite_stream;
+use zoog::import::take_id3v2_prefix;
+use zoog::interrupt::Never;
+use zoog::opus::{AnalysisSession, IdHeader as OpusIdHeader};
+use zoog::{progress, Codec, Error};
+
+/// The maximum duration of an Opus packet in samples at 48kHz (RFC 6716,
+/// section 3.2.5)
+const MAX_PACKET_SAMPLES: usize = 5760;
+
+/// The work measured by a benchmark
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Decoding the audio of each file
+    Decode,
+
+    /// Decoding and measuring the loudness of each file
+    Analysis,
+
+    /// Rewriting each file with its headers unchanged, discarding the output
+    Rewrite,
+}
+
+/// How the work of a benchmark is divided between threads
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Parallelism {
+    /// One file at a time on a single thread
+    None,
+
+    /// One file at a time, with the audio of each decoded in parallel
+    WithinFiles,
+
+    /// Several files at once, one per thread
+    AcrossFiles,
+}
+
+/// The time taken to process every file in a particular way
+#[derive(Clone, Debug, Serialize)]
+pub struct BenchResult {
+    /// The work measured
+    pub stage: Stage,
+
+    /// How the work was divided between threads
+    pub parallelism: Parallelism,
+
+    /// The number of threads used
+    pub threads: usize,
+
+    /// The time taken in seconds
+    pub seconds: f64,
+
+    /// The size of the files processed per second, in megabytes
+    pub megabytes_per_second: f64,
+
+    /// The duration of the audio processed per second, as a multiple of real
+    /// time
+    pub realtime_factor: f64,
+}
+
+/// Decodes the audio of the Ogg Opus file at `path`, returning its duration
+/// including any pre-skip
+fn decode_file(path: &Path) -> Result<Duration, Error> {
+    let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    let mut reader = BufReader::new(file);
+    take_id3v2_prefix(&mut reader)?;
+    let mut packet_reader = PacketReader::new(reader);
+    let mut next_packet = || packet_reader.read_packet().map_err(Error::OggDecode);
+    let id_header = next_packet()?.ok_or(Error::MissingStream(Codec::Opus))?;
+    let id_header = OpusIdHeader::try_parse(&id_header.data)?.ok_or(Error::MissingStream(Codec::Opus))?;
+    // The comment header is not needed
+    next_packet()?;
+    let channels = match id_header.num_output_channels() {
+        1 => Channels::Mono,
+        2 => Channels::Stereo,
+        n => return Err(Error::InvalidChannelCount(n)),
+    };
+    let sample_rate = id_header.output_sample_rate();
+    let sample_rate_u32 = u32::try_from(sample_rate).map_err(|_| Error::InvalidSampleRate(sample_rate))?;
+    let mut decoder = Decoder::new(sample_rate_u32, channels).map_err(Error::OpusError)?;
+    let mut samples = vec![0.0f32; MAX_PACKET_SAMPLES * id_header.num_output_channels()];
+    let mut num_samples = 0;
+    while let Some(packet) = next_packet()? {
+        num_samples += decoder.decode_float(&packet.data, &mut samples, false).map_err(Error::OpusError)?;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let seconds = num_samples as f64 / sample_rate as f64;
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Measures the loudness of the Ogg Opus file at `path`, decoding it in
+/// parallel if `parallel` is set
+fn analyze_file(path: &Path, parallel: bool) -> Result<(), Error> {
+    let mut session = if parallel { AnalysisSession::parallel() } else { AnalysisSession::default() };
+    session.analyze_path(path, &Never::default(), &mut progress::Ignore::default())?;
+    Ok(())
+}
+
+/// Rewrites the Ogg file at `path` without changing its headers, discarding the
+/// output
+fn rewrite_file(path: &Path) -> Result<(), Error> {
+    let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig {
+        action: CommentRewriterAction::NoChange,
+        drop_invalid: false,
+        normalize_keys: None,
+    });
+    let abort_on_unchanged = false;
+    rewrite_stream(rewrite, CommentHeaderSummary::default(), BufReader::new(file), io::sink(), abort_on_unchanged)?;
+    Ok(())
+}
+
+/// Reads every file once so that each benchmark finds them in the operating
+/// system's cache, returning their total size in bytes
+fn warm_cache(paths: &[PathBuf]) -> Result<u64, Error> {
+    let mut total = 0;
+    for path in paths {
+        let mut file = File::open(path).map_err(|e| Error::FileOpenError(path.clone(), e))?;
+        total += io::copy(&mut file, &mut io::sink()).map_err(|e| Error::FileReadError(path.clone(), e))?;
+    }
+    Ok(total)
+}
+
+/// Applies `op` to every file on the current thread or, if `across_files` is
+/// set, on the threads of `pool`, returning the time taken
+fn time<F>(paths: &[PathBuf], pool: &ThreadPool, across_files: bool, op: F) -> Result<Duration, Error>
+where
+    F: Fn(&Path) -> Result<(), Error> + Sync,
+{
+    let start = Instant::now();
+    if across_files {
+        pool.install(|| paths.par_iter().try_for_each(|path| op(path)))?;
+    } else {
+        paths.iter().try_for_each(|path| op(path))?;
+    }
+    Ok(start.elapsed())
+}
+
+/// Measures the throughput of decoding, analyzing and rewriting the Ogg Opus
+/// files at `paths`, first on a single thread and then using the threads of
+/// `pool`
+pub fn run(paths: &[PathBuf], pool: &ThreadPool) -> Result<Vec<BenchResult>, Error> {
+    let total_bytes = warm_cache(paths)?;
+    let mut audio = Duration::ZERO;
+    for path in paths {
+        audio += decode_file(path)?;
+    }
+    let runs = [
+        (Stage::Decode, Parallelism::None),
+        (Stage::Decode, Parallelism::AcrossFiles),
+        (Stage::Analysis, Parallelism::None),
+        (Stage::Analysis, Parallelism::WithinFiles),
+        (Stage::Analysis, Parallelism::AcrossFiles),
+        (Stage::Rewrite, Parallelism::None),
+        (Stage::Rewrite, Parallelism::AcrossFiles),
+    ];
+    let mut results = Vec::with_capacity(runs.len());
+    for (stage, parallelism) in runs {
+        let across_files = parallelism == Parallelism::AcrossFiles;
+        let elapsed = match stage {
+            Stage::Decode => time(paths, pool, across_files, |path| decode_file(path).map(|_| ()))?,
+            Stage::Analysis => {
+                let within_file = parallelism == Parallelism::WithinFiles;
+                pool.install(|| time(paths, pool, across_files, |path| analyze_file(path, within_file)))?
+            }
+            Stage::Rewrite => time(paths, pool, across_files, rewrite_file)?,
+        };
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+        #[allow(clippy::cast_precision_loss)]
+        let megabytes_per_second = total_bytes as f64 / 1_000_000.0 / seconds;
+        results.push(BenchResult {
+            stage,
+            parallelism,
+            threads: if parallelism == Parallelism::None { 1 } else { pool.current_num_threads() },
+            seconds,
+            megabytes_per_second,
+            realtime_factor: audio.as_secs_f64() / seconds,
+        });
+    }
+    Ok(results)
+}
+
+/// Writes the results as a table
+pub fn write_report<W: Write>(mut writer: W, results: &[BenchResult]) -> Result<(), io::Error> {
+    writeln!(
+        writer,
+        "{:<10} {:<14} {:>7} {:>10} {:>10} {:>10}",
+        "Stage", "Parallelism", "Threads", "Seconds", "MB/s", "Realtime"
+    )?;
+    for result in results {
+        let stage = match result.stage {
+            Stage::Decode => "decode",
+            Stage::Analysis => "analysis",
+            Stage::Rewrite => "rewrite",
+        };
+        let parallelism = match result.parallelism {
+            Parallelism::None => "none",
+            Parallelism::WithinFiles => "within files",
+            Parallelism::AcrossFiles => "across files",
+        };
+        writeln!(
+            writer,
+            "{:<10} {:<14} {:>7} {:>10.3} {:>10.1} {:>9.0}x",
+            stage, parallelism, result.threads, result.seconds, result.megabytes_per_second, result.realtime_factor
+        )?;
+    }
+    Ok(())
+}
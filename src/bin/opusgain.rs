@@ -1,5 +1,8 @@
 #![warn(clippy::pedantic)]
-#![allow(clippy::uninlined_format_args)]
+#![allow(clippy::uninlined_format_args, clippy::doc_markdown)]
+
+#[path = "../config.rs"]
+mod config;
 
 #[path = "../console_output.rs"]
 mod console_output;
@@ -7,30 +10,66 @@ mod console_output;
 #[path = "../ctrlc_handling.rs"]
 mod ctrlc_handling;
 
-#[path = "../output_file.rs"]
-mod output_file;
+#[path = "../exit_code.rs"]
+mod exit_code;
+
+#[path = "../input_files.rs"]
+mod input_files;
+
+#[path = "../priority.rs"]
+mod priority;
+
+#[path = "../progress_json.rs"]
+mod progress_json;
+
+#[path = "../rewrite_limiter.rs"]
+mod rewrite_limiter;
+
+#[path = "../run_state.rs"]
+mod run_state;
+
+#[path = "../text_diff.rs"]
+mod text_diff;
 
-use std::collections::{BTreeMap, HashMap};
+#[path = "../watch.rs"]
+mod watch;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
+use std::io::{self, BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use clap::{Parser, ValueEnum};
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use config::{Config, TagFormat, UserProfile};
 use console_output::{ConsoleOutput, Delayed as DelayedConsoleOutput, Standard};
 use ctrlc_handling::CtrlCChecker;
-use ogg::reading::PacketReader;
-use output_file::OutputFile;
+use input_files::{Glob, InputGroup, PathFilter};
 use parking_lot::Mutex;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use rayon::ThreadPoolBuilder;
+use progress_json::{ProgressEvent, ProgressReporter, RunSummary};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use rewrite_limiter::RewriteLimiter;
+use run_state::{RunState, RunTracker};
 use thiserror::Error;
-use zoog::header_rewriter::{rewrite_stream_with_interrupt, SubmitResult};
-use zoog::opus::{VolumeAnalyzer, TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
+use watch::DirectoryWatcher;
+use zoog::comment_rewrite::{CommentHeaderRewrite, CommentRewriterAction, CommentRewriterConfig};
+use zoog::header::FixedPointGain;
+use zoog::header_rewriter::{
+    rewrite_stream_preserving_pages_with_interrupt, rewrite_stream_with_interrupt, SubmitResult,
+};
+use zoog::import::take_id3v2_prefix;
+use zoog::interrupt::{Deadline, Interrupt as _};
+use zoog::loudness_cache::{CacheKey, LoudnessCache};
+use zoog::opus::{AlbumAnalysis, AnalysisSession, TrackAnalysis, TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
+use zoog::output_file::OutputFile;
 use zoog::volume_rewrite::{
-    GainsSummary, OpusGains, OutputGainMode, VolumeHeaderRewrite, VolumeRewriterConfig, VolumeTarget,
+    gains_match_target, GainsSummary, OpusGains, OutputGainMode, VolumeHeaderRewrite, VolumeProfile,
+    VolumeRewriterConfig, VolumeTarget, VOLUME_PROFILES,
 };
-use zoog::{Decibels, Error, R128_LUFS, REPLAY_GAIN_LUFS};
+use zoog::{detect_format, Codec, Decibels, Error, FileFormat};
 
 #[derive(Debug, Error)]
 enum AppError {
@@ -39,95 +78,243 @@ enum AppError {
 
     #[error("Unable to register Ctrl-C handler: `{0}`")]
     CtrlCRegistration(#[from] ctrlc_handling::CtrlCRegistrationError),
+
+    #[error("Stopped since the time limit was reached")]
+    TimeLimitReached,
+
+    #[error("Stopped since a file could not be processed")]
+    StoppedAfterFailure(i32),
+
+    #[error("None of the files could be processed")]
+    AllFilesFailed(i32),
+
+    #[error("{0} of {1} files could not be processed")]
+    SomeFilesFailed(usize, usize),
+
+    #[error("Invalid configuration file {}: {1}", .0.display())]
+    Config(PathBuf, String),
+}
+
+impl AppError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            AppError::Library(e) => exit_code::for_error_kind(e.kind()),
+            AppError::CtrlCRegistration(_) => exit_code::FAILURE,
+            AppError::TimeLimitReached => exit_code::TIME_LIMIT,
+            AppError::StoppedAfterFailure(code) | AppError::AllFilesFailed(code) => *code,
+            AppError::SomeFilesFailed(..) => exit_code::PARTIAL_FAILURE,
+            AppError::Config(..) => exit_code::INVALID_ARGUMENT,
+        }
+    }
 }
 
 fn main() {
     match main_impl() {
         Ok(()) => {}
+        Err(e @ (AppError::StoppedAfterFailure(..) | AppError::AllFilesFailed(..) | AppError::SomeFilesFailed(..))) => {
+            eprintln!("{}.", e);
+            std::process::exit(e.exit_code());
+        }
         Err(e) => {
             eprintln!("Aborted due to error: {}", e);
-            std::process::exit(1);
+            std::process::exit(e.exit_code());
         }
     }
 }
 
-fn check_running(checker: &CtrlCChecker) -> Result<(), Error> {
-    if checker.is_running() {
+/// Stops processing on Ctrl-C or once the time limit, if any, has passed
+type InterruptChecker = (CtrlCChecker, Option<Deadline>);
+
+fn check_running(checker: &InterruptChecker) -> Result<(), Error> {
+    if checker.is_set() {
+        Err(Error::Interrupted)
+    } else {
         Ok(())
+    }
+}
+
+/// Constructs an analysis session. When only a single file is being analyzed,
+/// it is decoded using all threads since there is no other work to use them.
+fn new_session(single_file: bool) -> AnalysisSession {
+    if single_file {
+        AnalysisSession::parallel()
     } else {
-        Err(Error::Interrupted)
+        AnalysisSession::default()
     }
 }
 
 fn apply_volume_analysis<P, C>(
-    analyzer: &mut VolumeAnalyzer, path: P, console_output: &C, report_error: bool, interrupt_checker: &CtrlCChecker,
-) -> Result<(), Error>
+    single_file: bool, path: P, console_output: &C, report_error: bool, interrupt_checker: &InterruptChecker,
+    progress: &ProgressReporter, cache: Option<&LoudnessCache>,
+) -> Result<TrackAnalysis, Error>
 where
     P: AsRef<Path>,
     C: ConsoleOutput,
 {
-    let mut body = || -> Result<(), Error> {
+    let body = || -> Result<TrackAnalysis, Error> {
         let input_path = path.as_ref();
-        let input_file = File::open(input_path).map_err(|e| Error::FileOpenError(input_path.to_path_buf(), e))?;
-        let input_file = BufReader::new(input_file);
-        let mut ogg_reader = PacketReader::new(input_file);
-        loop {
-            check_running(interrupt_checker)?;
-            match ogg_reader.read_packet() {
-                Err(e) => break Err(Error::OggDecode(e)),
-                Ok(None) => {
-                    analyzer.file_complete();
+        let cache_key = cache.map(|_| CacheKey::for_file(input_path)).transpose()?;
+        let cached = cache.zip(cache_key.as_ref()).and_then(|(cache, key)| cache.get(key));
+        let track = if let Some(track) = cached {
+            let lufs = track.lufs().as_f64();
+            writeln!(
+                console_output.out(),
+                "Using cached loudness for {} of {:.2} LUFS (ignoring output gain)",
+                input_path.display(),
+                lufs
+            )
+            .map_err(Error::ConsoleIoError)?;
+            track
+        } else {
+            let mut observer = progress.analysis_observer(input_path);
+            let track = new_session(single_file).analyze_path(input_path, interrupt_checker, &mut observer)?.clone();
+            let lufs = track.lufs().as_f64();
+            writeln!(
+                console_output.out(),
+                "Computed loudness of {} as {:.2} LUFS (ignoring output gain)",
+                input_path.display(),
+                lufs
+            )
+            .map_err(Error::ConsoleIoError)?;
+            if let (Some(cache), Some(key)) = (cache, cache_key.as_ref()) {
+                if let Err(e) = cache.insert(key, &track) {
                     writeln!(
-                        console_output.out(),
-                        "Computed loudness of {} as {:.2} LUFS (ignoring output gain)",
+                        console_output.err(),
+                        "Failed to update loudness cache for {}: {}",
                         input_path.display(),
-                        analyzer.last_track_lufs().expect("Last track volume unexpectedly missing").as_f64()
+                        e
                     )
                     .map_err(Error::ConsoleIoError)?;
-                    break Ok(());
                 }
-                Ok(Some(packet)) => analyzer.submit(packet)?,
             }
-        }
+            track
+        };
+        let path = ProgressEvent::path_string(input_path);
+        let lufs = track.lufs().as_f64();
+        progress.emit(&ProgressEvent::AnalysisComplete { path, lufs }).map_err(Error::ConsoleIoError)?;
+        Ok(track)
     };
     let result = body();
     if report_error {
         if let Err(ref e) = result {
             writeln!(console_output.err(), "Failed to analyze volume of {}: {}", path.as_ref().display(), e)
                 .map_err(Error::ConsoleIoError)?;
+            let path = ProgressEvent::path_string(path.as_ref());
+            progress.emit(&ProgressEvent::Error { path, message: e.to_string() }).map_err(Error::ConsoleIoError)?;
         }
     }
     result
 }
 
+/// Describes each of the gains on its own line
+fn gain_lines(gains: &OpusGains) -> Vec<String> {
+    let mut lines = vec![format!("Output Gain: {}", gains.output)];
+    if let Some(gain) = gains.track_r128 {
+        lines.push(format!("{}: {}", TAG_TRACK_GAIN, gain));
+    }
+    if let Some(gain) = gains.album_r128 {
+        lines.push(format!("{}: {}", TAG_ALBUM_GAIN, gain));
+    }
+    lines
+}
+
 fn print_gains<C: ConsoleOutput>(gains: &OpusGains, console: &C) -> Result<(), Error> {
-    let do_io = || {
-        writeln!(console.out(), "\tOutput Gain: {}", gains.output)?;
-        if let Some(gain) = gains.track_r128 {
-            writeln!(console.out(), "\t{}: {}", TAG_TRACK_GAIN, gain)?;
-        }
-        if let Some(gain) = gains.album_r128 {
-            writeln!(console.out(), "\t{}: {}", TAG_ALBUM_GAIN, gain)?;
-        }
-        Ok(())
+    for line in gain_lines(gains) {
+        writeln!(console.out(), "\t{}", line).map_err(Error::ConsoleIoError)?;
+    }
+    Ok(())
+}
+
+/// Reads the gains of a file from its headers without decoding any audio
+fn read_gains(path: &Path, interrupt_checker: &InterruptChecker) -> Result<Option<OpusGains>, Error> {
+    let input_file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig {
+        action: CommentRewriterAction::NoChange,
+        drop_invalid: false,
+        normalize_keys: None,
+    });
+    let summarize = GainsSummary::default();
+    let abort_on_unchanged = true;
+    let result = rewrite_stream_with_interrupt(
+        rewrite,
+        summarize,
+        BufReader::new(input_file),
+        io::sink(),
+        abort_on_unchanged,
+        interrupt_checker,
+    )?;
+    match result {
+        SubmitResult::HeadersUnchanged(gains) => Ok(Some(gains)),
+        SubmitResult::Good | SubmitResult::HeadersChanged { .. } => Ok(None),
+    }
+}
+
+/// Splits the files in `group` into those that still need processing and those
+/// whose gains already match the target. Files in an album are only skipped if
+/// the whole album is already tagged. Files whose gains cannot be read are
+/// never skipped so that any errors are reported when they are processed.
+fn partition_tagged_files(
+    group: InputGroup, target: VolumeTarget, mode: OutputGainMode, interrupt_checker: &InterruptChecker,
+) -> Result<(InputGroup, Vec<PathBuf>), Error> {
+    let gains = group
+        .paths
+        .par_iter()
+        .map(|path| match read_gains(path, interrupt_checker) {
+            Err(Error::Interrupted) => Err(Error::Interrupted),
+            Err(_) => Ok(None),
+            Ok(gains) => Ok(gains),
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let is_tagged = |gains: &[Option<OpusGains>]| {
+        let gains: Option<Vec<_>> = gains.iter().copied().collect();
+        gains.map_or(false, |gains| gains_match_target(&gains, target, mode, group.is_album))
     };
-    do_io().map_err(Error::ConsoleIoError)
+    let skip: Vec<bool> = if group.is_album {
+        vec![is_tagged(&gains); gains.len()]
+    } else {
+        gains.iter().map(|gains| is_tagged(std::slice::from_ref(gains))).collect()
+    };
+    let mut remaining = InputGroup { paths: Vec::new(), is_album: group.is_album };
+    let mut skipped = Vec::new();
+    for (path, skip) in group.paths.into_iter().zip(skip) {
+        if skip {
+            skipped.push(path);
+        } else {
+            remaining.paths.push(path);
+        }
+    }
+    Ok((remaining, skipped))
+}
+
+/// The loudness and sample peak of a track
+#[derive(Clone, Copy, Debug)]
+struct TrackVolume {
+    mean: Decibels,
+    peak: f32,
+}
+
+impl From<&TrackAnalysis> for TrackVolume {
+    fn from(track: &TrackAnalysis) -> TrackVolume { TrackVolume { mean: track.lufs(), peak: track.peak() } }
 }
 
 #[derive(Debug)]
 struct AlbumVolume {
     mean: Decibels,
-    tracks: HashMap<PathBuf, Decibels>,
+    peak: f32,
+    tracks: HashMap<PathBuf, TrackVolume>,
 }
 
 impl AlbumVolume {
     pub fn get_album_mean(&self) -> Decibels { self.mean }
 
-    pub fn get_track_mean(&self, path: &Path) -> Option<Decibels> { self.tracks.get(path).copied() }
+    pub fn get_album_peak(&self) -> f32 { self.peak }
+
+    pub fn get_track(&self, path: &Path) -> Option<TrackVolume> { self.tracks.get(path).copied() }
 }
 
 fn compute_album_volume<I, P, C>(
-    paths: I, console_output: &C, interrupt_checker: &CtrlCChecker,
+    paths: I, console_output: &C, interrupt_checker: &InterruptChecker, progress: &ProgressReporter,
+    cache: Option<&LoudnessCache>,
 ) -> Result<AlbumVolume, Error>
 where
     I: IntoIterator<Item = P>,
@@ -137,51 +324,113 @@ where
     let paths: Vec<_> = paths.into_iter().enumerate().collect();
     let tracks = Mutex::new(HashMap::new());
 
-    // This is a BTreeMap so we process the analyzers in the supplied order
-    let analyzers = Mutex::new(BTreeMap::new());
+    // This is a BTreeMap so we combine the tracks in the supplied order
+    let analyses = Mutex::new(BTreeMap::new());
 
+    let single_file = paths.len() == 1;
     paths.into_par_iter().panic_fuse().try_for_each(|(idx, input_path)| -> Result<(), Error> {
-        let mut analyzer = VolumeAnalyzer::default();
-        apply_volume_analysis(
-            &mut analyzer,
+        let track = apply_volume_analysis(
+            single_file,
             input_path.as_ref(),
             &DelayedConsoleOutput::new(console_output),
             true,
             interrupt_checker,
+            progress,
+            cache,
         )?;
-        tracks.lock().insert(
-            input_path.as_ref().to_path_buf(),
-            analyzer.last_track_lufs().expect("Track volume unexpectedly missing"),
-        );
-        analyzers.lock().insert(idx, analyzer);
+        tracks.lock().insert(input_path.as_ref().to_path_buf(), TrackVolume::from(&track));
+        analyses.lock().insert(idx, track);
         Ok(())
     })?;
 
-    let analyzers = analyzers.into_inner();
-    let analyzers: Vec<_> = analyzers.into_values().collect();
+    let album = AlbumAnalysis::new(analyses.into_inner().into_values().collect());
     let tracks = tracks.into_inner();
-    let mean = VolumeAnalyzer::mean_lufs_across_multiple(analyzers.iter());
-    let album_volume = AlbumVolume { mean, tracks };
+    let album_volume = AlbumVolume { mean: album.lufs(), peak: album.peak(), tracks };
     Ok(album_volume)
 }
 
-#[derive(Copy, Clone, Debug, ValueEnum)]
-enum Preset {
-    /// ReplayGain (normalize to -18 LUFS)
-    #[clap(name = "rg")]
-    ReplayGain,
+/// The volume target selected by `--preset`
+#[derive(Clone, Copy, Debug)]
+struct Preset {
+    target: VolumeTarget,
+    tag_compensation: bool,
+    peak_ceiling: Option<Decibels>,
+    tag_format: TagFormat,
+}
+
+impl Preset {
+    /// Finds the built-in or user-defined profile with the specified name
+    fn find(name: &str, config: &Config) -> Option<Preset> {
+        match VolumeProfile::find(name) {
+            Some(profile) => Some(Preset {
+                target: profile.target,
+                tag_compensation: true,
+                peak_ceiling: None,
+                tag_format: TagFormat::R128,
+            }),
+            None => config.profiles.get(name).map(|profile| Preset {
+                target: profile.target,
+                tag_compensation: profile.tag_compensation,
+                peak_ceiling: profile.peak_ceiling,
+                tag_format: profile.tag_format,
+            }),
+        }
+    }
+}
 
-    /// EBU R 128 (normalize -23 LUFS)
-    #[clap(name = "r128")]
-    R128,
+/// Accepts the names of the built-in profiles and those defined in the
+/// configuration file
+fn preset_parser(profiles: &BTreeMap<String, UserProfile>) -> PossibleValuesParser {
+    let built_in = VOLUME_PROFILES.iter().map(|profile| PossibleValue::new(profile.name).help(profile.description));
+    let user_defined = profiles.iter().map(|(name, profile)| {
+        let help = profile.description.clone().unwrap_or_else(|| String::from("user-defined profile"));
+        PossibleValue::new(name.clone()).help(help)
+    });
+    PossibleValuesParser::new(built_in.chain(user_defined))
+}
 
-    /// original source volume (set output gain to 0dB)
-    #[clap(name = "original")]
-    ZeroGain,
+fn load_config(path: &Path) -> Result<Config, AppError> {
+    let config = Config::load(path).map_err(|e| AppError::Config(path.to_path_buf(), e))?;
+    if let Some(name) = config.profiles.keys().find(|name| VolumeProfile::find(name).is_some()) {
+        let message = format!("The built-in profile `{}` cannot be redefined", name);
+        return Err(AppError::Config(path.to_path_buf(), message));
+    }
+    Ok(config)
+}
 
-    /// leave the output gain unchanged
-    #[clap(name = "no-change")]
-    NoChange,
+/// Parses the command line, using the configuration file for the available
+/// presets and for the defaults of options which are not specified
+fn parse_cli() -> Result<(Cli, Preset), AppError> {
+    let config = match Config::default_path() {
+        Some(path) => load_config(&path)?,
+        None => Config::default(),
+    };
+    let defaults = &config.defaults;
+    let mut command = Cli::command().mut_arg("preset", |arg| arg.value_parser(preset_parser(&config.profiles)));
+    if let Some(preset) = &defaults.preset {
+        command = command.mut_arg("preset", |arg| arg.default_value(preset.clone()));
+    }
+    if let Some(mode) = &defaults.output_gain_mode {
+        command = command.mut_arg("output_gain_mode", |arg| arg.default_value(mode.clone()));
+    }
+    if let Some(num_threads) = defaults.num_threads {
+        command = command.mut_arg("num_threads", |arg| arg.default_value(num_threads.to_string()));
+    }
+    if let Some(cache) = &defaults.cache {
+        command = command.mut_arg("cache", |arg| arg.default_value(cache.to_string_lossy().into_owned()));
+    }
+    if let Some(temp_dir) = &defaults.temp_dir {
+        command = command.mut_arg("temp_dir", |arg| arg.default_value(temp_dir.to_string_lossy().into_owned()));
+    }
+    if let Some(fsync) = defaults.fsync {
+        command = command.mut_arg("fsync", |arg| arg.default_value(fsync.to_string()));
+    }
+    let mut matches = command.get_matches_from(wild::args_os());
+    let album_group_indices: Vec<usize> = matches.indices_of("album_group").map(Iterator::collect).unwrap_or_default();
+    let mut cli = Cli::from_arg_matches_mut(&mut matches).unwrap_or_else(|e| e.exit());
+    cli.album_groups = input_files::split_occurrences(std::mem::take(&mut cli.album_group), &album_group_indices);
+    let preset = Preset::find(&cli.preset, &config).expect("Preset names should have been validated");
+    Ok((cli, preset))
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
@@ -193,26 +442,68 @@ enum OutputGainSetting {
     Track,
 }
 
-#[derive(Debug, Parser)]
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable text
+    Text,
+
+    /// A JSON object on the last line of standard output
+    Json,
+}
+
+#[derive(Clone, Debug, Parser)]
+#[allow(clippy::struct_excessive_bools)]
 #[clap(author, version, about = "Modifies Ogg Opus output gain values and R128 tags")]
 struct Cli {
     #[clap(short, long, action)]
     /// Enable album mode
     album: bool,
 
-    #[clap(value_enum, short, long, default_value_t = Preset::ReplayGain)]
-    /// Choices for modifying the output gain value
-    preset: Preset,
+    #[clap(long, value_name = "FILES", num_args = 1.., action = ArgAction::Append)]
+    /// Normalize the specified files together as an album, separately from
+    /// the other inputs. Every path up to the next option belongs to the
+    /// album. May be specified more than once to process several albums.
+    album_group: Vec<PathBuf>,
+
+    #[clap(skip)]
+    /// The files of each `--album-group`, in the order they were specified
+    album_groups: Vec<Vec<PathBuf>>,
+
+    #[clap(short, long, default_value = "rg")]
+    /// Choices for modifying the output gain value. Further profiles can be
+    /// defined in the configuration file.
+    preset: String,
 
     #[clap(value_enum, short, long, default_value_t = OutputGainSetting::Auto)]
     /// When modifying the output gain to target a particular LUFS, what volume
     /// should be used
     output_gain_mode: OutputGainSetting,
 
-    #[clap(required(true))]
-    /// The Opus files to process
+    #[clap(required_unless_present_any = ["files_from", "album_group", "watch"])]
+    /// The Opus files to process. M3U playlists will be expanded to the files
+    /// they reference.
     input_files: Vec<PathBuf>,
 
+    #[clap(long, value_name = "LIST")]
+    /// Read the paths of further files to process from the specified file, one
+    /// per line. Use `-` to read from standard input.
+    files_from: Option<PathBuf>,
+
+    #[clap(short = '0', long = "null", action, requires = "files_from")]
+    /// Paths read using `--files-from` are separated by NUL characters rather
+    /// than newlines
+    null_separated: bool,
+
+    #[clap(long, value_name = "PATTERN", value_parser = Glob::new)]
+    /// Only process files matching the specified wildcard pattern, such as
+    /// `*.opus`. May be specified more than once.
+    include: Vec<Glob>,
+
+    #[clap(long, value_name = "PATTERN", value_parser = Glob::new)]
+    /// Do not process files matching the specified wildcard pattern. May be
+    /// specified more than once.
+    exclude: Vec<Glob>,
+
     #[clap(short = 'n', long = "dry-run", action)]
     /// Display output without performing any file modification.
     dry_run: bool,
@@ -222,33 +513,326 @@ struct Cli {
     /// on the system.
     num_threads: usize,
 
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    /// Number of threads to use for rewriting files, separately from those
+    /// used for loudness analysis. Default is the number of processing threads.
+    rewrite_jobs: Option<u16>,
+
+    #[clap(long, action)]
+    /// Run at low CPU priority and, on Linux, idle I/O priority, so that
+    /// processing does not interfere with interactive use
+    nice: bool,
+
     #[clap(short, long, action)]
     /// Clear all R128 tags from the specified files. Output gain will remain
     /// unchanged regardless of the specified preset.
     clear: bool,
+
+    #[clap(long, action, conflicts_with = "clear")]
+    /// Clear only the R128_TRACK_GAIN tag from the specified files, leaving
+    /// any album gain tag and the output gain unchanged
+    clear_track: bool,
+
+    #[clap(long, action, conflicts_with = "clear")]
+    /// Clear only the R128_ALBUM_GAIN tag from the specified files, leaving
+    /// any track gain tag and the output gain unchanged
+    clear_album: bool,
+
+    #[clap(long, value_name = "DB", value_parser = parse_gain, allow_hyphen_values = true)]
+    #[clap(conflicts_with_all = ["preset", "clear", "clear_track", "clear_album", "album", "album_group"])]
+    /// Set the output gain to the specified value in decibels without
+    /// analyzing the files. Existing R128 tags are adjusted to compensate.
+    set_gain: Option<FixedPointGain>,
+
+    #[clap(long, action)]
+    /// Remove R128 tags when setting the output gain without analysis, either
+    /// with `--set-gain` or a user-defined profile, rather than adjusting them
+    /// to compensate for the change
+    no_tag_compensation: bool,
+
+    #[clap(long, action, conflicts_with_all = ["clear", "clear_track", "clear_album"])]
+    /// Skip files whose output gain and R128 tags already match the requested
+    /// preset without analyzing their volume. Albums are only skipped if every
+    /// file in the album matches.
+    skip_tagged: bool,
+
+    #[clap(long, value_name = "DIR")]
+    /// Store computed loudness in the specified directory and reuse it for
+    /// files which have not changed since
+    cache: Option<PathBuf>,
+
+    #[clap(long, action)]
+    /// Emit machine-readable progress events as JSON lines on standard error
+    progress_json: bool,
+
+    #[clap(value_enum, long, default_value_t = OutputFormat::Text)]
+    /// The format of the summary printed once all files have been processed
+    output_format: OutputFormat,
+
+    #[clap(long, value_name = "FILE", conflicts_with = "watch")]
+    /// Write the paths of the files which could not be processed to the
+    /// specified file, in the form read by `--files-from`, so that they can be
+    /// retried
+    write_failures: Option<PathBuf>,
+
+    #[clap(long, value_name = "DIR")]
+    /// Write temporary files to the specified directory rather than alongside
+    /// the files being rewritten
+    temp_dir: Option<PathBuf>,
+
+    #[clap(long, action)]
+    /// Sync each rewritten file and its directory to disk before moving on, so
+    /// that no file is lost or truncated if power fails
+    fsync: bool,
+
+    #[clap(long, action)]
+    /// Copy every page following the codec headers verbatim, preserving its
+    /// boundaries, lacing and granule position rather than re-paginating
+    /// the stream
+    preserve_pages: bool,
+
+    #[clap(long, value_name = "DURATION", value_parser = parse_time_limit)]
+    /// Stop once the specified time has elapsed, given as a whole number of
+    /// seconds or with a suffix of `s`, `m` or `h`. Files which have already
+    /// been rewritten are kept and the remainder are left unmodified.
+    time_limit: Option<Duration>,
+
+    #[clap(long, value_name = "FILE")]
+    /// When the run ends, write which files were completed, skipped and are
+    /// still pending to the specified file
+    state_file: Option<PathBuf>,
+
+    #[clap(long, value_name = "FILE")]
+    /// Skip files recorded as completed or skipped in a state file written by
+    /// `--state-file`. The state file is updated at the end of the run unless
+    /// `--state-file` specifies a different one.
+    resume: Option<PathBuf>,
+
+    #[clap(long, action)]
+    /// Continue processing the remaining files when a file cannot be processed.
+    /// This is the default.
+    keep_going: bool,
+
+    #[clap(long, action, conflicts_with = "keep_going")]
+    /// Stop processing once a file cannot be processed. Files already being
+    /// processed are allowed to finish.
+    fail_fast: bool,
+
+    #[clap(long, action)]
+    /// Exit with a failure status if any file could not be processed, rather
+    /// than only if none could
+    strict_exit: bool,
+
+    #[clap(long, value_name = "DIR", conflicts_with_all = ["input_files", "files_from", "resume", "album", "album_group"])]
+    /// Watch the specified directory and its subdirectories, processing files
+    /// as they are added or changed until interrupted
+    watch: Option<PathBuf>,
+}
+
+/// How often changes are checked for in `--watch` mode
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Parses a gain in decibels which can be represented as an output gain
+fn parse_gain(value: &str) -> Result<FixedPointGain, String> {
+    let gain: f64 = value.parse().map_err(|_| format!("`{}` is not a number", value))?;
+    FixedPointGain::try_from(Decibels::from(gain)).map_err(|_| {
+        let (min, max) = (FixedPointGain::from_fixed_point(i16::MIN), FixedPointGain::from_fixed_point(i16::MAX));
+        format!("Output gain must be between {} and {}", min, max)
+    })
+}
+
+/// Parses a whole number of seconds, or of minutes or hours if suffixed with
+/// `m` or `h`
+fn parse_time_limit(value: &str) -> Result<Duration, String> {
+    let (number, unit_secs) = match value.strip_suffix(['s', 'm', 'h']) {
+        Some(number) if value.ends_with('m') => (number, 60),
+        Some(number) if value.ends_with('h') => (number, 60 * 60),
+        Some(number) => (number, 1),
+        None => (value, 1),
+    };
+    let invalid = || format!("`{}` is not a valid duration", value);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    number.checked_mul(unit_secs).map(Duration::from_secs).ok_or_else(invalid)
 }
 
-#[allow(clippy::too_many_lines)]
 fn main_impl() -> Result<(), AppError> {
-    let interrupt_checker = CtrlCChecker::new()?;
-    let cli = Cli::parse_from(wild::args_os());
-    let album_mode = cli.album;
-    let num_threads = if cli.num_threads == 0 {
+    let ctrl_c = CtrlCChecker::new()?;
+    let (cli, preset) = parse_cli()?;
+    let deadline = cli.time_limit.map(Deadline::after);
+    let interrupt_checker = (ctrl_c, deadline);
+    let state_file = cli.state_file.clone().or_else(|| cli.resume.clone());
+    let tracker = RunTracker::default();
+    if cli.nice {
+        if let Err(e) = priority::lower_priority() {
+            eprintln!("Unable to lower process priority: {}", e);
+        }
+    }
+    // A scoped pool is used rather than the global one so that analysis and
+    // rewriting use the threads requested
+    let thread_pool = build_thread_pool(cli.num_threads)?;
+    let result = thread_pool.install(|| match cli.watch.clone() {
+        Some(dir) => watch_directory(&cli, preset, &dir, &interrupt_checker, &tracker),
+        None => process_files(cli, preset, &interrupt_checker, &tracker),
+    });
+    let state = tracker.state();
+    if let Err(AppError::Library(Error::Interrupted)) = result {
+        println!("Processing stopped before all files were processed.");
+        println!("Files completed: {}", state.completed.len());
+        println!("Files skipped: {}", state.skipped.len());
+        println!("Files pending: {}", state.pending.len());
+    }
+    if let Some(state_file) = state_file {
+        state.save(&state_file)?;
+    }
+    match result {
+        Err(AppError::Library(Error::Interrupted)) if deadline.is_set() => Err(AppError::TimeLimitReached),
+        result => result,
+    }
+}
+
+/// Removes the files which are not Ogg Opus, judging by their content, from
+/// `groups`, returning them along with their formats. Files which cannot be
+/// read are kept so that the error is reported when they are processed.
+fn remove_unsupported_files(groups: &mut Vec<InputGroup>) -> Vec<(PathBuf, FileFormat)> {
+    let mut removed = Vec::new();
+    for group in groups.iter_mut() {
+        group.paths.retain(|path| {
+            let format =
+                File::open(path).map_err(Error::ReadError).and_then(|file| detect_format(&mut BufReader::new(file)));
+            match format {
+                Ok(format) if format != FileFormat::Ogg(Codec::Opus) => {
+                    removed.push((path.clone(), format));
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+    groups.retain(|group| !group.paths.is_empty());
+    removed
+}
+
+/// Removes the files recorded as done in `state` from `groups`, recording them
+/// as done again in `tracker`. Albums are only removed if every file in them
+/// is done, since album loudness must be computed from all of the files.
+fn remove_done_files(groups: Vec<InputGroup>, state: &RunState, tracker: &RunTracker) -> Vec<InputGroup> {
+    let done = state.done();
+    let completed: HashSet<&Path> = state.completed.iter().map(PathBuf::as_path).collect();
+    let mut result = Vec::with_capacity(groups.len());
+    for mut group in groups {
+        if group.is_album && !group.paths.iter().all(|path| done.contains(path.as_path())) {
+            result.push(group);
+            continue;
+        }
+        group.paths.retain(|path| {
+            if completed.contains(path.as_path()) {
+                tracker.completed(path);
+            } else if done.contains(path.as_path()) {
+                tracker.skipped(path);
+            }
+            !done.contains(path.as_path())
+        });
+        if !group.paths.is_empty() {
+            result.push(group);
+        }
+    }
+    result
+}
+
+/// Writes the summary of a run in the specified format. The numbers of files
+/// skipped since they were already tagged or are not Ogg Opus are only
+/// described separately in text.
+fn write_summary<W: Write>(
+    mut writer: W, format: OutputFormat, summary: &RunSummary, num_skipped_tagged: Option<usize>,
+    num_unsupported: usize,
+) -> Result<(), io::Error> {
+    match format {
+        OutputFormat::Text => {
+            writeln!(writer, "Processing complete.")?;
+            writeln!(writer, "Total files processed: {}", summary.processed)?;
+            writeln!(writer, "Files processed but already normalized: {}", summary.unchanged)?;
+            if let Some(num_skipped) = num_skipped_tagged {
+                writeln!(writer, "Files skipped since already tagged: {}", num_skipped)?;
+            }
+            if num_unsupported > 0 {
+                writeln!(writer, "Files skipped since not Ogg Opus: {}", num_unsupported)?;
+            }
+            if summary.failed > 0 {
+                writeln!(writer, "Files which could not be processed: {}", summary.failed)?;
+            }
+        }
+        OutputFormat::Json => writeln!(writer, "{}", serde_json::to_string(summary)?)?,
+    }
+    writer.flush()
+}
+
+fn build_thread_pool(num_threads: usize) -> Result<ThreadPool, Error> {
+    let num_threads = if num_threads == 0 {
         eprintln!("The number of thread specified must be greater than 0.");
         Err(Error::InvalidThreadCount)
     } else {
         let num_cores = num_cpus::get();
-        let rounded = std::cmp::min(cli.num_threads, num_cores);
-        if rounded != cli.num_threads {
-            eprintln!("Rounding down number of threads from {} to {}.", cli.num_threads, num_cores);
+        let rounded = std::cmp::min(num_threads, num_cores);
+        if rounded != num_threads {
+            eprintln!("Rounding down number of threads from {} to {}.", num_threads, num_cores);
         }
         Ok(rounded)
     }?;
-    ThreadPoolBuilder::new().num_threads(num_threads).build_global().expect("Failed to initialize thread pool");
+    Ok(ThreadPoolBuilder::new().num_threads(num_threads).build().expect("Failed to initialize thread pool"))
+}
+
+/// Constructs the thread pool used for rewriting files. While a rewrite runs on
+/// this pool, the analysis thread waiting for it continues with other work.
+fn build_rewrite_pool(num_threads: usize) -> ThreadPool {
+    ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .thread_name(|idx| format!("rewrite-{}", idx))
+        .build()
+        .expect("Failed to initialize thread pool")
+}
+
+/// Processes files in `dir` as they are added or changed, until interrupted.
+/// Failures are reported but do not stop watching.
+fn watch_directory(
+    cli: &Cli, preset: Preset, dir: &Path, interrupt_checker: &InterruptChecker, tracker: &RunTracker,
+) -> Result<(), AppError> {
+    println!("Watching {} for new or changed files.\n", dir.display());
+    let mut watcher = DirectoryWatcher::new(dir, WATCH_INTERVAL)?;
+    loop {
+        let (ready, errors) = watcher.poll();
+        for e in errors {
+            eprintln!("{}", e);
+        }
+        if !ready.is_empty() {
+            let batch = Cli { input_files: ready.clone(), ..cli.clone() };
+            match process_files(batch, preset, interrupt_checker, tracker) {
+                Err(e @ AppError::Library(Error::Interrupted)) => return Err(e),
+                Err(e) => eprintln!("{}", e),
+                Ok(()) => {}
+            }
+            // Rewriting a file changes it, which should not cause it to be processed again
+            for path in &ready {
+                watcher.mark_reported(path);
+            }
+            println!();
+        }
+        let next_scan = Deadline::after(WATCH_INTERVAL);
+        while !next_scan.is_set() {
+            check_running(interrupt_checker)?;
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn process_files(
+    cli: Cli, preset: Preset, interrupt_checker: &InterruptChecker, tracker: &RunTracker,
+) -> Result<(), AppError> {
+    let album_mode = cli.album;
 
-    let output_gain_mode = match cli.output_gain_mode {
+    let output_gain_mode_for = |is_album| match cli.output_gain_mode {
         OutputGainSetting::Auto => {
-            if album_mode {
+            if is_album {
                 OutputGainMode::Album
             } else {
                 OutputGainMode::Track
@@ -256,42 +840,144 @@ fn main_impl() -> Result<(), AppError> {
         }
         OutputGainSetting::Track => OutputGainMode::Track,
     };
-    let volume_target = match cli.preset {
-        Preset::ReplayGain => VolumeTarget::LUFS(REPLAY_GAIN_LUFS),
-        Preset::R128 => VolumeTarget::LUFS(R128_LUFS),
-        Preset::ZeroGain => VolumeTarget::ZeroGain,
-        Preset::NoChange => VolumeTarget::NoChange,
-    };
+    let volume_target = cli.set_gain.map_or(preset.target, VolumeTarget::Absolute);
 
     let dry_run = cli.dry_run;
-    let clear = cli.clear;
-    let (album_mode, volume_target) = if clear {
+    let fsync = cli.fsync;
+    let preserve_pages = cli.preserve_pages;
+    let temp_dir = cli.temp_dir.as_deref();
+    let clear_track_gain = cli.clear || cli.clear_track;
+    let clear_album_gain = cli.clear || cli.clear_album;
+    let clear = clear_track_gain || clear_album_gain;
+    // A preset's tag format does not apply to a fixed output gain
+    let gain_only = cli.set_gain.is_none() && preset.tag_format == TagFormat::GainOnly;
+    let progress = ProgressReporter::new(cli.progress_json);
+    let rewrite_pool = build_rewrite_pool(cli.rewrite_jobs.map_or_else(rayon::current_num_threads, usize::from));
+    let cache = cli.cache.as_deref().map(LoudnessCache::open).transpose()?;
+    let volume_target = if clear {
         // We do not compute album loudness or change output gain when clearing tags
-        (false, VolumeTarget::NoChange)
+        VolumeTarget::NoChange
     } else {
-        (album_mode, volume_target)
+        volume_target
     };
+    // Setting an absolute output gain does not depend on the volume
+    let analyze = !clear && !matches!(volume_target, VolumeTarget::Absolute(_));
+    // Tags which are not cleared are kept, which compensation does when the
+    // output gain is unchanged. Analyzed files have their tags recomputed
+    // instead, and any which cannot be are removed.
+    let tag_compensation =
+        clear || (!analyze && !cli.no_tag_compensation && (cli.set_gain.is_some() || preset.tag_compensation));
 
+    let start_time = Instant::now();
     let num_processed = AtomicUsize::new(0);
+    let num_changed = AtomicUsize::new(0);
     let num_already_normalized = AtomicUsize::new(0);
+    let gain_change = Mutex::new(Decibels::default());
+    let mut num_skipped = 0;
+    let mut num_unsupported = 0;
 
     if dry_run {
         println!("Display-only mode is enabled so no files will actually be modified.\n");
     }
 
     let console_output = Standard::default();
-    let input_files = cli.input_files;
-    let album_volume =
-        if album_mode { Some(compute_album_volume(&input_files, &console_output, &interrupt_checker)?) } else { None };
+    let input_groups = {
+        let mut input_files = cli.input_files;
+        if let Some(list) = cli.files_from {
+            input_files.extend(input_files::read_file_list(&list, cli.null_separated)?);
+        }
+        let mut input_groups =
+            input_files::group_by_cue_sheets(input_files::expand_playlists(input_files)?, album_mode)?;
+        for paths in cli.album_groups {
+            input_groups.push(InputGroup { paths: input_files::expand_playlists(paths)?, is_album: true });
+        }
+        let num_filtered = PathFilter::new(cli.include, cli.exclude).apply(&mut input_groups);
+        if num_filtered > 0 {
+            println!("Ignoring {} files which do not match the include and exclude patterns.", num_filtered);
+        }
+        for (path, format) in remove_unsupported_files(&mut input_groups) {
+            println!("Skipping {} since it is not an Ogg Opus file ({}).", path.display(), format);
+            num_unsupported += 1;
+        }
+        let (input_groups, duplicates) = input_files::remove_duplicates(input_groups);
+        for duplicate in duplicates {
+            eprintln!(
+                "Warning: {} refers to the same file as {} so will only be processed once.",
+                duplicate.path.display(),
+                duplicate.original.display()
+            );
+        }
+        input_groups
+    };
+    for group in &input_groups {
+        tracker.add_inputs(&group.paths);
+    }
+    let input_groups = if let Some(resume) = cli.resume.as_deref() {
+        let state = RunState::load(resume)?;
+        let remaining = remove_done_files(input_groups, &state, tracker);
+        let num_remaining: usize = remaining.iter().map(|group| group.paths.len()).sum();
+        println!("Resuming from {} with {} files remaining.", resume.display(), num_remaining);
+        remaining
+    } else {
+        input_groups
+    };
+    let input_groups = if cli.skip_tagged {
+        let mut remaining_groups = Vec::with_capacity(input_groups.len());
+        for group in input_groups {
+            let mode = output_gain_mode_for(group.is_album);
+            let (remaining, skipped) = partition_tagged_files(group, volume_target, mode, interrupt_checker)?;
+            for path in skipped {
+                println!("Skipping {} since its gains already match the target.", path.display());
+                tracker.skipped(&path);
+                progress
+                    .emit(&ProgressEvent::FileSkipped { path: ProgressEvent::path_string(&path) })
+                    .map_err(Error::ConsoleIoError)?;
+                num_skipped += 1;
+            }
+            remaining_groups.push(remaining);
+        }
+        remaining_groups.retain(|group| !group.paths.is_empty());
+        remaining_groups
+    } else {
+        input_groups
+    };
+
+    // Album loudness is computed for each group that is an album before any
+    // file is rewritten
+    let mut album_volumes = Vec::with_capacity(input_groups.len());
+    for group in &input_groups {
+        let album_volume = if group.is_album && analyze {
+            Some(compute_album_volume(&group.paths, &console_output, interrupt_checker, &progress, cache.as_ref())?)
+        } else {
+            None
+        };
+        album_volumes.push(album_volume);
+    }
+    let input_files: Vec<_> = input_groups
+        .iter()
+        .zip(album_volumes.iter())
+        .flat_map(|(group, album_volume)| group.paths.iter().map(move |path| (path.clone(), album_volume.as_ref())))
+        .collect();
 
-    // Prevent us from rewriting more than one file at once. This is to stop us
-    // consuming too much disk space or leaving lots of temporary files around
-    // if we encounter an error.
-    let rewrite_mutex = Mutex::new(());
+    // Bound the number of concurrent rewrites so that their temporary files do
+    // not consume more disk space than is available
+    let rewrite_limiter = RewriteLimiter::new();
 
-    input_files.into_par_iter().panic_fuse().try_for_each(|input_path| -> Result<(), AppError> {
+    // Files which fail are reported and, unless failing fast, the remainder
+    // still processed. Interruption always stops the run.
+    let fail_fast = cli.fail_fast;
+    let failures = Mutex::new(Vec::new());
+    let num_attempted = input_files.len();
+    let single_file = num_attempted == 1;
+    input_files.into_par_iter().panic_fuse().try_for_each(|(input_path, album_volume)| -> Result<(), AppError> {
+        if fail_fast && !failures.lock().is_empty() {
+            return Ok(());
+        }
         let console = &DelayedConsoleOutput::new(&console_output);
         let body = || -> Result<(), AppError> {
+            progress
+                .emit(&ProgressEvent::FileStarted { path: ProgressEvent::path_string(&input_path) })
+                .map_err(Error::ConsoleIoError)?;
             writeln!(
                 console.out(),
                 "Processing file {} with target loudness of {}...",
@@ -299,52 +985,106 @@ fn main_impl() -> Result<(), AppError> {
                 volume_target.to_friendly_string()
             )
             .map_err(Error::ConsoleIoError)?;
-            let track_volume = if clear {
-                None
-            } else {
-                Some(match &album_volume {
-                    None => {
-                        let mut analyzer = VolumeAnalyzer::default();
-                        apply_volume_analysis(&mut analyzer, &input_path, console, false, &interrupt_checker)?;
-                        analyzer.last_track_lufs().expect("Last track volume unexpectedly missing")
+            let track_volume = if analyze {
+                Some(match album_volume {
+                    None => TrackVolume::from(&apply_volume_analysis(
+                        single_file,
+                        &input_path,
+                        console,
+                        false,
+                        interrupt_checker,
+                        &progress,
+                        cache.as_ref(),
+                    )?),
+                    Some(album_volume) => {
+                        album_volume.get_track(&input_path).expect("Could not find previously computed track volume")
                     }
-                    Some(album_volume) => album_volume
-                        .get_track_mean(&input_path)
-                        .expect("Could not find previously computed track volume"),
                 })
+            } else {
+                None
             };
+            let output_gain_mode = output_gain_mode_for(album_volume.is_some());
+            let peak = match (output_gain_mode, album_volume) {
+                (OutputGainMode::Album, Some(album_volume)) => Some(album_volume.get_album_peak()),
+                _ => track_volume.map(|track| track.peak),
+            };
+            // The output gain is limited so that the peak does not exceed the
+            // ceiling. A silent file has no peak to limit.
+            let max_output_gain = preset
+                .peak_ceiling
+                .zip(peak.filter(|peak| *peak > 0.0))
+                .map(|(ceiling, peak)| ceiling - Decibels::from_amplitude(f64::from(peak)));
             let rewriter_config = VolumeRewriterConfig {
                 output_gain: volume_target,
                 output_gain_mode,
-                track_volume,
-                album_volume: album_volume.as_ref().map(AlbumVolume::get_album_mean),
+                track_volume: track_volume.map(|track| track.mean),
+                album_volume: album_volume.map(AlbumVolume::get_album_mean),
+                max_output_gain,
+                tag_compensation,
+                clear_track_gain: clear_track_gain || gain_only,
+                clear_album_gain: clear_album_gain || gain_only,
             };
 
+            let cache_key = cache.as_ref().map(|_| CacheKey::for_file(&input_path)).transpose()?;
             let input_file = File::open(&input_path).map_err(|e| Error::FileOpenError(input_path.clone(), e))?;
+            let input_length = input_file.metadata().map_err(|e| Error::FileReadError(input_path.clone(), e))?.len();
             let mut input_file = BufReader::new(input_file);
 
             {
-                let rewrite_guard = rewrite_mutex.lock();
-                check_running(&interrupt_checker)?;
-                let mut output_file = OutputFile::new_target_or_discard(&input_path, dry_run)?;
-                let rewrite_result = {
-                    let mut output_file = BufWriter::new(&mut output_file);
-                    let rewrite = VolumeHeaderRewrite::new(rewriter_config);
-                    let summarize = GainsSummary::default();
-                    let abort_on_unchanged = true;
-                    rewrite_stream_with_interrupt(
-                        rewrite,
-                        summarize,
-                        &mut input_file,
-                        &mut output_file,
-                        abort_on_unchanged,
-                        &interrupt_checker,
-                    )
-                };
-                drop(input_file); // Important for Windows
+                // Nothing is written in dry-run mode
+                let temporary_size = if dry_run { 0 } else { input_length };
+                // Rewriting is I/O-bound, so it takes place on a separate pool to avoid
+                // occupying the threads used for analysis. The changed file is committed
+                // there too, so that its temporary space is reserved until then.
+                let rewrite_result =
+                    rewrite_limiter.install(&rewrite_pool, &input_path, temp_dir, temporary_size, || {
+                        check_running(interrupt_checker)?;
+                        let mut output_file = if dry_run {
+                            OutputFile::new_sink()
+                        } else {
+                            OutputFile::new_target_in(&input_path, temp_dir)?
+                        };
+                        output_file.check_free_space(input_length)?;
+                        output_file.set_durable(fsync);
+                        let rewrite_result = {
+                            let mut output_file = BufWriter::new(&mut output_file);
+                            let rewrite = VolumeHeaderRewrite::new(rewriter_config);
+                            let summarize = GainsSummary::default();
+                            let abort_on_unchanged = true;
+                            if preserve_pages {
+                                take_id3v2_prefix(&mut input_file).and_then(|tag| {
+                                    output_file
+                                        .write_all(tag.as_deref().unwrap_or_default())
+                                        .map_err(Error::WriteError)?;
+                                    rewrite_stream_preserving_pages_with_interrupt(
+                                        rewrite,
+                                        summarize,
+                                        &mut input_file,
+                                        &mut output_file,
+                                        abort_on_unchanged,
+                                        interrupt_checker,
+                                    )
+                                })
+                            } else {
+                                rewrite_stream_with_interrupt(
+                                    rewrite,
+                                    summarize,
+                                    &mut input_file,
+                                    &mut output_file,
+                                    abort_on_unchanged,
+                                    interrupt_checker,
+                                )
+                            }
+                        };
+                        drop(input_file); // Important for Windows
+                        if let Ok(SubmitResult::HeadersChanged { .. }) = rewrite_result {
+                            output_file.commit()?;
+                        }
+                        Ok(rewrite_result)
+                    })?;
                 num_processed.fetch_add(1, Ordering::Relaxed);
 
-                match rewrite_result {
+                let changed = match rewrite_result {
                     Err(e) => {
                         writeln!(console.err(), "Failure during processing of {}.", input_path.display())
                             .map_err(Error::ConsoleIoError)?;
@@ -360,38 +1100,141 @@ fn main_impl() -> Result<(), AppError> {
                             input_path.display(),
                         )
                         .map_err(Error::ConsoleIoError)?;
+                        false
                     }
                     Ok(SubmitResult::HeadersChanged { from: old_gains, to: new_gains }) => {
-                        output_file.commit()?;
-                        writeln!(console.out(), "Old gain values:").map_err(Error::ConsoleIoError)?;
-                        print_gains(&old_gains, console)?;
-                        writeln!(console.out(), "New gain values:").map_err(Error::ConsoleIoError)?;
-                        print_gains(&new_gains, console)?;
+                        if let (Some(cache), Some(cache_key), false) = (cache.as_ref(), cache_key.as_ref(), dry_run) {
+                            // Only the headers have changed so the cached loudness remains valid
+                            let result =
+                                CacheKey::for_file(&input_path).and_then(|new_key| cache.transfer(cache_key, &new_key));
+                            if let Err(e) = result {
+                                writeln!(
+                                    console.err(),
+                                    "Failed to update loudness cache for {}: {}",
+                                    input_path.display(),
+                                    e
+                                )
+                                .map_err(Error::ConsoleIoError)?;
+                            }
+                        }
+                        if dry_run {
+                            writeln!(console.out(), "Changes which would be made:").map_err(Error::ConsoleIoError)?;
+                            text_diff::write_diff(console.out(), &gain_lines(&old_gains), &gain_lines(&new_gains))
+                                .map_err(Error::ConsoleIoError)?;
+                        } else {
+                            writeln!(console.out(), "Old gain values:").map_err(Error::ConsoleIoError)?;
+                            print_gains(&old_gains, console)?;
+                            writeln!(console.out(), "New gain values:").map_err(Error::ConsoleIoError)?;
+                            print_gains(&new_gains, console)?;
+                        }
+                        num_changed.fetch_add(1, Ordering::Relaxed);
+                        {
+                            let mut gain_change = gain_change.lock();
+                            *gain_change = *gain_change + (new_gains.output - old_gains.output);
+                        }
+                        true
                     }
                     Ok(SubmitResult::HeadersUnchanged(gains)) => {
                         writeln!(console.out(), "All gains are already correct so doing nothing. Existing gains were:")
                             .map_err(Error::ConsoleIoError)?;
                         print_gains(&gains, console)?;
                         num_already_normalized.fetch_add(1, Ordering::Relaxed);
+                        false
                     }
-                }
-                drop(rewrite_guard);
+                };
+                progress
+                    .emit(&ProgressEvent::RewriteComplete { path: ProgressEvent::path_string(&input_path), changed })
+                    .map_err(Error::ConsoleIoError)?;
             }
             Ok(())
         };
         let result = body();
+        if result.is_ok() && !dry_run {
+            tracker.completed(&input_path);
+        }
         if let Err(ref e) = result {
             writeln!(console.err(), "Failed to rewrite {}: {}", input_path.display(), e)
                 .map_err(Error::ConsoleIoError)?;
+            progress
+                .emit(&ProgressEvent::Error { path: ProgressEvent::path_string(&input_path), message: e.to_string() })
+                .map_err(Error::ConsoleIoError)?;
         }
         writeln!(console.out()).map_err(Error::ConsoleIoError)?;
-        result
+        match result {
+            Err(AppError::Library(Error::Interrupted)) => result,
+            Err(e) => {
+                failures.lock().push((input_path, e));
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
     })?;
 
-    let num_processed = num_processed.into_inner();
-    let num_already_normalized = num_already_normalized.into_inner();
-    println!("Processing complete.");
-    println!("Total files processed: {}", num_processed);
-    println!("Files processed but already normalized: {}", num_already_normalized);
-    Ok(())
+    let failures = failures.into_inner();
+    let summary = RunSummary {
+        processed: num_processed.into_inner(),
+        changed: num_changed.into_inner(),
+        unchanged: num_already_normalized.into_inner(),
+        skipped: num_skipped + num_unsupported,
+        failed: failures.len(),
+        elapsed_seconds: start_time.elapsed().as_secs_f64(),
+        gain_change: gain_change.into_inner().as_f64(),
+    };
+    progress.emit(&ProgressEvent::Summary(summary.clone())).map_err(Error::ConsoleIoError)?;
+    if let Some(list) = cli.write_failures.as_deref() {
+        let paths: Vec<_> = failures.iter().map(|(path, _)| path.clone()).collect();
+        input_files::write_file_list(list, &paths, cli.null_separated)?;
+    }
+    let num_skipped_tagged = if cli.skip_tagged { Some(num_skipped) } else { None };
+    write_summary(io::stdout().lock(), cli.output_format, &summary, num_skipped_tagged, num_unsupported)
+        .map_err(Error::ConsoleIoError)?;
+    let Some((_, first_failure)) = failures.first() else { return Ok(()) };
+    eprintln!("\nThe following files could not be processed:");
+    for (path, e) in &failures {
+        eprintln!("  {}: {}", path.display(), e);
+    }
+    if fail_fast {
+        Err(AppError::StoppedAfterFailure(first_failure.exit_code()))
+    } else if failures.len() == num_attempted {
+        Err(AppError::AllFilesFailed(first_failure.exit_code()))
+    } else if cli.strict_exit {
+        Err(AppError::SomeFilesFailed(failures.len(), num_attempted))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_summary_written() -> Result<(), io::Error> {
+        let cli = Cli::try_parse_from(["opusgain", "--output-format", "json", "a.opus"]).expect("Valid arguments");
+        let summary = RunSummary {
+            processed: 3,
+            changed: 2,
+            unchanged: 1,
+            skipped: 4,
+            failed: 1,
+            elapsed_seconds: 0.5,
+            gain_change: -1.5,
+        };
+        let mut output = Vec::new();
+        write_summary(&mut output, cli.output_format, &summary, Some(3), 1)?;
+        let output = String::from_utf8(output).expect("Summary should be UTF-8");
+        assert_eq!(
+            output,
+            "{\"processed\":3,\"changed\":2,\"unchanged\":1,\"skipped\":4,\"failed\":1,\"elapsed_seconds\":0.5,\"gain_change\":-1.5}\n"
+        );
+
+        let cli = Cli::try_parse_from(["opusgain", "a.opus"]).expect("Valid arguments");
+        let mut output = Vec::new();
+        write_summary(&mut output, cli.output_format, &summary, Some(3), 1)?;
+        let output = String::from_utf8(output).expect("Summary should be UTF-8");
+        assert!(output.starts_with("Processing complete.\n"));
+        assert!(output.contains("Files skipped since already tagged: 3\n"));
+        assert!(output.ends_with("Files which could not be processed: 1\n"));
+        Ok(())
+    }
 }
@@ -0,0 +1,291 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::uninlined_format_args)]
+
+#[cfg(feature = "analysis")]
+#[path = "../bench.rs"]
+mod bench;
+
+#[path = "../encoder_stats.rs"]
+mod encoder_stats;
+
+#[path = "../library_audit.rs"]
+mod library_audit;
+
+#[path = "../library_scan.rs"]
+mod library_scan;
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::builder::PossibleValuesParser;
+use clap::{Parser, Subcommand, ValueEnum};
+use encoder_stats::{read_encoder_info, EncoderStats};
+use library_audit::{audit, read_gains, AuditedFile};
+use library_scan::{find_files, scan_file, write_csv, ScannedFile};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use thiserror::Error;
+use zoog::capabilities::capabilities;
+use zoog::output_file::OutputFile;
+use zoog::volume_rewrite::{VolumeProfile, VOLUME_PROFILES};
+
+#[derive(Debug, Error)]
+enum AppError {
+    #[error("Failed to write to standard output: `{0}`")]
+    StandardOutputWriteError(io::Error),
+
+    #[error("Failed to serialize output: `{0}`")]
+    SerializationError(#[from] serde_json::Error),
+
+    #[error("{0}")]
+    Library(#[from] zoog::Error),
+
+    #[error("Failed to initialize thread pool: `{0}`")]
+    ThreadPoolError(#[from] rayon::ThreadPoolBuildError),
+
+    #[error("{0} files would play at the wrong level")]
+    AuditFailed(usize),
+}
+
+fn main() {
+    if let Err(e) = main_impl() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[derive(Debug, Parser)]
+#[clap(author, version, about = "Query information about this installation of zoog and Ogg files.")]
+#[clap(arg_required_else_help = true, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[clap(long, action, exclusive = true)]
+    /// Print a JSON description of the codecs, tag conventions and features
+    /// supported by this build
+    capabilities: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Count the vendor strings and ENCODER tags of Ogg Opus and Ogg Vorbis
+    /// files to identify the software used to encode them
+    EncoderStats {
+        #[clap(long, action)]
+        /// Print the counts as a JSON object
+        json: bool,
+
+        #[clap(required = true)]
+        /// The files to read
+        input_files: Vec<PathBuf>,
+    },
+
+    /// Walk directories of Ogg Opus and Ogg Vorbis files and write a single
+    /// table of their tags, gains, durations and implied loudness
+    Scan {
+        #[clap(long, value_enum, default_value_t = ScanFormat::Json)]
+        /// The format of the table
+        format: ScanFormat,
+
+        #[clap(short, long, value_name = "FILE")]
+        /// Write the table to FILE instead of standard output. The file is
+        /// replaced only once the scan is complete.
+        output: Option<PathBuf>,
+
+        #[clap(required = true)]
+        /// The files and directories to scan. Directories are searched
+        /// recursively and files in other formats are skipped.
+        paths: Vec<PathBuf>,
+    },
+
+    /// Check that the output gain and R128 tags of Ogg Opus files agree with
+    /// each other and with a loudness preset, reporting files which would play
+    /// at the wrong level
+    Audit {
+        #[clap(short, long, default_value = "rg", value_parser = PossibleValuesParser::new(VOLUME_PROFILES.iter().map(|p| p.name)))]
+        /// The preset the files are expected to have been normalized with
+        preset: String,
+
+        #[clap(value_enum, short, long, default_value_t = AuditGainMode::Auto)]
+        /// The volume the output gain is expected to target
+        output_gain_mode: AuditGainMode,
+
+        #[clap(long, action)]
+        /// Print the files and their issues as JSON
+        json: bool,
+
+        #[clap(required = true)]
+        /// The files and directories to audit. Directories are searched
+        /// recursively and files in other formats are skipped. Files in the
+        /// same directory with album gain tags are checked as an album.
+        paths: Vec<PathBuf>,
+    },
+
+    /// Measure how quickly Ogg Opus files are decoded, analyzed and rewritten,
+    /// with and without parallelism. Files are only read.
+    #[cfg(feature = "analysis")]
+    Bench {
+        #[clap(short = 'j', long, default_value_t = num_cpus::get())]
+        /// Number of threads to use for the parallel measurements
+        num_threads: usize,
+
+        #[clap(long, action)]
+        /// Print the measurements as JSON
+        json: bool,
+
+        #[clap(required = true)]
+        /// The Ogg Opus files to process
+        input_files: Vec<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum AuditGainMode {
+    /// Album volume for files with an album gain tag and track volume
+    /// otherwise
+    Auto,
+
+    /// Track volume, even for files with an album gain tag
+    Track,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum ScanFormat {
+    /// An array of objects, one per file
+    Json,
+
+    /// A header row followed by a row per file, with one column per tag value
+    /// as accepted by `zoogcomment --from-table`
+    Csv,
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> Result<(), AppError> {
+    let json = serde_json::to_string_pretty(value)?;
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    writeln!(stdout, "{}", json).map_err(AppError::StandardOutputWriteError)
+}
+
+fn encoder_stats(input_files: &[PathBuf], json: bool) -> Result<(), AppError> {
+    let results: Vec<_> = input_files.par_iter().map(|path| read_encoder_info(path)).collect();
+    let mut stats = EncoderStats::default();
+    for (path, result) in input_files.iter().zip(results) {
+        match result {
+            Ok(info) => stats.add(&info),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                stats.add_unreadable();
+            }
+        }
+    }
+    if json {
+        print_json(&stats)
+    } else {
+        stats.write_report(io::stdout().lock()).map_err(AppError::StandardOutputWriteError)
+    }
+}
+
+fn write_scan<W: Write>(mut writer: W, files: &[ScannedFile], format: ScanFormat) -> Result<(), AppError> {
+    match format {
+        ScanFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, files)?;
+            writeln!(writer).map_err(AppError::StandardOutputWriteError)
+        }
+        ScanFormat::Csv => write_csv(writer, files).map_err(AppError::StandardOutputWriteError),
+    }
+}
+
+fn scan(paths: &[PathBuf], format: ScanFormat, output: Option<&Path>) -> Result<(), AppError> {
+    let mut input_files = Vec::new();
+    for path in paths {
+        find_files(path, &mut input_files)?;
+    }
+    let results: Vec<_> = input_files.par_iter().map(|path| scan_file(path)).collect();
+    let mut files = Vec::new();
+    for (path, result) in input_files.iter().zip(results) {
+        match result {
+            Ok(Some(file)) => files.push(file),
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to read {}: {}", path.display(), e),
+        }
+    }
+    match output {
+        None => write_scan(io::stdout().lock(), &files, format),
+        Some(path) => {
+            let mut output = OutputFile::new_target(path)?;
+            match write_scan(&mut output, &files, format) {
+                Ok(()) => Ok(output.commit()?),
+                Err(e) => {
+                    output.abort()?;
+                    Err(e)
+                }
+            }
+        }
+    }
+}
+
+fn audit_files(paths: &[PathBuf], preset: &str, mode: AuditGainMode, json: bool) -> Result<(), AppError> {
+    let target = VolumeProfile::find(preset).expect("Preset names should have been validated").target;
+    let mut input_files = Vec::new();
+    for path in paths {
+        find_files(path, &mut input_files)?;
+    }
+    let results: Vec<_> = input_files.par_iter().map(|path| read_gains(path)).collect();
+    let mut files = Vec::new();
+    for (path, result) in input_files.iter().zip(results) {
+        match result {
+            Ok(Some(file)) => files.push(file),
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to read {}: {}", path.display(), e),
+        }
+    }
+    let num_files = files.len();
+    let audited: Vec<AuditedFile> = audit(&files, target, mode == AuditGainMode::Track)
+        .into_iter()
+        .filter(|file| !file.issues.is_empty())
+        .collect();
+    if json {
+        print_json(&audited)?;
+    } else {
+        let mut stdout = io::stdout().lock();
+        for file in &audited {
+            for issue in &file.issues {
+                writeln!(stdout, "{}: {}", file.path.display(), issue).map_err(AppError::StandardOutputWriteError)?;
+            }
+        }
+        writeln!(stdout, "Audited {} Ogg Opus files.", num_files).map_err(AppError::StandardOutputWriteError)?;
+    }
+    if audited.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::AuditFailed(audited.len()))
+    }
+}
+
+#[cfg(feature = "analysis")]
+fn bench(input_files: &[PathBuf], num_threads: usize, json: bool) -> Result<(), AppError> {
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(num_threads).build()?;
+    let results = bench::run(input_files, &pool)?;
+    if json {
+        print_json(&results)
+    } else {
+        bench::write_report(io::stdout().lock(), &results).map_err(AppError::StandardOutputWriteError)
+    }
+}
+
+fn main_impl() -> Result<(), AppError> {
+    let cli = Cli::parse_from(wild::args_os());
+    if cli.capabilities {
+        print_json(&capabilities())?;
+    }
+    match cli.command {
+        None => Ok(()),
+        Some(Command::EncoderStats { json, input_files }) => encoder_stats(&input_files, json),
+        Some(Command::Scan { format, output, paths }) => scan(&paths, format, output.as_deref()),
+        Some(Command::Audit { preset, output_gain_mode, json, paths }) => {
+            audit_files(&paths, &preset, output_gain_mode, json)
+        }
+        #[cfg(feature = "analysis")]
+        Some(Command::Bench { num_threads, json, input_files }) => bench(&input_files, num_threads, json),
+    }
+}
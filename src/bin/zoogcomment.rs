@@ -4,29 +4,62 @@
 #[path = "../ctrlc_handling.rs"]
 mod ctrlc_handling;
 
-#[path = "../output_file.rs"]
-mod output_file;
+#[path = "../exit_code.rs"]
+mod exit_code;
+
+#[path = "../ffmetadata.rs"]
+mod ffmetadata;
+
+#[path = "../path_pattern.rs"]
+mod path_pattern;
+
+#[path = "../tag_table.rs"]
+mod tag_table;
+
+#[path = "../text_diff.rs"]
+mod text_diff;
 
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::convert::Into;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek as _, Write as _};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write as _};
 use std::ops::BitOrAssign;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use ctrlc_handling::CtrlCChecker;
-use output_file::OutputFile;
+use parking_lot::Mutex;
+use path_pattern::PathPattern;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use tag_table::TagTableRow;
 use thiserror::Error;
-use zoog::comment_rewrite::{CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig};
-use zoog::header::{parse_comment, validate_comment_field_name, CommentList, DiscreteCommentList};
-use zoog::header_rewriter::{rewrite_stream_with_interrupt, SubmitResult};
-use zoog::{escaping, Error};
+use zoog::comment_rewrite::{
+    CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig, RetainPredicate,
+};
+use zoog::header::{
+    parse_comment, validate_comment_field_name, CommentList, DiscreteCommentList, IdHeader, InvalidComment,
+};
+use zoog::header_rewriter::{
+    read_headers, rewrite_stream_preserving_pages_with_interrupt, rewrite_stream_with_interrupt, CodecHeaders,
+    HeaderRewrite, Paginated, Pagination, SubmitResult,
+};
+use zoog::ogg_page::{last_granule_position, salvage_pages};
+use zoog::opus::{TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
+use zoog::output_file::OutputFile;
+use zoog::tag_names::TagConvention;
+use zoog::{escaping, import, Error, TagEditor};
 
 const OGG_OPUS_EXTENSIONS: [&str; 7] = ["ogg", "ogv", "oga", "ogx", "ogm", "spx", "opus"];
 const STANDARD_STREAM_NAME: &str = "-";
 
+/// An input which can be rewound
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
 #[derive(Debug, Error)]
 enum AppError {
     #[error("{0}")]
@@ -40,16 +73,38 @@ enum AppError {
 
     #[error("Failed to read from standard input: `{0}`")]
     StandardInputReadError(io::Error),
+
+    #[error("The path {} does not match the pattern given with --infer-from-path", .0.display())]
+    PathPatternMismatch(PathBuf),
+
+    #[error("None of the files in the table could be tagged")]
+    AllRowsFailed(i32),
+
+    #[error("{0} of {1} files in the table could not be tagged")]
+    SomeRowsFailed(usize, usize),
+}
+
+impl AppError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            AppError::LibraryError(e) => exit_code::for_error_kind(e.kind()),
+            AppError::SilentExit | AppError::PathPatternMismatch(_) => exit_code::INVALID_ARGUMENT,
+            AppError::CtrlCRegistration(_) => exit_code::FAILURE,
+            AppError::StandardInputReadError(_) => exit_code::IO,
+            AppError::AllRowsFailed(code) => *code,
+            AppError::SomeRowsFailed(..) => exit_code::PARTIAL_FAILURE,
+        }
+    }
 }
 
 fn main() {
     if let Err(e) = main_impl() {
-        match e {
+        match &e {
             AppError::LibraryError(e) => eprintln!("Aborted due to error: {}", e),
             AppError::SilentExit => {}
             e => eprintln!("{}", e),
         }
-        std::process::exit(1);
+        std::process::exit(e.exit_code());
     }
 }
 
@@ -77,6 +132,11 @@ struct Cli {
     /// Specify a tag name or name-value mapping to be deleted
     delete: Vec<String>,
 
+    #[clap(short = 'k', long = "key", value_name = "NAME", conflicts_with = "replace", conflicts_with = "modify")]
+    /// In list mode, only list tags with the specified name. May be specified
+    /// more than once.
+    keys: Vec<String>,
+
     #[clap(short, long, action)]
     /// Use escapes \n, \r, \0 and \\ for tag-value input and output
     escapes: bool,
@@ -85,6 +145,11 @@ struct Cli {
     /// Display output without performing any file modification.
     dry_run: bool,
 
+    #[clap(long, action, conflicts_with = "list")]
+    /// Remove comment entries which could not be parsed instead of preserving
+    /// them
+    drop_invalid: bool,
+
     #[clap(short = 'I', long = "tags-in", conflicts_with = "list")]
     /// File for reading tags from
     tags_in: Option<PathBuf>,
@@ -93,14 +158,114 @@ struct Cli {
     /// File for writing tags to
     tags_out: Option<PathBuf>,
 
+    #[clap(long, value_enum, value_name = "FORMAT", default_value_t = TagsFormat::Text)]
+    /// The format of the files read with -I and written with -O or to
+    /// standard output in list mode
+    tags_format: TagsFormat,
+
+    #[clap(long, action, conflicts_with = "list")]
+    /// Append tags read from a FLAC or MP3 file with the same name as the
+    /// input file
+    import_sibling: bool,
+
+    #[clap(long, value_name = "FILE", conflicts_with = "list")]
+    /// Copy tags from the specified Ogg Opus or Ogg Vorbis file. In modify
+    /// mode they replace any tags with the same names.
+    copy_from: Option<PathBuf>,
+
+    #[clap(long, value_name = "PATTERN", value_parser = PathPattern::new, conflicts_with = "list")]
+    /// Set tags from parts of the input file's path matching a pattern such as
+    /// `%artist%/%album%/%track% - %title%`. In modify mode they replace any
+    /// tags with the same names.
+    infer_from_path: Option<PathPattern>,
+
+    #[clap(long, value_name = "CONVENTION", value_parser = TagConvention::from_str, conflicts_with = "list")]
+    /// Rename tags such as ALBUMARTIST and TOTALTRACKS to the names used by a
+    /// convention: picard, foobar2000 or xiph
+    normalize_keys: Option<TagConvention>,
+
+    #[clap(long, value_name = "VENDOR", conflicts_with = "list")]
+    /// Set the vendor string of the comment header
+    set_vendor: Option<String>,
+
+    #[clap(long, action, conflicts_with_all = ["modify", "replace", "keys", "tags_out"])]
+    /// In list mode, print the vendor string of the comment header instead of
+    /// the comments
+    show_vendor: bool,
+
+    #[clap(long, action)]
+    /// Recover a truncated or corrupted input file by keeping everything
+    /// before the first damaged page and ending the stream there
+    salvage: bool,
+
+    #[clap(long, action, conflicts_with = "list")]
+    /// Remove an ID3 tag preceding the Ogg data instead of preserving it
+    strip_id3v2: bool,
+
+    #[clap(long, value_name = "BYTES", conflicts_with = "list")]
+    /// Re-paginate Ogg Opus audio, ending each page once its packet data
+    /// reaches the specified size
+    page_size: Option<usize>,
+
+    #[clap(long, value_name = "COUNT", conflicts_with = "list", value_parser = clap::value_parser!(u16).range(1..))]
+    /// Re-paginate Ogg Opus audio, placing at most the specified number of
+    /// packets on each page
+    max_packets_per_page: Option<u16>,
+
+    #[clap(long, action, conflicts_with = "list")]
+    /// Re-paginate Ogg Opus audio, placing each packet on its own page
+    flush_packets: bool,
+
+    #[clap(long, action, conflicts_with_all = ["list", "page_size", "max_packets_per_page", "flush_packets"])]
+    /// Copy every page following the codec headers verbatim, preserving its
+    /// boundaries, lacing and granule position rather than re-paginating
+    /// the stream
+    preserve_pages: bool,
+
+    #[clap(long, value_name = "DIR")]
+    /// Write temporary files to the specified directory rather than alongside
+    /// the output file
+    temp_dir: Option<PathBuf>,
+
+    #[clap(long, action)]
+    /// Sync the output file and its directory to disk before exiting, so that
+    /// it is not lost or truncated if power fails
+    fsync: bool,
+
+    #[clap(long, action, conflicts_with = "list")]
+    /// Write the output file even if the comments are unchanged, rather than
+    /// leaving an existing file untouched
+    force_write: bool,
+
+    #[clap(long, value_name = "TABLE", conflicts_with_all = [
+        "input_file", "list", "tags", "delete", "tags_in", "tags_out", "import_sibling", "copy_from", "infer_from_path",
+        "set_vendor", "show_vendor",
+        "salvage", "strip_id3v2", "page_size", "max_packets_per_page", "flush_packets",
+    ])]
+    /// Tag each of the files listed in a CSV or JSON table with the tags given
+    /// alongside it, in modify or replace mode
+    from_table: Option<PathBuf>,
+
     /// Input file
-    input_file: PathBuf,
+    #[clap(required_unless_present = "from_table")]
+    input_file: Option<PathBuf>,
 
     /// Output file (cannot be specified in list mode)
     #[clap(conflicts_with = "list")]
     output_file: Option<PathBuf>,
 }
 
+/// The format of files of tags
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+enum TagsFormat {
+    /// One NAME=VALUE tag per line
+    Text,
+
+    /// ffmpeg's metadata format, with chapters in the form of the Vorbis
+    /// comment chapter extension
+    Ffmetadata,
+}
+
 #[derive(Clone, Copy, Debug)]
 enum OperationMode {
     List,
@@ -140,7 +305,7 @@ impl BitOrAssign for ValueMatch {
                 if rhs.len() > lhs.len() {
                     std::mem::swap(&mut rhs, &mut lhs);
                 }
-                lhs.extend(rhs.into_iter());
+                lhs.extend(rhs);
                 ValueMatch::ContainedIn(lhs)
             }
             _ => ValueMatch::All,
@@ -192,8 +357,8 @@ fn validate_comment_filename(path: &Path) -> Result<(), AppError> {
         ext.make_ascii_lowercase();
         if OGG_OPUS_EXTENSIONS.iter().any(|e| ext == *e) {
             eprintln!(
-                "Based on the file extension {:?} looks like it might be a media file. Refusing to use it for tags.",
-                path
+                "Based on the file extension \"{}\" looks like it might be a media file. Refusing to use it for tags.",
+                path.display()
             );
             return Err(AppError::SilentExit);
         }
@@ -229,6 +394,22 @@ where
     Ok(result)
 }
 
+/// Parses the names of the tags to be listed, returning `None` if all tags
+/// should be listed
+fn parse_list_key_args<S, I>(keys: I) -> Result<Option<KeyValueMatch>, Error>
+where
+    S: AsRef<str>,
+    I: IntoIterator<Item = S>,
+{
+    let mut result = None;
+    for key in keys {
+        let key = key.as_ref();
+        validate_comment_field_name(key)?;
+        result.get_or_insert_with(KeyValueMatch::default).add(key.to_string(), ValueMatch::All);
+    }
+    Ok(result)
+}
+
 fn read_comments_from_read<R, M, E>(read: R, escaped: bool, error_map: M) -> Result<DiscreteCommentList, E>
 where
     R: Read,
@@ -262,6 +443,256 @@ fn read_comments_from_stdin(escaped: bool) -> Result<DiscreteCommentList, AppErr
     read_comments_from_read(stdin, escaped, error_map)
 }
 
+/// Reads the tags from a file specified with `-I`, or from standard input if
+/// the file is `-`
+fn read_tags_file(path: &Path, format: TagsFormat, escaped: bool) -> Result<DiscreteCommentList, AppError> {
+    let is_stdin = path == std::ffi::OsStr::new(STANDARD_STREAM_NAME);
+    match format {
+        TagsFormat::Text if is_stdin => read_comments_from_stdin(escaped),
+        TagsFormat::Text => Ok(read_comments_from_file(path, escaped)?),
+        TagsFormat::Ffmetadata => {
+            let text = if is_stdin {
+                let mut text = String::new();
+                io::stdin().read_to_string(&mut text).map_err(AppError::StandardInputReadError)?;
+                text
+            } else {
+                fs::read_to_string(path).map_err(|e| Error::FileReadError(path.to_path_buf(), e))?
+            };
+            let comments = ffmetadata::parse_ffmetadata(&text)
+                .map_err(|e| Error::FileReadError(path.to_path_buf(), io::Error::new(io::ErrorKind::InvalidData, e)))?;
+            Ok(comments)
+        }
+    }
+}
+
+/// Determines the duration in milliseconds of the first logical stream of the
+/// Ogg data starting at `start`
+fn stream_duration_ms<R: Read + Seek>(mut input: R, start: u64) -> Result<u64, Error> {
+    input.seek(SeekFrom::Start(start)).map_err(Error::ReadError)?;
+    let (sample_rate, preskip) = match read_headers(&mut input)? {
+        CodecHeaders::Opus(id, _) => (id.output_sample_rate(), id.preskip_samples()),
+        CodecHeaders::Vorbis(id, _) => (id.output_sample_rate(), id.preskip_samples()),
+    };
+    input.seek(SeekFrom::Start(start)).map_err(Error::ReadError)?;
+    let samples = last_granule_position(&mut input)?.unwrap_or(0).saturating_sub(preskip as u64);
+    Ok((samples * 1000).checked_div(sample_rate as u64).unwrap_or(0))
+}
+
+/// Writes the tags listed in list mode in the specified format
+fn write_tags<W: io::Write>(
+    mut writer: W, comments: &DiscreteCommentList, format: TagsFormat, escape: bool, duration_ms: u64,
+) -> io::Result<()> {
+    match format {
+        TagsFormat::Text => comments.write_as_text(&mut writer, escape),
+        TagsFormat::Ffmetadata => ffmetadata::write_ffmetadata(&mut writer, comments, duration_ms),
+    }
+}
+
+/// Reads the tags from the FLAC or MP3 file with the same name as `path`
+fn read_sibling_comments(path: &Path) -> Result<DiscreteCommentList, AppError> {
+    if let Some(sibling) = import::find_sibling(path) {
+        Ok(import::read_tags(&sibling)?)
+    } else {
+        eprintln!("Unable to find a FLAC or MP3 file to import tags from for {}.", path.display());
+        Err(AppError::SilentExit)
+    }
+}
+
+/// Reads the tags of another Ogg file to be copied. The vendor string and any
+/// gain tags, which depend on the audio of the file, are not copied.
+fn read_copied_comments(path: &Path) -> Result<DiscreteCommentList, Error> {
+    let mut comments = TagEditor::open(path)?.comments().clone();
+    comments.set_vendor(None);
+    comments.remove_invalid_entries();
+    comments.remove_all(TAG_TRACK_GAIN);
+    comments.remove_all(TAG_ALBUM_GAIN);
+    Ok(comments)
+}
+
+/// Extracts tags from the path of the input file. The path is made absolute
+/// first so that patterns can match the names of its parent directories.
+fn infer_comments(pattern: &PathPattern, path: &Path) -> Result<DiscreteCommentList, AppError> {
+    let absolute = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let tags = pattern.match_path(&absolute).ok_or_else(|| AppError::PathPatternMismatch(path.to_path_buf()))?;
+    let mut comments = DiscreteCommentList::with_capacity(tags.len());
+    for (key, value) in tags {
+        comments.push(&key, &value)?;
+    }
+    Ok(comments)
+}
+
+/// The vendor string and comments as lines of text, as written by list mode
+fn comment_lines(comments: &DiscreteCommentList, escape: bool) -> Vec<String> {
+    let mut text = Vec::new();
+    comments.write_as_text(&mut text, escape).expect("Writing to a Vec should not fail");
+    let vendor = comments.vendor().map(|vendor| format!("Vendor: {}", vendor));
+    vendor.into_iter().chain(String::from_utf8_lossy(&text).lines().map(String::from)).collect()
+}
+
+/// Warns about comment entries which could not be parsed
+fn report_invalid_entries(entries: &[InvalidComment], escape: bool) {
+    for entry in entries {
+        let text = entry.to_string_lossy();
+        let text = if escape { escaping::escape_str(&text) } else { Cow::from(text.as_str()) };
+        eprintln!("Warning: invalid entry ({}): {}", entry.reason(), text);
+    }
+}
+
+/// Rewrites the comment header of a stream. If `preserve_pages` is set, the
+/// pages following the codec headers are copied verbatim rather than the
+/// stream being re-paginated.
+fn rewrite_comments<HR: HeaderRewrite<Error = Error>>(
+    rewrite: HR, input: impl Read + Seek, output: impl io::Write, abort_on_unchanged: bool, preserve_pages: bool,
+    interrupt_checker: &CtrlCChecker,
+) -> Result<SubmitResult<DiscreteCommentList>, Error> {
+    let summarize = CommentHeaderSummary::default();
+    if preserve_pages {
+        rewrite_stream_preserving_pages_with_interrupt(
+            rewrite,
+            summarize,
+            input,
+            output,
+            abort_on_unchanged,
+            interrupt_checker,
+        )
+    } else {
+        rewrite_stream_with_interrupt(rewrite, summarize, input, output, abort_on_unchanged, interrupt_checker)
+    }
+}
+
+/// Settings for rewriting each file listed in a tag table
+#[derive(Clone, Copy, Debug)]
+#[allow(clippy::struct_excessive_bools)]
+struct TableOptions<'a> {
+    operation_mode: OperationMode,
+    drop_invalid: bool,
+    normalize_keys: Option<TagConvention>,
+    escape: bool,
+    dry_run: bool,
+    fsync: bool,
+    preserve_pages: bool,
+    force_write: bool,
+    temp_dir: Option<&'a Path>,
+}
+
+/// Rewrites the comments of the file in a single table row, returning the
+/// comments before and after if they were changed
+fn apply_table_row(
+    row: &TagTableRow, options: TableOptions, interrupt_checker: &CtrlCChecker,
+) -> Result<Option<(DiscreteCommentList, DiscreteCommentList)>, Error> {
+    let mut append = DiscreteCommentList::default();
+    let mut replaced = KeyValueMatch::default();
+    for (key, values) in &row.tags {
+        for value in values {
+            append.push(key, value)?;
+        }
+        replaced.add(key.clone(), ValueMatch::All);
+    }
+    let action = match options.operation_mode {
+        OperationMode::List => CommentRewriterAction::NoChange,
+        OperationMode::Modify => {
+            let retain: RetainPredicate = Box::new(move |k, v| !replaced.matches(k, v));
+            CommentRewriterAction::Modify { retain, append }
+        }
+        OperationMode::Replace => CommentRewriterAction::Replace(append),
+    };
+    let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig {
+        action,
+        drop_invalid: options.drop_invalid,
+        normalize_keys: options.normalize_keys,
+    });
+
+    let path = &row.path;
+    let input_file = File::open(path).map_err(|e| Error::FileOpenError(path.clone(), e))?;
+    let input_length = input_file.metadata().map_err(|e| Error::FileReadError(path.clone(), e))?.len();
+    let mut input_file = BufReader::new(input_file);
+    let mut output_file =
+        if options.dry_run { OutputFile::new_sink() } else { OutputFile::new_target_in(path, options.temp_dir)? };
+    output_file.check_free_space(input_length)?;
+    output_file.set_durable(options.fsync);
+    let result = import::take_id3v2_prefix(&mut input_file).and_then(|id3v2_tag| {
+        let mut output_file = BufWriter::new(&mut output_file);
+        output_file.write_all(&id3v2_tag.unwrap_or_default()).map_err(Error::WriteError)?;
+        let abort_on_unchanged = !options.force_write;
+        rewrite_comments(
+            rewrite,
+            &mut input_file,
+            &mut output_file,
+            abort_on_unchanged,
+            options.preserve_pages,
+            interrupt_checker,
+        )
+    });
+    drop(input_file); // Important for Windows so we can overwrite
+    match result {
+        Ok(SubmitResult::HeadersChanged { from, to }) => {
+            output_file.commit()?;
+            Ok(Some((from, to)))
+        }
+        Ok(SubmitResult::HeadersUnchanged(_)) => {
+            if options.force_write {
+                output_file.commit()?;
+            } else {
+                output_file.abort()?;
+            }
+            Ok(None)
+        }
+        Ok(SubmitResult::Good) => {
+            output_file.abort()?;
+            Err(Error::MalformedCommentHeader.in_file(path))
+        }
+        Err(e) => {
+            output_file.abort()?;
+            Err(e.in_file(path))
+        }
+    }
+}
+
+/// Applies the tags in a table to each of the files it lists, in parallel
+fn apply_tag_table(table: &Path, options: TableOptions, interrupt_checker: &CtrlCChecker) -> Result<(), AppError> {
+    let rows = tag_table::read_tag_table(table)?;
+    let num_changed = AtomicUsize::new(0);
+    let num_unchanged = AtomicUsize::new(0);
+    let failures = Mutex::new(Vec::new());
+    rows.par_iter().try_for_each(|row| {
+        match apply_table_row(row, options, interrupt_checker) {
+            Ok(Some((from, to))) => {
+                num_changed.fetch_add(1, Ordering::Relaxed);
+                if options.dry_run {
+                    // Each diff is written in one piece so that those of
+                    // different files are not interleaved
+                    let mut diff = format!("Changes which would be made to {}:\n", row.path.display()).into_bytes();
+                    text_diff::write_diff(
+                        &mut diff,
+                        &comment_lines(&from, options.escape),
+                        &comment_lines(&to, options.escape),
+                    )
+                    .map_err(Error::ConsoleIoError)?;
+                    io::stdout().lock().write_all(&diff).map_err(Error::ConsoleIoError)?;
+                }
+            }
+            Ok(None) => {
+                num_unchanged.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(Error::Interrupted) => return Err(Error::Interrupted),
+            Err(e) => {
+                eprintln!("Failed to tag {}: {}", row.path.display(), e);
+                failures.lock().push(e);
+            }
+        }
+        Ok(())
+    })?;
+    let failures = failures.into_inner();
+    println!("Files changed: {}", num_changed.into_inner());
+    println!("Files already tagged: {}", num_unchanged.into_inner());
+    match failures.first() {
+        None => Ok(()),
+        Some(e) if failures.len() == rows.len() => Err(AppError::AllRowsFailed(exit_code::for_error_kind(e.kind()))),
+        Some(_) => Err(AppError::SomeRowsFailed(failures.len(), rows.len())),
+    }
+}
+
+#[allow(clippy::too_many_lines)]
 fn main_impl() -> Result<(), AppError> {
     let interrupt_checker = CtrlCChecker::new()?;
     let cli = Cli::parse_from(wild::args_os());
@@ -275,22 +706,70 @@ fn main_impl() -> Result<(), AppError> {
         }
     };
 
+    if let Some(ref table) = cli.from_table {
+        if let OperationMode::List = operation_mode {
+            eprintln!("Either --modify or --replace must be specified with --from-table");
+            return Err(AppError::SilentExit);
+        }
+        let options = TableOptions {
+            operation_mode,
+            drop_invalid: cli.drop_invalid,
+            normalize_keys: cli.normalize_keys,
+            escape: cli.escapes,
+            dry_run: cli.dry_run,
+            fsync: cli.fsync,
+            preserve_pages: cli.preserve_pages,
+            force_write: cli.force_write,
+            temp_dir: cli.temp_dir.as_deref(),
+        };
+        return apply_tag_table(table, options, &interrupt_checker);
+    }
+    let input_path = cli.input_file.expect("An input file is required without --from-table");
+    if cli.normalize_keys.is_some() && matches!(operation_mode, OperationMode::List) {
+        eprintln!("Either --modify or --replace must be specified with --normalize-keys");
+        return Err(AppError::SilentExit);
+    }
+
     for comment_file in [&cli.tags_in, &cli.tags_out].iter().copied().flatten() {
         validate_comment_filename(comment_file)?;
     }
 
     let dry_run = cli.dry_run;
+    let fsync = cli.fsync;
+    let temp_dir = cli.temp_dir.as_deref();
+    let new_output_file = |path: &Path| {
+        if dry_run {
+            Ok(OutputFile::new_sink())
+        } else {
+            OutputFile::new_target_in(path, temp_dir)
+        }
+    };
     let escape = cli.escapes;
-    let delete_tags = parse_delete_comment_args(cli.delete, escape)?;
+    let mut delete_tags = parse_delete_comment_args(cli.delete, escape)?;
+    let list_keys = parse_list_key_args(cli.keys)?;
     let append = {
-        let mut append = parse_new_comment_args(cli.tags, escape)?;
+        let mut append =
+            if cli.import_sibling { read_sibling_comments(&input_path)? } else { DiscreteCommentList::default() };
+        if let Some(ref source) = cli.copy_from {
+            let mut copied = read_copied_comments(source)?;
+            for (key, _) in copied.iter() {
+                delete_tags.add(key.to_string(), ValueMatch::All);
+            }
+            append.append(&mut copied);
+        }
+        if let Some(ref pattern) = cli.infer_from_path {
+            let mut inferred = infer_comments(pattern, &input_path)?;
+            for (key, _) in inferred.iter() {
+                delete_tags.add(key.to_string(), ValueMatch::All);
+            }
+            append.append(&mut inferred);
+        }
+        append.append(&mut parse_new_comment_args(cli.tags, escape)?);
         if let Some(ref file) = cli.tags_in {
-            let mut tags = if file == std::ffi::OsStr::new(STANDARD_STREAM_NAME) {
-                read_comments_from_stdin(escape)?
-            } else {
-                read_comments_from_file(file, escape)?
-            };
-            append.append(&mut tags);
+            append.append(&mut read_tags_file(file, cli.tags_format, escape)?);
+        }
+        if let Some(vendor) = cli.set_vendor {
+            append.set_vendor(Some(vendor));
         }
         append
     };
@@ -298,37 +777,69 @@ fn main_impl() -> Result<(), AppError> {
     let action = match operation_mode {
         OperationMode::List => CommentRewriterAction::NoChange,
         OperationMode::Modify => {
-            let retain: Box<dyn Fn(&str, &str) -> bool> = Box::new(|k, v| !delete_tags.matches(k, v));
+            let retain: RetainPredicate = Box::new(|k, v| !delete_tags.matches(k, v));
             CommentRewriterAction::Modify { retain, append }
         }
         OperationMode::Replace => CommentRewriterAction::Replace(append),
     };
 
-    let rewriter_config = CommentRewriterConfig { action };
-    let input_path = cli.input_file;
+    let rewriter_config =
+        CommentRewriterConfig { action, drop_invalid: cli.drop_invalid, normalize_keys: cli.normalize_keys };
     let output_path = cli.output_file.unwrap_or_else(|| input_path.clone());
     let input_file = File::open(&input_path).map_err(|e| Error::FileOpenError(input_path.clone(), e))?;
-    let mut input_file = BufReader::new(input_file);
+    let input_length = input_file.metadata().map_err(|e| Error::FileReadError(input_path.clone(), e))?.len();
+    let mut input_file: Box<dyn ReadSeek> = Box::new(BufReader::new(input_file));
+    // Any ID3v2 tag is written to the output separately so it is unaffected
+    // by salvaging
+    let id3v2_tag = import::take_id3v2_prefix(&mut input_file)?;
+    let strip_id3v2 = cli.strip_id3v2 && id3v2_tag.is_some();
+    let id3v2_tag = id3v2_tag.filter(|_| !cli.strip_id3v2).unwrap_or_default();
+    let mut salvaged = false;
+    if cli.salvage {
+        let mut data = Vec::new();
+        let summary = salvage_pages(&mut input_file, &mut data)?;
+        if let Some(ref e) = summary.error {
+            eprintln!("Warning: {} is damaged ({}). Discarding all data from that point.", input_path.display(), e);
+        } else if !summary.is_intact() {
+            eprintln!("Warning: {} is truncated. Ending the stream at the last complete packet.", input_path.display());
+        }
+        salvaged = !summary.is_intact();
+        input_file = Box::new(Cursor::new(data));
+    }
+    let ogg_start = input_file.stream_position().map_err(Error::ReadError)?;
 
     let mut output_file = match operation_mode {
         OperationMode::List => OutputFile::new_sink(),
-        OperationMode::Modify | OperationMode::Replace => OutputFile::new_target_or_discard(&output_path, dry_run)?,
+        OperationMode::Modify | OperationMode::Replace => new_output_file(&output_path)?,
     };
+    output_file.check_free_space(input_length)?;
+    output_file.set_durable(fsync);
 
+    let pagination = Pagination {
+        target_page_size: cli.page_size,
+        max_packets_per_page: cli.max_packets_per_page.map(usize::from),
+        flush_per_packet: cli.flush_packets,
+    };
+    // The whole stream is needed to re-paginate it, or to write it
+    // regardless, even if the headers are unchanged
+    let abort_on_unchanged = pagination.preserves_pages() && !cli.force_write;
     let rewrite_result = {
         let mut output_file = BufWriter::new(&mut output_file);
-        let rewrite = CommentHeaderRewrite::new(rewriter_config);
-        let summarize = CommentHeaderSummary::default();
-        let abort_on_unchanged = true;
-        rewrite_stream_with_interrupt(
+        output_file.write_all(&id3v2_tag).map_err(Error::WriteError)?;
+        let rewrite = Paginated::new(CommentHeaderRewrite::new(rewriter_config), pagination);
+        rewrite_comments(
             rewrite,
-            summarize,
             &mut input_file,
             &mut output_file,
             abort_on_unchanged,
+            cli.preserve_pages,
             &interrupt_checker,
         )
     };
+    let unchanged = matches!(rewrite_result, Ok(SubmitResult::HeadersUnchanged(_)));
+    if dry_run && unchanged && !matches!(operation_mode, OperationMode::List) {
+        println!("No changes to the comments would be made.");
+    }
     let mut commit = false;
     match rewrite_result {
         Err(e) => {
@@ -339,43 +850,68 @@ fn main_impl() -> Result<(), AppError> {
             // We finished processing the file but never got the headers
             eprintln!("File {} appeared to be oddly truncated. Doing nothing.", input_path.display());
         }
-        Ok(SubmitResult::HeadersUnchanged(comments)) => match operation_mode {
+        Ok(SubmitResult::HeadersUnchanged(mut comments)) => match operation_mode {
+            OperationMode::List if cli.show_vendor => {
+                let vendor = comments.vendor().unwrap_or_default();
+                let vendor = if escape { escaping::escape_str(vendor) } else { Cow::from(vendor) };
+                println!("{}", vendor);
+            }
             OperationMode::List => {
+                report_invalid_entries(comments.invalid_entries(), escape);
+                if let Some(list_keys) = &list_keys {
+                    comments.retain(|k, v| list_keys.matches(k, v));
+                }
+                let duration_ms = if cli.tags_format == TagsFormat::Ffmetadata && ffmetadata::has_chapters(&comments) {
+                    stream_duration_ms(&mut input_file, ogg_start)?
+                } else {
+                    0
+                };
                 if let Some(ref path) = cli.tags_out.filter(|p| p != std::ffi::OsStr::new(STANDARD_STREAM_NAME)) {
-                    let mut comment_file = OutputFile::new_target_or_discard(path, dry_run)?;
+                    let mut comment_file = new_output_file(path)?;
+                    comment_file.set_durable(fsync);
                     {
                         let mut comment_file = BufWriter::new(&mut comment_file);
-                        comments
-                            .write_as_text(&mut comment_file, escape)
+                        write_tags(&mut comment_file, &comments, cli.tags_format, escape, duration_ms)
                             .map_err(|e| Error::FileWriteError(path.into(), e))?;
                         comment_file.flush().map_err(|e| Error::FileWriteError(path.into(), e))?;
                     }
                     comment_file.commit()?;
                 } else {
-                    comments.write_as_text(io::stdout(), escape).map_err(Error::ConsoleIoError)?;
+                    write_tags(io::stdout(), &comments, cli.tags_format, escape, duration_ms)
+                        .map_err(Error::ConsoleIoError)?;
                 }
             }
+            OperationMode::Modify | OperationMode::Replace if !abort_on_unchanged => commit = true,
             OperationMode::Modify | OperationMode::Replace => {
                 // If these match we are definitely in-place. If they don't we're probably not,
                 // but can't be 100% certain. Hence we still do the copy via a
                 // temporary file rather than just invoking a filesystem copy.
-                if input_path != output_path {
-                    // Drop the existing output file and create a new one
-                    let mut old_output_file = OutputFile::new_target(&output_path)?;
+                if input_path != output_path || salvaged || strip_id3v2 {
+                    // Drop the existing output file and create a new one. This is
+                    // also needed when a salvaged stream is written in-place or
+                    // an ID3v2 tag is being removed.
+                    let mut old_output_file = new_output_file(&output_path)?;
+                    old_output_file.set_durable(fsync);
                     std::mem::swap(&mut output_file, &mut old_output_file);
                     old_output_file.abort()?;
                     // Copy the input file to the output file
-                    input_file.rewind().map_err(Error::ReadError)?;
+                    output_file.write_all(&id3v2_tag).map_err(|e| Error::FileWriteError(output_path.clone(), e))?;
+                    input_file.seek(SeekFrom::Start(ogg_start)).map_err(Error::ReadError)?;
                     std::io::copy(&mut input_file, &mut output_file)
                         .map_err(|e| Error::FileCopy(input_path, output_path, e))?;
                     commit = true;
                 }
             }
         },
-        Ok(SubmitResult::HeadersChanged { .. }) => {
+        Ok(SubmitResult::HeadersChanged { from, to }) => {
+            if dry_run {
+                println!("Changes which would be made:");
+                text_diff::write_diff(io::stdout(), &comment_lines(&from, escape), &comment_lines(&to, escape))
+                    .map_err(Error::ConsoleIoError)?;
+            }
             commit = true;
         }
-    };
+    }
     drop(input_file); // Important for Windows so we can overwrite
     if commit {
         output_file.commit()?;
@@ -422,6 +958,30 @@ mod tests {
 
         let result = Cli::try_parse_from(["zoogcomment", "--list", "-t", "TAG=VALUE", "input.ogg"]);
         assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--list", "--import-sibling", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--list", "-k", "ARTIST", "--key", "TITLE", "input.ogg"]);
+        assert!(result.is_ok());
+
+        let result = Cli::try_parse_from(["zoogcomment", "--modify", "-k", "ARTIST", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--list", "--copy-from", "source.ogg", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--list", "--infer-from-path", "%title%", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--show-vendor", "input.ogg"]);
+        assert!(result.is_ok());
+
+        let result = Cli::try_parse_from(["zoogcomment", "--show-vendor", "-k", "ARTIST", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--modify", "--show-vendor", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
     }
 
     #[test]
@@ -467,4 +1027,16 @@ mod tests {
         let result = Cli::try_parse_from(["zoogcomment", "--replace", "-d", "TAG=VALUE", "input.ogg"]);
         assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
     }
+
+    #[test]
+    fn cli_table_mode() {
+        let result = Cli::try_parse_from(["zoogcomment", "--modify", "--from-table", "tags.csv"]);
+        assert!(result.is_ok());
+
+        let result = Cli::try_parse_from(["zoogcomment", "--modify", "--from-table", "tags.csv", "input.ogg"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        let result = Cli::try_parse_from(["zoogcomment", "--modify", "--from-table", "tags.csv", "-t", "TAG=VALUE"]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
 }
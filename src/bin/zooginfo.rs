@@ -0,0 +1,281 @@
+#![warn(clippy::pedantic)]
+#![allow(clippy::uninlined_format_args)]
+
+#[path = "../exit_code.rs"]
+mod exit_code;
+
+use std::fs::File;
+use std::io::{self, BufReader, Seek, SeekFrom, Write as _};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
+use thiserror::Error;
+use zoog::header::{CommentHeader, IdHeader};
+use zoog::header_rewriter::{read_headers, CodecHeaders};
+use zoog::import::take_id3v2_prefix;
+use zoog::ogg_page::{copy_pages_with_policy, find_checksum_mismatches, last_granule_position, ChecksumPolicy};
+use zoog::output_file::OutputFile;
+use zoog::stream_stats::{read_stream_stats, StreamStats};
+use zoog::validate::validate_stream;
+use zoog::Error;
+
+#[derive(Debug, Error)]
+enum AppError {
+    #[error("{0}")]
+    LibraryError(#[from] Error),
+
+    #[error("Failed to write to standard output: `{0}`")]
+    StandardOutputWriteError(io::Error),
+
+    #[error("{0} of {1} files could not be read")]
+    UnreadableFiles(usize, usize, i32),
+
+    #[error("{0} of {1} files could not be read")]
+    SomeFilesUnreadable(usize, usize),
+
+    #[error("{0} of {1} files failed checks")]
+    ValidationFailed(usize, usize),
+}
+
+impl AppError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            AppError::LibraryError(e) => exit_code::for_error_kind(e.kind()),
+            AppError::StandardOutputWriteError(_) => exit_code::IO,
+            AppError::UnreadableFiles(_, _, code) => *code,
+            AppError::SomeFilesUnreadable(..) => exit_code::PARTIAL_FAILURE,
+            AppError::ValidationFailed(..) => exit_code::MALFORMED,
+        }
+    }
+}
+
+fn main() {
+    if let Err(e) = main_impl() {
+        eprintln!("{}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum RepairMode {
+    /// Replace incorrect checksums with the correct values
+    Recompute,
+
+    /// Remove pages with incorrect checksums
+    Skip,
+}
+
+#[derive(Debug, Parser)]
+#[allow(clippy::struct_excessive_bools)]
+#[clap(author, version, about = "Display the headers and duration of Ogg Opus and Ogg Vorbis files.")]
+struct Cli {
+    #[clap(long, action)]
+    /// Check Ogg Opus files against the requirements of RFC 7845 and report
+    /// any violations. Exits with a failure status if any are found.
+    strict: bool,
+
+    #[clap(long, action)]
+    /// Display statistics about the pages and packets of each file
+    stats: bool,
+
+    #[clap(long, action)]
+    /// Verify the checksum of every Ogg page and report any which are
+    /// incorrect. Exits with a failure status if any are found.
+    check_crc: bool,
+
+    #[clap(value_enum, long, value_name = "MODE")]
+    /// Rewrite files containing pages with incorrect checksums, either
+    /// correcting the checksums or removing the pages
+    repair_crc: Option<RepairMode>,
+
+    #[clap(long, action)]
+    /// Exit with a failure status if any file could not be read, rather than
+    /// only if none could
+    strict_exit: bool,
+
+    #[clap(required = true)]
+    /// The files to read
+    input_files: Vec<PathBuf>,
+}
+
+/// Converts a number of samples at `sample_rate` to a `Duration`
+fn samples_to_duration(samples: u64, sample_rate: u64) -> Duration {
+    Duration::from_secs(samples / sample_rate)
+        + Duration::from_nanos((samples % sample_rate) * 1_000_000_000 / sample_rate)
+}
+
+fn write_header_info<I: IdHeader, C: CommentHeader>(
+    out: &mut impl io::Write, id_header: &I, comment_header: &C, granule_position: Option<u64>,
+) -> io::Result<()> {
+    writeln!(out, "Channels: {}", id_header.num_output_channels())?;
+    if let Some(rate) = id_header.input_sample_rate() {
+        writeln!(out, "Input sample rate: {} Hz", rate)?;
+    }
+    writeln!(out, "Output sample rate: {} Hz", id_header.output_sample_rate())?;
+    if let Some(granule_position) = granule_position {
+        let samples = granule_position.saturating_sub(id_header.preskip_samples() as u64);
+        let duration = samples_to_duration(samples, id_header.output_sample_rate() as u64);
+        writeln!(out, "Duration: {:.3} s", duration.as_secs_f64())?;
+    }
+    writeln!(out, "Vendor: {}", comment_header.get_vendor())?;
+    writeln!(out, "Comments: {}", comment_header.len())
+}
+
+fn write_info(out: &mut impl io::Write, headers: &CodecHeaders, granule_position: Option<u64>) -> io::Result<()> {
+    writeln!(out, "Codec: {}", headers.codec())?;
+    match headers {
+        CodecHeaders::Opus(id_header, comment_header) => {
+            writeln!(out, "Version: {}", id_header.version())?;
+            writeln!(out, "Pre-skip: {} samples", id_header.pre_skip())?;
+            writeln!(out, "Output gain: {}", id_header.get_output_gain())?;
+            write_header_info(out, id_header, comment_header, granule_position)
+        }
+        CodecHeaders::Vorbis(id_header, comment_header) => {
+            write_header_info(out, id_header, comment_header, granule_position)
+        }
+    }
+}
+
+fn write_stats(out: &mut impl io::Write, stats: &StreamStats) -> io::Result<()> {
+    match (stats.min_page_size, stats.mean_page_size()) {
+        (Some(min), Some(mean)) => {
+            writeln!(out, "Pages: {} ({} to {} bytes, mean {:.1} bytes)", stats.pages, min, stats.max_page_size, mean)?;
+        }
+        _ => writeln!(out, "Pages: {}", stats.pages)?,
+    }
+    writeln!(out, "Packets: {} ({} continued across pages)", stats.packets, stats.continued_packets)?;
+    if let Some(samples) = stats.samples() {
+        writeln!(out, "Packet samples: {}", samples)?;
+    }
+    Ok(())
+}
+
+/// Rewrites the file at `path` if it contains pages with incorrect checksums,
+/// returning the number of pages repaired or removed
+fn repair_checksums(path: &Path, mode: RepairMode) -> Result<usize, Error> {
+    let policy = match mode {
+        RepairMode::Recompute => ChecksumPolicy::Repair,
+        RepairMode::Skip => ChecksumPolicy::Skip,
+    };
+    let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    let mut reader = BufReader::new(file);
+    let mut output = OutputFile::new_target(path)?;
+    let result = take_id3v2_prefix(&mut reader).and_then(|tag| {
+        output.write_all(tag.as_deref().unwrap_or_default()).map_err(Error::WriteError)?;
+        copy_pages_with_policy(reader, &mut output, policy)
+    });
+    match result {
+        Ok(0) => {
+            output.abort()?;
+            Ok(0)
+        }
+        Ok(affected) => {
+            output.commit()?;
+            Ok(affected)
+        }
+        Err(e) => {
+            output.abort()?;
+            Err(e)
+        }
+    }
+}
+
+/// Prints information about a file, returning whether it passed the requested
+/// checks
+fn process_file(path: &Path, cli: &Cli) -> Result<bool, AppError> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    writeln!(stdout, "{}:", path.display()).map_err(AppError::StandardOutputWriteError)?;
+    if let Some(mode) = cli.repair_crc {
+        let affected = repair_checksums(path, mode)?;
+        let action = match mode {
+            RepairMode::Recompute => "Repaired checksums of",
+            RepairMode::Skip => "Removed",
+        };
+        writeln!(stdout, "{} {} pages", action, affected).map_err(AppError::StandardOutputWriteError)?;
+    }
+
+    let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    let mut reader = BufReader::new(file);
+    if let Some(tag) = take_id3v2_prefix(&mut reader)? {
+        writeln!(stdout, "ID3v2 tag: {} bytes", tag.len()).map_err(AppError::StandardOutputWriteError)?;
+    }
+    let start = reader.stream_position().map_err(|e| Error::FileReadError(path.to_path_buf(), e))?;
+    let rewind = |reader: &mut BufReader<File>| {
+        reader.seek(SeekFrom::Start(start)).map_err(|e| Error::FileReadError(path.to_path_buf(), e))
+    };
+    if cli.check_crc {
+        let mismatches = find_checksum_mismatches(&mut reader)?;
+        for mismatch in &mismatches {
+            writeln!(
+                stdout,
+                "Checksum mismatch: stream {:#010x} page {} has checksum {:#010x}, expected {:#010x}",
+                mismatch.serial, mismatch.sequence, mismatch.stored, mismatch.computed
+            )
+            .map_err(AppError::StandardOutputWriteError)?;
+        }
+        if !mismatches.is_empty() {
+            return Ok(false);
+        }
+        rewind(&mut reader)?;
+    }
+
+    let headers = read_headers(&mut reader)?;
+    rewind(&mut reader)?;
+    let granule_position = last_granule_position(&mut reader)?;
+    write_info(&mut stdout, &headers, granule_position).map_err(AppError::StandardOutputWriteError)?;
+    if cli.stats {
+        rewind(&mut reader)?;
+        let stats = read_stream_stats(&mut reader)?;
+        write_stats(&mut stdout, &stats).map_err(AppError::StandardOutputWriteError)?;
+    }
+    if !cli.strict || !matches!(headers, CodecHeaders::Opus(..)) {
+        return Ok(true);
+    }
+
+    rewind(&mut reader)?;
+    let violations = validate_stream(&mut reader)?;
+    if violations.is_empty() {
+        writeln!(stdout, "Conforms to RFC 7845").map_err(AppError::StandardOutputWriteError)?;
+    }
+    for violation in &violations {
+        writeln!(stdout, "Violation: {}", violation).map_err(AppError::StandardOutputWriteError)?;
+    }
+    Ok(violations.is_empty())
+}
+
+fn main_impl() -> Result<(), AppError> {
+    let cli = Cli::parse_from(wild::args_os());
+    let (mut unreadable, mut nonconforming) = (0, 0);
+    // If no file could be read, the exit status reflects the first
+    let mut unreadable_code = None;
+    for (idx, path) in cli.input_files.iter().enumerate() {
+        if idx > 0 {
+            println!();
+        }
+        match process_file(path, &cli) {
+            Ok(true) => {}
+            Ok(false) => nonconforming += 1,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                unreadable_code.get_or_insert(e.exit_code());
+                unreadable += 1;
+            }
+        }
+    }
+    let total = cli.input_files.len();
+    if unreadable == total {
+        let code = unreadable_code.unwrap_or(exit_code::FAILURE);
+        Err(AppError::UnreadableFiles(unreadable, total, code))
+    } else if unreadable > 0 && cli.strict_exit {
+        Err(AppError::SomeFilesUnreadable(unreadable, total))
+    } else if nonconforming > 0 {
+        Err(AppError::ValidationFailed(nonconforming, total))
+    } else if unreadable > 0 {
+        eprintln!("Warning: {} of {} files could not be read.", unreadable, total);
+        Ok(())
+    } else {
+        Ok(())
+    }
+}
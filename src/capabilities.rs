@@ -0,0 +1,122 @@
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::opus::{TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
+use crate::volume_rewrite::{VolumeTarget, VOLUME_PROFILES};
+use crate::Codec;
+
+/// Description of the operations supported for a particular codec
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[non_exhaustive]
+pub struct CodecCapabilities {
+    /// The name of the codec
+    pub codec: String,
+
+    /// Whether comments can be listed and rewritten
+    pub comment_editing: bool,
+
+    /// Whether volume can be analyzed and output gain and gain tags rewritten
+    pub volume_rewrite: bool,
+
+    /// Names of the tags used to record track and album gain
+    pub gain_tags: Vec<&'static str>,
+}
+
+/// A named loudness target that gains can be normalized to
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[non_exhaustive]
+pub struct LoudnessPreset {
+    /// The name of the preset, as accepted by `opusgain --preset`
+    pub name: &'static str,
+
+    /// The target loudness in LUFS
+    pub lufs: f64,
+}
+
+/// Description of what this build of zoog supports, intended for tools which
+/// wrap zoog and need to adapt to the installed version
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// The version of the crate
+    pub version: &'static str,
+
+    /// Supported codecs
+    pub codecs: Vec<CodecCapabilities>,
+
+    /// Named loudness targets
+    pub loudness_presets: Vec<LoudnessPreset>,
+
+    /// The format of values stored in gain tags
+    pub gain_tag_format: &'static str,
+
+    /// The library used to decode audio for volume analysis, if analysis is
+    /// supported
+    pub decoder_backend: Option<&'static str>,
+
+    /// Optional Cargo features which were enabled at compile time
+    pub features: Vec<&'static str>,
+
+    /// Formats which tags can be imported from
+    pub tag_import_formats: Vec<&'static str>,
+}
+
+/// Returns a description of the capabilities of this build
+pub fn capabilities() -> Capabilities {
+    let mut features = Vec::new();
+    if cfg!(feature = "analysis") {
+        features.push("analysis");
+    }
+    if cfg!(feature = "async") {
+        features.push("async");
+    }
+    if cfg!(feature = "bin") {
+        features.push("bin");
+    }
+    if cfg!(feature = "static-libopus") {
+        features.push("static-libopus");
+    }
+    if cfg!(feature = "testing") {
+        features.push("testing");
+    }
+    if cfg!(feature = "arbitrary") {
+        features.push("arbitrary");
+    }
+    if cfg!(feature = "python") {
+        features.push("python");
+    }
+    if cfg!(feature = "serde") {
+        features.push("serde");
+    }
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        codecs: vec![
+            CodecCapabilities {
+                codec: Codec::Opus.to_string(),
+                comment_editing: true,
+                volume_rewrite: cfg!(feature = "analysis"),
+                gain_tags: vec![TAG_TRACK_GAIN, TAG_ALBUM_GAIN],
+            },
+            CodecCapabilities {
+                codec: Codec::Vorbis.to_string(),
+                comment_editing: true,
+                volume_rewrite: false,
+                gain_tags: Vec::new(),
+            },
+        ],
+        loudness_presets: VOLUME_PROFILES
+            .iter()
+            .filter_map(|profile| match profile.target {
+                VolumeTarget::LUFS(lufs) => Some(LoudnessPreset { name: profile.name, lufs: lufs.as_f64() }),
+                _ => None,
+            })
+            .collect(),
+        gain_tag_format: "Q7.8 fixed-point decibels relative to the output gain",
+        decoder_backend: if cfg!(feature = "analysis") { Some("libopus") } else { None },
+        features,
+        tag_import_formats: vec!["flac", "mp3"],
+    }
+}
@@ -1,7 +1,14 @@
 use std::fmt::{self, Display, Formatter};
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::header::{self, CommentHeader as _, IdHeader as _};
+use crate::header_rewriter::CodecHeaders;
+use crate::import::take_id3v2_prefix;
+use crate::ogg_page::Page;
+use crate::{opus, vorbis, Error, ErrorKind};
 
 /// Known audio codecs
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Codec {
     /// Opus
     Opus,
@@ -19,3 +26,182 @@ impl Display for Codec {
         write!(formatter, "{}", name)
     }
 }
+
+impl Codec {
+    /// All supported codecs
+    pub const ALL: [Codec; 2] = [Codec::Opus, Codec::Vorbis];
+
+    /// The signature at the start of the identification header
+    pub fn magic(self) -> &'static [u8] {
+        match self {
+            Codec::Opus => b"OpusHead",
+            Codec::Vorbis => b"\x01vorbis",
+        }
+    }
+
+    /// Identifies the codec of a logical stream from its first packet
+    pub fn identify(packet: &[u8]) -> Option<Codec> {
+        Codec::ALL.into_iter().find(|codec| packet.starts_with(codec.magic()))
+    }
+}
+
+/// The header types of a supported codec, used to parse the headers of a
+/// logical stream once its codec has been identified
+pub trait CodecFormat {
+    /// The codec described
+    const CODEC: Codec;
+
+    /// The identification header of the codec
+    type IdHeader: header::IdHeader + Clone + PartialEq;
+
+    /// The comment header of the codec
+    type CommentHeader: header::CommentHeader + Clone + PartialEq;
+
+    /// Combines headers of this codec into a `CodecHeaders`
+    fn into_codec_headers(id_header: Self::IdHeader, comment_header: Self::CommentHeader) -> CodecHeaders;
+
+    /// Parses the identification and comment header packets, which are the
+    /// first two packets of a logical stream
+    fn parse_headers(identification: &[u8], comment: &[u8]) -> Result<CodecHeaders, Error> {
+        let id_header = Self::IdHeader::try_parse(identification)?.ok_or(Error::UnknownCodec)?;
+        let comment_header = Self::CommentHeader::try_parse(comment)?;
+        Ok(Self::into_codec_headers(id_header, comment_header))
+    }
+}
+
+/// The headers of Ogg Opus streams
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpusFormat;
+
+impl CodecFormat for OpusFormat {
+    type CommentHeader = opus::CommentHeader;
+    type IdHeader = opus::IdHeader;
+
+    const CODEC: Codec = Codec::Opus;
+
+    fn into_codec_headers(id_header: opus::IdHeader, comment_header: opus::CommentHeader) -> CodecHeaders {
+        CodecHeaders::Opus(id_header, comment_header)
+    }
+}
+
+/// The headers of Ogg Vorbis streams
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VorbisFormat;
+
+impl CodecFormat for VorbisFormat {
+    type CommentHeader = vorbis::CommentHeader;
+    type IdHeader = vorbis::IdHeader;
+
+    const CODEC: Codec = Codec::Vorbis;
+
+    fn into_codec_headers(id_header: vorbis::IdHeader, comment_header: vorbis::CommentHeader) -> CodecHeaders {
+        CodecHeaders::Vorbis(id_header, comment_header)
+    }
+}
+
+/// The format of a file as identified from its content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FileFormat {
+    /// An Ogg stream using a known codec
+    Ogg(Codec),
+
+    /// An Ogg stream containing FLAC
+    OggFlac,
+
+    /// An Ogg stream using a codec which was not recognized
+    OggUnknown,
+
+    /// Content which is not an Ogg stream
+    Unknown,
+}
+
+impl FileFormat {
+    /// The codec of the stream, if it is one of the known codecs
+    pub fn codec(&self) -> Option<Codec> {
+        match self {
+            FileFormat::Ogg(codec) => Some(*codec),
+            _ => None,
+        }
+    }
+}
+
+impl Display for FileFormat {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            FileFormat::Ogg(codec) => write!(formatter, "Ogg {}", codec),
+            FileFormat::OggFlac => write!(formatter, "Ogg FLAC"),
+            FileFormat::OggUnknown => write!(formatter, "Ogg with an unknown codec"),
+            FileFormat::Unknown => write!(formatter, "unrecognized format"),
+        }
+    }
+}
+
+/// Identifies the format of the content at the current position of `reader`
+/// from the codec magic in the first Ogg page, skipping any ID3v2 tag. The
+/// reader is returned to its original position afterwards. Content which is
+/// truncated or malformed is reported as `FileFormat::Unknown` rather than
+/// an error.
+pub fn detect_format<R: Read + Seek>(reader: &mut R) -> Result<FileFormat, Error> {
+    let start = reader.stream_position().map_err(Error::ReadError)?;
+    let result = detect_format_at_position(reader);
+    reader.seek(SeekFrom::Start(start)).map_err(Error::ReadError)?;
+    match result {
+        Err(e) if e.kind() == ErrorKind::Malformed => Ok(FileFormat::Unknown),
+        result => result,
+    }
+}
+
+fn detect_format_at_position<R: Read + Seek>(reader: &mut R) -> Result<FileFormat, Error> {
+    take_id3v2_prefix(reader)?;
+    let Some(page) = Page::read_unverified(reader)? else { return Ok(FileFormat::Unknown) };
+    let data = page.data();
+    let format = if let Some(codec) = Codec::identify(data) {
+        FileFormat::Ogg(codec)
+    } else if data.starts_with(b"\x7fFLAC") {
+        FileFormat::OggFlac
+    } else {
+        FileFormat::OggUnknown
+    };
+    Ok(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+    use super::*;
+
+    fn detect_first_packet(packet: &[u8]) -> Result<FileFormat, Error> {
+        let mut stream = Vec::new();
+        let mut writer = PacketWriter::new(&mut stream);
+        writer.write_packet(packet.to_vec(), 1, PacketWriteEndInfo::EndStream, 0).map_err(Error::WriteError)?;
+        drop(writer);
+        let mut reader = Cursor::new(stream);
+        let format = detect_format(&mut reader)?;
+        assert_eq!(reader.position(), 0);
+        Ok(format)
+    }
+
+    #[test]
+    fn format_detection() -> Result<(), Error> {
+        assert_eq!(detect_first_packet(b"OpusHead\x01")?, FileFormat::Ogg(Codec::Opus));
+        assert_eq!(detect_first_packet(b"\x01vorbis\x00")?, FileFormat::Ogg(Codec::Vorbis));
+        assert_eq!(detect_first_packet(b"\x7fFLAC\x01\x00")?, FileFormat::OggFlac);
+        assert_eq!(detect_first_packet(b"\x80theora")?, FileFormat::OggUnknown);
+        assert_eq!(detect_format(&mut Cursor::new(b"fLaC\x00\x00\x00\x22"))?, FileFormat::Unknown);
+        assert_eq!(detect_format(&mut Cursor::new(b""))?, FileFormat::Unknown);
+        Ok(())
+    }
+
+    #[test]
+    fn codec_identified_from_first_packet() {
+        for codec in Codec::ALL {
+            assert_eq!(Codec::identify(codec.magic()), Some(codec));
+        }
+        assert_eq!(Codec::identify(b"OpusTags"), None);
+        assert!(matches!(OpusFormat::parse_headers(b"\x01vorbis", b""), Err(Error::UnknownCodec)));
+    }
+}
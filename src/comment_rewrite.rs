@@ -1,9 +1,13 @@
 use derivative::Derivative;
 
-use crate::header::{self, CommentList, DiscreteCommentList};
+use crate::header::{self, CommentEntry, CommentList, DiscreteCommentList};
 use crate::header_rewriter::{HeaderRewriteGeneric, HeaderSummarizeGeneric};
+use crate::tag_names::TagConvention;
 use crate::Error;
 
+/// Predicate deciding whether an existing comment should be retained
+pub type RetainPredicate<'a> = Box<dyn Fn(&str, &str) -> bool + 'a>;
+
 /// Mode type for `CommentRewriter`
 #[derive(Derivative)]
 #[derivative(Debug)]
@@ -11,7 +15,7 @@ pub enum CommentRewriterAction<'a> {
     NoChange,
     Modify {
         #[derivative(Debug = "ignore")]
-        retain: Box<dyn Fn(&str, &str) -> bool + 'a>,
+        retain: RetainPredicate<'a>,
         append: DiscreteCommentList,
     },
     Replace(DiscreteCommentList),
@@ -22,6 +26,14 @@ pub enum CommentRewriterAction<'a> {
 pub struct CommentRewriterConfig<'a> {
     /// The action to be performed
     pub action: CommentRewriterAction<'a>,
+
+    /// Whether comment entries which could not be parsed should be removed
+    /// rather than preserved
+    pub drop_invalid: bool,
+
+    /// The convention tags should be renamed to follow once the action has
+    /// been performed, if any
+    pub normalize_keys: Option<TagConvention>,
 }
 
 /// Parameterization struct for `HeaderRewriter` to rewrite ouput gain and R128
@@ -64,13 +76,30 @@ impl HeaderRewriteGeneric for CommentHeaderRewrite<'_> {
             CommentRewriterAction::NoChange => {}
             CommentRewriterAction::Replace(tags) => {
                 comment_header.clear();
-                comment_header.extend(tags.iter())?;
+                for entry in tags.entries() {
+                    match entry {
+                        CommentEntry::Valid(key, value) => comment_header.push(key, value)?,
+                        CommentEntry::Invalid(entry) => comment_header.push_invalid(entry.clone()),
+                    }
+                }
+                if let Some(vendor) = tags.vendor() {
+                    comment_header.set_vendor(vendor);
+                }
             }
             CommentRewriterAction::Modify { retain, append } => {
                 comment_header.retain(retain);
                 comment_header.extend(append.iter())?;
+                if let Some(vendor) = append.vendor() {
+                    comment_header.set_vendor(vendor);
+                }
             }
         }
+        if let Some(convention) = self.config.normalize_keys {
+            convention.normalize(comment_header)?;
+        }
+        if self.config.drop_invalid {
+            comment_header.remove_invalid_entries();
+        }
         Ok(())
     }
 }
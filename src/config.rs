@@ -0,0 +1,378 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+use zoog::header::FixedPointGain;
+use zoog::volume_rewrite::VolumeTarget;
+use zoog::Decibels;
+
+/// The environment variable which overrides the location of the configuration
+/// file
+pub const CONFIG_PATH_VARIABLE: &str = "ZOOG_CONFIG";
+
+/// A value in the configuration file
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    String(String),
+    Number(f64),
+    Boolean(bool),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Number(_) => "a number",
+            Value::Boolean(_) => "a boolean",
+        }
+    }
+}
+
+/// The key-value pairs of each table in a configuration file, with keys at
+/// the top level in the table with an empty name
+type Tables = BTreeMap<String, BTreeMap<String, Value>>;
+
+/// Which gains a profile records in the files it is applied to
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TagFormat {
+    /// The output gain along with `R128_TRACK_GAIN` and `R128_ALBUM_GAIN` tags
+    #[default]
+    R128,
+
+    /// Only the output gain. Any existing R128 tags are removed.
+    GainOnly,
+}
+
+/// A volume profile defined in the configuration file
+#[derive(Clone, Debug)]
+pub struct UserProfile {
+    /// A short description of the profile
+    pub description: Option<String>,
+
+    /// The volume targeted by the profile
+    pub target: VolumeTarget,
+
+    /// Whether R128 tags should be adjusted to compensate for output gain
+    /// changes made without analysis
+    pub tag_compensation: bool,
+
+    /// The level in dBFS which the output gain should not raise the sample
+    /// peak above
+    pub peak_ceiling: Option<Decibels>,
+
+    /// Which gains are recorded
+    pub tag_format: TagFormat,
+}
+
+/// Values to use for command-line options which are not specified
+#[derive(Clone, Debug, Default)]
+pub struct Defaults {
+    pub preset: Option<String>,
+    pub output_gain_mode: Option<String>,
+    pub num_threads: Option<usize>,
+    pub cache: Option<PathBuf>,
+    pub temp_dir: Option<PathBuf>,
+    pub fsync: Option<bool>,
+}
+
+/// Settings read from the user's configuration file
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// User-defined volume profiles, by name
+    pub profiles: BTreeMap<String, UserProfile>,
+
+    /// Defaults for command-line options
+    pub defaults: Defaults,
+}
+
+impl Config {
+    /// The location of the configuration file. This is `zoog/config.toml`
+    /// within the platform's configuration directory unless overridden by the
+    /// `ZOOG_CONFIG` environment variable.
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os(CONFIG_PATH_VARIABLE) {
+            return Some(PathBuf::from(path));
+        }
+        let config_dir = if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        };
+        config_dir.map(|dir| dir.join("zoog").join("config.toml"))
+    }
+
+    /// Reads the configuration file at `path`. A missing file results in an
+    /// empty configuration.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Parses the contents of a configuration file
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        for (table, mut values) in parse_tables(text)? {
+            if table == "defaults" {
+                config.defaults = Defaults {
+                    preset: take_string(&mut values, &table, "preset")?,
+                    output_gain_mode: take_string(&mut values, &table, "output_gain_mode")?,
+                    num_threads: take_count(&mut values, &table, "num_threads")?,
+                    cache: take_string(&mut values, &table, "cache")?.map(PathBuf::from),
+                    temp_dir: take_string(&mut values, &table, "temp_dir")?.map(PathBuf::from),
+                    fsync: take_boolean(&mut values, &table, "fsync")?,
+                };
+            } else if let Some(name) = table.strip_prefix("profiles.").filter(|name| !name.contains('.')) {
+                let profile = parse_profile(&mut values, &table)?;
+                config.profiles.insert(name.to_string(), profile);
+            } else if !values.is_empty() {
+                let location = if table.is_empty() { String::from("the top level") } else { format!("[{}]", table) };
+                return Err(format!("Unexpected settings at {}", location));
+            }
+            if let Some(key) = values.keys().next() {
+                return Err(format!("Unknown setting `{}` in [{}]", key, table));
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn parse_profile(values: &mut BTreeMap<String, Value>, table: &str) -> Result<UserProfile, String> {
+    let lufs = take_number(values, table, "target_lufs")?;
+    let output_gain = take_number(values, table, "output_gain")?;
+    let target = match (lufs, output_gain) {
+        (Some(lufs), None) => VolumeTarget::LUFS(Decibels::from(lufs)),
+        (None, Some(gain)) => {
+            let gain = FixedPointGain::try_from(Decibels::from(gain))
+                .map_err(|_| format!("The output gain in [{}] is out of range", table))?;
+            VolumeTarget::Absolute(gain)
+        }
+        _ => return Err(format!("[{}] must specify exactly one of `target_lufs` and `output_gain`", table)),
+    };
+    let peak_ceiling = take_number(values, table, "peak_ceiling")?.map(Decibels::from);
+    if peak_ceiling.is_some() && output_gain.is_some() {
+        return Err(format!("`peak_ceiling` in [{}] requires `target_lufs`", table));
+    }
+    let tag_format = match take_string(values, table, "tag_format")?.as_deref() {
+        None | Some("r128") => TagFormat::R128,
+        Some("gain-only") => TagFormat::GainOnly,
+        Some(format) => {
+            return Err(format!("`tag_format` in [{}] should be \"r128\" or \"gain-only\" but is `{}`", table, format))
+        }
+    };
+    Ok(UserProfile {
+        description: take_string(values, table, "description")?,
+        target,
+        tag_compensation: take_boolean(values, table, "tag_compensation")?.unwrap_or(true),
+        peak_ceiling,
+        tag_format,
+    })
+}
+
+fn type_error(table: &str, key: &str, expected: &str, value: &Value) -> String {
+    format!("`{}` in [{}] should be {} but is {}", key, table, expected, value.type_name())
+}
+
+fn take_string(values: &mut BTreeMap<String, Value>, table: &str, key: &str) -> Result<Option<String>, String> {
+    match values.remove(key) {
+        None => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value)),
+        Some(value) => Err(type_error(table, key, "a string", &value)),
+    }
+}
+
+fn take_number(values: &mut BTreeMap<String, Value>, table: &str, key: &str) -> Result<Option<f64>, String> {
+    match values.remove(key) {
+        None => Ok(None),
+        Some(Value::Number(value)) => Ok(Some(value)),
+        Some(value) => Err(type_error(table, key, "a number", &value)),
+    }
+}
+
+fn take_boolean(values: &mut BTreeMap<String, Value>, table: &str, key: &str) -> Result<Option<bool>, String> {
+    match values.remove(key) {
+        None => Ok(None),
+        Some(Value::Boolean(value)) => Ok(Some(value)),
+        Some(value) => Err(type_error(table, key, "a boolean", &value)),
+    }
+}
+
+fn take_count(values: &mut BTreeMap<String, Value>, table: &str, key: &str) -> Result<Option<usize>, String> {
+    match take_number(values, table, key)? {
+        None => Ok(None),
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        Some(value) if value.fract() == 0.0 && value >= 1.0 && value <= f64::from(u32::MAX) => Ok(Some(value as usize)),
+        Some(_) => Err(format!("`{}` in [{}] should be a positive whole number", key, table)),
+    }
+}
+
+/// Parses the subset of TOML used by the configuration file: tables, and keys
+/// with string, number and boolean values
+fn parse_tables(text: &str) -> Result<Tables, String> {
+    let mut tables = Tables::new();
+    let mut current = String::new();
+    tables.insert(current.clone(), BTreeMap::new());
+    for (index, line) in text.lines().enumerate() {
+        let at_line = |message: String| format!("Line {}: {}", index + 1, message);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let (name, rest) =
+                header.split_once(']').ok_or_else(|| at_line(String::from("unterminated table name")))?;
+            if !is_comment_or_empty(rest) {
+                return Err(at_line(String::from("unexpected text after table name")));
+            }
+            let name = name.trim();
+            if name.is_empty() || !name.split('.').all(is_bare_key) {
+                return Err(at_line(format!("invalid table name `{}`", name)));
+            }
+            if tables.contains_key(name) {
+                return Err(at_line(format!("table [{}] is defined more than once", name)));
+            }
+            current = name.to_string();
+            tables.insert(current.clone(), BTreeMap::new());
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| at_line(String::from("expected `key = value`")))?;
+        let key = key.trim();
+        if !is_bare_key(key) {
+            return Err(at_line(format!("invalid key `{}`", key)));
+        }
+        let (value, rest) = parse_value(value.trim()).map_err(at_line)?;
+        if !is_comment_or_empty(rest) {
+            return Err(at_line(String::from("unexpected text after value")));
+        }
+        let table = tables.get_mut(&current).expect("Current table should exist");
+        if table.insert(key.to_string(), value).is_some() {
+            return Err(at_line(format!("`{}` is defined more than once", key)));
+        }
+    }
+    Ok(tables)
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn is_comment_or_empty(text: &str) -> bool {
+    let text = text.trim();
+    text.is_empty() || text.starts_with('#')
+}
+
+/// Parses a value from the start of `text`, returning it along with the
+/// remaining text
+fn parse_value(text: &str) -> Result<(Value, &str), String> {
+    if let Some(rest) = text.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(value), &rest[index + 1..])),
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        _ => return Err(String::from("unsupported escape sequence in string")),
+                    };
+                    value.push(escaped);
+                }
+                c => value.push(c),
+            }
+        }
+        return Err(String::from("unterminated string"));
+    }
+    if let Some(rest) = text.strip_prefix('\'') {
+        let (value, rest) = rest.split_once('\'').ok_or_else(|| String::from("unterminated string"))?;
+        return Ok((Value::String(value.to_string()), rest));
+    }
+    let end = text.find(|c: char| c.is_whitespace() || c == '#').unwrap_or(text.len());
+    let (token, rest) = text.split_at(end);
+    let value = match token {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => {
+            let number: f64 =
+                token.replace('_', "").parse().map_err(|_| format!("`{}` is not a valid value", token))?;
+            if !number.is_finite() {
+                return Err(format!("`{}` is not a finite number", token));
+            }
+            Value::Number(number)
+        }
+    };
+    Ok((value, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_and_defaults_parsed() -> Result<(), String> {
+        let text = r#"
+            # Settings for opusgain
+            [defaults]
+            preset = "quiet"   # A user-defined profile
+            num_threads = 4
+            fsync = true
+
+            [profiles.quiet]
+            description = 'Background listening'
+            target_lufs = -30.5
+            tag_compensation = false
+            peak_ceiling = -1.5
+            tag_format = "gain-only"
+
+            [profiles.loud]
+            output_gain = 6
+        "#;
+        let config = Config::parse(text)?;
+        assert_eq!(config.defaults.preset.as_deref(), Some("quiet"));
+        assert_eq!(config.defaults.num_threads, Some(4));
+        assert_eq!(config.defaults.fsync, Some(true));
+        assert_eq!(config.defaults.cache, None);
+
+        let quiet = &config.profiles["quiet"];
+        assert_eq!(quiet.description.as_deref(), Some("Background listening"));
+        assert!(matches!(quiet.target, VolumeTarget::LUFS(lufs) if (lufs.as_f64() + 30.5).abs() < 1e-9));
+        assert!(!quiet.tag_compensation);
+        assert_eq!(quiet.peak_ceiling.map(|ceiling| ceiling.as_f64()), Some(-1.5));
+        assert_eq!(quiet.tag_format, TagFormat::GainOnly);
+
+        let loud = &config.profiles["loud"];
+        assert!(matches!(loud.target, VolumeTarget::Absolute(gain) if gain.as_fixed_point() == 6 * 256));
+        assert!(loud.tag_compensation);
+        assert!(loud.peak_ceiling.is_none());
+        assert_eq!(loud.tag_format, TagFormat::R128);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_configuration_rejected() {
+        let invalid = [
+            "[defaults]\nfsync = 1",
+            "[defaults]\nunknown = true",
+            "[profiles.a]\ntag_compensation = true",
+            "[profiles.a]\ntarget_lufs = -20\noutput_gain = 0",
+            "[profiles.a]\noutput_gain = 0\npeak_ceiling = -1",
+            "[profiles.a]\ntarget_lufs = -20\ntag_format = \"replaygain\"",
+            "[other]\nkey = 1",
+            "[defaults]\npreset = \"unterminated",
+            "[defaults]\nnum_threads = 0",
+            "[defaults]\n[defaults]",
+        ];
+        for text in invalid {
+            assert!(Config::parse(text).is_err(), "{:?} should be rejected", text);
+        }
+        assert!(Config::parse("").is_ok());
+    }
+}
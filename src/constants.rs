@@ -8,6 +8,12 @@ pub mod global {
     /// since ReplayGain does not use LUFS.
     pub const REPLAY_GAIN_LUFS: Decibels = Decibels::new(-18.0);
 
+    /// The LUFS value commonly recommended for podcasts (-16 LUFS)
+    pub const PODCAST_LUFS: Decibels = Decibels::new(-16.0);
+
+    /// The LUFS value commonly used by music streaming services (-14 LUFS)
+    pub const STREAMING_LUFS: Decibels = Decibels::new(-14.0);
+
     /// Separator between field-names and values in comments
     pub const FIELD_NAME_TERMINATOR: u8 = b'=';
 }
@@ -5,6 +5,53 @@ use zoog::interrupt::Interrupt;
 
 pub type CtrlCRegistrationError = ctrlc::Error;
 
+/// Set when the console window is closed or the user logs off or shuts down.
+/// These events are not passed on to the Ctrl-C handler since Windows
+/// terminates the process as soon as it returns.
+#[cfg(windows)]
+static CONSOLE_CLOSING: AtomicBool = AtomicBool::new(false);
+
+#[cfg(windows)]
+mod console_close {
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
+    use winapi::um::consoleapi::SetConsoleCtrlHandler;
+    use winapi::um::wincon::{CTRL_CLOSE_EVENT, CTRL_LOGOFF_EVENT, CTRL_SHUTDOWN_EVENT};
+
+    /// Windows terminates the process when the handler returns or after five
+    /// seconds, whichever comes first. Waiting for slightly less than this
+    /// gives the main thread time to remove its temporary files and exit.
+    const GRACE_PERIOD: Duration = Duration::from_millis(4500);
+
+    unsafe extern "system" fn handler(event: DWORD) -> BOOL {
+        match event {
+            CTRL_CLOSE_EVENT | CTRL_LOGOFF_EVENT | CTRL_SHUTDOWN_EVENT => {
+                super::CONSOLE_CLOSING.store(true, Ordering::Relaxed);
+                std::thread::sleep(GRACE_PERIOD);
+                TRUE
+            }
+            _ => FALSE,
+        }
+    }
+
+    /// Registers the handler. Handlers are called in the reverse order of
+    /// registration so this must follow the registration of the Ctrl-C
+    /// handler.
+    pub fn register() -> Result<(), std::io::Error> {
+        // SAFETY: `handler` only touches an atomic and sleeps
+        if unsafe { SetConsoleCtrlHandler(Some(handler), TRUE) } == FALSE {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Tracks whether the process has been asked to stop. This is triggered by
+/// Ctrl-C, as well as by SIGTERM and SIGHUP on Unix and by the console being
+/// closed on Windows.
 #[derive(Clone, Debug)]
 pub struct CtrlCChecker {
     running: Arc<AtomicBool>,
@@ -19,11 +66,19 @@ impl CtrlCChecker {
                 running.store(false, Ordering::Relaxed);
             })?;
         }
+        #[cfg(windows)]
+        console_close::register().map_err(ctrlc::Error::System)?;
         let result = CtrlCChecker { running };
         Ok(result)
     }
 
+    #[cfg(not(windows))]
     pub fn is_running(&self) -> bool { self.running.load(Ordering::Relaxed) }
+
+    #[cfg(windows)]
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed) && !CONSOLE_CLOSING.load(Ordering::Relaxed)
+    }
 }
 
 impl Interrupt for CtrlCChecker {
@@ -22,6 +22,9 @@ impl From<f64> for Decibels {
 
 impl Decibels {
     pub const fn new(value: f64) -> Decibels { Decibels { inner: value } }
+
+    /// The level of an amplitude relative to full scale, such as a sample peak
+    pub fn from_amplitude(amplitude: f64) -> Decibels { Decibels::new(20.0 * amplitude.log10()) }
 }
 
 impl Display for Decibels {
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::Path;
+
+use serde::Serialize;
+use zoog::comment_rewrite::{CommentHeaderRewrite, CommentRewriterAction, CommentRewriterConfig};
+use zoog::header::CommentHeader;
+use zoog::header_rewriter::{rewrite_stream, CodecHeaders, HeaderSummarize, SubmitResult};
+use zoog::{Codec, Error};
+
+/// The name of the comment recording the software used to encode a file
+const TAG_ENCODER: &str = "ENCODER";
+
+/// The encoder-related metadata of a single file
+#[derive(Clone, Debug)]
+pub struct EncoderInfo {
+    /// The codec of the file
+    pub codec: Codec,
+
+    /// The vendor string from the comment header, which identifies the
+    /// codec library
+    pub vendor: String,
+
+    /// The values of any `ENCODER` comments, which usually identify the
+    /// encoding application
+    pub encoders: Vec<String>,
+}
+
+impl EncoderInfo {
+    fn from_comment_header<C: CommentHeader>(codec: Codec, header: &C) -> EncoderInfo {
+        let encoders = header
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(TAG_ENCODER))
+            .map(|(_, value)| value.to_string())
+            .collect();
+        EncoderInfo { codec, vendor: header.get_vendor().to_string(), encoders }
+    }
+}
+
+/// Summarizes codec headers by returning the encoder-related metadata
+#[derive(Debug, Default)]
+struct EncoderSummary {}
+
+impl HeaderSummarize for EncoderSummary {
+    type Error = Error;
+    type Summary = EncoderInfo;
+
+    fn summarize(&self, headers: &CodecHeaders) -> Result<EncoderInfo, Error> {
+        let info = match headers {
+            CodecHeaders::Opus(_, comments) => EncoderInfo::from_comment_header(headers.codec(), comments),
+            CodecHeaders::Vorbis(_, comments) => EncoderInfo::from_comment_header(headers.codec(), comments),
+        };
+        Ok(info)
+    }
+}
+
+/// Reads the encoder-related metadata of the file at `path`
+pub fn read_encoder_info(path: &Path) -> Result<EncoderInfo, Error> {
+    let input_file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig {
+        action: CommentRewriterAction::NoChange,
+        drop_invalid: false,
+        normalize_keys: None,
+    });
+    let abort_on_unchanged = true;
+    let result =
+        rewrite_stream(rewrite, EncoderSummary::default(), BufReader::new(input_file), io::sink(), abort_on_unchanged)?;
+    match result {
+        SubmitResult::HeadersUnchanged(info) => Ok(info),
+        SubmitResult::Good | SubmitResult::HeadersChanged { .. } => Err(Error::MalformedCommentHeader),
+    }
+}
+
+/// Counts of codecs, vendor strings and `ENCODER` comments across a set of
+/// files
+#[derive(Debug, Default, Serialize)]
+pub struct EncoderStats {
+    /// Number of files whose headers were read
+    pub files: usize,
+
+    /// Number of files whose headers could not be read
+    pub unreadable: usize,
+
+    /// Number of files without an `ENCODER` comment
+    pub without_encoder_tag: usize,
+
+    /// Number of files of each codec
+    pub codecs: BTreeMap<String, usize>,
+
+    /// Number of files with each vendor string
+    pub vendors: BTreeMap<String, usize>,
+
+    /// Number of files with each `ENCODER` comment value
+    pub encoders: BTreeMap<String, usize>,
+}
+
+/// Orders counts from most to least frequent, then by name
+fn by_frequency(counts: &BTreeMap<String, usize>) -> Vec<(&str, usize)> {
+    let mut result: Vec<_> = counts.iter().map(|(name, count)| (name.as_str(), *count)).collect();
+    result.sort_by(|(name_a, count_a), (name_b, count_b)| count_b.cmp(count_a).then_with(|| name_a.cmp(name_b)));
+    result
+}
+
+fn write_counts<W: Write>(writer: &mut W, title: &str, counts: &BTreeMap<String, usize>) -> Result<(), io::Error> {
+    writeln!(writer, "\n{}:", title)?;
+    for (name, count) in by_frequency(counts) {
+        writeln!(writer, "{:>8}  {}", count, name)?;
+    }
+    Ok(())
+}
+
+impl EncoderStats {
+    /// Adds the metadata of a file
+    pub fn add(&mut self, info: &EncoderInfo) {
+        self.files += 1;
+        *self.codecs.entry(info.codec.to_string()).or_default() += 1;
+        *self.vendors.entry(info.vendor.clone()).or_default() += 1;
+        if info.encoders.is_empty() {
+            self.without_encoder_tag += 1;
+        }
+        for encoder in &info.encoders {
+            *self.encoders.entry(encoder.clone()).or_default() += 1;
+        }
+    }
+
+    /// Records a file whose headers could not be read
+    pub fn add_unreadable(&mut self) { self.unreadable += 1; }
+
+    /// Writes a human-readable report with the most frequent entries first
+    pub fn write_report<W: Write>(&self, mut writer: W) -> Result<(), io::Error> {
+        writeln!(writer, "Files read: {}", self.files)?;
+        if self.unreadable > 0 {
+            writeln!(writer, "Files that could not be read: {}", self.unreadable)?;
+        }
+        write_counts(&mut writer, "Codecs", &self.codecs)?;
+        write_counts(&mut writer, "Vendor strings", &self.vendors)?;
+        write_counts(&mut writer, "ENCODER tags", &self.encoders)?;
+        if self.without_encoder_tag > 0 {
+            writeln!(writer, "{:>8}  (no {} tag)", self.without_encoder_tag, TAG_ENCODER)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_is_ordered_by_frequency() {
+        let mut stats = EncoderStats::default();
+        let info = |vendor: &str, encoders: &[&str]| EncoderInfo {
+            codec: Codec::Opus,
+            vendor: vendor.to_string(),
+            encoders: encoders.iter().map(ToString::to_string).collect(),
+        };
+        stats.add(&info("libopus 1.3", &["opusenc 0.2"]));
+        stats.add(&info("libopus 1.1", &[]));
+        stats.add(&info("libopus 1.3", &["opusenc 0.2"]));
+        stats.add_unreadable();
+
+        let mut report = Vec::new();
+        stats.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        let expected = concat!(
+            "Files read: 3\n",
+            "Files that could not be read: 1\n",
+            "\nCodecs:\n",
+            "       3  Opus\n",
+            "\nVendor strings:\n",
+            "       2  libopus 1.3\n",
+            "       1  libopus 1.1\n",
+            "\nENCODER tags:\n",
+            "       2  opusenc 0.2\n",
+            "       1  (no ENCODER tag)\n",
+        );
+        assert_eq!(report, expected);
+    }
+}
@@ -1,9 +1,10 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use ogg::reading::OggReadError;
 use tempfile::PersistError;
 use thiserror::Error;
 
+use crate::header::ParseLimit;
 use crate::{escaping, Codec};
 
 /// The Zoog error type
@@ -58,6 +59,10 @@ pub enum Error {
     #[error("Malformed comment header")]
     MalformedCommentHeader,
 
+    /// An Opus audio packet was invalid
+    #[error("Malformed Opus packet")]
+    MalformedOpusPacket,
+
     /// Missing comment separator
     #[error("Missing separator in comment")]
     MissingCommentSeparator,
@@ -78,6 +83,11 @@ pub enum Error {
     #[error("Failed to delete `{0}` due to `{1}`")]
     FileDelete(PathBuf, std::io::Error),
 
+    /// A filesystem did not have enough free space for a file to be written.
+    /// The required and available space are in bytes.
+    #[error("Insufficient space to write to `{0}`: {1} bytes required but only {2} available")]
+    InsufficientSpace(PathBuf, u64, u64),
+
     /// A temporary file could not be persisted
     #[error("Failed to persist temporary file due to `{0}`")]
     PersistError(#[from] PersistError),
@@ -86,7 +96,12 @@ pub enum Error {
     #[error("Unsupported channel count: `{0}`")]
     InvalidChannelCount(usize),
 
+    /// An unsupported sample rate was found
+    #[error("Unsupported sample rate: `{0}`")]
+    InvalidSampleRate(usize),
+
     /// An error was returned from the Opus library
+    #[cfg(feature = "analysis")]
     #[error("Opus error: `{0}`")]
     OpusError(opus::Error),
 
@@ -137,4 +152,161 @@ pub enum Error {
     /// Audio parameters changed
     #[error("Channel count and/or sample rate changed between concatenated audio streams")]
     UnexpectedAudioParametersChange,
+
+    /// The format of a file to import tags from was not recognised
+    #[error("The format of `{0}` is not supported for importing tags")]
+    UnrecognisedTagSource(PathBuf),
+
+    /// The tags in a file to import tags from could not be parsed
+    #[error("Unable to parse the tags in `{0}`")]
+    MalformedTagSource(PathBuf),
+
+    /// A comment header exceeded a limit on the resources used to parse it
+    #[error("Comment header exceeded the limit on {0}")]
+    ParseLimitExceeded(ParseLimit),
+
+    /// A rewritten comment header would exceed a limit on the resources used
+    /// to parse it
+    #[error("The comment header would exceed the limit on {0}")]
+    CommentHeaderTooLarge(ParseLimit),
+
+    /// The volume needed to compute a gain was not supplied
+    #[error("The volume needed to compute the output gain was not supplied")]
+    MissingVolume,
+
+    /// An error which occurred while processing the specified file
+    #[error("`{0}`: {1}")]
+    InFile(PathBuf, Box<Error>),
+}
+
+/// A broad category of `Error`. Unlike the variants of `Error`, these are
+/// intended to remain stable so that callers can decide how to handle an
+/// error, such as whether to skip a file or abort entirely.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Reading from or writing to a file, stream or console failed
+    Io,
+
+    /// The input was corrupt or did not conform to its format
+    Malformed,
+
+    /// The input was valid but used a codec, version or feature which is not
+    /// supported
+    Unsupported,
+
+    /// The operation was interrupted
+    Interrupted,
+
+    /// A limit on the resources used to process the input was exceeded
+    Limits,
+
+    /// A value supplied by the caller was invalid
+    InvalidArgument,
+}
+
+impl Error {
+    /// The category of the error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            // Input which ends prematurely is truncated rather than unreadable
+            Error::ReadError(e) | Error::OggDecode(OggReadError::ReadError(e))
+                if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                ErrorKind::Malformed
+            }
+            Error::FileOpenError(..)
+            | Error::FileReadError(..)
+            | Error::FileWriteError(..)
+            | Error::FileCopy(..)
+            | Error::TempFileOpenError(..)
+            | Error::ReadError(_)
+            | Error::WriteError(_)
+            | Error::FileDelete(..)
+            | Error::InsufficientSpace(..)
+            | Error::PersistError(_)
+            | Error::ConsoleIoError(_)
+            | Error::OggDecode(OggReadError::ReadError(_)) => ErrorKind::Io,
+            Error::OggDecode(_)
+            | Error::MalformedIdentificationHeader
+            | Error::MalformedCommentHeader
+            | Error::MalformedOpusPacket
+            | Error::MissingCommentSeparator
+            | Error::UTF8Error(_)
+            | Error::InvalidR128Tag(_)
+            | Error::MalformedTagSource(_) => ErrorKind::Malformed,
+            #[cfg(feature = "analysis")]
+            Error::OpusError(_) => ErrorKind::Malformed,
+            Error::MissingStream(_)
+            | Error::UnknownCodec
+            | Error::GainOutOfBounds
+            | Error::InvalidChannelCount(_)
+            | Error::InvalidSampleRate(_)
+            | Error::UnsupportedCodecVersion(..)
+            | Error::UnsupportedCodec(_)
+            | Error::UnexpectedLogicalStream(_)
+            | Error::UnexpectedAudioParametersChange
+            | Error::UnrecognisedTagSource(_) => ErrorKind::Unsupported,
+            Error::Interrupted => ErrorKind::Interrupted,
+            Error::UnrepresentableValueInCommentHeader
+            | Error::ParseLimitExceeded(_)
+            | Error::CommentHeaderTooLarge(_) => ErrorKind::Limits,
+            Error::InvalidThreadCount
+            | Error::NoParentError(_)
+            | Error::NotAFilePath(_)
+            | Error::InvalidOpusCommentFieldName(_)
+            | Error::EscapeDecodeError(_)
+            | Error::MissingVolume => ErrorKind::InvalidArgument,
+            Error::InFile(_, e) => e.kind(),
+        }
+    }
+
+    /// The path of the file the error relates to, if known
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::FileOpenError(path, _)
+            | Error::FileReadError(path, _)
+            | Error::FileWriteError(path, _)
+            | Error::FileCopy(path, ..)
+            | Error::FileDelete(path, _)
+            | Error::InsufficientSpace(path, ..)
+            | Error::NoParentError(path)
+            | Error::NotAFilePath(path)
+            | Error::UnrecognisedTagSource(path)
+            | Error::MalformedTagSource(path)
+            | Error::InFile(path, _) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Attaches the path of the file being processed to the error, unless the
+    /// error already identifies a file
+    #[must_use]
+    pub fn in_file(self, path: &Path) -> Error {
+        if self.path().is_some() {
+            self
+        } else {
+            Error::InFile(path.to_path_buf(), Box::new(self))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kind_and_path_preserved_in_file_context() {
+        let path = Path::new("track.opus");
+        let error = Error::ParseLimitExceeded(ParseLimit::CommentCount).in_file(path);
+        assert_eq!(error.kind(), ErrorKind::Limits);
+        assert_eq!(error.path(), Some(path));
+        assert_eq!(error.to_string(), "`track.opus`: Comment header exceeded the limit on number of comments");
+
+        let other = Path::new("other.opus");
+        let error = error.in_file(other);
+        assert_eq!(error.path(), Some(path));
+        assert_eq!(Error::Interrupted.kind(), ErrorKind::Interrupted);
+        assert_eq!(Error::OggDecode(OggReadError::NoCapturePatternFound).kind(), ErrorKind::Malformed);
+    }
 }
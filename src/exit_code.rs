@@ -0,0 +1,45 @@
+use zoog::ErrorKind;
+
+/// Exit status for failures which do not fall into a more specific category
+pub const FAILURE: i32 = 1;
+
+/// Exit status for invalid command-line arguments, matching that used by
+/// `clap` for usage errors
+pub const INVALID_ARGUMENT: i32 = 2;
+
+/// Exit status for failures to read or write files or the console
+pub const IO: i32 = 3;
+
+/// Exit status for corrupt or non-conforming input
+pub const MALFORMED: i32 = 4;
+
+/// Exit status for input using an unsupported codec or feature
+pub const UNSUPPORTED: i32 = 5;
+
+/// Exit status for input exceeding a resource limit
+pub const LIMITS: i32 = 6;
+
+/// Exit status when some, but not all, of the files supplied could not be
+/// processed and `--strict-exit` was specified
+#[allow(dead_code)]
+pub const PARTIAL_FAILURE: i32 = 7;
+
+/// Exit status when a time limit is reached, matching that of `timeout(1)`
+#[allow(dead_code)]
+pub const TIME_LIMIT: i32 = 124;
+
+/// Exit status when interrupted, following the shell convention for SIGINT
+pub const INTERRUPTED: i32 = 130;
+
+/// The exit status for a library error of the given kind
+pub fn for_error_kind(kind: ErrorKind) -> i32 {
+    match kind {
+        ErrorKind::Io => IO,
+        ErrorKind::Malformed => MALFORMED,
+        ErrorKind::Unsupported => UNSUPPORTED,
+        ErrorKind::Interrupted => INTERRUPTED,
+        ErrorKind::Limits => LIMITS,
+        ErrorKind::InvalidArgument => INVALID_ARGUMENT,
+        _ => FAILURE,
+    }
+}
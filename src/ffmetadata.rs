@@ -0,0 +1,335 @@
+use std::io::{self, Write};
+
+use zoog::header::{CommentList, DiscreteCommentList};
+
+/// The first line of an ffmpeg metadata file
+const HEADER: &str = ";FFMETADATA1";
+
+/// Characters which must be escaped with a backslash in keys and values
+const SPECIAL_CHARACTERS: [char; 5] = ['=', ';', '#', '\\', '\n'];
+
+/// Vorbis comment names which ffmpeg maps to different generic keys
+const KEY_MAPPINGS: [(&str, &str); 4] =
+    [("ALBUMARTIST", "album_artist"), ("TRACKNUMBER", "track"), ("DISCNUMBER", "disc"), ("DESCRIPTION", "comment")];
+
+/// The time base ffmpeg assumes for a chapter without a `TIMEBASE` entry
+const DEFAULT_TIMEBASE: (u64, u64) = (1, 1_000_000_000);
+
+/// A logical line of an ffmpeg metadata file
+#[derive(Debug, Eq, PartialEq)]
+enum Line {
+    Section(String),
+    Entry(String, String),
+}
+
+/// A chapter in the form of the Vorbis comment chapter extension
+#[derive(Debug, Default)]
+struct Chapter {
+    start_ms: u64,
+    title: Option<String>,
+}
+
+fn vorbis_key(key: &str) -> String {
+    KEY_MAPPINGS
+        .iter()
+        .find(|(_, ffmpeg)| ffmpeg.eq_ignore_ascii_case(key))
+        .map_or_else(|| key.to_ascii_uppercase(), |(vorbis, _)| (*vorbis).to_string())
+}
+
+fn ffmpeg_key(key: &str) -> String {
+    KEY_MAPPINGS
+        .iter()
+        .find(|(vorbis, _)| vorbis.eq_ignore_ascii_case(key))
+        .map_or_else(|| key.to_ascii_lowercase(), |(_, ffmpeg)| (*ffmpeg).to_string())
+}
+
+/// Splits the text following the header into logical lines, removing escapes
+/// and comments. Keys are separated from values by the first unescaped `=`.
+fn parse_lines(text: &str) -> Result<Vec<Line>, String> {
+    let mut lines = Vec::new();
+    let mut number = 2;
+    for raw in split_unescaped(text, '\n') {
+        let line_number = number;
+        number += 1 + raw.matches('\n').count();
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+        if raw.is_empty() || raw.starts_with(';') || raw.starts_with('#') {
+            continue;
+        }
+        if raw.starts_with('[') && raw.ends_with(']') {
+            lines.push(Line::Section(raw[1..raw.len() - 1].to_string()));
+            continue;
+        }
+        let mut parts = split_unescaped(raw, '=');
+        let key = parts.next().map(unescape).unwrap_or_default();
+        let rest: Vec<_> = parts.collect();
+        if rest.is_empty() || key.is_empty() {
+            return Err(format!("line {}: expected KEY=VALUE", line_number));
+        }
+        lines.push(Line::Entry(key, unescape(&rest.join("="))));
+    }
+    Ok(lines)
+}
+
+/// Splits `text` at each occurrence of `separator` not preceded by a
+/// backslash, leaving escapes in place
+fn split_unescaped(text: &str, separator: char) -> impl Iterator<Item = &str> {
+    let mut escaped = false;
+    text.split(move |c| {
+        let split = !escaped && c == separator;
+        escaped = !escaped && c == '\\';
+        split
+    })
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if SPECIAL_CHARACTERS.contains(&c) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Formats a time as used by the Vorbis comment chapter extension
+fn format_chapter_time(ms: u64) -> String {
+    format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+/// Parses a time of the form `HH:MM:SS.mmm` as used by the Vorbis comment
+/// chapter extension
+fn parse_chapter_time(text: &str) -> Option<u64> {
+    let (hms, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let mut fields = hms.split(':').map(|field| field.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (fields.next()??, fields.next()??, fields.next()??);
+    if fields.next().is_some() || minutes >= 60 || seconds >= 60 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let ms = fraction.bytes().chain(std::iter::repeat(b'0')).take(3).fold(0, |ms, b| ms * 10 + u64::from(b - b'0'));
+    Some((hours * 3600 + minutes * 60 + seconds) * 1000 + ms)
+}
+
+/// Returns the chapter number of a `CHAPTERnnn` or `CHAPTERnnnNAME` tag and
+/// whether it is the name
+fn chapter_tag(key: &str) -> Option<(u32, bool)> {
+    let key = key.to_ascii_uppercase();
+    let rest = key.strip_prefix("CHAPTER")?;
+    let (digits, is_name) = match rest.strip_suffix("NAME") {
+        Some(digits) => (digits, true),
+        None => (rest, false),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((digits.parse().ok()?, is_name))
+}
+
+fn parse_chapter(entries: &[(String, String)]) -> Result<Chapter, String> {
+    let mut timebase = DEFAULT_TIMEBASE;
+    let mut start = None;
+    let mut chapter = Chapter::default();
+    for (key, value) in entries {
+        let invalid = || format!("invalid chapter {} `{}`", key, value);
+        match key.as_str() {
+            "TIMEBASE" => {
+                let (num, den) = value.split_once('/').ok_or_else(invalid)?;
+                timebase = (num.trim().parse().map_err(|_| invalid())?, den.trim().parse().map_err(|_| invalid())?);
+                if timebase.1 == 0 {
+                    return Err(invalid());
+                }
+            }
+            "START" => start = Some(value.trim().parse::<u64>().map_err(|_| invalid())?),
+            // The chapter extension has no end times since each chapter ends
+            // where the next starts
+            "END" => {
+                value.trim().parse::<u64>().map_err(|_| invalid())?;
+            }
+            key if key.eq_ignore_ascii_case("title") => chapter.title = Some(value.clone()),
+            _ => {}
+        }
+    }
+    let to_ms = |time: u64| {
+        let ms = u128::from(time) * u128::from(timebase.0) * 1000 / u128::from(timebase.1);
+        u64::try_from(ms).map_err(|_| String::from("chapter time out of range"))
+    };
+    chapter.start_ms = to_ms(start.ok_or_else(|| String::from("chapter has no START"))?)?;
+    Ok(chapter)
+}
+
+/// Parses an ffmpeg metadata file. Global metadata becomes tags and chapters
+/// become `CHAPTERnnn` and `CHAPTERnnnNAME` tags as described by the Vorbis
+/// comment chapter extension. Stream metadata is ignored.
+pub fn parse_ffmetadata(text: &str) -> Result<DiscreteCommentList, String> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let (first, body) = text.split_once('\n').unwrap_or((text, ""));
+    if first.trim_end_matches('\r') != HEADER {
+        return Err(format!("the file does not start with `{}`", HEADER));
+    }
+    let mut comments = DiscreteCommentList::default();
+    let mut chapters = Vec::new();
+    let mut section: Option<(String, Vec<(String, String)>)> = None;
+    let mut finish_section = |section: Option<(String, Vec<(String, String)>)>| -> Result<(), String> {
+        match section {
+            Some((name, entries)) if name == "CHAPTER" => chapters.push(parse_chapter(&entries)?),
+            _ => {}
+        }
+        Ok(())
+    };
+    for line in parse_lines(body)? {
+        match line {
+            Line::Section(name) => finish_section(section.replace((name, Vec::new())))?,
+            Line::Entry(key, value) => match section {
+                Some((_, ref mut entries)) => entries.push((key, value)),
+                None => comments.push(&vorbis_key(&key), &value).map_err(|e| e.to_string())?,
+            },
+        }
+    }
+    finish_section(section)?;
+    chapters.sort_by_key(|chapter| chapter.start_ms);
+    for (index, chapter) in chapters.iter().enumerate() {
+        let key = format!("CHAPTER{:03}", index + 1);
+        comments.push(&key, &format_chapter_time(chapter.start_ms)).map_err(|e| e.to_string())?;
+        if let Some(ref title) = chapter.title {
+            comments.push(&format!("{}NAME", key), title).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(comments)
+}
+
+/// Returns whether the comments contain any chapters
+pub fn has_chapters(comments: &DiscreteCommentList) -> bool {
+    comments
+        .iter()
+        .any(|(key, value)| matches!(chapter_tag(key), Some((_, false))) && parse_chapter_time(value).is_some())
+}
+
+/// Writes comments as an ffmpeg metadata file. Chapters described by
+/// `CHAPTERnnn` and `CHAPTERnnnNAME` tags are written as chapter sections,
+/// each ending where the next starts and the last ending at `duration_ms`.
+pub fn write_ffmetadata<W: Write>(mut writer: W, comments: &DiscreteCommentList, duration_ms: u64) -> io::Result<()> {
+    let mut chapters: Vec<(u32, Chapter)> = Vec::new();
+    for (key, value) in comments.iter() {
+        if let Some((number, false)) = chapter_tag(key) {
+            if let Some(start_ms) = parse_chapter_time(value) {
+                chapters.push((number, Chapter { start_ms, ..Chapter::default() }));
+            }
+        }
+    }
+    for (key, value) in comments.iter() {
+        if let Some((number, true)) = chapter_tag(key) {
+            if let Some((_, chapter)) = chapters.iter_mut().find(|(n, _)| *n == number) {
+                chapter.title.get_or_insert_with(|| value.to_string());
+            }
+        }
+    }
+    chapters.sort_by_key(|(number, chapter)| (chapter.start_ms, *number));
+
+    writeln!(writer, "{}", HEADER)?;
+    for (key, value) in comments.iter() {
+        let is_chapter = chapter_tag(key).map_or(false, |(number, _)| chapters.iter().any(|(n, _)| *n == number));
+        if !is_chapter {
+            writeln!(writer, "{}={}", escape(&ffmpeg_key(key)), escape(value))?;
+        }
+    }
+    for (index, (_, chapter)) in chapters.iter().enumerate() {
+        let end_ms = chapters.get(index + 1).map_or(duration_ms, |(_, next)| next.start_ms).max(chapter.start_ms);
+        writeln!(writer, "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}", chapter.start_ms, end_ms)?;
+        if let Some(ref title) = chapter.title {
+            writeln!(writer, "title={}", escape(title))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ffmetadata_parsed() -> Result<(), String> {
+        let text = ";FFMETADATA1\n\
+                    title=bike\\\\shed\n\
+                    ;this is a comment\n\
+                    album_artist=FFmpeg troll team\n\
+                    comment=multi\\\n\
+                    line \\= \\; \\#\n\
+                    \n\
+                    [CHAPTER]\n\
+                    TIMEBASE=1/10\n\
+                    START=615\n\
+                    END=700\n\
+                    title=Second\n\
+                    [CHAPTER]\n\
+                    TIMEBASE=1/1000\n\
+                    START=0\n\
+                    #chapter ends at 0:01:00\n\
+                    END=60000\n\
+                    title=chapter \\#1\n\
+                    [STREAM]\n\
+                    title=ignored\n";
+        let comments = parse_ffmetadata(text)?;
+        let entries: Vec<_> = comments.iter().collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("TITLE", "bike\\shed"),
+                ("ALBUMARTIST", "FFmpeg troll team"),
+                ("DESCRIPTION", "multi\nline = ; #"),
+                ("CHAPTER001", "00:00:00.000"),
+                ("CHAPTER001NAME", "chapter #1"),
+                ("CHAPTER002", "00:01:01.500"),
+                ("CHAPTER002NAME", "Second"),
+            ]
+        );
+        assert!(parse_ffmetadata("title=No header\n").is_err());
+        assert!(parse_ffmetadata(";FFMETADATA1\n[CHAPTER]\nEND=5\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn ffmetadata_round_trips() -> Result<(), String> {
+        let mut comments = DiscreteCommentList::default();
+        comments.push("TITLE", "A=B; #1").map_err(|e| e.to_string())?;
+        comments.push("TRACKNUMBER", "3").map_err(|e| e.to_string())?;
+        comments.push("CHAPTER002", "00:00:30.250").map_err(|e| e.to_string())?;
+        comments.push("CHAPTER001", "00:00:00.000").map_err(|e| e.to_string())?;
+        comments.push("CHAPTER001NAME", "Intro").map_err(|e| e.to_string())?;
+        let mut output = Vec::new();
+        write_ffmetadata(&mut output, &comments, 90_000).map_err(|e| e.to_string())?;
+        let text = String::from_utf8(output).map_err(|e| e.to_string())?;
+        assert_eq!(
+            text,
+            ";FFMETADATA1\n\
+             title=A\\=B\\; \\#1\n\
+             track=3\n\
+             \n[CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=30250\ntitle=Intro\n\
+             \n[CHAPTER]\nTIMEBASE=1/1000\nSTART=30250\nEND=90000\n"
+        );
+        let parsed = parse_ffmetadata(&text)?;
+        let entries: Vec<_> = parsed.iter().collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("TITLE", "A=B; #1"),
+                ("TRACKNUMBER", "3"),
+                ("CHAPTER001", "00:00:00.000"),
+                ("CHAPTER001NAME", "Intro"),
+                ("CHAPTER002", "00:00:30.250"),
+            ]
+        );
+        Ok(())
+    }
+}
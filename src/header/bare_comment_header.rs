@@ -0,0 +1,51 @@
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+use crate::header::{CommentHeaderGeneric, CommentHeaderSpecifics};
+use crate::Error;
+
+/// Comment header logic for a comment block with neither a magic signature
+/// nor any data following the comments
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BareSpecifics {}
+
+impl CommentHeaderSpecifics for BareSpecifics {
+    fn get_magic() -> Cow<'static, [u8]> { Cow::Borrowed(&[]) }
+
+    fn read_suffix<R: Read>(&mut self, _reader: &mut R) -> Result<(), Error> { Ok(()) }
+
+    fn write_suffix<W: Write>(&self, _writer: &mut W) -> Result<(), Error> { Ok(()) }
+}
+
+/// Manipulates a comment block consisting of only the vendor string and the
+/// length-prefixed comments. This is the form used outside of Ogg, for example
+/// by the `VORBIS_COMMENT` metadata block of FLAC files.
+pub type BareCommentHeader = CommentHeaderGeneric<BareSpecifics>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{format_comment, parse_comment, CommentHeader, CommentList};
+
+    #[test]
+    fn bare_block_round_trip() -> Result<(), Error> {
+        let mut header = BareCommentHeader::default();
+        header.set_vendor("vendor");
+        header.push("TITLE", "A title")?;
+        let mut data = Vec::new();
+        header.serialize_into(&mut data)?;
+        let expected: &[u8] = b"\x06\x00\x00\x00vendor\x01\x00\x00\x00\x0d\x00\x00\x00TITLE=A title";
+        assert_eq!(data, expected);
+        assert_eq!(BareCommentHeader::try_parse(&data)?, header);
+        Ok(())
+    }
+
+    #[test]
+    fn format_is_inverse_of_parse() -> Result<(), Error> {
+        let comment = format_comment("ARTIST", "Someone = Else")?;
+        assert_eq!(comment, "ARTIST=Someone = Else");
+        assert_eq!(parse_comment(&comment)?, ("ARTIST", "Someone = Else"));
+        assert!(format_comment("BAD=KEY", "value").is_err());
+        Ok(())
+    }
+}
@@ -1,21 +1,31 @@
-use std::io::Write;
+use std::io::{self, Write};
 
-use crate::header::{CommentList, DiscreteCommentList};
+use crate::header::{CommentList, DiscreteCommentList, ParseLimit, ParseLimits};
 use crate::Error;
 
 /// Trait for codec comment headers
 pub trait CommentHeader: CommentList {
     /// Attempts to parse the supplied slice as a comment header. An error
     /// is returned if the header is believed to be corrupt, otherwise the
-    /// parsed header is returned.
+    /// parsed header is returned. The default `ParseLimits` are applied.
     fn try_parse(data: &[u8]) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        Self::try_parse_with_limits(data, &ParseLimits::default())
+    }
+
+    /// Like `try_parse`, but fails with `Error::ParseLimitExceeded` if the
+    /// header exceeds any of `limits`
+    fn try_parse_with_limits(data: &[u8], limits: &ParseLimits) -> Result<Self, Error>
     where
         Self: Sized;
 
     /// Sets the vendor field.
     fn set_vendor(&mut self, vendor: &str);
 
-    /// Returns the comments in the header as a `DiscreteCommentList`.
+    /// Returns the comments in the header as a `DiscreteCommentList`, including
+    /// the vendor string.
     fn to_discrete_comment_list(&self) -> DiscreteCommentList;
 
     /// Gets the vendor field.
@@ -23,4 +33,48 @@ pub trait CommentHeader: CommentList {
 
     /// Writes the serialized header
     fn serialize_into<W: Write>(&self, writer: &mut W) -> Result<(), Error>;
+
+    /// Checks that the serialized header would be accepted by a reader
+    /// applying `limits`, failing with `Error::CommentHeaderTooLarge` if the
+    /// number of comments, the length of the vendor string or any comment, or
+    /// the total size of the header exceeds them
+    fn check_limits(&self, limits: &ParseLimits) -> Result<(), Error> {
+        let mut counter = ByteCounter(0);
+        self.serialize_into(&mut counter)?;
+        check_serialized_limits(self, counter.0, limits)
+    }
+}
+
+/// Checks `header`, which is `serialized_size` bytes long once serialized,
+/// against `limits` as `CommentHeader::check_limits()` does
+pub(crate) fn check_serialized_limits<C>(header: &C, serialized_size: usize, limits: &ParseLimits) -> Result<(), Error>
+where
+    C: CommentHeader + ?Sized,
+{
+    let invalid_entries = header.invalid_entries();
+    if header.len() + invalid_entries.len() > limits.max_comments {
+        return Err(Error::CommentHeaderTooLarge(ParseLimit::CommentCount));
+    }
+    let comment_lengths = header.iter().map(|(k, v)| k.len() + v.len() + 1);
+    let invalid_lengths = invalid_entries.iter().map(|comment| comment.as_bytes().len());
+    let vendor_length = std::iter::once(header.get_vendor().len());
+    if vendor_length.chain(comment_lengths).chain(invalid_lengths).any(|len| len > limits.max_comment_len) {
+        return Err(Error::CommentHeaderTooLarge(ParseLimit::CommentLength));
+    }
+    if serialized_size > limits.max_header_size {
+        return Err(Error::CommentHeaderTooLarge(ParseLimit::HeaderSize));
+    }
+    Ok(())
+}
+
+/// A `Write` which discards its input, counting the bytes written
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 = self.0.saturating_add(buf.len());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
 }
@@ -4,7 +4,10 @@ use std::io::{Cursor, Read, Write};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use derivative::Derivative;
 
-use crate::header::{parse_comment, CommentList, DiscreteCommentList};
+use crate::header::{
+    parse_comment, CommentEntry, CommentList, DiscreteCommentList, InvalidComment, InvalidCommentReason, ParseLimit,
+    ParseLimits, Utf8Policy,
+};
 use crate::{header, Error, FIELD_NAME_TERMINATOR};
 
 /// Implementation-specific details of comment headers (Opus versus Vorbis)
@@ -31,27 +34,80 @@ pub struct CommentHeaderGeneric<S> {
     specifics: S,
 }
 
+impl<S: CommentHeaderSpecifics + Default> CommentHeaderGeneric<S> {
+    /// Constructs a header with the specified vendor string and no comments
+    pub fn empty(vendor: &str) -> CommentHeaderGeneric<S> {
+        CommentHeaderGeneric {
+            vendor: vendor.into(),
+            user_comments: DiscreteCommentList::default(),
+            specifics: S::default(),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "arbitrary"))]
+impl<'a, S: arbitrary::Arbitrary<'a>> arbitrary::Arbitrary<'a> for CommentHeaderGeneric<S> {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<CommentHeaderGeneric<S>> {
+        let vendor = u.arbitrary()?;
+        let mut user_comments: DiscreteCommentList = u.arbitrary()?;
+        // Lists parsed from a header never have a vendor string of their own
+        user_comments.set_vendor(None);
+        Ok(CommentHeaderGeneric { vendor, user_comments, specifics: u.arbitrary()? })
+    }
+}
+
+impl<S> CommentHeaderGeneric<S> {
+    /// Constructs a header from its parsed components
+    pub(crate) fn from_parts(
+        vendor: String, user_comments: DiscreteCommentList, specifics: S,
+    ) -> CommentHeaderGeneric<S> {
+        CommentHeaderGeneric { vendor, user_comments, specifics }
+    }
+
+    /// The format-specific state of the header
+    pub(crate) fn specifics(&self) -> &S { &self.specifics }
+}
+
 impl<S: CommentHeaderSpecifics + Default> header::CommentHeader for CommentHeaderGeneric<S> {
-    fn try_parse(data: &[u8]) -> Result<CommentHeaderGeneric<S>, Error> {
+    fn try_parse_with_limits(data: &[u8], limits: &ParseLimits) -> Result<CommentHeaderGeneric<S>, Error> {
         let magic = S::get_magic();
         let identical = data.iter().take(magic.len()).eq(magic.iter());
         if !identical {
             return Err(Error::MalformedCommentHeader);
         }
+        if data.len() > limits.max_header_size {
+            return Err(Error::ParseLimitExceeded(ParseLimit::HeaderSize));
+        }
         let mut reader = Cursor::new(&data[magic.len()..]);
-        let vendor_len = Self::read_length(&mut reader)?;
-        let mut vendor = vec![0u8; vendor_len as usize];
-        Self::read_exact(&mut reader, &mut vendor)?;
-        let vendor = String::from_utf8(vendor)?;
-        let num_comments = Self::read_length(&mut reader)?;
-        let mut user_comments = DiscreteCommentList::with_capacity(num_comments as usize);
+        let vendor = String::from_utf8(read_length_prefixed_limited(&mut reader, limits.max_comment_len)?)?;
+        let num_comments = reader.read_u32::<LittleEndian>().map_err(|_| Error::MalformedCommentHeader)?;
+        if num_comments as usize > limits.max_comments {
+            return Err(Error::ParseLimitExceeded(ParseLimit::CommentCount));
+        }
+        // Each comment occupies at least four bytes so this bounds the allocation
+        let capacity = std::cmp::min(num_comments as usize, data.len() / 4);
+        let mut user_comments = DiscreteCommentList::with_capacity(capacity);
         for _ in 0..num_comments {
-            let comment_len = Self::read_length(&mut reader)?;
-            let mut comment = vec![0u8; comment_len as usize];
-            Self::read_exact(&mut reader, &mut comment)?;
-            let comment = String::from_utf8(comment)?;
-            let (key, value) = parse_comment(&comment)?;
-            user_comments.push(key, value)?;
+            let comment = read_length_prefixed_limited(&mut reader, limits.max_comment_len)?;
+            // A single malformed comment should not prevent the rest of the header from
+            // being used
+            let comment = match String::from_utf8(comment) {
+                Ok(comment) => comment,
+                Err(e) => {
+                    user_comments.push_invalid(InvalidComment::new(e.into_bytes(), InvalidCommentReason::InvalidUtf8));
+                    continue;
+                }
+            };
+            match parse_comment(&comment) {
+                Ok((key, value)) => user_comments.push(key, value)?,
+                Err(e) => {
+                    let reason = match e {
+                        Error::MissingCommentSeparator => InvalidCommentReason::MissingSeparator,
+                        _ => InvalidCommentReason::InvalidFieldName,
+                    };
+                    user_comments.push_invalid(InvalidComment::new(comment.into_bytes(), reason));
+                }
+            }
         }
         let mut specifics = S::default();
         specifics.read_suffix(&mut reader)?;
@@ -61,15 +117,21 @@ impl<S: CommentHeaderSpecifics + Default> header::CommentHeader for CommentHeade
 
     fn serialize_into<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
         writer.write_all(&S::get_magic()).map_err(Error::WriteError)?;
-        let vendor = self.vendor.as_bytes();
-        let vendor_len = vendor.len().try_into().map_err(|_| Error::UnrepresentableValueInCommentHeader)?;
-        writer.write_u32::<LittleEndian>(vendor_len).map_err(Error::WriteError)?;
-        writer.write_all(vendor).map_err(Error::WriteError)?;
-        let user_comments_len =
-            self.user_comments.len().try_into().map_err(|_| Error::UnrepresentableValueInCommentHeader)?;
+        write_length_prefixed(writer, self.vendor.as_bytes())?;
+        let user_comments_len = (self.user_comments.len() + self.user_comments.invalid_entries().len())
+            .try_into()
+            .map_err(|_| Error::UnrepresentableValueInCommentHeader)?;
         writer.write_u32::<LittleEndian>(user_comments_len).map_err(Error::WriteError)?;
         let field_name_terminator = [FIELD_NAME_TERMINATOR];
-        for (k, v) in self.user_comments.iter().map(|(k, v)| (k.as_bytes(), v.as_bytes())) {
+        // Preserved invalid entries are written in their original positions
+        for entry in self.user_comments.entries() {
+            let (k, v) = match entry {
+                CommentEntry::Valid(k, v) => (k.as_bytes(), v.as_bytes()),
+                CommentEntry::Invalid(comment) => {
+                    write_length_prefixed(writer, comment.as_bytes())?;
+                    continue;
+                }
+            };
             let comment_len = k.len() + v.len() + 1;
             let comment_len = comment_len.try_into().map_err(|_| Error::UnrepresentableValueInCommentHeader)?;
             writer.write_u32::<LittleEndian>(comment_len).map_err(Error::WriteError)?;
@@ -85,21 +147,48 @@ impl<S: CommentHeaderSpecifics + Default> header::CommentHeader for CommentHeade
 
     fn get_vendor(&self) -> &str { self.vendor.as_str() }
 
-    fn to_discrete_comment_list(&self) -> DiscreteCommentList { self.user_comments.clone() }
+    fn to_discrete_comment_list(&self) -> DiscreteCommentList {
+        let mut comments = self.user_comments.clone();
+        comments.set_vendor(Some(self.vendor.clone()));
+        comments
+    }
 }
 
-impl<S> CommentHeaderGeneric<S> {
-    fn read_length<R: Read>(mut reader: R) -> Result<u32, Error> {
-        reader.read_u32::<LittleEndian>().map_err(|_| Error::MalformedCommentHeader)
-    }
+/// Reads a byte string preceded by its length as a 32-bit little-endian
+/// integer, the encoding used for the vendor string and each comment in a
+/// comment header
+pub fn read_length_prefixed<R: Read>(reader: &mut R) -> Result<Vec<u8>, Error> {
+    read_length_prefixed_limited(reader, usize::MAX)
+}
 
-    fn read_exact<R: Read>(mut reader: R, data: &mut [u8]) -> Result<(), Error> {
-        reader.read_exact(data).map_err(|_| Error::MalformedCommentHeader)
+/// Like `read_length_prefixed`, but fails if the length exceeds `max_len`
+fn read_length_prefixed_limited<R: Read>(reader: &mut R, max_len: usize) -> Result<Vec<u8>, Error> {
+    let len = reader.read_u32::<LittleEndian>().map_err(|_| Error::MalformedCommentHeader)?;
+    if len as usize > max_len {
+        return Err(Error::ParseLimitExceeded(ParseLimit::CommentLength));
+    }
+    let mut data = Vec::new();
+    reader.take(u64::from(len)).read_to_end(&mut data).map_err(|_| Error::MalformedCommentHeader)?;
+    if data.len() == len as usize {
+        Ok(data)
+    } else {
+        Err(Error::MalformedCommentHeader)
     }
 }
 
+/// Writes a byte string preceded by its length as a 32-bit little-endian
+/// integer
+pub fn write_length_prefixed<W: Write>(writer: &mut W, data: &[u8]) -> Result<(), Error> {
+    let len = data.len().try_into().map_err(|_| Error::UnrepresentableValueInCommentHeader)?;
+    writer.write_u32::<LittleEndian>(len).map_err(Error::WriteError)?;
+    writer.write_all(data).map_err(Error::WriteError)
+}
+
 impl<S: CommentHeaderSpecifics> CommentList for CommentHeaderGeneric<S> {
-    type Iter<'b> = <DiscreteCommentList as CommentList>::Iter<'b> where Self: 'b;
+    type Iter<'b>
+        = <DiscreteCommentList as CommentList>::Iter<'b>
+    where
+        Self: 'b;
 
     fn len(&self) -> usize { self.user_comments.len() }
 
@@ -118,6 +207,16 @@ impl<S: CommentHeaderSpecifics> CommentList for CommentHeaderGeneric<S> {
     fn iter(&self) -> Self::Iter<'_> { self.user_comments.iter() }
 
     fn retain<F: FnMut(&str, &str) -> bool>(&mut self, f: F) { self.user_comments.retain(f) }
+
+    fn invalid_entries(&self) -> &[InvalidComment] { self.user_comments.invalid_entries() }
+
+    fn remove_invalid_entries(&mut self) { self.user_comments.remove_invalid_entries() }
+
+    fn push_invalid(&mut self, entry: InvalidComment) { self.user_comments.push_invalid(entry) }
+
+    fn convert_invalid_utf8(&mut self, policy: Utf8Policy) -> Result<usize, Error> {
+        self.user_comments.convert_invalid_utf8(policy)
+    }
 }
 
 #[cfg(test)]
@@ -181,12 +280,93 @@ mod tests {
         assert!(CommentHeaderTest::try_parse(&header).is_err());
     }
 
+    #[test]
+    fn invalid_entries_are_preserved() -> Result<(), Error> {
+        let mut data = TEST_MAGIC.to_vec();
+        let vendor = b"vendor";
+        let comments: [&[u8]; 4] = [b"KEY=value", b"no separator", b"KEY2=\xFF\xFE", b"KEY3=value3"];
+        data.write_u32::<LittleEndian>(vendor.len().try_into().unwrap()).unwrap();
+        data.extend(vendor);
+        data.write_u32::<LittleEndian>(comments.len().try_into().unwrap()).unwrap();
+        for comment in comments {
+            data.write_u32::<LittleEndian>(comment.len().try_into().unwrap()).unwrap();
+            data.extend(comment);
+        }
+        data.extend(TEST_SUFFIX);
+
+        let mut header = CommentHeaderTest::try_parse(&data)?;
+        assert_eq!(header.len(), 2);
+        assert_eq!(header.get_first("KEY3"), Some("value3"));
+        let invalid = header.invalid_entries();
+        assert_eq!(invalid.len(), 2);
+        assert_eq!(invalid[0].as_bytes(), b"no separator");
+        assert_eq!(invalid[0].reason(), InvalidCommentReason::MissingSeparator);
+        assert_eq!(invalid[1].as_bytes(), b"KEY2=\xFF\xFE");
+        assert_eq!(invalid[1].reason(), InvalidCommentReason::InvalidUtf8);
+
+        // Invalid entries survive a round-trip in their original positions
+        assert_eq!(comment_header_as_vec(&header)?, data);
+        let reparsed = CommentHeaderTest::try_parse(&comment_header_as_vec(&header)?)?;
+        assert_eq!(reparsed.invalid_entries(), header.invalid_entries());
+        assert_eq!(reparsed.len(), 2);
+
+        // And can be dropped
+        header.remove_invalid_entries();
+        let reparsed = CommentHeaderTest::try_parse(&comment_header_as_vec(&header)?)?;
+        assert!(reparsed.invalid_entries().is_empty());
+        assert_eq!(reparsed.len(), 2);
+        Ok(())
+    }
+
     #[test]
     fn truncated_header() {
         let header = TEST_MAGIC.to_vec();
         match CommentHeaderTest::try_parse(&header) {
             Err(Error::MalformedCommentHeader) => {}
             _ => panic!("Wrong error for malformed header"),
-        };
+        }
+    }
+
+    #[test]
+    fn parse_limits_enforced() -> Result<(), Error> {
+        let mut header = CommentHeaderTest::default();
+        header.set_vendor("vendor");
+        header.push("TITLE", "A title")?;
+        header.push("ARTIST", "An artist")?;
+        let data = comment_header_as_vec(&header)?;
+        assert_eq!(CommentHeaderTest::try_parse_with_limits(&data, &ParseLimits::UNLIMITED)?.len(), 2);
+
+        let limits = ParseLimits { max_comments: 1, ..ParseLimits::UNLIMITED };
+        let result = CommentHeaderTest::try_parse_with_limits(&data, &limits);
+        assert!(matches!(result, Err(Error::ParseLimitExceeded(ParseLimit::CommentCount))));
+
+        let limits = ParseLimits { max_comment_len: 13, ..ParseLimits::UNLIMITED };
+        let result = CommentHeaderTest::try_parse_with_limits(&data, &limits);
+        assert!(matches!(result, Err(Error::ParseLimitExceeded(ParseLimit::CommentLength))));
+
+        let limits = ParseLimits { max_header_size: data.len() - 1, ..ParseLimits::UNLIMITED };
+        let result = CommentHeaderTest::try_parse_with_limits(&data, &limits);
+        assert!(matches!(result, Err(Error::ParseLimitExceeded(ParseLimit::HeaderSize))));
+        Ok(())
+    }
+
+    #[test]
+    fn write_limits_checked() -> Result<(), Error> {
+        let mut header = CommentHeaderTest::default();
+        header.set_vendor("vendor");
+        header.push("TITLE", "A title")?;
+        header.push("ARTIST", "An artist")?;
+        let data = comment_header_as_vec(&header)?;
+        header.check_limits(&ParseLimits { max_header_size: data.len(), ..ParseLimits::UNLIMITED })?;
+
+        let limits = ParseLimits { max_comments: 1, ..ParseLimits::UNLIMITED };
+        assert!(matches!(header.check_limits(&limits), Err(Error::CommentHeaderTooLarge(ParseLimit::CommentCount))));
+
+        let limits = ParseLimits { max_comment_len: 13, ..ParseLimits::UNLIMITED };
+        assert!(matches!(header.check_limits(&limits), Err(Error::CommentHeaderTooLarge(ParseLimit::CommentLength))));
+
+        let limits = ParseLimits { max_header_size: data.len() - 1, ..ParseLimits::UNLIMITED };
+        assert!(matches!(header.check_limits(&limits), Err(Error::CommentHeaderTooLarge(ParseLimit::HeaderSize))));
+        Ok(())
     }
 }
@@ -1,7 +1,7 @@
 use std::borrow::Cow;
 use std::io::{self, Write};
 
-use crate::header::FixedPointGain;
+use crate::header::{FixedPointGain, InvalidComment, InvalidCommentReason, Utf8Policy};
 use crate::{escaping, Error, FIELD_NAME_TERMINATOR};
 
 /// Provides functionality for manipulating comment lists
@@ -16,7 +16,7 @@ pub trait CommentList {
     /// Does the header contain any user comments?
     fn is_empty(&self) -> bool { self.len() == 0 }
 
-    /// Removes all items
+    /// Removes all items, including invalid entries
     fn clear(&mut self);
 
     /// Returns the first mapped value for the specified key.
@@ -39,6 +39,63 @@ pub trait CommentList {
     /// Retain only the key value mappings for which the predicate returns true
     fn retain<F: FnMut(&str, &str) -> bool>(&mut self, f: F);
 
+    /// Entries which could not be parsed as key-value mappings. These are not
+    /// visible via the other accessors but are preserved unless removed.
+    fn invalid_entries(&self) -> &[InvalidComment];
+
+    /// Removes all entries which could not be parsed as key-value mappings
+    fn remove_invalid_entries(&mut self);
+
+    /// Records an entry which could not be parsed as a key-value mapping
+    fn push_invalid(&mut self, entry: InvalidComment);
+
+    /// Returns the raw bytes of the first value for the specified key. Unlike
+    /// `get_first()`, this also finds values which are not valid UTF-8, though
+    /// only after any valid values.
+    fn get_first_raw(&self, key: &str) -> Option<&[u8]> {
+        self.get_first(key).map(str::as_bytes).or_else(|| {
+            let mut mappings = self.invalid_entries().iter().filter_map(InvalidComment::raw_mapping);
+            mappings.find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v)
+        })
+    }
+
+    /// Appends the specified mapping with a value which need not be valid
+    /// UTF-8. Such a value is written unchanged, but is only visible as an
+    /// invalid entry and through `get_first_raw()`.
+    fn push_raw(&mut self, key: &str, value: &[u8]) -> Result<(), Error> {
+        if let Ok(value) = std::str::from_utf8(value) {
+            return self.push(key, value);
+        }
+        let mut data = format_comment(key, "")?.into_bytes();
+        data.extend_from_slice(value);
+        self.push_invalid(InvalidComment::new(data, InvalidCommentReason::InvalidUtf8));
+        Ok(())
+    }
+
+    /// Applies `policy` to the entries whose values are not valid UTF-8,
+    /// returning the number which became ordinary comments
+    fn convert_invalid_utf8(&mut self, policy: Utf8Policy) -> Result<usize, Error> {
+        let is_invalid_utf8 = |entry: &InvalidComment| entry.reason() == InvalidCommentReason::InvalidUtf8;
+        match policy {
+            Utf8Policy::Preserve | Utf8Policy::Strict => check_invalid_utf8(self.invalid_entries(), policy).map(|()| 0),
+            Utf8Policy::Lossy => {
+                let entries = self.invalid_entries().to_vec();
+                self.remove_invalid_entries();
+                let mut converted = 0;
+                for entry in entries {
+                    match entry.raw_mapping().filter(|_| is_invalid_utf8(&entry)) {
+                        Some((key, value)) => {
+                            self.push(key, &String::from_utf8_lossy(value))?;
+                            converted += 1;
+                        }
+                        None => self.push_invalid(entry),
+                    }
+                }
+                Ok(converted)
+            }
+        }
+    }
+
     /// Write each comment in the user-friendly textual representation
     fn write_as_text<W: Write>(&self, mut writer: W, escape: bool) -> Result<(), io::Error> {
         for (k, v) in self.iter() {
@@ -91,6 +148,17 @@ pub fn parse_comment(comment: &str) -> Result<(&str, &str), Error> {
     Ok((key, &value[1..]))
 }
 
+/// Produces the textual representation of an Opus comment from a field name and
+/// value. This is the inverse of `parse_comment`.
+pub fn format_comment(key: &str, value: &str) -> Result<String, Error> {
+    validate_comment_field_name(key)?;
+    let mut comment = String::with_capacity(key.len() + value.len() + 1);
+    comment.push_str(key);
+    comment.push(char::from(FIELD_NAME_TERMINATOR));
+    comment.push_str(value);
+    Ok(comment)
+}
+
 /// Validates the field name of a comment
 pub fn validate_comment_field_name(field_name: &str) -> Result<(), Error> {
     for c in field_name.chars() {
@@ -101,3 +169,14 @@ pub fn validate_comment_field_name(field_name: &str) -> Result<(), Error> {
     }
     Ok(())
 }
+
+/// Fails if `policy` is `Utf8Policy::Strict` and any of `entries` is not valid
+/// UTF-8
+pub(crate) fn check_invalid_utf8(entries: &[InvalidComment], policy: Utf8Policy) -> Result<(), Error> {
+    if policy == Utf8Policy::Strict {
+        for entry in entries.iter().filter(|entry| entry.reason() == InvalidCommentReason::InvalidUtf8) {
+            String::from_utf8(entry.as_bytes().to_vec())?;
+        }
+    }
+    Ok(())
+}
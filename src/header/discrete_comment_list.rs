@@ -1,12 +1,26 @@
 use std::sync::Arc;
 
-use crate::header::{validate_comment_field_name, CommentList};
+use crate::header::{
+    check_invalid_utf8, validate_comment_field_name, CommentList, InvalidComment, InvalidCommentReason, Utf8Policy,
+};
 use crate::Error;
 
 /// Stand-alone representation of an Ogg Opus comment list
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct DiscreteCommentList {
     comments: Vec<(Arc<String>, Arc<String>)>,
+    invalid: Vec<InvalidComment>,
+    // The number of valid comments preceding each invalid entry
+    invalid_positions: Vec<usize>,
+    vendor: Option<String>,
+}
+
+/// A single entry of a `DiscreteCommentList`, whether or not it is a valid
+/// key-value mapping
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum CommentEntry<'a> {
+    Valid(&'a str, &'a str),
+    Invalid(&'a InvalidComment),
 }
 
 impl DiscreteCommentList {
@@ -14,15 +28,85 @@ impl DiscreteCommentList {
 
     /// Allocates a list with the specified capacity
     pub fn with_capacity(cap: usize) -> DiscreteCommentList {
-        DiscreteCommentList { comments: Vec::with_capacity(cap) }
+        DiscreteCommentList {
+            comments: Vec::with_capacity(cap),
+            invalid: Vec::new(),
+            invalid_positions: Vec::new(),
+            vendor: None,
+        }
+    }
+
+    /// Appends all comments from the other list, leaving it empty. The vendor
+    /// string of the other list is taken if this list does not have one.
+    pub fn append(&mut self, other: &mut DiscreteCommentList) {
+        let offset = self.comments.len();
+        self.invalid_positions.extend(other.invalid_positions.drain(..).map(|position| position + offset));
+        self.comments.append(&mut other.comments);
+        self.invalid.append(&mut other.invalid);
+        if self.vendor.is_none() {
+            self.vendor = other.vendor.take();
+        }
     }
 
-    /// Appends all comments from the other list, leaving it empty
-    pub fn append(&mut self, other: &mut DiscreteCommentList) { self.comments.append(&mut other.comments); }
+    /// The vendor string of the comment header the list was taken from. When
+    /// the list is used to rewrite a comment header, a vendor string which is
+    /// set replaces that of the header.
+    pub fn vendor(&self) -> Option<&str> { self.vendor.as_deref() }
+
+    /// Sets or clears the vendor string
+    pub fn set_vendor(&mut self, vendor: Option<String>) { self.vendor = vendor; }
+
+    /// Iterates over all entries, including invalid ones, in their original
+    /// order
+    pub(crate) fn entries(&self) -> internal::Entries<'_> {
+        internal::Entries {
+            comments: self.comments.iter(),
+            invalid: self.invalid.iter().zip(self.invalid_positions.iter().copied()).peekable(),
+            position: 0,
+        }
+    }
+
+    /// Retains the comments for which `f` returns true, keeping invalid entries
+    /// in place relative to the remaining comments
+    fn retain_comments<F: FnMut(&mut (Arc<String>, Arc<String>)) -> bool>(&mut self, mut f: F) {
+        let mut retained_before = Vec::with_capacity(self.comments.len() + 1);
+        retained_before.push(0);
+        self.comments.retain_mut(|comment| {
+            let retained = f(comment);
+            let count = retained_before.last().copied().unwrap_or_default();
+            retained_before.push(count + usize::from(retained));
+            retained
+        });
+        for position in &mut self.invalid_positions {
+            *position = retained_before[*position];
+        }
+    }
+}
+
+/// Generates lists whose comments all have valid field names
+#[cfg(any(test, feature = "arbitrary"))]
+impl<'a> arbitrary::Arbitrary<'a> for DiscreteCommentList {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<DiscreteCommentList> {
+        let mut comments = Vec::new();
+        for _ in 0..u.arbitrary_len::<(String, String)>()? {
+            let key = u.arbitrary::<String>()?.chars().filter(|c| matches!(c, ' '..='<' | '>'..='}')).collect();
+            let value: String = u.arbitrary()?;
+            comments.push((Arc::new(key), Arc::new(value)));
+        }
+        let invalid: Vec<InvalidComment> = u.arbitrary()?;
+        let mut invalid_positions = Vec::with_capacity(invalid.len());
+        for _ in 0..invalid.len() {
+            invalid_positions.push(u.int_in_range(0..=comments.len())?);
+        }
+        invalid_positions.sort_unstable();
+        Ok(DiscreteCommentList { comments, invalid, invalid_positions, vendor: u.arbitrary()? })
+    }
 }
 
 mod internal {
-    use super::Arc;
+    use std::iter::{Copied, Peekable, Zip};
+
+    use super::{Arc, CommentEntry, InvalidComment};
 
     /// Iterator for `DiscreteCommentList`
     pub struct Iter<'a> {
@@ -34,6 +118,26 @@ mod internal {
 
         fn next(&mut self) -> Option<Self::Item> { self.inner.next().map(|(k, v)| (k.as_str(), v.as_str())) }
     }
+
+    /// Iterator over all entries of `DiscreteCommentList`
+    pub(crate) struct Entries<'a> {
+        pub(super) comments: std::slice::Iter<'a, (Arc<String>, Arc<String>)>,
+        pub(super) invalid: Peekable<Zip<std::slice::Iter<'a, InvalidComment>, Copied<std::slice::Iter<'a, usize>>>>,
+        pub(super) position: usize,
+    }
+
+    impl<'a> Iterator for Entries<'a> {
+        type Item = CommentEntry<'a>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if let Some((entry, _)) = self.invalid.next_if(|(_, position)| *position <= self.position) {
+                return Some(CommentEntry::Invalid(entry));
+            }
+            let (k, v) = self.comments.next()?;
+            self.position += 1;
+            Some(CommentEntry::Valid(k, v))
+        }
+    }
 }
 
 impl CommentList for DiscreteCommentList {
@@ -43,17 +147,20 @@ impl CommentList for DiscreteCommentList {
 
     fn is_empty(&self) -> bool { self.comments.is_empty() }
 
-    fn clear(&mut self) { self.comments.clear() }
+    fn clear(&mut self) {
+        self.comments.clear();
+        self.remove_invalid_entries();
+    }
 
     fn get_first(&self, key: &str) -> Option<&str> {
         self.comments.iter().find(|(k, _)| Self::keys_equal(k, key)).map(|(_, v)| v.as_str())
     }
 
-    fn remove_all(&mut self, key: &str) { self.comments.retain(|(k, _)| !Self::keys_equal(key, k)); }
+    fn remove_all(&mut self, key: &str) { self.retain_comments(|(k, _)| !Self::keys_equal(key, k)); }
 
     fn replace(&mut self, key: &str, value: &str) -> Result<(), Error> {
         let mut found = false;
-        self.comments.retain_mut(|(k, ref mut v)| {
+        self.retain_comments(|(k, ref mut v)| {
             if Self::keys_equal(k, key) {
                 if found {
                     // If we have already found the key, discard this mapping
@@ -83,12 +190,51 @@ impl CommentList for DiscreteCommentList {
 
     fn iter(&self) -> Self::Iter<'_> { Self::Iter { inner: self.comments.iter() } }
 
-    fn retain<F: FnMut(&str, &str) -> bool>(&mut self, mut f: F) { self.comments.retain(|(k, v)| f(k, v)); }
+    fn retain<F: FnMut(&str, &str) -> bool>(&mut self, mut f: F) { self.retain_comments(|(k, v)| f(k, v)); }
+
+    fn invalid_entries(&self) -> &[InvalidComment] { &self.invalid }
+
+    fn remove_invalid_entries(&mut self) {
+        self.invalid.clear();
+        self.invalid_positions.clear();
+    }
+
+    fn push_invalid(&mut self, entry: InvalidComment) {
+        self.invalid.push(entry);
+        self.invalid_positions.push(self.comments.len());
+    }
+
+    fn convert_invalid_utf8(&mut self, policy: Utf8Policy) -> Result<usize, Error> {
+        if policy != Utf8Policy::Lossy {
+            return check_invalid_utf8(&self.invalid, policy).map(|()| 0);
+        }
+        // Rebuild the list so that converted entries keep their positions
+        let mut result = DiscreteCommentList::with_capacity(self.comments.len() + self.invalid.len());
+        let mut converted = 0;
+        for entry in self.entries() {
+            match entry {
+                CommentEntry::Valid(key, value) => result.push(key, value)?,
+                CommentEntry::Invalid(entry) => {
+                    match entry.raw_mapping().filter(|_| entry.reason() == InvalidCommentReason::InvalidUtf8) {
+                        Some((key, value)) => {
+                            result.push(key, &String::from_utf8_lossy(value))?;
+                            converted += 1;
+                        }
+                        None => result.push_invalid(entry.clone()),
+                    }
+                }
+            }
+        }
+        result.vendor = self.vendor.take();
+        *self = result;
+        Ok(converted)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::header::{InvalidCommentReason, Utf8Policy};
 
     #[test]
     fn replace_pushes_on_missing() -> Result<(), Error> {
@@ -178,4 +324,61 @@ mod tests {
         assert_eq!(list_1, list_2);
         Ok(())
     }
+
+    #[test]
+    fn raw_values_accessible() -> Result<(), Error> {
+        let mut list = DiscreteCommentList::default();
+        list.push_raw("TITLE", b"Caf\xe9")?;
+        list.push_raw("ARTIST", "Caf\u{e9}".as_bytes())?;
+        list.push_invalid(InvalidComment::new(b"no separator".to_vec(), InvalidCommentReason::MissingSeparator));
+        assert!(list.push_raw("BAD=KEY", b"\xff").is_err());
+        assert_eq!(list.get_first("TITLE"), None);
+        assert_eq!(list.get_first_raw("title"), Some(&b"Caf\xe9"[..]));
+        assert_eq!(list.get_first_raw("ARTIST"), Some("Caf\u{e9}".as_bytes()));
+        assert_eq!(list.invalid_entries().len(), 2);
+
+        assert!(matches!(list.convert_invalid_utf8(Utf8Policy::Strict), Err(Error::UTF8Error(_))));
+        assert_eq!(list.convert_invalid_utf8(Utf8Policy::Preserve)?, 0);
+        assert_eq!(list.convert_invalid_utf8(Utf8Policy::Lossy)?, 1);
+        assert_eq!(list.get_first("TITLE"), Some("Caf\u{fffd}"));
+        assert_eq!(list.invalid_entries().len(), 1);
+        assert_eq!(list.convert_invalid_utf8(Utf8Policy::Strict)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_entries_keep_positions() -> Result<(), Error> {
+        let invalid = |data: &[u8]| InvalidComment::new(data.to_vec(), InvalidCommentReason::MissingSeparator);
+        let mut list = DiscreteCommentList::default();
+        list.push_invalid(invalid(b"first"));
+        list.push("A", "1")?;
+        list.push("B", "2")?;
+        list.push_invalid(invalid(b"second"));
+        list.push("A", "3")?;
+        list.push_raw("C", b"\xff")?;
+        list.remove_all("a");
+        list.replace("B", "4")?;
+        list.push("D", "5")?;
+
+        let mut other = DiscreteCommentList::default();
+        other.push("E", "6")?;
+        other.push_invalid(invalid(b"third"));
+        list.append(&mut other);
+        assert_eq!(list.convert_invalid_utf8(Utf8Policy::Lossy)?, 1);
+
+        let entries: Vec<_> = list.entries().collect();
+        assert_eq!(
+            entries,
+            vec![
+                CommentEntry::Invalid(&invalid(b"first")),
+                CommentEntry::Valid("B", "4"),
+                CommentEntry::Invalid(&invalid(b"second")),
+                CommentEntry::Valid("C", "\u{fffd}"),
+                CommentEntry::Valid("D", "5"),
+                CommentEntry::Valid("E", "6"),
+                CommentEntry::Invalid(&invalid(b"third")),
+            ]
+        );
+        Ok(())
+    }
 }
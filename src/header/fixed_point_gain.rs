@@ -35,6 +35,13 @@ impl FixedPointGain {
     }
 }
 
+#[cfg(any(test, feature = "arbitrary"))]
+impl<'a> arbitrary::Arbitrary<'a> for FixedPointGain {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<FixedPointGain> {
+        Ok(FixedPointGain { value: u.arbitrary()? })
+    }
+}
+
 impl TryFrom<Decibels> for FixedPointGain {
     type Error = Error;
 
@@ -42,7 +49,7 @@ impl TryFrom<Decibels> for FixedPointGain {
         let fixed = (value.as_f64() * 256.0).round();
         #[allow(clippy::cast_possible_truncation)]
         let value = fixed as i16;
-        if (f64::from(value) - fixed).abs() < std::f64::EPSILON {
+        if (f64::from(value) - fixed).abs() < f64::EPSILON {
             Ok(FixedPointGain { value })
         } else {
             Err(Error::GainOutOfBounds)
@@ -79,7 +86,7 @@ mod tests {
 
     #[test]
     fn positive_overflow() {
-        let max_gain = FixedPointGain { value: std::i16::MAX };
+        let max_gain = FixedPointGain { value: i16::MAX };
         let one = FixedPointGain { value: 1 };
         assert_eq!(max_gain.checked_add(one), None);
         assert_eq!(one.checked_add(max_gain), None);
@@ -87,7 +94,7 @@ mod tests {
 
     #[test]
     fn negative_overflow() {
-        let min_gain = FixedPointGain { value: std::i16::MIN };
+        let min_gain = FixedPointGain { value: i16::MIN };
         let neg_one = FixedPointGain { value: -1 };
         assert_eq!(min_gain.checked_add(neg_one), None);
         assert_eq!(neg_one.checked_add(min_gain), None);
@@ -95,13 +102,13 @@ mod tests {
 
     #[test]
     fn negate_lowest_value() {
-        let min_gain = FixedPointGain { value: std::i16::MIN };
+        let min_gain = FixedPointGain { value: i16::MIN };
         assert_eq!(min_gain.checked_neg(), None);
     }
 
     #[test]
     fn decibel_conversion() {
-        for value in std::i16::MIN..=std::i16::MAX {
+        for value in i16::MIN..=i16::MAX {
             let gain = FixedPointGain { value };
             let decibels = gain.as_decibels();
             let gain2 = FixedPointGain::try_from(decibels).unwrap();
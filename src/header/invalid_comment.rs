@@ -0,0 +1,108 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::header::validate_comment_field_name;
+use crate::FIELD_NAME_TERMINATOR;
+
+/// The reason a comment entry could not be interpreted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InvalidCommentReason {
+    /// The entry did not contain a field name terminator
+    MissingSeparator,
+
+    /// The field name contained characters which are not permitted
+    InvalidFieldName,
+
+    /// The entry was not valid UTF-8
+    InvalidUtf8,
+}
+
+impl Display for InvalidCommentReason {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        let description = match self {
+            InvalidCommentReason::MissingSeparator => "missing separator",
+            InvalidCommentReason::InvalidFieldName => "invalid field name",
+            InvalidCommentReason::InvalidUtf8 => "invalid UTF-8",
+        };
+        write!(formatter, "{}", description)
+    }
+}
+
+/// A comment entry from a comment header which could not be parsed as a
+/// key-value mapping. The raw bytes are retained so that the entry can be
+/// reported or written back unchanged.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InvalidComment {
+    data: Vec<u8>,
+    reason: InvalidCommentReason,
+}
+
+impl InvalidComment {
+    /// Constructs an invalid comment from its raw bytes
+    pub fn new(data: Vec<u8>, reason: InvalidCommentReason) -> InvalidComment { InvalidComment { data, reason } }
+
+    /// The raw bytes of the entry
+    pub fn as_bytes(&self) -> &[u8] { &self.data }
+
+    /// Why the entry could not be parsed
+    pub fn reason(&self) -> InvalidCommentReason { self.reason }
+
+    /// The entry as a string, with invalid UTF-8 sequences replaced
+    pub fn to_string_lossy(&self) -> String { String::from_utf8_lossy(&self.data).into_owned() }
+
+    /// The field name and raw value of the entry, if it has a valid field
+    /// name. This is the case for entries whose value is not valid UTF-8.
+    pub fn raw_mapping(&self) -> Option<(&str, &[u8])> {
+        let offset = self.data.iter().position(|&b| b == FIELD_NAME_TERMINATOR)?;
+        let key = std::str::from_utf8(&self.data[..offset]).ok()?;
+        validate_comment_field_name(key).ok()?;
+        Some((key, &self.data[(offset + 1)..]))
+    }
+}
+
+/// Generates entries which are invalid for the reason they record, so that they
+/// are classified identically when parsed back
+#[cfg(any(test, feature = "arbitrary"))]
+impl<'a> arbitrary::Arbitrary<'a> for InvalidComment {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<InvalidComment> {
+        let reason = *u.choose(&[
+            InvalidCommentReason::MissingSeparator,
+            InvalidCommentReason::InvalidFieldName,
+            InvalidCommentReason::InvalidUtf8,
+        ])?;
+        let separator = char::from(FIELD_NAME_TERMINATOR);
+        let data = match reason {
+            InvalidCommentReason::MissingSeparator => u.arbitrary::<String>()?.replace(separator, "").into_bytes(),
+            InvalidCommentReason::InvalidFieldName => {
+                let mut key = u.arbitrary::<String>()?.replace(separator, "");
+                if validate_comment_field_name(&key).is_ok() {
+                    key.push('~');
+                }
+                let value: String = u.arbitrary()?;
+                format!("{}{}{}", key, separator, value).into_bytes()
+            }
+            InvalidCommentReason::InvalidUtf8 => {
+                let mut data: Vec<u8> = u.arbitrary()?;
+                // 0xFF never occurs in UTF-8
+                data.push(0xFF);
+                data
+            }
+        };
+        Ok(InvalidComment { data, reason })
+    }
+}
+
+/// How comment values which are not valid UTF-8 are treated by
+/// `CommentList::convert_invalid_utf8()`
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Utf8Policy {
+    /// Keep such values unchanged as invalid entries
+    #[default]
+    Preserve,
+
+    /// Replace invalid sequences with U+FFFD, turning the entries into
+    /// ordinary comments
+    Lossy,
+
+    /// Fail with `Error::UTF8Error`
+    Strict,
+}
@@ -1,16 +1,22 @@
+mod bare_comment_header;
 mod comment_header;
 mod comment_header_generic;
 mod comment_list;
 mod discrete_comment_list;
 mod fixed_point_gain;
 mod id_header;
+mod invalid_comment;
+mod parse_limits;
 
 #[cfg(test)]
 pub(crate) mod test_utils;
 
+pub use bare_comment_header::*;
 pub use comment_header::*;
 pub use comment_header_generic::*;
 pub use comment_list::*;
 pub use discrete_comment_list::*;
 pub use fixed_point_gain::*;
 pub use id_header::*;
+pub use invalid_comment::*;
+pub use parse_limits::*;
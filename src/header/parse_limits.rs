@@ -0,0 +1,53 @@
+use std::fmt::{Display, Formatter};
+
+/// A resource limit which can be exceeded while parsing a comment header
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseLimit {
+    /// The number of comments
+    CommentCount,
+
+    /// The length of the vendor string or a single comment
+    CommentLength,
+
+    /// The size of the serialized header
+    HeaderSize,
+}
+
+impl Display for ParseLimit {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        let name = match self {
+            ParseLimit::CommentCount => "number of comments",
+            ParseLimit::CommentLength => "comment length",
+            ParseLimit::HeaderSize => "header size",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Limits on the resources used when parsing a comment header, for use when
+/// processing untrusted input. Exceeding any of them causes parsing to fail
+/// with `Error::ParseLimitExceeded`. The defaults are generous enough for
+/// headers containing embedded cover art.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ParseLimits {
+    /// The maximum number of comments, including those which are invalid
+    pub max_comments: usize,
+
+    /// The maximum length in bytes of the vendor string or a single comment
+    pub max_comment_len: usize,
+
+    /// The maximum size in bytes of the serialized header
+    pub max_header_size: usize,
+}
+
+impl ParseLimits {
+    /// Limits which are never exceeded
+    pub const UNLIMITED: ParseLimits =
+        ParseLimits { max_comments: usize::MAX, max_comment_len: usize::MAX, max_header_size: usize::MAX };
+}
+
+impl Default for ParseLimits {
+    fn default() -> ParseLimits {
+        ParseLimits { max_comments: 1 << 20, max_comment_len: 64 << 20, max_header_size: 128 << 20 }
+    }
+}
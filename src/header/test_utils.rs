@@ -1,3 +1,4 @@
+use arbitrary::{Arbitrary, Unstructured};
 use rand::distributions::{Standard, Uniform};
 use rand::Rng;
 
@@ -44,3 +45,10 @@ pub(crate) fn comment_header_as_vec<C: header::CommentHeader>(c: &C) -> Result<V
     c.serialize_into(&mut serialized)?;
     Ok(serialized)
 }
+
+/// Generates a value with `Arbitrary` from random bytes, as a fuzzer would
+pub(crate) fn random_arbitrary<T: for<'a> Arbitrary<'a>, R: Rng>(engine: &mut R) -> T {
+    let len = engine.sample(Uniform::new_inclusive(0, 4 * MAX_STRING_LENGTH));
+    let data: Vec<u8> = engine.sample_iter(&Standard).take(len).collect();
+    T::arbitrary(&mut Unstructured::new(&data)).expect("Unable to generate arbitrary value")
+}
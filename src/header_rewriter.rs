@@ -1,14 +1,22 @@
+use std::cell::Cell;
 use std::collections::VecDeque;
-use std::io::{Read, Seek, Write};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
 use std::marker::PhantomData;
+use std::path::Path;
 
 use derivative::Derivative;
 use ogg::writing::{PacketWriteEndInfo, PacketWriter};
 use ogg::{Packet, PacketReader};
 
-use crate::header::{CommentHeader as _, IdHeader as _};
+use crate::header::{CommentHeader as _, IdHeader as _, ParseLimits};
+use crate::import::take_id3v2_prefix;
 use crate::interrupt::{Interrupt, Never};
-use crate::{header, opus, vorbis, Codec, Error};
+use crate::ogg_page::Page;
+use crate::operation::OperationHandle;
+use crate::output_file::OutputFile;
+use crate::progress::{self, ProgressObserver, RewriteProgress, RewriteStage};
+use crate::{header, opus, vorbis, Codec, CodecFormat, Error, OpusFormat, VorbisFormat};
 
 /// The result of submitting a packet to a `HeaderRewriter`
 #[derive(Debug)]
@@ -66,6 +74,15 @@ impl CodecHeaders {
             CodecHeaders::Vorbis(_, c) => c.serialize_into(writer),
         }
     }
+
+    /// Checks the comment header, which is `serialized_size` bytes long once
+    /// serialized, against `limits` (see `CommentHeader::check_limits`)
+    fn check_comment_header_limits(&self, serialized_size: usize, limits: &ParseLimits) -> Result<(), Error> {
+        match self {
+            CodecHeaders::Opus(_, c) => header::check_serialized_limits(c, serialized_size, limits),
+            CodecHeaders::Vorbis(_, c) => header::check_serialized_limits(c, serialized_size, limits),
+        }
+    }
 }
 
 /// Trait for types used to summarize codec headers
@@ -114,6 +131,67 @@ where
     }
 }
 
+/// A packet of an Ogg stream other than the codec headers being rewritten
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamPacket {
+    /// The content of the packet
+    pub data: Vec<u8>,
+
+    /// The serial of the logical stream the packet belongs to
+    pub serial: u32,
+
+    /// The granule position of the page the packet ends on
+    pub granule_position: u64,
+
+    /// Whether the packet should be the last on its page
+    pub last_in_page: bool,
+
+    /// Whether the packet is the last of its logical stream
+    pub last_in_stream: bool,
+}
+
+impl From<Packet> for StreamPacket {
+    fn from(packet: Packet) -> StreamPacket {
+        StreamPacket {
+            serial: packet.stream_serial(),
+            granule_position: packet.absgp_page(),
+            last_in_page: packet.last_in_page(),
+            last_in_stream: packet.last_in_stream(),
+            data: packet.data,
+        }
+    }
+}
+
+/// Controls how the audio packets of a rewritten Ogg Opus stream are divided
+/// into pages. The default preserves the page boundaries of the input stream.
+/// Otherwise, pages are ended according to these options alone, with the
+/// granule position of each packet derived from the durations of the packets
+/// on its original page.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Pagination {
+    /// End a page once the packet data on it reaches this many bytes
+    pub target_page_size: Option<usize>,
+
+    /// End a page once this many packets have been completed on it
+    pub max_packets_per_page: Option<usize>,
+
+    /// End a page after every packet
+    pub flush_per_packet: bool,
+}
+
+impl Pagination {
+    /// Whether the page boundaries of the input stream are preserved
+    pub fn preserves_pages(&self) -> bool { *self == Pagination::default() }
+
+    /// Whether a page holding `packets` packets and `bytes` bytes of packet
+    /// data should be ended
+    fn page_full(&self, packets: usize, bytes: usize) -> bool {
+        self.flush_per_packet
+            || self.max_packets_per_page.map_or(false, |max| packets >= max)
+            || self.target_page_size.map_or(false, |target| bytes >= target)
+    }
+}
+
 /// Trait for codec header rewriting
 pub trait HeaderRewrite {
     /// Type for errors thrown during header update
@@ -121,6 +199,108 @@ pub trait HeaderRewrite {
 
     /// Rewrites the Opus and Opus comment headers
     fn rewrite(&self, headers: &mut CodecHeaders) -> Result<(), Self::Error>;
+
+    /// Rewrites a packet other than the identification and comment headers.
+    /// The packets appended to `output` are written in place of `packet`, so
+    /// it can be modified, dropped or have further packets inserted around it.
+    /// Packets of all logical streams are supplied, including any further
+    /// codec-specific header packets. The default implementation passes the
+    /// packet through unaltered.
+    ///
+    /// This is only called by `HeaderRewriter`. The page-preserving rewrite
+    /// functions copy everything after the headers verbatim. Since only header
+    /// changes are reported via `SubmitResult`, rewrites altering packets
+    /// should not abort when the headers are unchanged.
+    fn rewrite_packet(&mut self, packet: StreamPacket, output: &mut Vec<StreamPacket>) -> Result<(), Self::Error> {
+        output.push(packet);
+        Ok(())
+    }
+
+    /// Parses the identification and comment header packets, returning the
+    /// headers and whether any damage to them had to be repaired, in which
+    /// case they are treated as changed. The default implementation fails on
+    /// malformed headers. Rewrites which repair damaged headers override this
+    /// to parse them leniently.
+    fn parse_headers(&self, identification: &[u8], comment: &[u8]) -> Result<(CodecHeaders, bool), Error> {
+        parse_codec_headers(identification, comment).map(|headers| (headers, false))
+    }
+
+    /// How the audio packets of the rewritten stream should be divided into
+    /// pages. Like `rewrite_packet()`, this is only honoured by
+    /// `HeaderRewriter` and only for Ogg Opus streams, since the durations of
+    /// Vorbis packets are not known without the codec setup header. The
+    /// default preserves the pages of the input stream.
+    fn pagination(&self) -> Pagination { Pagination::default() }
+
+    /// The limits the rewritten comment header must satisfy, so that readers
+    /// applying them can read it back. Rewriting fails with
+    /// `Error::CommentHeaderTooLarge` if they are exceeded. The default is
+    /// `ParseLimits::default()`.
+    fn comment_header_limits(&self) -> ParseLimits { ParseLimits::default() }
+}
+
+/// Wraps a `HeaderRewrite` to divide the rewritten audio packets into pages
+/// according to a `Pagination`
+#[derive(Debug)]
+pub struct Paginated<HR> {
+    rewrite: HR,
+    pagination: Pagination,
+}
+
+impl<HR: HeaderRewrite> Paginated<HR> {
+    /// Constructs a rewrite which applies `rewrite` and re-paginates the
+    /// stream according to `pagination`
+    pub fn new(rewrite: HR, pagination: Pagination) -> Paginated<HR> { Paginated { rewrite, pagination } }
+}
+
+impl<HR: HeaderRewrite> HeaderRewrite for Paginated<HR> {
+    type Error = HR::Error;
+
+    fn rewrite(&self, headers: &mut CodecHeaders) -> Result<(), Self::Error> { self.rewrite.rewrite(headers) }
+
+    fn rewrite_packet(&mut self, packet: StreamPacket, output: &mut Vec<StreamPacket>) -> Result<(), Self::Error> {
+        self.rewrite.rewrite_packet(packet, output)
+    }
+
+    fn parse_headers(&self, identification: &[u8], comment: &[u8]) -> Result<(CodecHeaders, bool), Error> {
+        self.rewrite.parse_headers(identification, comment)
+    }
+
+    fn pagination(&self) -> Pagination { self.pagination }
+
+    fn comment_header_limits(&self) -> ParseLimits { self.rewrite.comment_header_limits() }
+}
+
+/// Wraps a `HeaderRewrite` to check the rewritten comment header against
+/// different `ParseLimits`
+#[derive(Debug)]
+pub struct Limited<HR> {
+    rewrite: HR,
+    limits: ParseLimits,
+}
+
+impl<HR: HeaderRewrite> Limited<HR> {
+    /// Constructs a rewrite which applies `rewrite` and fails if the rewritten
+    /// comment header exceeds `limits`
+    pub fn new(rewrite: HR, limits: ParseLimits) -> Limited<HR> { Limited { rewrite, limits } }
+}
+
+impl<HR: HeaderRewrite> HeaderRewrite for Limited<HR> {
+    type Error = HR::Error;
+
+    fn rewrite(&self, headers: &mut CodecHeaders) -> Result<(), Self::Error> { self.rewrite.rewrite(headers) }
+
+    fn rewrite_packet(&mut self, packet: StreamPacket, output: &mut Vec<StreamPacket>) -> Result<(), Self::Error> {
+        self.rewrite.rewrite_packet(packet, output)
+    }
+
+    fn parse_headers(&self, identification: &[u8], comment: &[u8]) -> Result<(CodecHeaders, bool), Error> {
+        self.rewrite.parse_headers(identification, comment)
+    }
+
+    fn pagination(&self) -> Pagination { self.rewrite.pagination() }
+
+    fn comment_header_limits(&self) -> ParseLimits { self.limits }
 }
 
 /// Trait for implementing `HeaderRewrite` when different codecs can be treated
@@ -149,6 +329,171 @@ where
     }
 }
 
+/// Parses the headers of a logical stream, dispatching on the codec
+/// identified from its first packet
+fn parse_codec_headers(identification: &[u8], comment: &[u8]) -> Result<CodecHeaders, Error> {
+    match Codec::identify(identification) {
+        Some(Codec::Opus) => OpusFormat::parse_headers(identification, comment),
+        Some(Codec::Vorbis) => VorbisFormat::parse_headers(identification, comment),
+        None => Err(Error::UnknownCodec),
+    }
+}
+
+/// Applies a rewrite to the identification and comment header packets,
+/// replacing their content with the rewritten headers
+fn apply_rewrite<HR, HS, E>(
+    rewrite: &HR, summarize: &HS, id_header_data: &mut Vec<u8>, comment_header_data: &mut Vec<u8>,
+) -> Result<SubmitResult<HS::Summary>, E>
+where
+    HR: HeaderRewrite<Error = E>,
+    HS: HeaderSummarize<Error = E>,
+    E: From<Error>,
+{
+    // Parse headers
+    let (original_headers, repaired) = rewrite.parse_headers(id_header_data, comment_header_data)?;
+    let mut headers = original_headers.clone();
+    let summary_before = summarize.summarize(&headers)?;
+    rewrite.rewrite(&mut headers)?;
+    let summary_after = summarize.summarize(&headers)?;
+
+    // We compare headers rather than the values of the `OpusGains` structs because
+    // using the latter glosses over issues such as duplicate or invalid gain tags
+    // which we will fix if present.
+    let changed = repaired || headers != original_headers;
+    tracing::debug!(codec = %headers.codec(), changed, "Rewrote codec headers");
+    // Update ID header
+    id_header_data.clear();
+    headers.serialize_id_header(id_header_data)?;
+    // Update comment header, refusing to write one which could not be read back
+    comment_header_data.clear();
+    headers.serialize_comment_header(comment_header_data)?;
+    headers.check_comment_header_limits(comment_header_data.len(), &rewrite.comment_header_limits())?;
+
+    Ok(if changed {
+        SubmitResult::HeadersChanged { from: summary_before, to: summary_after }
+    } else {
+        SubmitResult::HeadersUnchanged(summary_before)
+    })
+}
+
+/// Divides the audio packets of a logical stream into pages according to a
+/// `Pagination`. Packets are held until the packet ending their original page
+/// is seen, so that the granule positions of the others can be derived from
+/// the packet durations.
+#[derive(Debug)]
+struct Repaginator {
+    serial: u32,
+    pagination: Pagination,
+    held: Vec<StreamPacket>,
+    last_granule: Option<u64>,
+    page_segments: usize,
+    page_packets: usize,
+    page_bytes: usize,
+}
+
+impl Repaginator {
+    fn new(serial: u32, pagination: Pagination) -> Repaginator {
+        Repaginator {
+            serial,
+            pagination,
+            held: Vec::new(),
+            last_granule: None,
+            page_segments: 0,
+            page_packets: 0,
+            page_bytes: 0,
+        }
+    }
+
+    /// The granule positions of the held packets, or `None` for those which
+    /// cannot be determined. Packets are counted from the previous granule
+    /// position on the final page, which may trim the end of the stream, and
+    /// back from the granule position of their page otherwise, since the first
+    /// page may start at an offset.
+    fn held_granules(&self) -> Vec<Option<u64>> {
+        let Some(last) = self.held.last() else { return Vec::new() };
+        let page_granule = last.granule_position;
+        let durations = self
+            .held
+            .iter()
+            .map(|packet| opus::PacketInfo::parse(&packet.data).ok().map(|info| info.samples() as u64))
+            .collect::<Option<Vec<_>>>();
+        let mut granules = match (durations, self.last_granule) {
+            (None, _) => vec![None; self.held.len()],
+            (Some(durations), Some(mut granule)) if last.last_in_stream => durations
+                .iter()
+                .map(|duration| {
+                    granule += duration;
+                    Some(granule.min(page_granule))
+                })
+                .collect(),
+            (Some(durations), _) => {
+                let mut granule = page_granule;
+                let mut granules: Vec<_> = durations
+                    .iter()
+                    .rev()
+                    .map(|duration| {
+                        let end = granule;
+                        granule = granule.saturating_sub(*duration);
+                        Some(end)
+                    })
+                    .collect();
+                granules.reverse();
+                granules
+            }
+        };
+        if let Some(granule) = granules.last_mut() {
+            *granule = Some(page_granule);
+        }
+        granules
+    }
+
+    /// Accounts for a packet of `len` bytes being added to the current page,
+    /// mirroring `PacketWriter`, which ends a page whenever it reaches 255
+    /// segments
+    fn add_to_page(&mut self, len: usize) {
+        let segments = len / 255 + 1;
+        let total = self.page_segments + segments;
+        if total < 255 {
+            self.page_segments = total;
+            self.page_packets += 1;
+            self.page_bytes += len;
+        } else {
+            self.page_segments = total % 255;
+            self.page_packets = usize::from(self.page_segments > 0);
+            self.page_bytes = if self.page_segments > 0 { len - (segments - self.page_segments) * 255 } else { 0 };
+        }
+    }
+
+    /// Adds a packet of the stream, appending any packets which can now be
+    /// written to `output` along with how they end their page
+    fn push(&mut self, packet: StreamPacket, output: &mut Vec<(StreamPacket, PacketWriteEndInfo)>) {
+        let ends_page = packet.last_in_page || packet.last_in_stream;
+        self.held.push(packet);
+        if !ends_page {
+            return;
+        }
+        let granules = self.held_granules();
+        for (mut packet, granule) in std::mem::take(&mut self.held).into_iter().zip(granules) {
+            self.add_to_page(packet.data.len());
+            let end = if packet.last_in_stream {
+                PacketWriteEndInfo::EndStream
+            } else if granule.is_some() && self.pagination.page_full(self.page_packets, self.page_bytes) {
+                PacketWriteEndInfo::EndPage
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            if end != PacketWriteEndInfo::NormalPacket {
+                (self.page_segments, self.page_packets, self.page_bytes) = (0, 0, 0);
+            }
+            if let Some(granule) = granule {
+                packet.granule_position = granule;
+                self.last_granule = Some(granule);
+            }
+            output.push((packet, end));
+        }
+    }
+}
+
 /// Re-writes an Ogg Opus stream with modified headers
 #[derive(Derivative)]
 #[derivative(Debug)]
@@ -159,7 +504,11 @@ pub struct HeaderRewriter<'a, HR: HeaderRewrite, HS: HeaderSummarize, W: Write,
     header_packet: Option<Packet>,
     state: State,
     #[derivative(Debug = "ignore")]
-    packet_queue: VecDeque<Packet>,
+    packet_queue: VecDeque<StreamPacket>,
+    #[derivative(Debug = "ignore")]
+    rewritten_packets: Vec<StreamPacket>,
+    #[derivative(Debug = "ignore")]
+    repaginator: Option<Repaginator>,
     header_rewrite: HR,
     header_summarize: HS,
     _error: PhantomData<E>,
@@ -181,99 +530,85 @@ where
             header_packet: None,
             state: State::AwaitingHeader,
             packet_queue: VecDeque::new(),
+            rewritten_packets: Vec::new(),
+            repaginator: None,
             header_rewrite: rewrite,
             header_summarize: summarize,
             _error: PhantomData,
         }
     }
 
-    fn parse_codec_headers(identification: &[u8], comment: &[u8]) -> Result<CodecHeaders, Error> {
-        if let Some(opus_header) = opus::IdHeader::try_parse(identification)? {
-            let comment_header = opus::CommentHeader::try_parse(comment)?;
-            return Ok(CodecHeaders::Opus(opus_header, comment_header));
-        }
-        if let Some(vorbis_header) = vorbis::IdHeader::try_parse(identification)? {
-            let comment_header = vorbis::CommentHeader::try_parse(comment)?;
-            return Ok(CodecHeaders::Vorbis(vorbis_header, comment_header));
-        }
-        Err(Error::UnknownCodec)
-    }
-
     /// Submits a new packet to the rewriter. If `Ready` is returned, another
     /// packet from the same stream should continue to be submitted. If
     /// `HeadersUnchanged` is returned, the supplied stream did not need
     /// any alterations. In this case, the partial output should be discarded
     /// and no further packets submitted.
+    #[allow(clippy::missing_panics_doc)]
     pub fn submit(&mut self, mut packet: Packet) -> Result<SubmitResult<HS::Summary>, E>
     where
         HR::Error: From<Error>,
     {
         let packet_serial = packet.stream_serial();
+        let mut result = SubmitResult::Good;
         match self.state {
             State::AwaitingHeader => {
                 self.header_packet = Some(packet);
                 self.state = State::AwaitingComments { serial: packet_serial };
             }
             State::AwaitingComments { serial } if serial == packet_serial => {
-                // Parse Opus header
                 let mut id_header_packet = self.header_packet.take().expect("Missing header packet");
-                let (summary_before, summary_after, changed) = {
-                    // Parse headers
-                    let original_headers = Self::parse_codec_headers(&id_header_packet.data, &packet.data)?;
-                    let mut headers = original_headers.clone();
-                    let summary_before = self.header_summarize.summarize(&headers)?;
-                    self.header_rewrite.rewrite(&mut headers)?;
-                    let summary_after = self.header_summarize.summarize(&headers)?;
-
-                    // We compare headers rather than the values of the `OpusGains` structs because
-                    // using the latter glosses over issues such as duplicate or invalid gain tags
-                    // which we will fix if present.
-                    let changed = headers != original_headers;
-                    // Update ID header
-                    id_header_packet.data.clear();
-                    headers.serialize_id_header(&mut id_header_packet.data)?;
-                    // Update comment header
-                    packet.data.clear();
-                    headers.serialize_comment_header(&mut packet.data)?;
-                    (summary_before, summary_after, changed)
-                };
-                self.packet_queue.push_back(id_header_packet);
-                self.packet_queue.push_back(packet);
+                result = apply_rewrite(
+                    &self.header_rewrite,
+                    &self.header_summarize,
+                    &mut id_header_packet.data,
+                    &mut packet.data,
+                )?;
+                let pagination = self.header_rewrite.pagination();
+                if !pagination.preserves_pages() && Codec::identify(&id_header_packet.data) == Some(Codec::Opus) {
+                    self.repaginator = Some(Repaginator::new(serial, pagination));
+                }
+                self.write_packet(id_header_packet.into())?;
+                self.write_packet(packet.into())?;
                 self.state = State::Forwarding;
-
-                return Ok(if changed {
-                    SubmitResult::HeadersChanged { from: summary_before, to: summary_after }
-                } else {
-                    SubmitResult::HeadersUnchanged(summary_before)
-                });
             }
             State::AwaitingComments { .. } | State::Forwarding => {
-                self.packet_queue.push_back(packet);
+                self.header_rewrite.rewrite_packet(packet.into(), &mut self.rewritten_packets)?;
+                self.packet_queue.extend(self.rewritten_packets.drain(..));
             }
         }
 
         while let Some(packet) = self.packet_queue.pop_front() {
-            self.write_packet(packet)?;
+            match self.repaginator.as_mut() {
+                Some(repaginator) if repaginator.serial == packet.serial => {
+                    let mut ready = Vec::new();
+                    repaginator.push(packet, &mut ready);
+                    for (packet, packet_info) in ready {
+                        self.write_packet_with_end(packet, packet_info)?;
+                    }
+                }
+                _ => self.write_packet(packet)?,
+            }
         }
-        Ok(SubmitResult::Good)
+        Ok(result)
     }
 
-    fn write_packet(&mut self, packet: Packet) -> Result<(), Error> {
-        // This is an attempt to help polymorphization by moving the writer dependent
-        // code into a separate function
+    fn write_packet(&mut self, packet: StreamPacket) -> Result<(), Error> {
         let packet_info = Self::packet_write_end_info(&packet);
-        let packet_serial = packet.stream_serial();
-        let packet_granule = packet.absgp_page();
+        self.write_packet_with_end(packet, packet_info)
+    }
 
+    fn write_packet_with_end(&mut self, packet: StreamPacket, packet_info: PacketWriteEndInfo) -> Result<(), Error> {
+        // This is an attempt to help polymorphization by moving the writer dependent
+        // code into a separate function
         self.packet_writer
-            .write_packet(packet.data, packet_serial, packet_info, packet_granule)
+            .write_packet(packet.data, packet.serial, packet_info, packet.granule_position)
             .map_err(Error::WriteError)
     }
 
-    fn packet_write_end_info(packet: &Packet) -> PacketWriteEndInfo {
-        if packet.last_in_stream() {
+    fn packet_write_end_info(packet: &StreamPacket) -> PacketWriteEndInfo {
+        if packet.last_in_stream {
             PacketWriteEndInfo::EndStream
-        } else if packet.last_in_page() {
+        } else if packet.last_in_page {
             PacketWriteEndInfo::EndPage
         } else {
             PacketWriteEndInfo::NormalPacket
@@ -281,6 +616,39 @@ where
     }
 }
 
+/// Tracks the position of a reader or the number of bytes written to a
+/// writer so that progress can be reported while it is owned elsewhere
+pub(crate) struct Counted<'a, T> {
+    pub(crate) inner: T,
+    pub(crate) count: &'a Cell<u64>,
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count.set(self.count.get() + len as u64);
+        Ok(len)
+    }
+}
+
+impl<R: Seek> Seek for Counted<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = self.inner.seek(pos)?;
+        self.count.set(position);
+        Ok(position)
+    }
+}
+
+impl<W: Write> Write for Counted<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.count.set(self.count.get() + len as u64);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
 /// Convenience function for performing a rewrite.
 ///
 /// Rewrites the headers of an Ogg Opus stream using the supplied
@@ -288,8 +656,12 @@ where
 /// immediately if it is detected that no headers were modified, otherwise it
 /// will continue to rewrite the stream until the input stream is exhausted, an
 /// error occurs or the interrupt condition is set.
+///
+/// An ID3v2 tag preceding the first Ogg page is copied to the output
+/// unaltered. It can be removed by consuming it from `input` with
+/// `import::take_id3v2_prefix` beforehand.
 pub fn rewrite_stream_with_interrupt<HR, HS, R, W, I, E>(
-    rewrite: HR, summarize: HS, input: R, mut output: W, abort_on_unchanged: bool, interrupt: &I,
+    rewrite: HR, summarize: HS, input: R, output: W, abort_on_unchanged: bool, interrupt: &I,
 ) -> Result<SubmitResult<HS::Summary>, E>
 where
     HR: HeaderRewrite<Error = E>,
@@ -299,10 +671,37 @@ where
     I: Interrupt,
     E: From<Error>,
 {
+    let mut observer = progress::Ignore::default();
+    rewrite_stream_with_progress(rewrite, summarize, input, output, abort_on_unchanged, interrupt, &mut observer)
+}
+
+/// Identical to `rewrite_stream_with_interrupt` except that `observer` is
+/// updated with the progress of the rewrite after each packet is processed.
+/// `bytes_read` is the position in `input`, so it is relative to the start of
+/// the stream if `input` is not initially at position zero.
+pub fn rewrite_stream_with_progress<HR, HS, R, W, I, O, E>(
+    rewrite: HR, summarize: HS, input: R, output: W, abort_on_unchanged: bool, interrupt: &I, observer: &mut O,
+) -> Result<SubmitResult<HS::Summary>, E>
+where
+    HR: HeaderRewrite<Error = E>,
+    HS: HeaderSummarize<Error = E>,
+    R: Read + Seek,
+    W: Write,
+    I: Interrupt,
+    O: ProgressObserver,
+    E: From<Error>,
+{
+    let (bytes_read, bytes_written) = (Cell::new(0), Cell::new(0));
+    let mut input = Counted { inner: input, count: &bytes_read };
+    let mut output = Counted { inner: output, count: &bytes_written };
+    if let Some(tag) = take_id3v2_prefix(&mut input)? {
+        output.write_all(&tag).map_err(Error::WriteError)?;
+    }
     let mut ogg_reader = PacketReader::new(input);
     let ogg_writer = PacketWriter::new(&mut output);
     let mut rewriter = HeaderRewriter::new(rewrite, summarize, ogg_writer);
     let mut result = SubmitResult::Good;
+    let mut progress = RewriteProgress::default();
     loop {
         if interrupt.is_set() {
             return Err(Error::Interrupted.into());
@@ -311,10 +710,23 @@ where
             Err(e) => break Err(Error::OggDecode(e).into()),
             Ok(None) => {
                 // Make sure to flush any buffered data
-                break output.flush().map(|_| result).map_err(|e| Error::WriteError(e).into());
+                output.flush().map_err(Error::WriteError)?;
+                progress.bytes_read = bytes_read.get();
+                progress.bytes_written = bytes_written.get();
+                progress.stage = RewriteStage::Complete;
+                observer.update(&progress);
+                break Ok(result);
             }
             Ok(Some(packet)) => {
                 let submit_result = rewriter.submit(packet);
+                progress.packets += 1;
+                progress.bytes_read = bytes_read.get();
+                progress.bytes_written = bytes_written.get();
+                progress.stage = match rewriter.state {
+                    State::AwaitingHeader | State::AwaitingComments { .. } => RewriteStage::Headers,
+                    State::Forwarding => RewriteStage::Audio,
+                };
+                observer.update(&progress);
                 match submit_result {
                     Ok(SubmitResult::Good) => {
                         // We can continue submitting packets
@@ -337,6 +749,25 @@ where
     }
 }
 
+/// Performs a rewrite as described for `rewrite_stream_with_interrupt` on a new
+/// thread, returning a handle which can cancel it, report its progress and
+/// wait for its result
+pub fn rewrite_stream_in_background<HR, HS, R, W, E>(
+    rewrite: HR, summarize: HS, input: R, output: W, abort_on_unchanged: bool,
+) -> OperationHandle<RewriteProgress, SubmitResult<HS::Summary>, E>
+where
+    HR: HeaderRewrite<Error = E> + Send + 'static,
+    HS: HeaderSummarize<Error = E> + Send + 'static,
+    HS::Summary: Send,
+    R: Read + Seek + Send + 'static,
+    W: Write + Send + 'static,
+    E: From<Error> + Send + 'static,
+{
+    OperationHandle::spawn(move |interrupt, observer| {
+        rewrite_stream_with_progress(rewrite, summarize, input, output, abort_on_unchanged, interrupt, observer)
+    })
+}
+
 /// Identical to `rewrite_stream_with_interrupt` except the rewrite loop cannot
 /// be interrupted.
 pub fn rewrite_stream<HR, HS, R, W, E>(
@@ -351,3 +782,762 @@ where
 {
     rewrite_stream_with_interrupt(rewrite, summarize, input, output, abort_on_unchanged, &Never::default())
 }
+
+/// Pages of a logical stream read before its header packets are complete
+#[derive(Debug, Default)]
+struct HeaderPages {
+    /// Pages of all logical streams in the order they were read
+    pages: Vec<Page>,
+
+    /// Complete header packets of the stream being rewritten
+    packets: Vec<Vec<u8>>,
+
+    /// A header packet which continues onto the next page
+    partial: Vec<u8>,
+}
+
+impl HeaderPages {
+    /// Adds a page of the stream being rewritten, returning `true` once the
+    /// identification and comment header packets are complete and the final
+    /// page read does not continue a packet onto the next
+    fn add_header_page(&mut self, page: Page) -> bool {
+        for (fragment, ends) in page.packet_fragments() {
+            self.partial.extend_from_slice(fragment);
+            if ends {
+                self.packets.push(std::mem::take(&mut self.partial));
+            }
+        }
+        let complete = self.packets.len() >= 2 && page.ends_packet();
+        self.pages.push(page);
+        complete
+    }
+
+    /// Outputs the buffered pages, replacing the header pages of the stream
+    /// with serial `serial` with pages containing `packets`. Returns the
+    /// difference in the number of header pages.
+    fn rewrite_into(self, output: &mut Vec<Page>, serial: u32, packets: &[Vec<u8>]) -> Result<u32, Error> {
+        let stream_pages: Vec<_> = self.pages.iter().filter(|p| p.serial() == serial).collect();
+        let (Some(first), Some(last)) = (stream_pages.first(), stream_pages.last()) else {
+            return Err(Error::MalformedCommentHeader);
+        };
+        let id_pages = Page::paginate(&packets[..1], serial, first.sequence(), first.granule_position(), true);
+        let comment_sequence = first.sequence().wrapping_add(page_count(id_pages.len())?);
+        let comment_pages = Page::paginate(&packets[1..], serial, comment_sequence, last.granule_position(), false);
+        let offset = page_count(id_pages.len() + comment_pages.len())?.wrapping_sub(page_count(stream_pages.len())?);
+
+        let mut replacements = [id_pages, comment_pages].into_iter();
+        for page in self.pages {
+            if page.serial() == serial {
+                output.extend(replacements.next().into_iter().flatten());
+            } else {
+                output.push(page);
+            }
+        }
+        Ok(offset)
+    }
+}
+
+fn page_count(count: usize) -> Result<u32, Error> { u32::try_from(count).map_err(|_| Error::MalformedCommentHeader) }
+
+/// Rewrites the headers of the first logical stream in an Ogg file one page at
+/// a time, copying all other pages verbatim
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct PageRewriter<HR: HeaderRewrite, HS: HeaderSummarize> {
+    header_rewrite: HR,
+    header_summarize: HS,
+    #[derivative(Debug = "ignore")]
+    header_pages: Option<HeaderPages>,
+    stream_serial: Option<u32>,
+    sequence_offset: u32,
+    #[derivative(Debug = "ignore")]
+    result: SubmitResult<HS::Summary>,
+}
+
+impl<HR, HS, E> PageRewriter<HR, HS>
+where
+    HR: HeaderRewrite<Error = E>,
+    HS: HeaderSummarize<Error = E>,
+    E: From<Error>,
+{
+    fn new(header_rewrite: HR, header_summarize: HS) -> PageRewriter<HR, HS> {
+        PageRewriter {
+            header_rewrite,
+            header_summarize,
+            header_pages: Some(HeaderPages::default()),
+            stream_serial: None,
+            sequence_offset: 0,
+            result: SubmitResult::Good,
+        }
+    }
+
+    /// Submits a page, appending any pages which are ready to be written to
+    /// `output`
+    fn submit(&mut self, mut page: Page, output: &mut Vec<Page>) -> Result<(), E> {
+        let serial = *self.stream_serial.get_or_insert(page.serial());
+        match self.header_pages.as_mut() {
+            Some(pending) if serial == page.serial() => {
+                if pending.add_header_page(page) {
+                    let mut pending = self.header_pages.take().expect("Header pages unexpectedly missing");
+                    let mut packets = std::mem::take(&mut pending.packets);
+                    let (id_header, rest) = packets.split_at_mut(1);
+                    self.result =
+                        apply_rewrite(&self.header_rewrite, &self.header_summarize, &mut id_header[0], &mut rest[0])?;
+                    self.sequence_offset = pending.rewrite_into(output, serial, &packets)?;
+                }
+            }
+            Some(pending) => pending.pages.push(page),
+            None => {
+                if page.serial() == serial && self.sequence_offset != 0 {
+                    page.set_sequence(page.sequence().wrapping_add(self.sequence_offset));
+                    if page.is_last_in_stream() {
+                        // A chained stream could reuse the serial
+                        self.sequence_offset = 0;
+                    }
+                }
+                output.push(page);
+            }
+        }
+        Ok(())
+    }
+
+    /// Have the headers been rewritten without any changes
+    fn headers_unchanged(&self) -> bool { matches!(self.result, SubmitResult::HeadersUnchanged(_)) }
+
+    /// Completes the rewrite, appending any remaining pages to `output`. If the
+    /// headers were never completed the stream is passed through unaltered.
+    fn finish(self, output: &mut Vec<Page>) -> SubmitResult<HS::Summary> {
+        if let Some(pending) = self.header_pages {
+            output.extend(pending.pages);
+        }
+        self.result
+    }
+}
+
+/// Like `rewrite_stream_with_interrupt` except that only the pages containing
+/// the codec headers are regenerated. All subsequent pages are copied verbatim
+/// rather than being re-paginated, so the audio portion of the stream is
+/// guaranteed to be bit-identical. If the rewritten headers occupy a different
+/// number of pages than the originals, the sequence numbers (and therefore
+/// checksums) of the following pages of the stream are adjusted to match.
+/// Page checksums are verified while reading.
+#[allow(clippy::needless_pass_by_value)]
+pub fn rewrite_stream_preserving_pages_with_interrupt<HR, HS, R, W, I, E>(
+    rewrite: HR, summarize: HS, mut input: R, mut output: W, abort_on_unchanged: bool, interrupt: &I,
+) -> Result<SubmitResult<HS::Summary>, E>
+where
+    HR: HeaderRewrite<Error = E>,
+    HS: HeaderSummarize<Error = E>,
+    R: Read,
+    W: Write,
+    I: Interrupt,
+    E: From<Error>,
+{
+    let mut rewriter = PageRewriter::new(rewrite, summarize);
+    let mut pages = Vec::new();
+    loop {
+        if interrupt.is_set() {
+            return Err(Error::Interrupted.into());
+        }
+        let Some(page) = Page::read(&mut input)? else { break };
+        rewriter.submit(page, &mut pages)?;
+        if abort_on_unchanged && rewriter.headers_unchanged() {
+            return Ok(rewriter.finish(&mut pages));
+        }
+        for page in pages.drain(..) {
+            page.write(&mut output)?;
+        }
+    }
+    let result = rewriter.finish(&mut pages);
+    for page in &pages {
+        page.write(&mut output)?;
+    }
+    output.flush().map_err(Error::WriteError)?;
+    Ok(result)
+}
+
+/// Identical to `rewrite_stream_preserving_pages_with_interrupt` except the
+/// rewrite loop cannot be interrupted.
+pub fn rewrite_stream_preserving_pages<HR, HS, R, W, E>(
+    rewrite: HR, summarize: HS, input: R, output: W, abort_on_unchanged: bool,
+) -> Result<SubmitResult<HS::Summary>, E>
+where
+    HR: HeaderRewrite<Error = E>,
+    HS: HeaderSummarize<Error = E>,
+    R: Read,
+    W: Write,
+    E: From<Error>,
+{
+    rewrite_stream_preserving_pages_with_interrupt(
+        rewrite,
+        summarize,
+        input,
+        output,
+        abort_on_unchanged,
+        &Never::default(),
+    )
+}
+
+/// Summarizes codec headers by returning a copy of them
+#[derive(Debug, Default)]
+struct CopyHeaders {}
+
+impl HeaderSummarize for CopyHeaders {
+    type Error = Error;
+    type Summary = CodecHeaders;
+
+    fn summarize(&self, headers: &CodecHeaders) -> Result<CodecHeaders, Error> { Ok(headers.clone()) }
+}
+
+/// Leaves the codec headers unchanged
+#[derive(Debug, Default)]
+struct KeepHeaders {}
+
+impl HeaderRewrite for KeepHeaders {
+    type Error = Error;
+
+    fn rewrite(&self, _headers: &mut CodecHeaders) -> Result<(), Error> { Ok(()) }
+}
+
+/// Reads the codec headers of the first logical stream of an Ogg file. Only
+/// the pages containing the headers are consumed from `input`. A leading
+/// ID3v2 tag is skipped.
+pub fn read_headers<R: Read + Seek>(mut input: R) -> Result<CodecHeaders, Error> {
+    take_id3v2_prefix(&mut input)?;
+    let abort_on_unchanged = true;
+    let result = rewrite_stream_preserving_pages(
+        KeepHeaders::default(),
+        CopyHeaders::default(),
+        input,
+        io::sink(),
+        abort_on_unchanged,
+    )?;
+    match result {
+        SubmitResult::HeadersUnchanged(headers) => Ok(headers),
+        SubmitResult::Good | SubmitResult::HeadersChanged { .. } => Err(Error::MalformedCommentHeader),
+    }
+}
+
+/// Rewrites the headers of the file at `path` in place, copying the pages
+/// following the codec headers verbatim. The output is written to a temporary
+/// file alongside the original, which is only replaced if the headers
+/// changed. Returns whether the file was replaced. A leading ID3v2 tag is
+/// preserved. Fails with `Error::InsufficientSpace` before writing anything if
+/// the filesystem lacks room for the temporary. Any error returned identifies
+/// `path` or the directory involved.
+pub fn rewrite_file<HR: HeaderRewrite<Error = Error>>(path: &Path, rewrite: HR) -> Result<bool, Error> {
+    rewrite_file_to(path, path, rewrite, false)
+}
+
+/// Like `rewrite_file`, but writes the rewritten stream to `output_path`. If
+/// `always_write` is set, the output is written even if the headers are
+/// unchanged, complementing the `abort_on_unchanged` flag of the stream
+/// rewriting functions. Returns whether the output was written.
+pub fn rewrite_file_to<HR: HeaderRewrite<Error = Error>>(
+    input_path: &Path, output_path: &Path, rewrite: HR, always_write: bool,
+) -> Result<bool, Error> {
+    let _span = tracing::debug_span!("rewrite_file", path = %input_path.display()).entered();
+    let input = File::open(input_path).map_err(|e| Error::FileOpenError(input_path.to_path_buf(), e))?;
+    let input_length = input.metadata().map_err(|e| Error::FileReadError(input_path.to_path_buf(), e))?.len();
+    let mut input = BufReader::new(input);
+    let mut output = OutputFile::new_target(output_path)?;
+    output.check_free_space(input_length)?;
+    let result = take_id3v2_prefix(&mut input).and_then(|tag| {
+        output.write_all(tag.as_deref().unwrap_or_default()).map_err(Error::WriteError)?;
+        let abort_on_unchanged = !always_write;
+        rewrite_stream_preserving_pages(rewrite, CopyHeaders::default(), input, &mut output, abort_on_unchanged)
+    });
+    match result {
+        Ok(SubmitResult::HeadersChanged { .. }) => {
+            output.commit()?;
+            Ok(true)
+        }
+        Ok(SubmitResult::HeadersUnchanged(_)) if always_write => {
+            output.commit()?;
+            Ok(true)
+        }
+        Ok(SubmitResult::HeadersUnchanged(_)) => {
+            output.abort()?;
+            Ok(false)
+        }
+        Ok(SubmitResult::Good) => {
+            output.abort()?;
+            Err(Error::MalformedCommentHeader.in_file(input_path))
+        }
+        Err(e) => {
+            output.abort()?;
+            Err(e.in_file(input_path))
+        }
+    }
+}
+
+/// Asynchronous equivalent of `rewrite_stream_preserving_pages`. Pages
+/// following the codec headers are copied verbatim. The rewrite can be
+/// abandoned by dropping the returned future.
+#[cfg(feature = "async")]
+pub async fn rewrite_stream_async<HR, HS, R, W, E>(
+    rewrite: HR, summarize: HS, mut input: R, mut output: W, abort_on_unchanged: bool,
+) -> Result<SubmitResult<HS::Summary>, E>
+where
+    HR: HeaderRewrite<Error = E>,
+    HS: HeaderSummarize<Error = E>,
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+    E: From<Error>,
+{
+    use tokio::io::AsyncWriteExt as _;
+
+    let mut rewriter = PageRewriter::new(rewrite, summarize);
+    let mut pages = Vec::new();
+    while let Some(page) = Page::read_async(&mut input).await? {
+        rewriter.submit(page, &mut pages)?;
+        if abort_on_unchanged && rewriter.headers_unchanged() {
+            return Ok(rewriter.finish(&mut pages));
+        }
+        for page in pages.drain(..) {
+            page.write_async(&mut output).await?;
+        }
+    }
+    let result = rewriter.finish(&mut pages);
+    for page in &pages {
+        page.write_async(&mut output).await?;
+    }
+    output.flush().await.map_err(Error::WriteError)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::comment_rewrite::{
+        CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig,
+    };
+    use crate::header::{CommentList, DiscreteCommentList};
+    use crate::ogg_page::NO_GRANULE_POSITION;
+    use crate::testing::StreamBuilder;
+
+    const SERIAL: u32 = 0x5eed;
+
+    fn opus_stream() -> Result<Vec<u8>, Error> {
+        let (id_header, comment_header) = StreamBuilder::default().header_packets()?;
+        let mut output = Vec::new();
+        let mut writer = PacketWriter::new(&mut output);
+        let write = |writer: &mut PacketWriter<_>, data: Vec<u8>, end, granule| {
+            writer.write_packet(data, SERIAL, end, granule).map_err(Error::WriteError)
+        };
+        write(&mut writer, id_header, PacketWriteEndInfo::EndPage, 0)?;
+        write(&mut writer, comment_header, PacketWriteEndInfo::EndPage, 0)?;
+        for i in 0..40u8 {
+            let end = match i {
+                39 => PacketWriteEndInfo::EndStream,
+                _ if i % 7 == 6 => PacketWriteEndInfo::EndPage,
+                _ => PacketWriteEndInfo::NormalPacket,
+            };
+            write(&mut writer, vec![i; 100 + usize::from(i) * 50], end, u64::from(i) * 960)?;
+        }
+        drop(writer);
+        Ok(output)
+    }
+
+    fn read_pages(data: &[u8]) -> Result<Vec<Page>, Error> {
+        let mut reader = Cursor::new(data);
+        let mut pages = Vec::new();
+        while let Some(page) = Page::read(&mut reader)? {
+            pages.push(page);
+        }
+        Ok(pages)
+    }
+
+    #[test]
+    fn audio_pages_preserved() -> Result<(), Error> {
+        let input = opus_stream()?;
+        let mut append = DiscreteCommentList::default();
+        append.push("COMMENT", &"x".repeat(150_000))?;
+        let config = CommentRewriterConfig {
+            action: CommentRewriterAction::Modify { retain: Box::new(|_, _| true), append },
+            drop_invalid: false,
+            normalize_keys: None,
+        };
+        let mut output = Vec::new();
+        let result = rewrite_stream_preserving_pages(
+            CommentHeaderRewrite::new(config),
+            CommentHeaderSummary::default(),
+            Cursor::new(&input),
+            &mut output,
+            false,
+        )?;
+        assert!(matches!(result, SubmitResult::HeadersChanged { .. }));
+
+        let original = read_pages(&input)?;
+        let rewritten = read_pages(&output)?;
+        // The enlarged comment header requires two additional pages
+        assert_eq!(rewritten.len(), original.len() + 2);
+        for (before, after) in original[2..].iter().zip(&rewritten[4..]) {
+            assert_eq!(after.sequence(), before.sequence() + 2);
+            assert_eq!(after.granule_position(), before.granule_position());
+            assert_eq!(after.lacing(), before.lacing());
+            assert_eq!(after.data(), before.data());
+        }
+
+        // Rewriting again with no changes leaves the stream identical
+        let config = CommentRewriterConfig {
+            action: CommentRewriterAction::NoChange,
+            drop_invalid: false,
+            normalize_keys: None,
+        };
+        let mut unchanged = Vec::new();
+        rewrite_stream_preserving_pages(
+            CommentHeaderRewrite::new(config),
+            CommentHeaderSummary::default(),
+            Cursor::new(&input),
+            &mut unchanged,
+            false,
+        )?;
+        assert_eq!(unchanged, input);
+        Ok(())
+    }
+
+    #[test]
+    fn comment_header_split_at_page_boundary() -> Result<(), Error> {
+        // The most packet data a single page can hold
+        const PAGE_DATA: usize = 255 * 255;
+        let input = opus_stream()?;
+        let original_pages = read_pages(&input)?.len();
+        // A header which fills a page exactly needs a zero-length segment on a
+        // second page to terminate it
+        for (header_size, header_pages) in [(PAGE_DATA - 1, 1), (PAGE_DATA, 2), (PAGE_DATA + 1, 2), (2 * PAGE_DATA, 3)]
+        {
+            for preserve_pages in [false, true] {
+                let mut comments = DiscreteCommentList::default();
+                // Magic, vendor string, comment count and the length and key of
+                // the comment take 32 bytes
+                comments.push("COMMENT", &"x".repeat(header_size - 32))?;
+                let config = CommentRewriterConfig {
+                    action: CommentRewriterAction::Replace(comments),
+                    drop_invalid: false,
+                    normalize_keys: None,
+                };
+                let rewrite = CommentHeaderRewrite::new(config);
+                let summarize = CommentHeaderSummary::default();
+                let mut output = Vec::new();
+                if preserve_pages {
+                    rewrite_stream_preserving_pages(rewrite, summarize, Cursor::new(&input), &mut output, false)?;
+                } else {
+                    rewrite_stream(rewrite, summarize, Cursor::new(&input), &mut output, false)?;
+                }
+
+                let pages = read_pages(&output)?;
+                assert_eq!(pages.len(), original_pages - 1 + header_pages);
+                let comment_pages = &pages[1..=header_pages];
+                assert!(comment_pages.iter().skip(1).all(Page::is_continuation));
+                assert!(!pages[header_pages + 1].is_continuation());
+                for page in comment_pages {
+                    let expected = if page.ends_packet() { 0 } else { NO_GRANULE_POSITION };
+                    assert_eq!(page.granule_position(), expected);
+                }
+                let mut serialized = Vec::new();
+                read_headers(Cursor::new(&output))?.serialize_comment_header(&mut serialized)?;
+                assert_eq!(serialized.len(), header_size);
+
+                // The multi-page header is reassembled when read back
+                let config = CommentRewriterConfig {
+                    action: CommentRewriterAction::NoChange,
+                    drop_invalid: false,
+                    normalize_keys: None,
+                };
+                let mut unchanged = Vec::new();
+                let result = rewrite_stream_preserving_pages(
+                    CommentHeaderRewrite::new(config),
+                    CommentHeaderSummary::default(),
+                    Cursor::new(&output),
+                    &mut unchanged,
+                    false,
+                )?;
+                assert!(matches!(result, SubmitResult::HeadersUnchanged(_)));
+                assert_eq!(unchanged, output);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn split_packet_pages_preserved() -> Result<(), Error> {
+        let (id_header, comment_header) = StreamBuilder::default().header_packets()?;
+        let mut input = Vec::new();
+        Page::paginate(&[id_header], SERIAL, 0, 0, true)[0].write(&mut input)?;
+        Page::paginate(&[comment_header], SERIAL, 1, 0, false)[0].write(&mut input)?;
+        // Pages which end part-way through a packet with fewer than 255
+        // segments, which re-pagination would not reproduce
+        let packet = [&[0xfc][..], &[0; 599]].concat();
+        for (sequence, header_type, granule, lacing, data) in [
+            (2u32, 0u8, 960u64, &[255, 255, 90, 255][..], [&packet[..], &packet[..255]].concat()),
+            (3, 1, 1920, &[255, 90], packet[255..].to_vec()),
+            (4, 4, 2880, &[255, 255, 90], packet.clone()),
+        ] {
+            let mut raw = b"OggS\x00".to_vec();
+            raw.push(header_type);
+            raw.extend(granule.to_le_bytes());
+            raw.extend(SERIAL.to_le_bytes());
+            raw.extend(sequence.to_le_bytes());
+            raw.extend([0; 4]);
+            raw.push(u8::try_from(lacing.len()).expect("Too many segments"));
+            raw.extend(lacing);
+            raw.extend(data);
+            let mut page = Page::read_unverified(&mut Cursor::new(raw))?.expect("Page missing");
+            page.repair_checksum();
+            page.write(&mut input)?;
+        }
+
+        let mut append = DiscreteCommentList::default();
+        append.push("TITLE", "Preserved")?;
+        let config = CommentRewriterConfig {
+            action: CommentRewriterAction::Modify { retain: Box::new(|_, _| true), append },
+            drop_invalid: false,
+            normalize_keys: None,
+        };
+        let mut output = Vec::new();
+        rewrite_stream_preserving_pages(
+            CommentHeaderRewrite::new(config),
+            CommentHeaderSummary::default(),
+            Cursor::new(&input),
+            &mut output,
+            false,
+        )?;
+        let (original, rewritten) = (read_pages(&input)?, read_pages(&output)?);
+        assert_eq!(original[2..], rewritten[2..]);
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_rewrite_matches_sync() -> Result<(), Error> {
+        let input = opus_stream()?;
+        let config = || {
+            let mut append = DiscreteCommentList::default();
+            append.push("TITLE", "Async").expect("Invalid comment");
+            CommentRewriterConfig {
+                action: CommentRewriterAction::Modify { retain: Box::new(|_, _| true), append },
+                drop_invalid: false,
+                normalize_keys: None,
+            }
+        };
+        let mut expected = Vec::new();
+        rewrite_stream_preserving_pages(
+            CommentHeaderRewrite::new(config()),
+            CommentHeaderSummary::default(),
+            Cursor::new(&input),
+            &mut expected,
+            false,
+        )?;
+        let runtime = tokio::runtime::Builder::new_current_thread().build().map_err(Error::ReadError)?;
+        let mut output = Vec::new();
+        let result = runtime.block_on(rewrite_stream_async(
+            CommentHeaderRewrite::new(config()),
+            CommentHeaderSummary::default(),
+            input.as_slice(),
+            &mut output,
+            false,
+        ))?;
+        assert!(matches!(result, SubmitResult::HeadersChanged { .. }));
+        assert_eq!(output, expected);
+        Ok(())
+    }
+
+    /// Duplicates every packet following the headers
+    #[derive(Debug)]
+    struct DuplicatePackets {}
+
+    impl HeaderRewrite for DuplicatePackets {
+        type Error = Error;
+
+        fn rewrite(&self, _headers: &mut CodecHeaders) -> Result<(), Error> { Ok(()) }
+
+        fn rewrite_packet(&mut self, packet: StreamPacket, output: &mut Vec<StreamPacket>) -> Result<(), Error> {
+            output.push(StreamPacket { last_in_page: false, last_in_stream: false, ..packet.clone() });
+            output.push(packet);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn packets_rewritten() -> Result<(), Error> {
+        let input = opus_stream()?;
+        let mut output = Vec::new();
+        rewrite_stream(DuplicatePackets {}, CommentHeaderSummary::default(), Cursor::new(&input), &mut output, false)?;
+
+        let mut reader = PacketReader::new(Cursor::new(&output));
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.read_packet().map_err(Error::OggDecode)? {
+            packets.push(packet);
+        }
+        assert_eq!(packets.len(), 2 + 40 * 2);
+        for (i, pair) in packets[2..].chunks(2).enumerate() {
+            assert_eq!(pair[0].data, pair[1].data);
+            assert_eq!(pair[1].data[0], u8::try_from(i).expect("Too many packets"));
+        }
+        assert!(packets.last().map_or(false, Packet::last_in_stream));
+        Ok(())
+    }
+
+    #[test]
+    fn comment_header_limits_applied() -> Result<(), Error> {
+        let input = opus_stream()?;
+        let rewrite_with = |limits| {
+            let mut comments = DiscreteCommentList::default();
+            comments.push("TITLE", "Title")?;
+            let config = CommentRewriterConfig {
+                action: CommentRewriterAction::Replace(comments),
+                drop_invalid: false,
+                normalize_keys: None,
+            };
+            let rewrite = Limited::new(CommentHeaderRewrite::new(config), limits);
+            rewrite_stream(rewrite, CommentHeaderSummary::default(), Cursor::new(&input), std::io::sink(), false)
+        };
+        // Magic, vendor string, comment count and the comment take 35 bytes
+        rewrite_with(ParseLimits { max_header_size: 35, ..ParseLimits::default() })?;
+        let result = rewrite_with(ParseLimits { max_header_size: 34, ..ParseLimits::default() });
+        assert!(matches!(result, Err(Error::CommentHeaderTooLarge(header::ParseLimit::HeaderSize))));
+        let result = rewrite_with(ParseLimits { max_comment_len: 10, ..ParseLimits::default() });
+        assert!(matches!(result, Err(Error::CommentHeaderTooLarge(header::ParseLimit::CommentLength))));
+        Ok(())
+    }
+
+    #[test]
+    fn packets_repaginated() -> Result<(), Error> {
+        // Ten 20ms packets on pages of four, with the stream starting at an
+        // offset of 480 samples and the final page trimming 100 samples
+        let mut input = Vec::new();
+        let mut writer = PacketWriter::new(&mut input);
+        let (header, comments) = StreamBuilder::default().header_packets()?;
+        writer.write_packet(header, SERIAL, PacketWriteEndInfo::EndPage, 0).map_err(Error::WriteError)?;
+        writer.write_packet(comments, SERIAL, PacketWriteEndInfo::EndPage, 0).map_err(Error::WriteError)?;
+        for (i, granule) in (1..=10u64).zip([4, 4, 4, 4, 8, 8, 8, 8, 10, 10]) {
+            let end = match i {
+                10 => PacketWriteEndInfo::EndStream,
+                4 | 8 => PacketWriteEndInfo::EndPage,
+                _ => PacketWriteEndInfo::NormalPacket,
+            };
+            let granule = 480 + granule * 960 - if i == 10 { 100 } else { 0 };
+            writer.write_packet(vec![0xfc; 100], SERIAL, end, granule).map_err(Error::WriteError)?;
+        }
+        drop(writer);
+
+        let rewrite_pages = |pagination| -> Result<Vec<(usize, u64)>, Error> {
+            let config = CommentRewriterConfig {
+                action: CommentRewriterAction::NoChange,
+                drop_invalid: false,
+                normalize_keys: None,
+            };
+            let rewrite = Paginated::new(CommentHeaderRewrite::new(config), pagination);
+            let mut output = Vec::new();
+            rewrite_stream(rewrite, CommentHeaderSummary::default(), Cursor::new(&input), &mut output, false)?;
+            let pages = read_pages(&output)?;
+            Ok(pages[2..].iter().map(|page| (page.packet_fragments().len(), page.granule_position())).collect())
+        };
+        let pagination = Pagination { flush_per_packet: true, ..Pagination::default() };
+        let expected: Vec<_> = (1..=10).map(|i| (1, 480 + i * 960 - if i == 10 { 100 } else { 0 })).collect();
+        assert_eq!(rewrite_pages(pagination)?, expected);
+
+        let pagination = Pagination { max_packets_per_page: Some(3), ..Pagination::default() };
+        let expected = [(3, 480 + 3 * 960), (3, 480 + 6 * 960), (3, 480 + 9 * 960), (1, 480 + 10 * 960 - 100)];
+        assert_eq!(rewrite_pages(pagination)?, expected);
+
+        let pagination = Pagination { target_page_size: Some(450), ..Pagination::default() };
+        assert_eq!(rewrite_pages(pagination)?, [(5, 480 + 5 * 960), (5, 480 + 10 * 960 - 100)]);
+        Ok(())
+    }
+
+    #[test]
+    fn id3v2_prefix_preserved() -> Result<(), Error> {
+        let tag = b"ID3\x04\x00\x00\x00\x00\x00\x02\x00\x00".to_vec();
+        let mut input = tag.clone();
+        input.extend(opus_stream()?);
+        let mut append = DiscreteCommentList::default();
+        append.push("TITLE", "Tagged")?;
+        let action = CommentRewriterAction::Modify { retain: Box::new(|_, _| true), append };
+        let rewrite =
+            CommentHeaderRewrite::new(CommentRewriterConfig { action, drop_invalid: false, normalize_keys: None });
+        let mut output = Vec::new();
+        rewrite_stream(rewrite, CommentHeaderSummary::default(), Cursor::new(&input), &mut output, false)?;
+        assert!(output.starts_with(&tag));
+
+        let headers = read_headers(Cursor::new(&output))?;
+        let CodecHeaders::Opus(_, comment_header) = headers else { panic!("Not an Opus stream") };
+        assert_eq!(comment_header.get_first("TITLE"), Some("Tagged"));
+        Ok(())
+    }
+
+    #[test]
+    fn unchanged_file_written_when_requested() -> Result<(), Error> {
+        let dir = tempfile::tempdir().map_err(Error::ReadError)?;
+        let (input_path, output_path) = (dir.path().join("in.opus"), dir.path().join("out.opus"));
+        let input = opus_stream()?;
+        std::fs::write(&input_path, &input).map_err(Error::WriteError)?;
+        let no_change = || {
+            CommentHeaderRewrite::new(CommentRewriterConfig {
+                action: CommentRewriterAction::NoChange,
+                drop_invalid: false,
+                normalize_keys: None,
+            })
+        };
+        assert!(!rewrite_file_to(&input_path, &output_path, no_change(), false)?);
+        assert!(!output_path.exists());
+        assert!(rewrite_file_to(&input_path, &output_path, no_change(), true)?);
+        assert_eq!(std::fs::read(&output_path).map_err(Error::ReadError)?, input);
+        Ok(())
+    }
+
+    #[test]
+    fn progress_reported() -> Result<(), Error> {
+        let input = opus_stream()?;
+        let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig {
+            action: CommentRewriterAction::NoChange,
+            drop_invalid: false,
+            normalize_keys: None,
+        });
+        let mut updates = Vec::new();
+        let mut output = Vec::new();
+        rewrite_stream_with_progress(
+            rewrite,
+            CommentHeaderSummary::default(),
+            Cursor::new(&input),
+            &mut output,
+            false,
+            &Never::default(),
+            &mut |progress: &RewriteProgress| updates.push(*progress),
+        )?;
+        assert_eq!(updates.len(), 43);
+        assert_eq!(updates[0].stage, RewriteStage::Headers);
+        assert_eq!(updates[2].stage, RewriteStage::Audio);
+        assert!(updates.windows(2).all(|w| w[0].bytes_read <= w[1].bytes_read));
+        let last = updates.last().expect("No progress reported");
+        assert_eq!(last.stage, RewriteStage::Complete);
+        assert_eq!(last.packets, 42);
+        assert_eq!(last.bytes_read, input.len() as u64);
+        assert_eq!(last.bytes_written, output.len() as u64);
+        Ok(())
+    }
+
+    #[test]
+    fn rewrite_in_background() -> Result<(), Error> {
+        let input = opus_stream()?;
+        let len = input.len() as u64;
+        let handle = rewrite_stream_in_background(
+            KeepHeaders::default(),
+            CopyHeaders::default(),
+            Cursor::new(input),
+            io::sink(),
+            false,
+        );
+        while !handle.is_finished() {
+            std::thread::yield_now();
+        }
+        assert_eq!(handle.progress().stage, RewriteStage::Complete);
+        assert_eq!(handle.progress().bytes_read, len);
+        let result = handle.join()?;
+        assert!(matches!(result, SubmitResult::HeadersUnchanged(CodecHeaders::Opus(..))));
+        Ok(())
+    }
+}
@@ -0,0 +1,378 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::header::{
+    validate_comment_field_name, BareCommentHeader, CommentHeader as _, CommentList, DiscreteCommentList,
+};
+use crate::Error;
+
+const FLAC_MAGIC: &[u8] = b"fLaC";
+const FLAC_BLOCK_TYPE_VORBIS_COMMENT: u8 = 4;
+const FLAC_LAST_BLOCK_FLAG: u8 = 0x80;
+
+const ID3V2_MAGIC: &[u8] = b"ID3";
+const ID3V2_HEADER_SIZE: usize = 10;
+const ID3V2_FLAG_UNSYNCHRONISATION: u8 = 0x80;
+const ID3V2_FLAG_EXTENDED_HEADER: u8 = 0x40;
+const ID3V2_FLAG_FOOTER: u8 = 0x10;
+
+/// Extensions of files which are searched for by `find_sibling`
+const SIBLING_EXTENSIONS: [&str; 4] = ["flac", "mp3", "FLAC", "MP3"];
+
+/// Prefix of comment keys which are not imported. ReplayGain values do not
+/// apply to the re-encoded audio.
+const SKIPPED_KEY_PREFIX: &str = "REPLAYGAIN_";
+
+/// Mapping from ID3v2 text frame identifiers (version 2.3/2.4 and 2.2) to
+/// comment field names
+const ID3V2_TEXT_FRAMES: [(&str, &str, &str); 14] = [
+    ("TIT2", "TT2", "TITLE"),
+    ("TPE1", "TP1", "ARTIST"),
+    ("TALB", "TAL", "ALBUM"),
+    ("TPE2", "TP2", "ALBUMARTIST"),
+    ("TCOM", "TCM", "COMPOSER"),
+    ("TPE3", "TP3", "CONDUCTOR"),
+    ("TCON", "TCO", "GENRE"),
+    ("TYER", "TYE", "DATE"),
+    ("TDRC", "TDRC", "DATE"),
+    ("TCOP", "TCR", "COPYRIGHT"),
+    ("TPUB", "TPB", "ORGANIZATION"),
+    ("TSRC", "TRC", "ISRC"),
+    ("TBPM", "TBP", "BPM"),
+    ("TIT3", "TT3", "SUBTITLE"),
+];
+
+/// Searches for a FLAC or MP3 file with the same name as `path` apart from the
+/// extension
+pub fn find_sibling(path: &Path) -> Option<PathBuf> {
+    SIBLING_EXTENSIONS.iter().map(|ext| path.with_extension(ext)).find(|sibling| sibling != path && sibling.is_file())
+}
+
+/// Reads the tags from a FLAC file (Vorbis comments) or MP3 file (ID3v2) and
+/// converts them to a comment list. ReplayGain tags are not imported.
+pub fn read_tags(path: &Path) -> Result<DiscreteCommentList, Error> {
+    let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    let mut reader = BufReader::new(file);
+    read_tags_from(&mut reader).map_err(|e| match e {
+        Error::MalformedCommentHeader => Error::MalformedTagSource(path.to_path_buf()),
+        Error::UnknownCodec => Error::UnrecognisedTagSource(path.to_path_buf()),
+        Error::ReadError(e) => Error::FileReadError(path.to_path_buf(), e),
+        e => e,
+    })
+}
+
+/// Identical to `read_tags` except the tags are read from the start of a
+/// reader rather than a file. `UnknownCodec` is returned if the format is not
+/// recognised and `MalformedCommentHeader` if the tags cannot be parsed.
+pub fn read_tags_from<R: Read + Seek>(reader: &mut R) -> Result<DiscreteCommentList, Error> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic).map_err(|_| Error::UnknownCodec)?;
+    let comments = if magic == FLAC_MAGIC {
+        read_flac_comments(reader)?
+    } else if magic.starts_with(ID3V2_MAGIC) {
+        reader.seek(SeekFrom::Start(0)).map_err(Error::ReadError)?;
+        read_id3v2_comments(reader)?
+    } else {
+        return Err(Error::UnknownCodec);
+    };
+    let mut result = DiscreteCommentList::with_capacity(comments.len());
+    result.extend(comments.iter().filter(|(k, _)| !k.to_ascii_uppercase().starts_with(SKIPPED_KEY_PREFIX)))?;
+    Ok(result)
+}
+
+/// Reads an ID3v2 tag located at the current position of `reader`, as found
+/// at the start of some Ogg files, returning its raw bytes including any
+/// footer. If there is no tag, the reader is returned to its original position
+/// and `None` is returned.
+pub fn take_id3v2_prefix<R: Read + Seek>(reader: &mut R) -> Result<Option<Vec<u8>>, Error> {
+    let start = reader.stream_position().map_err(Error::ReadError)?;
+    let mut header = [0u8; ID3V2_HEADER_SIZE];
+    let is_tag = reader.read_exact(&mut header).is_ok()
+        && header.starts_with(ID3V2_MAGIC)
+        && header[6..].iter().all(|b| b & 0x80 == 0);
+    if !is_tag {
+        reader.seek(SeekFrom::Start(start)).map_err(Error::ReadError)?;
+        return Ok(None);
+    }
+    let footer_size = if header[5] & ID3V2_FLAG_FOOTER == 0 { 0 } else { ID3V2_HEADER_SIZE };
+    let tag_size = decode_synchsafe([header[6], header[7], header[8], header[9]]) + footer_size;
+    let mut tag = header.to_vec();
+    tag.resize(ID3V2_HEADER_SIZE + tag_size, 0);
+    reader.read_exact(&mut tag[ID3V2_HEADER_SIZE..]).map_err(Error::ReadError)?;
+    Ok(Some(tag))
+}
+
+fn read_flac_comments<R: Read + Seek>(reader: &mut R) -> Result<DiscreteCommentList, Error> {
+    loop {
+        let mut block_header = [0u8; 4];
+        reader.read_exact(&mut block_header).map_err(|_| Error::MalformedCommentHeader)?;
+        let block_type = block_header[0] & !FLAC_LAST_BLOCK_FLAG;
+        let block_len = u32::from_be_bytes([0, block_header[1], block_header[2], block_header[3]]);
+        if block_type == FLAC_BLOCK_TYPE_VORBIS_COMMENT {
+            let mut block = vec![0u8; block_len as usize];
+            reader.read_exact(&mut block).map_err(|_| Error::MalformedCommentHeader)?;
+            let header = BareCommentHeader::try_parse(&block)?;
+            return Ok(header.to_discrete_comment_list());
+        }
+        if (block_header[0] & FLAC_LAST_BLOCK_FLAG) != 0 {
+            return Ok(DiscreteCommentList::default());
+        }
+        reader.seek(SeekFrom::Current(i64::from(block_len))).map_err(Error::ReadError)?;
+    }
+}
+
+/// Decodes a 28-bit "synchsafe" integer
+fn decode_synchsafe(bytes: [u8; 4]) -> usize { bytes.iter().fold(0, |acc, b| (acc << 7) | usize::from(b & 0x7F)) }
+
+/// Reverses the ID3v2 unsynchronisation scheme, which inserts a zero byte after
+/// every 0xFF
+fn remove_unsynchronisation(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut previous = 0u8;
+    for &b in data {
+        if !(previous == 0xFF && b == 0) {
+            result.push(b);
+        }
+        previous = b;
+    }
+    result
+}
+
+fn decode_utf16<I: Iterator<Item = u16>>(units: I) -> String {
+    char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+}
+
+/// Decodes the text in an ID3v2 frame with the specified encoding byte.
+/// Multiple values are separated by NUL characters.
+fn decode_id3v2_text(encoding: u8, data: &[u8]) -> Vec<String> {
+    let text = match encoding {
+        0 => data.iter().copied().map(char::from).collect(),
+        1 | 2 => {
+            let mut big_endian = encoding == 2;
+            let mut data = data;
+            if data.starts_with(&[0xFF, 0xFE]) {
+                big_endian = false;
+                data = &data[2..];
+            } else if data.starts_with(&[0xFE, 0xFF]) {
+                big_endian = true;
+                data = &data[2..];
+            }
+            let units = data.chunks_exact(2).map(|pair| {
+                let pair = [pair[0], pair[1]];
+                if big_endian {
+                    u16::from_be_bytes(pair)
+                } else {
+                    u16::from_le_bytes(pair)
+                }
+            });
+            decode_utf16(units)
+        }
+        _ => String::from_utf8_lossy(data).into_owned(),
+    };
+    text.split('\0').map(|v| v.trim_start_matches('\u{FEFF}')).filter(|v| !v.is_empty()).map(String::from).collect()
+}
+
+/// Splits a value such as `3/12` into a number and total
+fn split_number_and_total(value: &str) -> (&str, Option<&str>) {
+    match value.split_once('/') {
+        Some((number, total)) => (number.trim(), Some(total.trim()).filter(|t| !t.is_empty())),
+        None => (value.trim(), None),
+    }
+}
+
+fn push_id3v2_frame(comments: &mut DiscreteCommentList, id: &str, data: &[u8]) -> Result<(), Error> {
+    let Some((&encoding, body)) = data.split_first() else { return Ok(()) };
+    let text_key = ID3V2_TEXT_FRAMES.iter().find(|(id_4, id_3, _)| *id_4 == id || *id_3 == id).map(|(_, _, key)| *key);
+    if let Some(key) = text_key {
+        for value in decode_id3v2_text(encoding, body) {
+            comments.push(key, &value)?;
+        }
+        return Ok(());
+    }
+    let numbered = match id {
+        "TRCK" | "TRK" => Some(("TRACKNUMBER", "TRACKTOTAL")),
+        "TPOS" | "TPA" => Some(("DISCNUMBER", "DISCTOTAL")),
+        _ => None,
+    };
+    if let Some((number_key, total_key)) = numbered {
+        for value in decode_id3v2_text(encoding, body) {
+            let (number, total) = split_number_and_total(&value);
+            comments.push(number_key, number)?;
+            if let Some(total) = total {
+                comments.push(total_key, total)?;
+            }
+        }
+        return Ok(());
+    }
+    match id {
+        "TXXX" | "TXX" => {
+            // A description followed by one or more values
+            let mut values = decode_id3v2_text(encoding, body).into_iter();
+            if let Some(description) = values.next() {
+                let key = description.to_ascii_uppercase();
+                if validate_comment_field_name(&key).is_ok() {
+                    for value in values {
+                        comments.push(&key, &value)?;
+                    }
+                }
+            }
+        }
+        "COMM" | "COM" if body.len() > 3 => {
+            // A language code, a short description and then the comment text
+            let mut values = decode_id3v2_text(encoding, &body[3..]).into_iter();
+            if let Some(text) = values.next_back() {
+                comments.push("COMMENT", &text)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn read_id3v2_comments<R: Read>(reader: &mut R) -> Result<DiscreteCommentList, Error> {
+    let mut header = [0u8; ID3V2_HEADER_SIZE];
+    reader.read_exact(&mut header).map_err(|_| Error::MalformedCommentHeader)?;
+    let version = header[3];
+    let flags = header[5];
+    let tag_size = decode_synchsafe([header[6], header[7], header[8], header[9]]);
+    let mut tag = vec![0u8; tag_size];
+    reader.read_exact(&mut tag).map_err(|_| Error::MalformedCommentHeader)?;
+    if (flags & ID3V2_FLAG_UNSYNCHRONISATION) != 0 {
+        tag = remove_unsynchronisation(&tag);
+    }
+    let (id_len, header_len) = match version {
+        2 => (3, 6),
+        3 | 4 => (4, 10),
+        _ => return Err(Error::MalformedCommentHeader),
+    };
+    let mut offset = 0;
+    if version != 2 && (flags & ID3V2_FLAG_EXTENDED_HEADER) != 0 && tag.len() >= 4 {
+        let size_bytes = [tag[0], tag[1], tag[2], tag[3]];
+        offset = if version == 3 { u32::from_be_bytes(size_bytes) as usize + 4 } else { decode_synchsafe(size_bytes) };
+    }
+    let mut comments = DiscreteCommentList::default();
+    while offset + header_len <= tag.len() {
+        let frame_header = &tag[offset..(offset + header_len)];
+        if frame_header[0] == 0 {
+            // Padding
+            break;
+        }
+        let id = String::from_utf8_lossy(&frame_header[..id_len]).into_owned();
+        let size_bytes = &frame_header[id_len..];
+        let frame_len = match version {
+            2 => u32::from_be_bytes([0, size_bytes[0], size_bytes[1], size_bytes[2]]) as usize,
+            3 => u32::from_be_bytes([size_bytes[0], size_bytes[1], size_bytes[2], size_bytes[3]]) as usize,
+            _ => decode_synchsafe([size_bytes[0], size_bytes[1], size_bytes[2], size_bytes[3]]),
+        };
+        let start = offset + header_len;
+        let end = start.checked_add(frame_len).filter(|end| *end <= tag.len()).ok_or(Error::MalformedCommentHeader)?;
+        push_id3v2_frame(&mut comments, &id, &tag[start..end])?;
+        offset = end;
+    }
+    Ok(comments)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn id3v2_frame(id: &str, data: &[u8]) -> Vec<u8> {
+        let mut frame = id.as_bytes().to_vec();
+        frame.extend(u32::try_from(data.len()).unwrap().to_be_bytes());
+        frame.extend([0, 0]);
+        frame.extend(data);
+        frame
+    }
+
+    fn collect(list: &DiscreteCommentList) -> Vec<(String, String)> {
+        list.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn flac_vorbis_comments() -> Result<(), Error> {
+        let mut comments = BareCommentHeader::default();
+        comments.set_vendor("reference libFLAC");
+        comments.push("TITLE", "Title")?;
+        comments.push("REPLAYGAIN_TRACK_GAIN", "-3.00 dB")?;
+        let mut block = Vec::new();
+        comments.serialize_into(&mut block)?;
+
+        let mut data = FLAC_MAGIC.to_vec();
+        // A STREAMINFO block followed by the final VORBIS_COMMENT block
+        data.extend([0, 0, 0, 34]);
+        data.extend([0u8; 34]);
+        data.push(FLAC_LAST_BLOCK_FLAG | FLAC_BLOCK_TYPE_VORBIS_COMMENT);
+        data.extend(&u32::try_from(block.len()).unwrap().to_be_bytes()[1..]);
+        data.extend(block);
+
+        let parsed = read_tags_from(&mut Cursor::new(data))?;
+        assert_eq!(parsed.get_first("TITLE"), Some("Title"));
+        assert_eq!(parsed.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn id3v23_frames() -> Result<(), Error> {
+        let mut frames = Vec::new();
+        frames.extend(id3v2_frame("TIT2", b"\x00Title"));
+        frames.extend(id3v2_frame("TPE1", b"\x01\xFF\xFEA\x00r\x00t\x00"));
+        frames.extend(id3v2_frame("TRCK", b"\x033/12"));
+        frames.extend(id3v2_frame("TXXX", b"\x03MusicBrainz Album Id\x00abc"));
+        frames.extend(id3v2_frame("COMM", b"\x00engdesc\x00A comment"));
+        frames.extend(id3v2_frame("APIC", b"\x00ignored"));
+        frames.extend([0u8; 16]);
+
+        let mut data = b"ID3\x03\x00\x00".to_vec();
+        let size = u32::try_from(frames.len()).unwrap();
+        data.extend([21, 14, 7, 0].map(|shift| ((size >> shift) & 0x7F).to_be_bytes()[3]));
+        data.extend(frames);
+        data.extend(b"\xFF\xFBaudio");
+
+        let parsed = read_tags_from(&mut Cursor::new(data))?;
+        let expected: Vec<(String, String)> = [
+            ("TITLE", "Title"),
+            ("ARTIST", "Art"),
+            ("TRACKNUMBER", "3"),
+            ("TRACKTOTAL", "12"),
+            ("MUSICBRAINZ ALBUM ID", "abc"),
+            ("COMMENT", "A comment"),
+        ]
+        .iter()
+        .map(|&(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(collect(&parsed), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn unsynchronisation() {
+        assert_eq!(remove_unsynchronisation(&[0xFF, 0x00, 0xE0, 0xFF, 0x00, 0x00]), vec![0xFF, 0xE0, 0xFF, 0x00]);
+    }
+
+    #[test]
+    fn unknown_format() {
+        let result = read_tags_from(&mut Cursor::new(b"OggS\x00\x02"));
+        assert!(matches!(result, Err(Error::UnknownCodec)));
+    }
+
+    #[test]
+    fn id3v2_prefix_taken() -> Result<(), Error> {
+        let mut data = b"ID3\x04\x00\x10\x00\x00\x00\x04".to_vec();
+        data.extend(b"body3DI\x04\x00\x10\x00\x00\x00\x04");
+        data.extend(b"OggS");
+        let mut reader = Cursor::new(data);
+        let prefix = take_id3v2_prefix(&mut reader)?.expect("ID3v2 tag not found");
+        assert_eq!(prefix.len(), 24);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).map_err(Error::ReadError)?;
+        assert_eq!(rest, b"OggS");
+
+        let mut reader = Cursor::new(b"OggS".to_vec());
+        assert_eq!(take_id3v2_prefix(&mut reader)?, None);
+        assert_eq!(reader.position(), 0);
+        Ok(())
+    }
+}
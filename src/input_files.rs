@@ -0,0 +1,601 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use zoog::output_file::OutputFile;
+use zoog::Error;
+
+/// Name used to refer to the standard input stream
+const STANDARD_STREAM_NAME: &str = "-";
+
+/// Extensions of playlists which are expanded to the files they reference
+const PLAYLIST_EXTENSIONS: [&str; 2] = ["m3u", "m3u8"];
+
+/// Extension of CUE sheets, which are treated as describing an album
+const CUE_SHEET_EXTENSION: &str = "cue";
+
+/// A set of input files which should be processed together
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputGroup {
+    /// The files in the group
+    pub paths: Vec<PathBuf>,
+
+    /// Whether the files should be treated as a single album
+    pub is_album: bool,
+}
+
+/// An input which refers to the same file as an earlier input
+#[derive(Clone, Debug, PartialEq)]
+pub struct DuplicateInput {
+    /// The path which was ignored
+    pub path: PathBuf,
+
+    /// The path by which the file will be processed
+    pub original: PathBuf,
+}
+
+/// Identifies a file independently of the path used to refer to it
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum FileIdentity {
+    #[cfg(unix)]
+    Inode {
+        device: u64,
+        inode: u64,
+    },
+    Path(PathBuf),
+}
+
+impl FileIdentity {
+    /// Determines the identity of the file at `path`. Where the file cannot be
+    /// queried, the path itself is used so that the error is reported when the
+    /// file is processed.
+    fn of(path: &Path) -> FileIdentity {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if let Ok(metadata) = fs::metadata(path) {
+                return FileIdentity::Inode { device: metadata.dev(), inode: metadata.ino() };
+            }
+        }
+        FileIdentity::Path(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()))
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf { PathBuf::from(String::from_utf8_lossy(bytes).into_owned()) }
+
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &Path) -> Vec<u8> { path.to_string_lossy().into_owned().into_bytes() }
+
+/// Splits the contents of a file list into paths. Entries are separated by
+/// newlines or, if `nul_separated` is set, NUL characters. Empty entries are
+/// ignored.
+fn parse_file_list(data: &[u8], nul_separated: bool) -> Vec<PathBuf> {
+    let separator = if nul_separated { b'\0' } else { b'\n' };
+    data.split(|b| *b == separator)
+        .map(|entry| if nul_separated { entry } else { entry.strip_suffix(b"\r").unwrap_or(entry) })
+        .filter(|entry| !entry.is_empty())
+        .map(path_from_bytes)
+        .collect()
+}
+
+/// Reads a list of paths from the specified file, or from standard input if
+/// the path is `-`
+pub fn read_file_list(path: &Path, nul_separated: bool) -> Result<Vec<PathBuf>, Error> {
+    let mut data = Vec::new();
+    if path == OsStr::new(STANDARD_STREAM_NAME) {
+        io::stdin().lock().read_to_end(&mut data).map_err(Error::ReadError)?;
+    } else {
+        let mut file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+        file.read_to_end(&mut data).map_err(|e| Error::FileReadError(path.to_path_buf(), e))?;
+    }
+    Ok(parse_file_list(&data, nul_separated))
+}
+
+/// Formats a list of paths in the form parsed by `parse_file_list()`
+fn format_file_list(paths: &[PathBuf], nul_separated: bool) -> Vec<u8> {
+    let separator = if nul_separated { b'\0' } else { b'\n' };
+    let mut data = Vec::new();
+    for path in paths {
+        data.extend(path_to_bytes(path));
+        data.push(separator);
+    }
+    data
+}
+
+/// Atomically replaces the file at `path` with a list of `paths` which can be
+/// read back by `read_file_list()`
+pub fn write_file_list(path: &Path, paths: &[PathBuf], nul_separated: bool) -> Result<(), Error> {
+    let mut output = OutputFile::new_target(path)?;
+    match output.write_all(&format_file_list(paths, nul_separated)) {
+        Ok(()) => output.commit(),
+        Err(e) => {
+            output.abort()?;
+            Err(Error::FileWriteError(path.to_path_buf(), e))
+        }
+    }
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .map_or(false, |ext| extensions.iter().any(|e| ext == *e))
+}
+
+/// Does the path look like an M3U playlist?
+fn is_playlist(path: &Path) -> bool { has_extension(path, &PLAYLIST_EXTENSIONS) }
+
+/// Does the path look like a CUE sheet?
+fn is_cue_sheet(path: &Path) -> bool { has_extension(path, &[CUE_SHEET_EXTENSION]) }
+
+fn read_whole_file(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    let mut file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    file.read_to_end(&mut data).map_err(|e| Error::FileReadError(path.to_path_buf(), e))?;
+    Ok(data)
+}
+
+/// Decodes the `%XX` escape sequences of a URL, failing if one is malformed
+fn percent_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let hex_digit = |byte: Option<&u8>| byte.and_then(|byte| char::from(*byte).to_digit(16));
+    let mut result = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'%' {
+            let value = hex_digit(bytes.next())? * 16 + hex_digit(bytes.next())?;
+            result.push(u8::try_from(value).ok()?);
+        } else {
+            result.push(byte);
+        }
+    }
+    Some(result)
+}
+
+/// Converts a `file:` URL naming a file on the local host to a path
+fn path_from_file_url(url: &str) -> io::Result<PathBuf> {
+    let unsupported = || io::Error::new(io::ErrorKind::InvalidData, format!("Unsupported playlist entry `{}`", url));
+    let remainder = url.get("file://".len()..).ok_or_else(unsupported)?;
+    let (host, path) = remainder.find('/').map_or((remainder, ""), |idx| remainder.split_at(idx));
+    if path.is_empty() || !(host.is_empty() || host.eq_ignore_ascii_case("localhost")) {
+        return Err(unsupported());
+    }
+    // Windows paths with a drive letter are written as `file:///C:/...`
+    #[cfg(windows)]
+    let path = if path.as_bytes().get(2) == Some(&b':') { &path[1..] } else { path };
+    percent_decode(path.as_bytes()).map(|path| path_from_bytes(&path)).ok_or_else(unsupported)
+}
+
+/// Parses the entries of an M3U/M3U8 playlist. Relative entries are resolved
+/// against `base`. Comments and extended M3U directives are skipped, and
+/// `file:` URLs are converted to paths.
+fn parse_playlist(data: &[u8], base: &Path) -> io::Result<Vec<PathBuf>> {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    let text = String::from_utf8_lossy(data);
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            if line.get(.."file:".len()).map_or(false, |scheme| scheme.eq_ignore_ascii_case("file:")) {
+                path_from_file_url(line)
+            } else {
+                Ok(base.join(line))
+            }
+        })
+        .collect()
+}
+
+/// Replaces any playlists in the supplied paths with the files they reference,
+/// in playlist order
+pub fn expand_playlists<I>(paths: I) -> Result<Vec<PathBuf>, Error>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    let mut result = Vec::new();
+    for path in paths {
+        if is_playlist(&path) {
+            let data = read_whole_file(&path)?;
+            let base = path.parent().unwrap_or_else(|| Path::new(""));
+            result.extend(parse_playlist(&data, base).map_err(|e| Error::FileReadError(path.clone(), e))?);
+        } else {
+            result.push(path);
+        }
+    }
+    Ok(result)
+}
+
+/// Parses the files referenced by `FILE` commands in a CUE sheet, resolving
+/// relative paths against `base`. Files referenced more than once are only
+/// returned once.
+fn parse_cue_sheet(data: &[u8], base: &Path) -> Vec<PathBuf> {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    let text = String::from_utf8_lossy(data);
+    let mut result: Vec<PathBuf> = Vec::new();
+    for line in text.lines().map(str::trim) {
+        let (command, arguments) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        if !command.eq_ignore_ascii_case("FILE") {
+            continue;
+        }
+        let arguments = arguments.trim();
+        // The file name is followed by the file type and may be quoted
+        let name = if let Some(quoted) = arguments.strip_prefix('"') {
+            quoted.split_once('"').map_or(quoted, |(name, _)| name)
+        } else {
+            arguments.rsplit_once(char::is_whitespace).map_or(arguments, |(name, _)| name.trim_end())
+        };
+        if name.is_empty() {
+            continue;
+        }
+        let path = base.join(name);
+        if !result.contains(&path) {
+            result.push(path);
+        }
+    }
+    result
+}
+
+/// Splits the supplied paths into groups. Each CUE sheet becomes an album
+/// containing the files it references. All other paths form a single group
+/// which is an album only if `album` is set.
+pub fn group_by_cue_sheets<I>(paths: I, album: bool) -> Result<Vec<InputGroup>, Error>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    let mut ungrouped = InputGroup { paths: Vec::new(), is_album: album };
+    let mut cue_groups = Vec::new();
+    for path in paths {
+        if is_cue_sheet(&path) {
+            let data = read_whole_file(&path)?;
+            let base = path.parent().unwrap_or_else(|| Path::new(""));
+            cue_groups.push(InputGroup { paths: parse_cue_sheet(&data, base), is_album: true });
+        } else {
+            ungrouped.paths.push(path);
+        }
+    }
+    let result = std::iter::once(ungrouped).chain(cue_groups).filter(|g| !g.paths.is_empty()).collect();
+    Ok(result)
+}
+
+/// Divides the values of a command-line option which may be specified more
+/// than once into the values of each occurrence, given the index of each value
+/// among the arguments. Since the option itself occupies an index, the values
+/// of separate occurrences are never at consecutive indices.
+pub fn split_occurrences<T>(values: Vec<T>, indices: &[usize]) -> Vec<Vec<T>> {
+    let mut result: Vec<Vec<T>> = Vec::new();
+    let mut previous_index = None;
+    for (value, &index) in values.into_iter().zip(indices) {
+        match result.last_mut() {
+            Some(occurrence) if previous_index == index.checked_sub(1) => occurrence.push(value),
+            _ => result.push(vec![value]),
+        }
+        previous_index = Some(index);
+    }
+    result
+}
+
+/// Removes inputs which refer to a file that has already been seen, whether by
+/// the same path or another one (e.g. a symbolic or hard link). Files which
+/// are part of an album are kept in preference to the same file outside of
+/// one, otherwise the first occurrence is kept. Groups left empty are
+/// removed.
+pub fn remove_duplicates(mut groups: Vec<InputGroup>) -> (Vec<InputGroup>, Vec<DuplicateInput>) {
+    let mut seen: HashMap<FileIdentity, PathBuf> = HashMap::new();
+    let mut duplicates = Vec::new();
+    // Sorting is stable so groups are otherwise visited in the supplied order
+    let mut order: Vec<usize> = (0..groups.len()).collect();
+    order.sort_by_key(|&idx| !groups[idx].is_album);
+    for idx in order {
+        let paths = std::mem::take(&mut groups[idx].paths);
+        for path in paths {
+            match seen.entry(FileIdentity::of(&path)) {
+                Entry::Occupied(original) => {
+                    duplicates.push(DuplicateInput { path, original: original.get().clone() });
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(path.clone());
+                    groups[idx].paths.push(path);
+                }
+            }
+        }
+    }
+    groups.retain(|group| !group.paths.is_empty());
+    (groups, duplicates)
+}
+
+/// An element of a wildcard pattern
+#[derive(Clone, Debug, PartialEq)]
+enum GlobToken {
+    Literal(char),
+    AnyChar,
+    AnySequence,
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl GlobToken {
+    /// Whether a token other than `AnySequence` matches `c`
+    fn matches(&self, c: char) -> bool {
+        match self {
+            GlobToken::Literal(literal) => *literal == c,
+            GlobToken::AnyChar => true,
+            GlobToken::AnySequence => false,
+            GlobToken::Class { negated, ranges } => ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated,
+        }
+    }
+}
+
+/// A shell-style wildcard pattern. `*` matches any sequence of characters,
+/// `?` matches any single character and `[...]` matches any character in the
+/// set, or not in it if the set starts with `!` or `^`. Patterns which
+/// contain a `/` are matched against the whole path, otherwise only the file
+/// name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Glob {
+    tokens: Vec<GlobToken>,
+    whole_path: bool,
+}
+
+impl Glob {
+    /// Parses a pattern, failing if a character set is not terminated
+    pub fn new(pattern: &str) -> Result<Glob, String> {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '*' => GlobToken::AnySequence,
+                '?' => GlobToken::AnyChar,
+                '[' => {
+                    let mut set: Vec<char> = Vec::new();
+                    loop {
+                        match chars.next() {
+                            // A `]` immediately after the opening bracket is part of the set
+                            Some(']') if !set.is_empty() && set != ['!'] && set != ['^'] => break,
+                            Some(c) => set.push(c),
+                            None => return Err(format!("Unterminated character set in pattern `{}`", pattern)),
+                        }
+                    }
+                    let negated = matches!(set.first(), Some('!' | '^'));
+                    let set = if negated { &set[1..] } else { &set[..] };
+                    let mut ranges = Vec::new();
+                    let mut idx = 0;
+                    while idx < set.len() {
+                        if idx + 2 < set.len() && set[idx + 1] == '-' {
+                            ranges.push((set[idx], set[idx + 2]));
+                            idx += 3;
+                        } else {
+                            ranges.push((set[idx], set[idx]));
+                            idx += 1;
+                        }
+                    }
+                    GlobToken::Class { negated, ranges }
+                }
+                c => GlobToken::Literal(c),
+            };
+            tokens.push(token);
+        }
+        Ok(Glob { tokens, whole_path: pattern.contains('/') })
+    }
+
+    fn matches_str(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let (mut pattern_idx, mut text_idx) = (0, 0);
+        // The most recent `*` and the text position it was tried at
+        let mut backtrack = None;
+        while text_idx < text.len() {
+            match self.tokens.get(pattern_idx) {
+                Some(GlobToken::AnySequence) => {
+                    backtrack = Some((pattern_idx, text_idx));
+                    pattern_idx += 1;
+                }
+                Some(token) if token.matches(text[text_idx]) => {
+                    pattern_idx += 1;
+                    text_idx += 1;
+                }
+                _ => match backtrack {
+                    Some((star_idx, star_text_idx)) => {
+                        backtrack = Some((star_idx, star_text_idx + 1));
+                        pattern_idx = star_idx + 1;
+                        text_idx = star_text_idx + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+        self.tokens[pattern_idx..].iter().all(|token| *token == GlobToken::AnySequence)
+    }
+
+    /// Whether the pattern matches `path`
+    pub fn matches(&self, path: &Path) -> bool {
+        if self.whole_path {
+            let path = path.to_string_lossy();
+            #[cfg(windows)]
+            let path = path.replace('\\', "/");
+            self.matches_str(&path)
+        } else {
+            path.file_name().map_or(false, |name| self.matches_str(&name.to_string_lossy()))
+        }
+    }
+}
+
+/// Selects input files using `--include` and `--exclude` patterns
+#[derive(Clone, Debug, Default)]
+pub struct PathFilter {
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+}
+
+impl PathFilter {
+    pub fn new(include: Vec<Glob>, exclude: Vec<Glob>) -> PathFilter { PathFilter { include, exclude } }
+
+    /// Whether `path` matches any include pattern, or there are none, and
+    /// matches no exclude pattern
+    pub fn accepts(&self, path: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.matches(path)))
+            && !self.exclude.iter().any(|glob| glob.matches(path))
+    }
+
+    /// Removes the paths not accepted by the filter from `groups`, returning
+    /// the number removed. Groups left empty are removed.
+    pub fn apply(&self, groups: &mut Vec<InputGroup>) -> usize {
+        let mut removed = 0;
+        for group in groups.iter_mut() {
+            let before = group.paths.len();
+            group.paths.retain(|path| self.accepts(path));
+            removed += before - group.paths.len();
+        }
+        groups.retain(|group| !group.paths.is_empty());
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newline_separated_list() {
+        let list = parse_file_list(b"a.opus\r\n\nb c.opus\nd.opus", false);
+        let expected: Vec<PathBuf> = ["a.opus", "b c.opus", "d.opus"].iter().map(PathBuf::from).collect();
+        assert_eq!(list, expected);
+    }
+
+    #[test]
+    fn nul_separated_list() {
+        let list = parse_file_list(b"a\n.opus\0\0b.opus\0", true);
+        let expected: Vec<PathBuf> = ["a\n.opus", "b.opus"].iter().map(PathBuf::from).collect();
+        assert_eq!(list, expected);
+    }
+
+    #[test]
+    fn formatted_list_parsed() {
+        let paths: Vec<PathBuf> = ["a.opus", "dir/b c.opus"].iter().map(PathBuf::from).collect();
+        for nul_separated in [false, true] {
+            assert_eq!(parse_file_list(&format_file_list(&paths, nul_separated), nul_separated), paths);
+        }
+        assert_eq!(format_file_list(&paths, false), b"a.opus\ndir/b c.opus\n");
+    }
+
+    #[test]
+    fn occurrences_split() {
+        let values = vec!["a", "b", "c", "d", "e"];
+        assert_eq!(split_occurrences(values, &[2, 3, 5, 8, 9]), vec![vec!["a", "b"], vec!["c"], vec!["d", "e"]]);
+        assert!(split_occurrences(Vec::<&str>::new(), &[]).is_empty());
+    }
+
+    #[test]
+    fn playlist_detection() {
+        assert!(is_playlist(Path::new("album.m3u")));
+        assert!(is_playlist(Path::new("album.M3U8")));
+        assert!(!is_playlist(Path::new("album.opus")));
+        assert!(!is_playlist(Path::new("m3u")));
+    }
+
+    #[test]
+    fn playlist_parsing() -> io::Result<()> {
+        let playlist = b"\xEF\xBB\xBF#EXTM3U\n#EXTINF:123,Artist - Title\n01.opus\n\n/music/02.opus\r\n";
+        let entries = parse_playlist(playlist, Path::new("/albums/x"))?;
+        let expected: Vec<PathBuf> = ["/albums/x/01.opus", "/music/02.opus"].iter().map(PathBuf::from).collect();
+        assert_eq!(entries, expected);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn playlist_file_urls() -> io::Result<()> {
+        let playlist = b"file:///music/My%20Song.opus\nFILE://localhost/music/%C3%A9t%c3%a9.opus\n";
+        let entries = parse_playlist(playlist, Path::new("/albums/x"))?;
+        let expected: Vec<PathBuf> =
+            ["/music/My Song.opus", "/music/\u{e9}t\u{e9}.opus"].iter().map(PathBuf::from).collect();
+        assert_eq!(entries, expected);
+
+        for entry in ["file://03.opus", "file://server/music/03.opus", "file:///music/%2x.opus", "file:///%2"] {
+            assert!(parse_playlist(entry.as_bytes(), Path::new("/albums/x")).is_err(), "Accepted {}", entry);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn cue_sheet_parsing() {
+        let cue = concat!(
+            "REM GENRE Rock\r\n",
+            "PERFORMER \"Someone\"\r\n",
+            "FILE \"01 - First.opus\" WAVE\r\n",
+            "  TRACK 01 AUDIO\r\n",
+            "    INDEX 01 00:00:00\r\n",
+            "file 02.opus MP3\r\n",
+            "  TRACK 02 AUDIO\r\n",
+            "FILE \"01 - First.opus\" WAVE\r\n",
+        );
+        let entries = parse_cue_sheet(cue.as_bytes(), Path::new("/albums/x"));
+        let expected: Vec<PathBuf> =
+            ["/albums/x/01 - First.opus", "/albums/x/02.opus"].iter().map(PathBuf::from).collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn duplicate_removal() -> Result<(), io::Error> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("a.opus");
+        let other = dir.path().join("b.opus");
+        let link = dir.path().join("link.opus");
+        fs::write(&file, b"")?;
+        fs::write(&other, b"")?;
+        std::os::unix::fs::symlink(&file, &link)?;
+        let groups = vec![
+            InputGroup { paths: vec![link.clone(), other.clone(), file.clone()], is_album: false },
+            InputGroup { paths: vec![file.clone()], is_album: true },
+            InputGroup { paths: vec![dir.path().join(".").join("a.opus")], is_album: true },
+        ];
+        let (groups, duplicates) = remove_duplicates(groups);
+        let expected_groups = vec![
+            InputGroup { paths: vec![other], is_album: false },
+            InputGroup { paths: vec![file.clone()], is_album: true },
+        ];
+        assert_eq!(groups, expected_groups);
+        assert_eq!(duplicates.len(), 3);
+        assert!(duplicates.iter().all(|d| d.original == file));
+        Ok(())
+    }
+
+    #[test]
+    fn glob_matching() -> Result<(), String> {
+        let matches = |pattern: &str, path: &str| Glob::new(pattern).map(|glob| glob.matches(Path::new(path)));
+        assert!(matches("*.opus", "/music/a.opus")?);
+        assert!(!matches("*.opus", "/music/a.opus.bak")?);
+        assert!(matches("*demo*", "demo.opus")?);
+        assert!(matches("?.opus", "a.opus")?);
+        assert!(!matches("?.opus", "ab.opus")?);
+        assert!(matches("[0-9]*", "01 - Intro.opus")?);
+        assert!(!matches("[!0-9]*", "01 - Intro.opus")?);
+        assert!(matches("[]]", "]")?);
+        assert!(matches("*/live/*", "/music/live/a.opus")?);
+        assert!(!matches("live", "/music/live/a.opus")?);
+        assert!(Glob::new("[a-z").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn path_filtering() -> Result<(), String> {
+        let filter = PathFilter::new(vec![Glob::new("*.opus")?], vec![Glob::new("*demo*")?]);
+        let mut groups = vec![
+            InputGroup { paths: vec!["a.opus".into(), "demo.opus".into(), "b.ogg".into()], is_album: false },
+            InputGroup { paths: vec!["demo 2.opus".into()], is_album: true },
+        ];
+        assert_eq!(filter.apply(&mut groups), 3);
+        assert_eq!(groups, vec![InputGroup { paths: vec!["a.opus".into()], is_album: false }]);
+        Ok(())
+    }
+}
@@ -1,9 +1,35 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 /// Allows reading the status of a potential interrupt
 pub trait Interrupt {
     /// Has the interrupt been triggered?
     fn is_set(&self) -> bool;
 }
 
+impl<I: Interrupt + ?Sized> Interrupt for &I {
+    fn is_set(&self) -> bool { (**self).is_set() }
+}
+
+impl<I: Interrupt + ?Sized> Interrupt for Arc<I> {
+    fn is_set(&self) -> bool { (**self).is_set() }
+}
+
+/// `None` is never triggered
+impl<I: Interrupt> Interrupt for Option<I> {
+    fn is_set(&self) -> bool { self.as_ref().map_or(false, Interrupt::is_set) }
+}
+
+/// A pair of interrupts is triggered when either of them is
+impl<A: Interrupt, B: Interrupt> Interrupt for (A, B) {
+    fn is_set(&self) -> bool { self.0.is_set() || self.1.is_set() }
+}
+
+impl Interrupt for AtomicBool {
+    fn is_set(&self) -> bool { self.load(Ordering::Relaxed) }
+}
+
 /// An interrupt that is never triggered
 #[derive(Debug, Default)]
 pub struct Never {}
@@ -11,3 +37,73 @@ pub struct Never {}
 impl Interrupt for Never {
     fn is_set(&self) -> bool { false }
 }
+
+/// An interrupt that is triggered by calling `set()` on it or any of its
+/// clones
+#[derive(Clone, Debug, Default)]
+pub struct Flag {
+    inner: Arc<AtomicBool>,
+}
+
+impl Flag {
+    /// Constructs a flag which is not yet set
+    pub fn new() -> Flag { Flag::default() }
+
+    /// Triggers the interrupt
+    pub fn set(&self) { self.inner.store(true, Ordering::Relaxed); }
+}
+
+impl Interrupt for Flag {
+    fn is_set(&self) -> bool { self.inner.is_set() }
+}
+
+/// An interrupt that is triggered once a point in time has passed
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    /// The point in time, or `None` if it lies beyond what `Instant` can
+    /// represent
+    at: Option<Instant>,
+}
+
+impl Deadline {
+    /// Constructs an interrupt triggered at `at`
+    pub fn new(at: Instant) -> Deadline { Deadline { at: Some(at) } }
+
+    /// Constructs an interrupt triggered once `timeout` has elapsed from now.
+    /// A timeout too large to be represented is never triggered.
+    pub fn after(timeout: Duration) -> Deadline { Deadline { at: Instant::now().checked_add(timeout) } }
+}
+
+impl Interrupt for Deadline {
+    fn is_set(&self) -> bool { self.at.map_or(false, |at| Instant::now() >= at) }
+}
+
+#[cfg(feature = "async")]
+impl Interrupt for tokio_util::sync::CancellationToken {
+    fn is_set(&self) -> bool { self.is_cancelled() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flag_shared_between_clones() {
+        let flag = Flag::new();
+        let observer = flag.clone();
+        assert!(!observer.is_set());
+        flag.set();
+        assert!(observer.is_set());
+        assert!(Arc::new(observer).is_set());
+        assert!(Deadline::after(Duration::ZERO).is_set());
+        assert!(!Deadline::after(Duration::from_secs(3600)).is_set());
+        assert!((Never::default(), Some(Deadline::after(Duration::ZERO))).is_set());
+        assert!(!(Never::default(), None::<Deadline>).is_set());
+    }
+
+    #[test]
+    fn unrepresentable_deadline_is_never_set() {
+        assert!(!Deadline::after(Duration::from_secs(u64::MAX)).is_set());
+        assert!(!Deadline::after(Duration::MAX).is_set());
+    }
+}
@@ -5,6 +5,11 @@ mod codec;
 mod constants;
 mod decibels;
 mod error;
+mod opus_file;
+mod tag_editor;
+
+/// Introspection of the functionality supported by this build
+pub mod capabilities;
 
 /// Functionality for escaping and unescaping values for command-line tools
 pub mod escaping;
@@ -12,12 +17,24 @@ pub mod escaping;
 /// Functionality for rewriting Ogg Opus streams with new headers
 pub mod header_rewriter;
 
+/// Reading and writing of individual Ogg pages
+pub mod ogg_page;
+
+/// Statistics describing the pages and packets of Ogg streams
+pub mod stream_stats;
+
 /// Functionality for rewriting Ogg Opus streams with new comments
 pub mod comment_rewrite;
 
 /// Support for detecting an operation should be interrupted
 pub mod interrupt;
 
+/// Support for observing the progress of long-running operations
+pub mod progress;
+
+/// Handles for cancelling and monitoring operations running on other threads
+pub mod operation;
+
 /// Functionality for rewriting Ogg Opus streams with altered output gain and
 /// volume tags
 pub mod volume_rewrite;
@@ -25,9 +42,36 @@ pub mod volume_rewrite;
 /// Functionality for manipulating headers
 pub mod header;
 
+/// Persistent storage of computed track loudness
+#[cfg(feature = "analysis")]
+pub mod loudness_cache;
+
+/// Output files which atomically replace their destination once complete
+pub mod output_file;
+
+/// Functionality for reading tags from FLAC and MP3 files
+pub mod import;
+
+/// Translation between the tag naming conventions of different applications
+pub mod tag_names;
+
+/// Checking of Ogg Opus streams against the requirements of RFC 7845
+pub mod validate;
+
+/// Repair of damaged comment headers
+pub mod repair;
+
 /// Types for manipulating headers of Ogg Opus streams
 pub mod opus;
 
+/// Generation of Ogg Opus streams for testing
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+/// Python bindings, built as an extension module named `zoog`
+#[cfg(feature = "python")]
+mod python;
+
 /// Types for manipulating headers of Ogg Vorbis streams
 pub mod vorbis;
 
@@ -35,3 +79,5 @@ pub use codec::*;
 pub use constants::global::*;
 pub use decibels::*;
 pub use error::*;
+pub use opus_file::*;
+pub use tag_editor::*;
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use zoog::header::CommentList;
+use zoog::header_rewriter::{read_headers, CodecHeaders};
+use zoog::opus::{TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
+use zoog::volume_rewrite::{gains_close, OpusGains, VolumeTarget};
+use zoog::{detect_format, Codec, Decibels, Error, FileFormat};
+
+/// A combination of gains which would cause a file to play at the wrong level
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "issue", rename_all = "snake_case")]
+pub enum AuditIssue {
+    /// A gain tag is present but its value cannot be parsed, so players
+    /// ignore it
+    InvalidTag { tag: String, value: String },
+
+    /// There is no `R128_TRACK_GAIN` tag
+    MissingTrackGain,
+
+    /// There is no `R128_ALBUM_GAIN` tag although other files in the same
+    /// directory have one
+    MissingAlbumGain,
+
+    /// The output gain does not bring the track or album to the target
+    /// loudness, so players which ignore the tags play it at the wrong level
+    OutputGainMismatch { expected_db: f64, actual_db: f64 },
+
+    /// The album gain implies a different album loudness from most other files
+    /// in the same directory
+    InconsistentAlbumGain { album_loudness_lufs: f64, expected_lufs: f64 },
+}
+
+impl Display for AuditIssue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            AuditIssue::InvalidTag { tag, value } => write!(f, "{} has the invalid value `{}`", tag, value),
+            AuditIssue::MissingTrackGain => write!(f, "{} is missing", TAG_TRACK_GAIN),
+            AuditIssue::MissingAlbumGain => {
+                write!(f, "{} is missing but other files in the directory have it", TAG_ALBUM_GAIN)
+            }
+            AuditIssue::OutputGainMismatch { expected_db, actual_db } => {
+                write!(f, "output gain is {:.2} dB but should be {:.2} dB", actual_db, expected_db)
+            }
+            AuditIssue::InconsistentAlbumGain { album_loudness_lufs, expected_lufs } => write!(
+                f,
+                "{} implies an album loudness of {:.2} LUFS but the rest of the album implies {:.2} LUFS",
+                TAG_ALBUM_GAIN, album_loudness_lufs, expected_lufs
+            ),
+        }
+    }
+}
+
+/// The gains of an Ogg Opus file
+#[derive(Clone, Debug)]
+pub struct FileGains {
+    /// The path of the file
+    pub path: PathBuf,
+
+    /// The output gain and the values of the gain tags which could be parsed
+    pub gains: OpusGains,
+
+    /// The gain tags whose values could not be parsed
+    pub invalid_tags: Vec<AuditIssue>,
+}
+
+/// Reads the gains of the file at `path`, returning `None` if it is not an Ogg
+/// Opus file
+pub fn read_gains(path: &Path) -> Result<Option<FileGains>, Error> {
+    let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    let mut reader = BufReader::new(file);
+    if detect_format(&mut reader)? != FileFormat::Ogg(Codec::Opus) {
+        return Ok(None);
+    }
+    let CodecHeaders::Opus(id_header, comment_header) = read_headers(&mut reader)? else { return Ok(None) };
+    let invalid_tags = [TAG_TRACK_GAIN, TAG_ALBUM_GAIN]
+        .into_iter()
+        .filter(|tag| comment_header.get_gain_from_tag(tag).is_err())
+        .map(|tag| AuditIssue::InvalidTag {
+            tag: tag.to_string(),
+            value: comment_header.get_first(tag).unwrap_or_default().to_string(),
+        })
+        .collect();
+    let gains = OpusGains::from_headers(&id_header, &comment_header);
+    Ok(Some(FileGains { path: path.to_path_buf(), gains, invalid_tags }))
+}
+
+/// The issues found with a single file
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditedFile {
+    /// The path of the file
+    pub path: PathBuf,
+
+    /// The issues found, which is empty if the file would play at the right
+    /// level
+    pub issues: Vec<AuditIssue>,
+}
+
+/// Chooses the album loudness agreed on by the most files, preferring the
+/// earliest in the case of a tie
+fn majority_loudness(loudness: &[Decibels]) -> Option<Decibels> {
+    let agreeing = |candidate: Decibels| loudness.iter().filter(|other| gains_close(candidate, **other)).count();
+    loudness.iter().copied().rev().max_by_key(|candidate| agreeing(*candidate))
+}
+
+/// Checks the gains of each file against `target`. The output gain is expected
+/// to target the album loudness of files with an album gain tag unless
+/// `track_output_gain` is set. Files in the same directory which have album
+/// gain tags are treated as a single album, whose tags must agree on its
+/// loudness.
+pub fn audit(files: &[FileGains], target: VolumeTarget, track_output_gain: bool) -> Vec<AuditedFile> {
+    let mut album_loudness: BTreeMap<&Path, Vec<Decibels>> = BTreeMap::new();
+    for file in files {
+        let directory = file.path.parent().unwrap_or_else(|| Path::new(""));
+        let entry = album_loudness.entry(directory).or_default();
+        entry.extend(file.gains.implied_album_volume());
+    }
+    let album_loudness: BTreeMap<&Path, Decibels> = album_loudness
+        .into_iter()
+        .filter_map(|(directory, loudness)| majority_loudness(&loudness).map(|majority| (directory, majority)))
+        .collect();
+
+    let mut result = Vec::with_capacity(files.len());
+    for file in files {
+        let gains = file.gains;
+        let mut issues = file.invalid_tags.clone();
+        let track_loudness = gains.implied_track_volume();
+        let file_album_loudness = gains.implied_album_volume();
+        if track_loudness.is_none() {
+            issues.push(AuditIssue::MissingTrackGain);
+        }
+        let directory = file.path.parent().unwrap_or_else(|| Path::new(""));
+        match (file_album_loudness, album_loudness.get(directory)) {
+            (None, Some(_)) => issues.push(AuditIssue::MissingAlbumGain),
+            (Some(loudness), Some(&expected)) if !gains_close(loudness, expected) => {
+                issues.push(AuditIssue::InconsistentAlbumGain {
+                    album_loudness_lufs: loudness.as_f64(),
+                    expected_lufs: expected.as_f64(),
+                });
+            }
+            _ => {}
+        }
+        let expected_output_gain = match target {
+            VolumeTarget::NoChange => None,
+            VolumeTarget::ZeroGain => Some(Decibels::default()),
+            VolumeTarget::Absolute(gain) => Some(gain.into()),
+            VolumeTarget::LUFS(lufs) => {
+                let loudness = if track_output_gain { track_loudness } else { file_album_loudness.or(track_loudness) };
+                loudness.map(|loudness| lufs - loudness)
+            }
+        };
+        if let Some(expected) = expected_output_gain {
+            if !gains_close(expected, gains.output) {
+                issues.push(AuditIssue::OutputGainMismatch {
+                    expected_db: expected.as_f64(),
+                    actual_db: gains.output.as_f64(),
+                });
+            }
+        }
+        result.push(AuditedFile { path: file.path.clone(), issues });
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use zoog::R128_LUFS;
+
+    use super::*;
+
+    #[test]
+    fn inconsistent_gains_reported() {
+        let file = |path: &str, output: f64, track: Option<f64>, album: Option<f64>| FileGains {
+            path: PathBuf::from(path),
+            gains: OpusGains {
+                output: Decibels::from(output),
+                track_r128: track.map(Decibels::from),
+                album_r128: album.map(Decibels::from),
+            },
+            invalid_tags: Vec::new(),
+        };
+        let issues = |files: Vec<FileGains>, track_output_gain| -> Vec<Vec<AuditIssue>> {
+            audit(&files, VolumeTarget::LUFS(R128_LUFS), track_output_gain).into_iter().map(|f| f.issues).collect()
+        };
+        // A track at -20 LUFS tagged for -23 LUFS
+        assert_eq!(issues(vec![file("a.opus", -3.0, Some(0.0), None)], false), vec![vec![]]);
+        assert_eq!(
+            issues(vec![file("a.opus", 0.0, Some(-3.0), None)], false),
+            vec![vec![AuditIssue::OutputGainMismatch { expected_db: -3.0, actual_db: 0.0 }]]
+        );
+        assert_eq!(issues(vec![file("a.opus", 0.0, None, None)], false), vec![vec![AuditIssue::MissingTrackGain]]);
+
+        // An album at -20 LUFS where one track disagrees and one is untagged
+        let album = vec![
+            file("album/1.opus", -3.0, Some(1.0), Some(0.0)),
+            file("album/2.opus", -3.0, Some(-1.0), Some(0.0)),
+            file("album/3.opus", -3.0, Some(0.0), Some(2.0)),
+            file("album/4.opus", -3.0, Some(0.0), None),
+        ];
+        let result = issues(album.clone(), false);
+        assert_eq!(&result[..2], &[vec![], vec![]]);
+        assert_eq!(
+            result[2],
+            vec![
+                AuditIssue::InconsistentAlbumGain { album_loudness_lufs: -22.0, expected_lufs: -20.0 },
+                AuditIssue::OutputGainMismatch { expected_db: -1.0, actual_db: -3.0 },
+            ]
+        );
+        assert_eq!(result[3], vec![AuditIssue::MissingAlbumGain]);
+
+        // The output gain of album tracks targeting their track loudness
+        let result = issues(album, true);
+        assert_eq!(result[0], vec![AuditIssue::OutputGainMismatch { expected_db: -2.0, actual_db: -3.0 }]);
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use zoog::header::{CommentHeader, IdHeader};
+use zoog::header_rewriter::{read_headers, CodecHeaders};
+use zoog::import::take_id3v2_prefix;
+use zoog::ogg_page::last_granule_position;
+use zoog::volume_rewrite::OpusGains;
+use zoog::{detect_format, Decibels, Error, FileFormat, R128_LUFS};
+
+/// The metadata of a single file found by a scan
+#[derive(Clone, Debug, Serialize)]
+pub struct ScannedFile {
+    /// The path of the file
+    pub path: PathBuf,
+
+    /// The codec of the file
+    pub codec: String,
+
+    /// The vendor string from the comment header
+    pub vendor: String,
+
+    /// The number of output channels
+    pub channels: usize,
+
+    /// The duration of the first logical stream in seconds
+    pub duration_seconds: f64,
+
+    /// The output gain in decibels (Opus only)
+    pub output_gain_db: Option<f64>,
+
+    /// The gain stored in the `R128_TRACK_GAIN` tag in decibels (Opus only)
+    pub track_gain_db: Option<f64>,
+
+    /// The gain stored in the `R128_ALBUM_GAIN` tag in decibels (Opus only)
+    pub album_gain_db: Option<f64>,
+
+    /// The loudness of the track before the output gain is applied, implied by
+    /// the output gain and `R128_TRACK_GAIN` tag
+    pub track_loudness_lufs: Option<f64>,
+
+    /// The loudness of the album before the output gain is applied, implied by
+    /// the output gain and `R128_ALBUM_GAIN` tag
+    pub album_loudness_lufs: Option<f64>,
+
+    /// The values of each tag in the order they occur, by upper-case name
+    pub tags: BTreeMap<String, Vec<String>>,
+}
+
+/// The loudness at which a gain relative to the output gain reaches the R128
+/// reference level
+fn implied_loudness(output: Decibels, gain: Option<Decibels>) -> Option<f64> {
+    gain.map(|gain| (R128_LUFS - gain - output).as_f64())
+}
+
+fn tags_by_name<C: CommentHeader>(header: &C) -> BTreeMap<String, Vec<String>> {
+    let mut tags: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (key, value) in header.iter() {
+        tags.entry(key.to_ascii_uppercase()).or_default().push(value.to_string());
+    }
+    tags
+}
+
+/// Reads the metadata of the file at `path`, returning `None` if it is not an
+/// Ogg Opus or Ogg Vorbis file
+pub fn scan_file(path: &Path) -> Result<Option<ScannedFile>, Error> {
+    let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    let mut reader = BufReader::new(file);
+    if !matches!(detect_format(&mut reader)?, FileFormat::Ogg(_)) {
+        return Ok(None);
+    }
+    let headers = read_headers(&mut reader)?;
+    reader.rewind().map_err(|e| Error::FileReadError(path.to_path_buf(), e))?;
+    take_id3v2_prefix(&mut reader)?;
+    let granule_position = last_granule_position(&mut reader)?.unwrap_or(0);
+
+    let (channels, sample_rate, preskip) = match &headers {
+        CodecHeaders::Opus(id, _) => (id.num_output_channels(), id.output_sample_rate(), id.preskip_samples()),
+        CodecHeaders::Vorbis(id, _) => (id.num_output_channels(), id.output_sample_rate(), id.preskip_samples()),
+    };
+    let (vendor, tags) = match &headers {
+        CodecHeaders::Opus(_, comments) => (comments.get_vendor(), tags_by_name(comments)),
+        CodecHeaders::Vorbis(_, comments) => (comments.get_vendor(), tags_by_name(comments)),
+    };
+    let gains = match &headers {
+        CodecHeaders::Opus(id, comments) => Some(OpusGains::from_headers(id, comments)),
+        CodecHeaders::Vorbis(..) => None,
+    };
+    #[allow(clippy::cast_precision_loss)]
+    let duration_seconds = if sample_rate == 0 {
+        0.0
+    } else {
+        granule_position.saturating_sub(preskip as u64) as f64 / sample_rate as f64
+    };
+    Ok(Some(ScannedFile {
+        path: path.to_path_buf(),
+        codec: headers.codec().to_string(),
+        vendor: vendor.to_string(),
+        channels,
+        duration_seconds,
+        output_gain_db: gains.map(|gains| gains.output.as_f64()),
+        track_gain_db: gains.and_then(|gains| gains.track_r128).map(|gain| gain.as_f64()),
+        album_gain_db: gains.and_then(|gains| gains.album_r128).map(|gain| gain.as_f64()),
+        track_loudness_lufs: gains.and_then(|gains| implied_loudness(gains.output, gains.track_r128)),
+        album_loudness_lufs: gains.and_then(|gains| implied_loudness(gains.output, gains.album_r128)),
+        tags,
+    }))
+}
+
+/// Finds all files within `path`, or `path` itself if it is a file, in sorted
+/// order. Symbolic links to directories are not followed.
+pub fn find_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let metadata = fs::symlink_metadata(path).map_err(|e| Error::FileReadError(path.to_path_buf(), e))?;
+    if !metadata.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let entries = fs::read_dir(path).map_err(|e| Error::FileReadError(path.to_path_buf(), e))?;
+    let mut children = entries
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::FileReadError(path.to_path_buf(), e))?;
+    children.sort();
+    for child in children {
+        let metadata = fs::symlink_metadata(&child).map_err(|e| Error::FileReadError(child.clone(), e))?;
+        if metadata.is_dir() {
+            find_files(&child, files)?;
+        } else if fs::metadata(&child).map_or(false, |metadata| metadata.is_file()) {
+            files.push(child);
+        }
+    }
+    Ok(())
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn optional_number(value: Option<f64>) -> String { value.map(|value| value.to_string()).unwrap_or_default() }
+
+/// Writes the files as CSV with a header row. Each tag has a column for as
+/// many values as any file has for it, so a tag with several values is spread
+/// across columns with the same name.
+pub fn write_csv<W: Write>(mut writer: W, files: &[ScannedFile]) -> io::Result<()> {
+    let mut tag_columns: BTreeMap<&str, usize> = BTreeMap::new();
+    for file in files {
+        for (name, values) in &file.tags {
+            let count = tag_columns.entry(name).or_default();
+            *count = (*count).max(values.len());
+        }
+    }
+    let mut header: Vec<String> = [
+        "path",
+        "codec",
+        "vendor",
+        "channels",
+        "duration_seconds",
+        "output_gain_db",
+        "track_gain_db",
+        "album_gain_db",
+        "track_loudness_lufs",
+        "album_loudness_lufs",
+    ]
+    .iter()
+    .map(|name| (*name).to_string())
+    .collect();
+    for (name, count) in &tag_columns {
+        header.extend(std::iter::repeat((*name).to_string()).take(*count));
+    }
+    writeln!(writer, "{}", header.iter().map(|name| csv_field(name)).collect::<Vec<_>>().join(","))?;
+    for file in files {
+        let mut fields = vec![
+            file.path.to_string_lossy().into_owned(),
+            file.codec.clone(),
+            file.vendor.clone(),
+            file.channels.to_string(),
+            file.duration_seconds.to_string(),
+            optional_number(file.output_gain_db),
+            optional_number(file.track_gain_db),
+            optional_number(file.album_gain_db),
+            optional_number(file.track_loudness_lufs),
+            optional_number(file.album_loudness_lufs),
+        ];
+        for (name, count) in &tag_columns {
+            let values = file.tags.get(*name).map_or(&[][..], Vec::as_slice);
+            fields.extend((0..*count).map(|index| values.get(index).cloned().unwrap_or_default()));
+        }
+        writeln!(writer, "{}", fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(","))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_columns_per_tag_value() -> io::Result<()> {
+        let file = |path: &str, tags: &[(&str, &[&str])]| ScannedFile {
+            path: PathBuf::from(path),
+            codec: String::from("Opus"),
+            vendor: String::from("vendor"),
+            channels: 2,
+            duration_seconds: 1.5,
+            output_gain_db: Some(-2.0),
+            track_gain_db: None,
+            album_gain_db: None,
+            track_loudness_lufs: None,
+            album_loudness_lufs: None,
+            tags: tags
+                .iter()
+                .map(|(name, values)| ((*name).to_string(), values.iter().map(|v| (*v).to_string()).collect()))
+                .collect(),
+        };
+        let files = [
+            file("a.opus", &[("ARTIST", &["One", "Two"]), ("TITLE", &["A, \"B\""])]),
+            file("b.opus", &[("ARTIST", &["Three"])]),
+        ];
+        let mut output = Vec::new();
+        write_csv(&mut output, &files)?;
+        let expected = "path,codec,vendor,channels,duration_seconds,output_gain_db,track_gain_db,album_gain_db,\
+                        track_loudness_lufs,album_loudness_lufs,ARTIST,ARTIST,TITLE\n\
+                        a.opus,Opus,vendor,2,1.5,-2,,,,,One,Two,\"A, \"\"B\"\"\"\n\
+                        b.opus,Opus,vendor,2,1.5,-2,,,,,Three,,\n";
+        assert_eq!(String::from_utf8_lossy(&output), expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::opus::TrackAnalysis;
+use crate::{Decibels, Error};
+
+/// Signature at the start of every cache entry
+const ENTRY_MAGIC: &[u8] = b"ZoogLoud";
+
+/// Version of the entry format. Entries with other versions are ignored.
+const ENTRY_VERSION: u32 = 2;
+
+/// Extension of cache entry files
+const ENTRY_EXTENSION: &str = "loudness";
+
+/// Identifies a particular version of a file. If the file is modified, its key
+/// will change.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CacheKey {
+    path: String,
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+}
+
+impl CacheKey {
+    /// Computes the key for the current state of the file at `path`
+    pub fn for_file(path: &Path) -> Result<CacheKey, Error> {
+        let read_error = |e| Error::FileReadError(path.to_path_buf(), e);
+        let canonical = fs::canonicalize(path).map_err(read_error)?;
+        let metadata = fs::metadata(&canonical).map_err(read_error)?;
+        let modified = metadata.modified().map_err(read_error)?;
+        let since_epoch = modified
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| read_error(io::Error::new(io::ErrorKind::InvalidData, "modification time before 1970")))?;
+        Ok(CacheKey {
+            path: canonical.to_string_lossy().into_owned(),
+            size: metadata.len(),
+            modified_secs: since_epoch.as_secs(),
+            modified_nanos: since_epoch.subsec_nanos(),
+        })
+    }
+
+    /// The 64-bit FNV-1a hash of the path, which unlike the standard library
+    /// hashers is guaranteed to be stable
+    fn path_hash(&self) -> u64 {
+        self.path.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ u64::from(b)).wrapping_mul(0x100_0000_01b3))
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        let path_len = u32::try_from(self.path.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path too long for cache entry"))?;
+        writer.write_u32::<LittleEndian>(path_len)?;
+        writer.write_all(self.path.as_bytes())?;
+        writer.write_u64::<LittleEndian>(self.size)?;
+        writer.write_u64::<LittleEndian>(self.modified_secs)?;
+        writer.write_u32::<LittleEndian>(self.modified_nanos)
+    }
+
+    fn read<R: Read>(reader: &mut R) -> Result<CacheKey, io::Error> {
+        let path_len = reader.read_u32::<LittleEndian>()?;
+        let mut path = Vec::new();
+        reader.take(u64::from(path_len)).read_to_end(&mut path)?;
+        let path = String::from_utf8(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let size = reader.read_u64::<LittleEndian>()?;
+        let modified_secs = reader.read_u64::<LittleEndian>()?;
+        let modified_nanos = reader.read_u32::<LittleEndian>()?;
+        Ok(CacheKey { path, size, modified_secs, modified_nanos })
+    }
+}
+
+/// An on-disk cache of computed track loudness, stored as one file per track
+/// in a directory. Entries are written atomically so the same cache may be
+/// shared between threads, processes and tools.
+#[derive(Clone, Debug)]
+pub struct LoudnessCache {
+    dir: PathBuf,
+}
+
+impl LoudnessCache {
+    /// Opens the cache in the specified directory, creating it if necessary
+    pub fn open(dir: &Path) -> Result<LoudnessCache, Error> {
+        fs::create_dir_all(dir).map_err(|e| Error::FileWriteError(dir.to_path_buf(), e))?;
+        Ok(LoudnessCache { dir: dir.to_path_buf() })
+    }
+
+    fn entry_path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(format!("{:016x}.{}", key.path_hash(), ENTRY_EXTENSION))
+    }
+
+    /// Looks up the loudness of the file identified by `key`. Entries which are
+    /// missing, were stored for a different version of the file or cannot be
+    /// read are all treated as absent.
+    pub fn get(&self, key: &CacheKey) -> Option<TrackAnalysis> {
+        let file = File::open(self.entry_path(key)).ok()?;
+        let mut reader = BufReader::new(file);
+        let read_entry = |reader: &mut BufReader<File>| -> Result<Option<TrackAnalysis>, io::Error> {
+            let mut magic = [0u8; ENTRY_MAGIC.len()];
+            reader.read_exact(&mut magic)?;
+            if magic != ENTRY_MAGIC || reader.read_u32::<LittleEndian>()? != ENTRY_VERSION {
+                return Ok(None);
+            }
+            if CacheKey::read(reader)? != *key {
+                return Ok(None);
+            }
+            let lufs = Decibels::from(reader.read_f64::<LittleEndian>()?);
+            let peak = reader.read_f32::<LittleEndian>()?;
+            let duration = Duration::new(reader.read_u64::<LittleEndian>()?, reader.read_u32::<LittleEndian>()?);
+            let num_windows = reader.read_u64::<LittleEndian>()?;
+            let mut window_powers = Vec::new();
+            for _ in 0..num_windows {
+                window_powers.push(reader.read_f32::<LittleEndian>()?);
+            }
+            Ok(Some(TrackAnalysis::new(lufs, peak, duration, window_powers)))
+        };
+        read_entry(&mut reader).ok().flatten()
+    }
+
+    /// Stores the loudness of the file identified by `key`, replacing any
+    /// existing entry for the same path
+    pub fn insert(&self, key: &CacheKey, loudness: &TrackAnalysis) -> Result<(), Error> {
+        let temp = tempfile::Builder::new()
+            .suffix(".tmp")
+            .tempfile_in(&self.dir)
+            .map_err(|e| Error::TempFileOpenError(self.dir.clone(), e))?;
+        {
+            let mut writer = BufWriter::new(temp.as_file());
+            let mut write_entry = || -> Result<(), io::Error> {
+                writer.write_all(ENTRY_MAGIC)?;
+                writer.write_u32::<LittleEndian>(ENTRY_VERSION)?;
+                key.write(&mut writer)?;
+                writer.write_f64::<LittleEndian>(loudness.lufs().as_f64())?;
+                writer.write_f32::<LittleEndian>(loudness.peak())?;
+                writer.write_u64::<LittleEndian>(loudness.duration().as_secs())?;
+                writer.write_u32::<LittleEndian>(loudness.duration().subsec_nanos())?;
+                writer.write_u64::<LittleEndian>(loudness.window_powers().len() as u64)?;
+                for power in loudness.window_powers() {
+                    writer.write_f32::<LittleEndian>(power)?;
+                }
+                writer.flush()
+            };
+            write_entry().map_err(|e| Error::FileWriteError(temp.path().to_path_buf(), e))?;
+        }
+        temp.persist(self.entry_path(key))?;
+        Ok(())
+    }
+
+    /// Associates the entry stored for `from` with `to`. This is intended for
+    /// use after a file has been rewritten without altering its audio, so that
+    /// its loudness does not need to be recomputed.
+    pub fn transfer(&self, from: &CacheKey, to: &CacheKey) -> Result<(), Error> {
+        match self.get(from) {
+            Some(loudness) => self.insert(to, &loudness),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_get_and_invalidate() -> Result<(), Error> {
+        let dir = tempfile::tempdir().map_err(Error::ReadError)?;
+        let cache = LoudnessCache::open(&dir.path().join("cache"))?;
+        let track_path = dir.path().join("track.opus");
+        fs::write(&track_path, b"audio").map_err(Error::WriteError)?;
+
+        let key = CacheKey::for_file(&track_path)?;
+        assert!(cache.get(&key).is_none());
+        let duration = Duration::from_millis(1234);
+        cache.insert(&key, &TrackAnalysis::new(Decibels::from(-20.5), 0.75, duration, vec![0.25, 0.5]))?;
+        let cached = cache.get(&key).expect("Cache entry missing");
+        assert!((cached.lufs().as_f64() - -20.5).abs() < f64::EPSILON);
+        assert!((cached.peak() - 0.75).abs() < f32::EPSILON);
+        assert_eq!(cached.duration(), duration);
+        assert_eq!(cached.window_powers().collect::<Vec<_>>(), vec![0.25, 0.5]);
+
+        fs::write(&track_path, b"modified audio").map_err(Error::WriteError)?;
+        let new_key = CacheKey::for_file(&track_path)?;
+        assert!(cache.get(&new_key).is_none());
+        cache.transfer(&key, &new_key)?;
+        assert!(cache.get(&new_key).is_some());
+        assert!(cache.get(&key).is_none());
+        Ok(())
+    }
+}
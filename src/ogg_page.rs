@@ -0,0 +1,666 @@
+use std::io::{self, Read, Write};
+
+use byteorder::{ByteOrder, LittleEndian};
+use ogg::OggReadError;
+
+use crate::Error;
+
+/// The signature at the start of every Ogg page
+const CAPTURE_PATTERN: &[u8] = b"OggS";
+
+/// The length of the fixed-size portion of a page header
+const HEADER_LEN: usize = 27;
+
+/// The offset of the checksum within the page header
+const CHECKSUM_OFFSET: usize = 22;
+
+/// The lacing value of a segment which does not end a packet
+const MAX_LACING_VALUE: u8 = 255;
+
+/// The maximum number of segments in a page
+const MAX_SEGMENTS: usize = 255;
+
+pub(crate) const FLAG_CONTINUED: u8 = 0x01;
+pub(crate) const FLAG_FIRST: u8 = 0x02;
+pub(crate) const FLAG_LAST: u8 = 0x04;
+
+/// The granule position of a page on which no packet ends
+pub const NO_GRANULE_POSITION: u64 = u64::MAX;
+
+#[allow(clippy::cast_possible_truncation)]
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < table.len() {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 == 0 { crc << 1 } else { (crc << 1) ^ 0x04c1_1db7 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Lookup table for the CRC used by Ogg (polynomial 0x04c11db7, no
+/// reflection, zero initial value and no final XOR)
+static CRC_TABLE: [u32; 256] = crc_table();
+
+fn crc_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| (crc << 8) ^ CRC_TABLE[usize::from(crc.to_be_bytes()[0] ^ b)])
+}
+
+/// How pages whose stored checksum does not match their content are handled
+/// when read
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ChecksumPolicy {
+    /// Fail with an error
+    #[default]
+    Reject,
+
+    /// Discard the page and continue with the next one
+    Skip,
+
+    /// Keep the page and replace its checksum with the correct one
+    Repair,
+}
+
+/// A page whose stored checksum does not match its content
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ChecksumMismatch {
+    /// The serial number of the logical stream the page belongs to
+    pub serial: u32,
+
+    /// The sequence number of the page
+    pub sequence: u32,
+
+    /// The checksum stored in the page
+    pub stored: u32,
+
+    /// The checksum computed from the content of the page
+    pub computed: u32,
+}
+
+/// A single page of an Ogg stream. Unlike the packet-level interface of the
+/// `ogg` crate, this allows pages to be copied without altering their
+/// boundaries.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Page {
+    header_type: u8,
+    granule_position: u64,
+    serial: u32,
+    sequence: u32,
+    checksum: u32,
+    lacing: Vec<u8>,
+    data: Vec<u8>,
+}
+
+/// Generates pages with a correct checksum and only the header flags defined
+/// by RFC 3533
+#[cfg(any(test, feature = "arbitrary"))]
+impl<'a> arbitrary::Arbitrary<'a> for Page {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Page> {
+        let header_type = u.arbitrary::<u8>()? & (FLAG_CONTINUED | FLAG_FIRST | FLAG_LAST);
+        let num_segments = u.arbitrary_len::<u8>()?.min(MAX_SEGMENTS);
+        let lacing = (0..num_segments).map(|_| u.arbitrary()).collect::<arbitrary::Result<Vec<u8>>>()?;
+        let data_len = lacing.iter().copied().map(usize::from).sum();
+        let data = (0..data_len).map(|_| u.arbitrary()).collect::<arbitrary::Result<Vec<u8>>>()?;
+        let mut page = Page {
+            header_type,
+            granule_position: u.arbitrary()?,
+            serial: u.arbitrary()?,
+            sequence: u.arbitrary()?,
+            checksum: 0,
+            lacing,
+            data,
+        };
+        page.checksum = page.compute_checksum();
+        Ok(page)
+    }
+}
+
+impl Page {
+    fn empty(serial: u32, sequence: u32, header_type: u8) -> Page {
+        Page {
+            header_type,
+            granule_position: NO_GRANULE_POSITION,
+            serial,
+            sequence,
+            checksum: 0,
+            lacing: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+
+    /// Constructs a page from its lacing values and segment data, computing its
+    /// checksum
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn from_segments(
+        serial: u32, sequence: u32, header_type: u8, granule_position: u64, lacing: Vec<u8>, data: Vec<u8>,
+    ) -> Page {
+        let mut page = Page { header_type, granule_position, serial, sequence, checksum: 0, lacing, data };
+        page.checksum = page.compute_checksum();
+        page
+    }
+
+    /// Parses the fixed-size portion of a page header, returning the page
+    /// without its segments along with the number of lacing values
+    fn from_header(header: &[u8; HEADER_LEN]) -> Result<(Page, usize), Error> {
+        if &header[..CAPTURE_PATTERN.len()] != CAPTURE_PATTERN {
+            return Err(Error::OggDecode(OggReadError::NoCapturePatternFound));
+        }
+        if header[4] != 0 {
+            return Err(Error::OggDecode(OggReadError::InvalidStreamStructVer(header[4])));
+        }
+        let page = Page {
+            header_type: header[5],
+            granule_position: LittleEndian::read_u64(&header[6..14]),
+            serial: LittleEndian::read_u32(&header[14..18]),
+            sequence: LittleEndian::read_u32(&header[18..22]),
+            checksum: LittleEndian::read_u32(&header[CHECKSUM_OFFSET..26]),
+            lacing: Vec::new(),
+            data: Vec::new(),
+        };
+        Ok((page, usize::from(header[26])))
+    }
+
+    /// The length of the segment data described by the lacing values
+    fn data_len(&self) -> usize { self.lacing.iter().copied().map(usize::from).sum() }
+
+    fn verify_checksum(self) -> Result<Page, Error> {
+        let computed = self.compute_checksum();
+        if computed == self.checksum {
+            Ok(self)
+        } else {
+            Err(Error::OggDecode(OggReadError::HashMismatch(self.checksum, computed)))
+        }
+    }
+
+    /// Reads the next page, verifying its checksum. Returns `None` if the end
+    /// of the input was reached before the start of a page.
+    pub fn read<R: Read>(reader: &mut R) -> Result<Option<Page>, Error> {
+        Page::read_unverified(reader)?.map(Page::verify_checksum).transpose()
+    }
+
+    /// Reads the next page, handling an incorrect checksum according to
+    /// `policy`. Returns `None` if the end of the input was reached before the
+    /// start of a page.
+    pub fn read_with_policy<R: Read>(reader: &mut R, policy: ChecksumPolicy) -> Result<Option<Page>, Error> {
+        match policy {
+            ChecksumPolicy::Reject => Page::read(reader),
+            ChecksumPolicy::Skip => {
+                while let Some(page) = Page::read_unverified(reader)? {
+                    if page.has_valid_checksum() {
+                        return Ok(Some(page));
+                    }
+                }
+                Ok(None)
+            }
+            ChecksumPolicy::Repair => {
+                let mut page = Page::read_unverified(reader)?;
+                if let Some(page) = page.as_mut() {
+                    page.repair_checksum();
+                }
+                Ok(page)
+            }
+        }
+    }
+
+    /// Reads the next page without verifying its checksum. Returns `None` if
+    /// the end of the input was reached before the start of a page.
+    pub fn read_unverified<R: Read>(reader: &mut R) -> Result<Option<Page>, Error> {
+        let mut header = [0u8; HEADER_LEN];
+        let mut filled = 0;
+        while filled < header.len() {
+            match reader.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(Error::ReadError(io::ErrorKind::UnexpectedEof.into())),
+                Ok(len) => filled += len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::ReadError(e)),
+            }
+        }
+        let (mut page, num_segments) = Page::from_header(&header)?;
+        page.lacing = vec![0u8; num_segments];
+        reader.read_exact(&mut page.lacing).map_err(Error::ReadError)?;
+        page.data = vec![0u8; page.data_len()];
+        reader.read_exact(&mut page.data).map_err(Error::ReadError)?;
+        Ok(Some(page))
+    }
+
+    /// Asynchronous equivalent of `read`
+    #[cfg(feature = "async")]
+    pub async fn read_async<R: tokio::io::AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Page>, Error> {
+        use tokio::io::AsyncReadExt as _;
+
+        let mut header = [0u8; HEADER_LEN];
+        let mut filled = 0;
+        while filled < header.len() {
+            match reader.read(&mut header[filled..]).await.map_err(Error::ReadError)? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(Error::ReadError(io::ErrorKind::UnexpectedEof.into())),
+                len => filled += len,
+            }
+        }
+        let (mut page, num_segments) = Page::from_header(&header)?;
+        page.lacing = vec![0u8; num_segments];
+        reader.read_exact(&mut page.lacing).await.map_err(Error::ReadError)?;
+        page.data = vec![0u8; page.data_len()];
+        reader.read_exact(&mut page.data).await.map_err(Error::ReadError)?;
+        page.verify_checksum().map(Some)
+    }
+
+    fn header_bytes(&self, checksum: u32) -> [u8; HEADER_LEN] {
+        let mut header = [0u8; HEADER_LEN];
+        header[..CAPTURE_PATTERN.len()].copy_from_slice(CAPTURE_PATTERN);
+        header[5] = self.header_type;
+        LittleEndian::write_u64(&mut header[6..14], self.granule_position);
+        LittleEndian::write_u32(&mut header[14..18], self.serial);
+        LittleEndian::write_u32(&mut header[18..22], self.sequence);
+        LittleEndian::write_u32(&mut header[CHECKSUM_OFFSET..26], checksum);
+        header[26] = u8::try_from(self.lacing.len()).expect("Too many segments in page");
+        header
+    }
+
+    /// Computes the checksum the page should have given its content
+    pub fn compute_checksum(&self) -> u32 {
+        let crc = crc_update(0, &self.header_bytes(0));
+        let crc = crc_update(crc, &self.lacing);
+        crc_update(crc, &self.data)
+    }
+
+    /// The checksum stored in the page header
+    pub fn checksum(&self) -> u32 { self.checksum }
+
+    /// Does the stored checksum match the content of the page
+    pub fn has_valid_checksum(&self) -> bool { self.checksum == self.compute_checksum() }
+
+    /// Replaces the stored checksum with one computed from the content of the
+    /// page
+    pub fn repair_checksum(&mut self) { self.checksum = self.compute_checksum(); }
+
+    /// Writes the page exactly as it was read or constructed
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.header_bytes(self.checksum)).map_err(Error::WriteError)?;
+        writer.write_all(&self.lacing).map_err(Error::WriteError)?;
+        writer.write_all(&self.data).map_err(Error::WriteError)
+    }
+
+    /// Asynchronous equivalent of `write`
+    #[cfg(feature = "async")]
+    pub async fn write_async<W: tokio::io::AsyncWrite + Unpin>(&self, writer: &mut W) -> Result<(), Error> {
+        use tokio::io::AsyncWriteExt as _;
+
+        writer.write_all(&self.header_bytes(self.checksum)).await.map_err(Error::WriteError)?;
+        writer.write_all(&self.lacing).await.map_err(Error::WriteError)?;
+        writer.write_all(&self.data).await.map_err(Error::WriteError)
+    }
+
+    /// The serial number of the logical stream the page belongs to
+    pub fn serial(&self) -> u32 { self.serial }
+
+    /// The sequence number of the page within its logical stream
+    pub fn sequence(&self) -> u32 { self.sequence }
+
+    /// Changes the sequence number of the page, updating the checksum to match
+    pub fn set_sequence(&mut self, sequence: u32) {
+        self.sequence = sequence;
+        self.checksum = self.compute_checksum();
+    }
+
+    /// Removes any trailing packet fragment which does not end on this page
+    /// and marks the page as the last in its stream
+    fn terminate_stream(&mut self) {
+        let end = self.lacing.iter().rposition(|&len| len != MAX_LACING_VALUE).map_or(0, |idx| idx + 1);
+        self.lacing.truncate(end);
+        self.data.truncate(self.data_len());
+        self.header_type |= FLAG_LAST;
+        self.checksum = self.compute_checksum();
+    }
+
+    /// The granule position of the last packet ending on this page
+    pub fn granule_position(&self) -> u64 { self.granule_position }
+
+    /// Is the page the first of its logical stream
+    pub fn is_first_in_stream(&self) -> bool { self.header_type & FLAG_FIRST != 0 }
+
+    /// Is the page the last of its logical stream
+    pub fn is_last_in_stream(&self) -> bool { self.header_type & FLAG_LAST != 0 }
+
+    /// Does the page begin with the continuation of a packet from the previous
+    /// page
+    pub fn is_continuation(&self) -> bool { self.header_type & FLAG_CONTINUED != 0 }
+
+    /// Does the final packet on the page end on it, rather than continuing on
+    /// the next page
+    pub fn ends_packet(&self) -> bool { self.lacing.last().map_or(false, |&len| len != MAX_LACING_VALUE) }
+
+    /// The lacing values of the segments in the page
+    pub fn lacing(&self) -> &[u8] { &self.lacing }
+
+    /// The concatenated segment data of the page
+    pub fn data(&self) -> &[u8] { &self.data }
+
+    /// The size of the page in bytes, including its header
+    pub fn size(&self) -> usize { HEADER_LEN + self.lacing.len() + self.data.len() }
+
+    /// Splits the page data into packet fragments. Each fragment is paired with
+    /// a flag indicating whether the packet ends with that fragment.
+    pub fn packet_fragments(&self) -> Vec<(&[u8], bool)> {
+        let mut fragments = Vec::new();
+        let (mut start, mut end) = (0, 0);
+        for &len in &self.lacing {
+            end += usize::from(len);
+            if len != MAX_LACING_VALUE {
+                fragments.push((&self.data[start..end], true));
+                start = end;
+            }
+        }
+        if !self.lacing.is_empty() && !self.ends_packet() {
+            fragments.push((&self.data[start..end], false));
+        }
+        fragments
+    }
+
+    /// Splits complete packets into pages of a logical stream, starting with
+    /// sequence number `first_sequence`. All pages on which a packet ends are
+    /// given the same granule position, so this is intended for header packets
+    /// rather than audio.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn paginate(
+        packets: &[Vec<u8>], serial: u32, first_sequence: u32, granule_position: u64, first_in_stream: bool,
+    ) -> Vec<Page> {
+        let mut pages = Vec::new();
+        if packets.is_empty() {
+            return pages;
+        }
+        let mut current = Page::empty(serial, first_sequence, if first_in_stream { FLAG_FIRST } else { 0 });
+        for packet in packets {
+            let mut remaining = packet.as_slice();
+            loop {
+                if current.lacing.len() == MAX_SEGMENTS {
+                    let header_type = if current.ends_packet() { 0 } else { FLAG_CONTINUED };
+                    let next = Page::empty(serial, current.sequence.wrapping_add(1), header_type);
+                    pages.push(std::mem::replace(&mut current, next));
+                }
+                let len = remaining.len().min(usize::from(MAX_LACING_VALUE));
+                current.lacing.push(u8::try_from(len).expect("Segment length exceeds lacing value"));
+                current.data.extend_from_slice(&remaining[..len]);
+                remaining = &remaining[len..];
+                if len < usize::from(MAX_LACING_VALUE) {
+                    break;
+                }
+            }
+        }
+        pages.push(current);
+        for page in &mut pages {
+            if page.lacing.iter().any(|&len| len != MAX_LACING_VALUE) {
+                page.granule_position = granule_position;
+            }
+            page.checksum = page.compute_checksum();
+        }
+        pages
+    }
+}
+
+/// Reads every page from `reader` and returns those whose checksums are
+/// incorrect
+pub fn find_checksum_mismatches<R: Read>(mut reader: R) -> Result<Vec<ChecksumMismatch>, Error> {
+    let mut mismatches = Vec::new();
+    while let Some(page) = Page::read_unverified(&mut reader)? {
+        let computed = page.compute_checksum();
+        if computed != page.checksum {
+            mismatches.push(ChecksumMismatch {
+                serial: page.serial,
+                sequence: page.sequence,
+                stored: page.checksum,
+                computed,
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Copies every page from `reader` to `writer`, handling pages with incorrect
+/// checksums according to `policy`. Returns the number of pages which were
+/// repaired or skipped. Skipping pages leaves gaps in the sequence numbers of
+/// their streams.
+pub fn copy_pages_with_policy<R: Read, W: Write>(
+    mut reader: R, mut writer: W, policy: ChecksumPolicy,
+) -> Result<usize, Error> {
+    let mut affected = 0;
+    while let Some(mut page) = Page::read_unverified(&mut reader)? {
+        if !page.has_valid_checksum() {
+            affected += 1;
+            match policy {
+                ChecksumPolicy::Reject => page = page.verify_checksum()?,
+                ChecksumPolicy::Skip => continue,
+                ChecksumPolicy::Repair => page.repair_checksum(),
+            }
+        }
+        page.write(&mut writer)?;
+    }
+    writer.flush().map_err(Error::WriteError)?;
+    Ok(affected)
+}
+
+/// The outcome of `salvage_pages`
+#[derive(Debug, Default)]
+pub struct SalvageSummary {
+    /// The error which stopped reading, if the input was damaged
+    pub error: Option<Error>,
+
+    /// The number of logical streams which were given an end-of-stream page
+    pub streams_terminated: usize,
+}
+
+impl SalvageSummary {
+    /// Was the input copied without alteration
+    pub fn is_intact(&self) -> bool { self.error.is_none() && self.streams_terminated == 0 }
+}
+
+/// Copies pages from `reader` to `writer` until the input ends or a truncated
+/// or corrupt page is encountered. Rather than failing, any logical streams
+/// which have not ended are terminated after the last complete packet, so the
+/// output is a valid Ogg stream. I/O errors other than unexpected end of input
+/// are still returned as errors.
+pub fn salvage_pages<R: Read, W: Write>(mut reader: R, mut writer: W) -> Result<SalvageSummary, Error> {
+    // For each unterminated stream, the pages read since and including the
+    // most recent one on which a packet ended
+    let mut pending: Vec<(u32, Vec<Page>)> = Vec::new();
+    let error = loop {
+        let page = match Page::read(&mut reader) {
+            Ok(Some(page)) => page,
+            Ok(None) => break None,
+            Err(e @ Error::OggDecode(_)) => break Some(e),
+            Err(Error::ReadError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break Some(Error::ReadError(e)),
+            Err(e) => return Err(e),
+        };
+        let idx = pending.iter().position(|(serial, _)| *serial == page.serial).unwrap_or_else(|| {
+            pending.push((page.serial, Vec::new()));
+            pending.len() - 1
+        });
+        let pages = &mut pending[idx].1;
+        if page.is_last_in_stream() || page.lacing.iter().any(|&len| len != MAX_LACING_VALUE) {
+            for page in pages.drain(..) {
+                page.write(&mut writer)?;
+            }
+        }
+        if page.is_last_in_stream() {
+            page.write(&mut writer)?;
+            pending.remove(idx);
+        } else {
+            pages.push(page);
+        }
+    };
+
+    if let Some(error) = &error {
+        tracing::warn!(%error, "Stream damaged, discarding remaining data");
+    }
+    let mut summary = SalvageSummary { error, streams_terminated: 0 };
+    for (serial, pages) in pending {
+        let Some(mut page) = pages.into_iter().next() else { continue };
+        page.terminate_stream();
+        if !page.lacing.is_empty() {
+            tracing::debug!(serial, sequence = page.sequence(), "Terminated truncated stream");
+            page.write(&mut writer)?;
+            summary.streams_terminated += 1;
+        }
+    }
+    writer.flush().map_err(Error::WriteError)?;
+    Ok(summary)
+}
+
+/// Finds the granule position of the final page of the first logical stream
+/// read from `reader`. Pages without a granule position are skipped.
+pub fn last_granule_position<R: Read>(mut reader: R) -> Result<Option<u64>, Error> {
+    let mut serial = None;
+    let mut last = None;
+    while let Some(page) = Page::read(&mut reader)? {
+        if *serial.get_or_insert(page.serial()) != page.serial() {
+            continue;
+        }
+        if page.granule_position() != NO_GRANULE_POSITION {
+            last = Some(page.granule_position());
+        }
+        if page.is_last_in_stream() {
+            break;
+        }
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::header::test_utils::random_arbitrary;
+
+    #[test]
+    fn paginate_and_read_back() -> Result<(), Error> {
+        let packets = vec![vec![1u8; 10], vec![2u8; 255 * 300], Vec::new()];
+        let pages = Page::paginate(&packets, 0x1234, 5, 0, false);
+        assert_eq!(pages.len(), 2);
+        assert!(pages[1].is_continuation());
+        assert_eq!(pages[0].granule_position(), 0);
+
+        let mut encoded = Vec::new();
+        for page in &pages {
+            page.write(&mut encoded)?;
+        }
+        let mut reader = Cursor::new(encoded);
+        let mut decoded = Vec::new();
+        while let Some(page) = Page::read(&mut reader)? {
+            decoded.push(page);
+        }
+        assert_eq!(decoded, pages);
+        assert_eq!(decoded[1].sequence(), 6);
+
+        let mut reassembled = vec![Vec::new()];
+        for page in &decoded {
+            for (fragment, ends) in page.packet_fragments() {
+                reassembled.last_mut().expect("No packet").extend_from_slice(fragment);
+                if ends {
+                    reassembled.push(Vec::new());
+                }
+            }
+        }
+        reassembled.pop();
+        assert_eq!(reassembled, packets);
+        Ok(())
+    }
+
+    #[test]
+    fn arbitrary_pages_round_trip() -> Result<(), Error> {
+        let mut rng = SmallRng::seed_from_u64(40723);
+        for _ in 0..256 {
+            let page: Page = random_arbitrary(&mut rng);
+            let mut encoded = Vec::new();
+            page.write(&mut encoded)?;
+            assert_eq!(Page::read(&mut Cursor::new(&encoded))?, Some(page));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn checksum_mismatch_detected() -> Result<(), Error> {
+        let mut page = Page::paginate(&[b"packet".to_vec()], 1, 0, 0, true).remove(0);
+        let mut encoded = Vec::new();
+        page.write(&mut encoded)?;
+        // Corrupt the packet data
+        *encoded.last_mut().expect("Empty page") ^= 1;
+        assert!(matches!(
+            Page::read(&mut Cursor::new(&encoded)),
+            Err(Error::OggDecode(OggReadError::HashMismatch(..)))
+        ));
+
+        page.set_sequence(7);
+        encoded.clear();
+        page.write(&mut encoded)?;
+        assert_eq!(Page::read(&mut Cursor::new(&encoded))?, Some(page));
+        Ok(())
+    }
+
+    #[test]
+    fn checksums_repaired_or_skipped() -> Result<(), Error> {
+        let pages = Page::paginate(&[vec![1u8; 255 * 300], b"packet".to_vec()], 1, 0, 0, true);
+        let mut encoded = Vec::new();
+        for page in &pages {
+            page.write(&mut encoded)?;
+        }
+        // Corrupt the final byte of the second page
+        *encoded.last_mut().expect("Empty stream") ^= 1;
+
+        let mismatches = find_checksum_mismatches(Cursor::new(&encoded))?;
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!((mismatches[0].serial, mismatches[0].sequence), (1, 1));
+        assert!(copy_pages_with_policy(Cursor::new(&encoded), Vec::new(), ChecksumPolicy::Reject).is_err());
+
+        let mut skipped = Vec::new();
+        assert_eq!(copy_pages_with_policy(Cursor::new(&encoded), &mut skipped, ChecksumPolicy::Skip)?, 1);
+        assert_eq!(Page::read(&mut Cursor::new(&skipped))?.as_ref(), pages.first());
+
+        let mut repaired = Vec::new();
+        assert_eq!(copy_pages_with_policy(Cursor::new(&encoded), &mut repaired, ChecksumPolicy::Repair)?, 1);
+        assert_eq!(repaired.len(), encoded.len());
+        assert!(find_checksum_mismatches(Cursor::new(&repaired))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_stream_salvaged() -> Result<(), Error> {
+        let packets = [vec![1u8; 10], vec![2u8; 255 * 300]];
+        let mut pages = Page::paginate(&packets, 1, 0, 960, true);
+        pages.extend(Page::paginate(&[vec![3u8; 20]], 1, 2, 1920, false));
+        pages.last_mut().expect("No pages").terminate_stream();
+        let mut encoded = Vec::new();
+        for page in &pages {
+            page.write(&mut encoded)?;
+        }
+        let first_page_len = pages[0].data().len() + pages[0].lacing().len() + HEADER_LEN;
+        // Truncate inside the second page, part way through the second packet
+        let intact = encoded.clone();
+        encoded.truncate(first_page_len + 100);
+
+        let mut salvaged = Vec::new();
+        let summary = salvage_pages(Cursor::new(&encoded), &mut salvaged)?;
+        assert!(matches!(summary.error, Some(Error::ReadError(_))));
+        assert_eq!(summary.streams_terminated, 1);
+
+        let page = Page::read(&mut Cursor::new(&salvaged))?.expect("No page salvaged");
+        assert!(page.is_last_in_stream());
+        assert_eq!(page.packet_fragments(), [(packets[0].as_slice(), true)]);
+        assert_eq!(salvaged.len(), HEADER_LEN + 1 + packets[0].len());
+
+        let mut copied = Vec::new();
+        assert!(salvage_pages(Cursor::new(&intact), &mut copied)?.is_intact());
+        assert_eq!(copied, intact);
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+
+use crate::interrupt::{Flag, Interrupt as _};
+use crate::progress::{AnalysisObserver, AnalysisProgress, ProgressObserver, RewriteProgress};
+use crate::Error;
+
+/// An observer which records the most recent progress update so that it can be
+/// read from another thread
+#[derive(Debug, Default)]
+pub struct SharedProgress<P> {
+    latest: Arc<Mutex<P>>,
+}
+
+impl<P: Copy> SharedProgress<P> {
+    /// The most recent progress update
+    pub fn get(&self) -> P { *self.lock() }
+
+    fn set(&mut self, progress: &P) { *self.lock() = *progress; }
+
+    // A panic cannot leave a `Copy` value partially written, so poisoning is
+    // ignored
+    fn lock(&self) -> MutexGuard<'_, P> { self.latest.lock().unwrap_or_else(PoisonError::into_inner) }
+}
+
+impl<P> Clone for SharedProgress<P> {
+    fn clone(&self) -> SharedProgress<P> { SharedProgress { latest: Arc::clone(&self.latest) } }
+}
+
+impl ProgressObserver for SharedProgress<RewriteProgress> {
+    fn update(&mut self, progress: &RewriteProgress) { self.set(progress); }
+}
+
+impl AnalysisObserver for SharedProgress<AnalysisProgress> {
+    fn update(&mut self, progress: &AnalysisProgress) { self.set(progress); }
+}
+
+/// A long-running operation executing on its own thread. This combines an
+/// interrupt and a progress observer so that a front-end can cancel the
+/// operation, display its progress and collect its result from a single
+/// object.
+#[derive(Debug)]
+pub struct OperationHandle<P, T, E = Error> {
+    thread: JoinHandle<Result<T, E>>,
+    interrupt: Flag,
+    progress: SharedProgress<P>,
+}
+
+impl<P, T, E> OperationHandle<P, T, E>
+where
+    P: Copy + Default + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    /// Runs `op` on a new thread. `op` is passed the interrupt triggered by
+    /// `cancel()` and the observer whose latest update is returned by
+    /// `progress()`.
+    pub fn spawn<F>(op: F) -> OperationHandle<P, T, E>
+    where
+        F: FnOnce(&Flag, &mut SharedProgress<P>) -> Result<T, E> + Send + 'static,
+    {
+        let interrupt = Flag::new();
+        let progress = SharedProgress::default();
+        let thread = {
+            let interrupt = interrupt.clone();
+            let mut progress = progress.clone();
+            thread::spawn(move || op(&interrupt, &mut progress))
+        };
+        OperationHandle { thread, interrupt, progress }
+    }
+}
+
+impl<P: Copy, T, E> OperationHandle<P, T, E> {
+    /// Asks the operation to stop. Operations check for cancellation between
+    /// packets, after which `join()` returns `Error::Interrupted`.
+    pub fn cancel(&self) { self.interrupt.set(); }
+
+    /// Whether `cancel()` has been called
+    pub fn is_cancelled(&self) -> bool { self.interrupt.is_set() }
+
+    /// The most recent progress reported by the operation, or the default value
+    /// if none has been reported yet
+    pub fn progress(&self) -> P { self.progress.get() }
+
+    /// Whether the operation has finished, in which case `join()` will not
+    /// block
+    pub fn is_finished(&self) -> bool { self.thread.is_finished() }
+
+    /// Waits for the operation to finish and returns its result. A panic on the
+    /// operation's thread is propagated to the caller.
+    pub fn join(self) -> Result<T, E> {
+        match self.thread.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::progress::RewriteStage;
+
+    #[test]
+    fn cancellation_interrupts() {
+        let (started_sender, started) = mpsc::channel();
+        let handle: OperationHandle<RewriteProgress, ()> = OperationHandle::spawn(move |interrupt, progress| {
+            ProgressObserver::update(progress, &RewriteProgress { packets: 1, ..RewriteProgress::default() });
+            started_sender.send(()).expect("Test thread exited");
+            while !interrupt.is_set() {
+                thread::yield_now();
+            }
+            Err(Error::Interrupted)
+        });
+        started.recv().expect("Operation thread exited");
+        assert_eq!(handle.progress().packets, 1);
+        assert_eq!(handle.progress().stage, RewriteStage::Headers);
+        assert!(!handle.is_cancelled());
+        handle.cancel();
+        assert!(handle.is_cancelled());
+        assert!(matches!(handle.join(), Err(Error::Interrupted)));
+    }
+}
@@ -31,9 +31,28 @@ impl header::CommentHeaderSpecifics for Specifics {
     }
 }
 
+/// Generates suffix data only when it would be preserved on parsing
+#[cfg(any(test, feature = "arbitrary"))]
+impl<'a> arbitrary::Arbitrary<'a> for Specifics {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Specifics> {
+        let mut suffix_data: Vec<u8> = u.arbitrary()?;
+        if let Some(first) = suffix_data.first_mut() {
+            *first |= 1;
+        }
+        Ok(Specifics { suffix_data })
+    }
+}
+
 /// Manipulates an Ogg Opus comment header
 pub type CommentHeader = CommentHeaderGeneric<Specifics>;
 
+impl CommentHeader {
+    /// Returns the binary data following the comments which was preserved
+    /// since the least significant bit of its first byte is set. This is empty
+    /// if there was no such data.
+    pub fn suffix_data(&self) -> &[u8] { &self.specifics().suffix_data }
+}
+
 #[cfg(test)]
 mod tests {
     use rand::distributions::{Distribution, Uniform};
@@ -41,7 +60,7 @@ mod tests {
     use rand::SeedableRng;
 
     use super::*;
-    use crate::header::test_utils::{comment_header_as_vec, create_random_header};
+    use crate::header::test_utils::{comment_header_as_vec, create_random_header, random_arbitrary};
     use crate::header::CommentHeader as _;
 
     #[test]
@@ -55,6 +74,7 @@ mod tests {
         assert!(original_data.len() < padded_data.len());
         let processed_data = {
             let header = CommentHeader::try_parse(&padded_data)?;
+            assert!(header.suffix_data().is_empty());
             comment_header_as_vec(&header)?
         };
         assert_eq!(original_data, processed_data);
@@ -77,9 +97,32 @@ mod tests {
         assert!(original_data.len() < padded_data.len());
         let processed_data = {
             let header = CommentHeader::try_parse(&padded_data)?;
+            assert_eq!(header.suffix_data(), &padded_data[original_data.len()..]);
             comment_header_as_vec(&header)?
         };
         assert_eq!(padded_data, processed_data);
         Ok(())
     }
+
+    #[test]
+    fn empty_header() -> Result<(), Error> {
+        let header = CommentHeader::empty("zoog");
+        let mut expected = b"OpusTags".to_vec();
+        expected.extend_from_slice(&[4, 0, 0, 0]);
+        expected.extend_from_slice(b"zoog");
+        expected.extend_from_slice(&[0, 0, 0, 0]);
+        assert_eq!(comment_header_as_vec(&header)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn arbitrary_headers_round_trip() -> Result<(), Error> {
+        let mut rng = SmallRng::seed_from_u64(26811);
+        for _ in 0..256 {
+            let header: CommentHeader = random_arbitrary(&mut rng);
+            let parsed = CommentHeader::try_parse(&comment_header_as_vec(&header)?)?;
+            assert_eq!(parsed, header);
+        }
+        Ok(())
+    }
 }
@@ -1,8 +1,6 @@
-use std::io::{Cursor, Write};
+use std::io::Write;
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-
-use crate::header::{self, FixedPointGain};
+use crate::header::{self, FixedPointGain, IdHeader as _};
 use crate::{Codec, Error};
 
 const OPUS_MIN_HEADER_SIZE: usize = 19;
@@ -11,6 +9,140 @@ const OPUS_MAGIC: &[u8] = b"OpusHead";
 /// The internal and preferred Opus sample rate (RFC 7845, section 5.1)
 const OPUS_DECODE_SAMPLE_RATE: usize = 48000;
 
+/// Offset of the channel mapping family within the identification header
+const CHANNEL_MAPPING_OFFSET: usize = 18;
+
+/// The channel mapping of an Opus stream (RFC 7845, section 5.1.1)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelMapping {
+    /// The channel mapping family
+    pub family: u8,
+
+    /// The number of Opus streams in each Ogg packet
+    pub stream_count: u8,
+
+    /// The number of streams whose decoders output two channels
+    pub coupled_count: u8,
+
+    /// The index of the decoded channel for each output channel, or 255 for a
+    /// silent channel
+    pub mapping: Vec<u8>,
+}
+
+impl ChannelMapping {
+    /// The value of a mapping table entry denoting a silent output channel
+    pub const SILENT: u8 = 255;
+
+    /// The number of channels output by the Opus decoders before mapping
+    pub fn decoded_channels(&self) -> usize { usize::from(self.stream_count) + usize::from(self.coupled_count) }
+}
+
+/// Generates mappings which are valid for a family other than 0
+#[cfg(any(test, feature = "arbitrary"))]
+impl<'a> arbitrary::Arbitrary<'a> for ChannelMapping {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<ChannelMapping> {
+        let family = u.int_in_range(1..=u8::MAX)?;
+        let stream_count = u.int_in_range(1..=u8::MAX)?;
+        let coupled_count = u.int_in_range(0..=stream_count)?;
+        let max_index = u8::try_from(usize::from(stream_count) + usize::from(coupled_count) - 1)
+            .unwrap_or(ChannelMapping::SILENT - 1);
+        let channels = u.int_in_range(1..=u8::MAX)?;
+        let mut mapping = Vec::with_capacity(usize::from(channels));
+        for _ in 0..channels {
+            let silent = u.ratio(1, 8)?;
+            mapping.push(if silent { ChannelMapping::SILENT } else { u.int_in_range(0..=max_index)? });
+        }
+        Ok(ChannelMapping { family, stream_count, coupled_count, mapping })
+    }
+}
+
+/// Builds a new Opus identification header. This defaults to stereo with no
+/// pre-skip, output gain or input sample rate.
+#[derive(Clone, Debug)]
+pub struct IdHeaderBuilder {
+    channels: u8,
+    pre_skip: u16,
+    input_sample_rate: Option<u32>,
+    output_gain: FixedPointGain,
+    channel_mapping: Option<ChannelMapping>,
+}
+
+impl Default for IdHeaderBuilder {
+    fn default() -> IdHeaderBuilder {
+        IdHeaderBuilder {
+            channels: 2,
+            pre_skip: 0,
+            input_sample_rate: None,
+            output_gain: FixedPointGain::default(),
+            channel_mapping: None,
+        }
+    }
+}
+
+impl IdHeaderBuilder {
+    /// Sets the number of output channels
+    #[must_use]
+    pub fn channels(mut self, channels: u8) -> IdHeaderBuilder {
+        self.channels = channels;
+        self
+    }
+
+    /// Sets the number of samples at 48kHz to discard from the start of the
+    /// decoded output
+    #[must_use]
+    pub fn pre_skip(mut self, samples: u16) -> IdHeaderBuilder {
+        self.pre_skip = samples;
+        self
+    }
+
+    /// Sets the sample rate of the audio before it was encoded
+    #[must_use]
+    pub fn input_sample_rate(mut self, rate: Option<u32>) -> IdHeaderBuilder {
+        self.input_sample_rate = rate;
+        self
+    }
+
+    /// Sets the output gain
+    #[must_use]
+    pub fn output_gain(mut self, gain: FixedPointGain) -> IdHeaderBuilder {
+        self.output_gain = gain;
+        self
+    }
+
+    /// Sets an explicit channel mapping. Without one, channel mapping family 0
+    /// is used, which supports only mono and stereo.
+    #[must_use]
+    pub fn channel_mapping(mut self, mapping: ChannelMapping) -> IdHeaderBuilder {
+        self.channel_mapping = Some(mapping);
+        self
+    }
+
+    /// Constructs the header, checking that the channel count and mapping are
+    /// consistent
+    pub fn build(self) -> Result<IdHeader, Error> {
+        let mut data = Vec::with_capacity(OPUS_MIN_HEADER_SIZE);
+        data.extend_from_slice(OPUS_MAGIC);
+        data.push(1);
+        data.push(self.channels);
+        data.extend_from_slice(&self.pre_skip.to_le_bytes());
+        data.extend_from_slice(&self.input_sample_rate.unwrap_or(0).to_le_bytes());
+        data.extend_from_slice(&self.output_gain.as_fixed_point().to_le_bytes());
+        match self.channel_mapping {
+            None => data.push(0),
+            Some(mapping) => {
+                data.extend_from_slice(&[mapping.family, mapping.stream_count, mapping.coupled_count]);
+                data.extend_from_slice(&mapping.mapping);
+            }
+        }
+        let header = IdHeader::try_parse(&data)?.ok_or(Error::MalformedIdentificationHeader)?;
+        let mapping = header.channel_mapping()?;
+        if mapping.mapping.len() != usize::from(self.channels) {
+            return Err(Error::MalformedIdentificationHeader);
+        }
+        Ok(header)
+    }
+}
+
 /// Allows querying and modification of an Opus identification header
 #[derive(Clone, Debug, PartialEq)]
 pub struct IdHeader {
@@ -42,43 +174,51 @@ impl header::IdHeader for IdHeader {
         writer.write_all(&self.data).map_err(Error::WriteError)
     }
 
-    fn num_output_channels(&self) -> usize {
-        let mut reader = Cursor::new(&self.data[9..10]);
-        let value = reader.read_u8().expect("Error reading output channel count");
-        value.into()
-    }
+    fn num_output_channels(&self) -> usize { self.data[9].into() }
 
     fn input_sample_rate(&self) -> Option<usize> {
-        let mut reader = Cursor::new(&self.data[12..16]);
-        let value = reader.read_u32::<LittleEndian>().expect("Error reading sample rate");
+        let value = u32::from_le_bytes([self.data[12], self.data[13], self.data[14], self.data[15]]);
         if value == 0 {
             None
         } else {
-            Some(value.try_into().expect("Could not convert sample rate to usize"))
+            Some(value as usize)
         }
     }
 
     fn output_sample_rate(&self) -> usize { OPUS_DECODE_SAMPLE_RATE }
 
-    fn preskip_samples(&self) -> usize {
-        let mut reader = Cursor::new(&self.data[10..12]);
-        let value = reader.read_u16::<LittleEndian>().expect("Error reading pre-skip sample count");
-        value.into()
+    fn preskip_samples(&self) -> usize { self.pre_skip().into() }
+}
+
+/// Generates headers which are accepted by `IdHeader::channel_mapping()`
+#[cfg(any(test, feature = "arbitrary"))]
+impl<'a> arbitrary::Arbitrary<'a> for IdHeader {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<IdHeader> {
+        let mut builder =
+            IdHeader::builder().pre_skip(u.arbitrary()?).input_sample_rate(u.arbitrary()?).output_gain(u.arbitrary()?);
+        builder = if u.arbitrary()? {
+            let mapping: ChannelMapping = u.arbitrary()?;
+            let channels = u8::try_from(mapping.mapping.len()).map_err(|_| arbitrary::Error::IncorrectFormat)?;
+            builder.channels(channels).channel_mapping(mapping)
+        } else {
+            builder.channels(u.int_in_range(1..=2)?)
+        };
+        builder.build().map_err(|_| arbitrary::Error::IncorrectFormat)
     }
 }
 
 impl IdHeader {
+    /// Returns a builder for constructing a new header
+    pub fn builder() -> IdHeaderBuilder { IdHeaderBuilder::default() }
+
     /// The current output gain set in the header
     pub fn get_output_gain(&self) -> FixedPointGain {
-        let mut reader = Cursor::new(&self.data[16..18]);
-        let value = reader.read_i16::<LittleEndian>().expect("Error reading gain");
-        FixedPointGain::from_fixed_point(value)
+        FixedPointGain::from_fixed_point(i16::from_le_bytes([self.data[16], self.data[17]]))
     }
 
     /// Sets the header's output gain
     pub fn set_output_gain(&mut self, gain: FixedPointGain) {
-        let mut writer = Cursor::new(&mut self.data[16..18]);
-        writer.write_i16::<LittleEndian>(gain.as_fixed_point()).expect("Error writing gain");
+        self.data[16..18].copy_from_slice(&gain.as_fixed_point().to_le_bytes());
     }
 
     /// Applies a delta to the header's output gain. This may return an error if
@@ -90,9 +230,143 @@ impl IdHeader {
         Ok(())
     }
 
+    /// The number of samples at 48kHz to discard from the start of the decoded
+    /// output
+    pub fn pre_skip(&self) -> u16 { u16::from_le_bytes([self.data[10], self.data[11]]) }
+
+    /// Sets the number of samples at 48kHz to discard from the start of the
+    /// decoded output
+    pub fn set_pre_skip(&mut self, samples: u16) { self.data[10..12].copy_from_slice(&samples.to_le_bytes()); }
+
+    /// Sets the sample rate of the audio before it was encoded, which is
+    /// returned by `input_sample_rate()`. `None` indicates it is unspecified.
+    pub fn set_input_sample_rate(&mut self, rate: Option<u32>) {
+        self.data[12..16].copy_from_slice(&rate.unwrap_or(0).to_le_bytes());
+    }
+
+    /// Parses the channel mapping family and, for families other than 0, the
+    /// stream counts and channel mapping table which follow it. For family 0
+    /// the implicit mapping of a single stream is returned.
+    pub fn channel_mapping(&self) -> Result<ChannelMapping, Error> {
+        let channels = self.num_output_channels();
+        let family = self.data[CHANNEL_MAPPING_OFFSET];
+        let mapping = if family == 0 {
+            if channels > 2 {
+                return Err(Error::MalformedIdentificationHeader);
+            }
+            let coupled_count = u8::from(channels == 2);
+            ChannelMapping { family, stream_count: 1, coupled_count, mapping: (0..=coupled_count).collect() }
+        } else {
+            let table = &self.data[(CHANNEL_MAPPING_OFFSET + 1)..];
+            let (Some(&stream_count), Some(&coupled_count)) = (table.first(), table.get(1)) else {
+                return Err(Error::MalformedIdentificationHeader);
+            };
+            let mapping = table.get(2..(2 + channels)).ok_or(Error::MalformedIdentificationHeader)?.to_vec();
+            ChannelMapping { family, stream_count, coupled_count, mapping }
+        };
+        let decoded_channels = mapping.decoded_channels();
+        let valid_indices =
+            mapping.mapping.iter().all(|&idx| idx == ChannelMapping::SILENT || usize::from(idx) < decoded_channels);
+        if mapping.stream_count == 0 || mapping.coupled_count > mapping.stream_count || !valid_indices {
+            return Err(Error::MalformedIdentificationHeader);
+        }
+        Ok(mapping)
+    }
+
     /// Gets the Opus encapsulation version
-    pub fn version(&self) -> u8 {
-        let mut reader = Cursor::new(&self.data[8..9]);
-        reader.read_u8().expect("Error reading output channel count")
+    pub fn version(&self) -> u8 { self.data[8] }
+
+    /// Sets the Opus encapsulation version. Headers with a version other than 1
+    /// will be rejected when parsed.
+    pub fn set_version(&mut self, version: u8) { self.data[8] = version; }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::header::test_utils::random_arbitrary;
+
+    #[test]
+    fn pre_skip_round_trip() -> Result<(), Error> {
+        let mut data = b"OpusHead".to_vec();
+        data.extend_from_slice(&[1, 2, 0x38, 0x01, 0x80, 0xbb, 0, 0, 0, 0, 0]);
+        let mut header = IdHeader::try_parse(&data)?.expect("Not an Opus header");
+        assert_eq!(header.pre_skip(), 312);
+        header.set_pre_skip(3840);
+        assert_eq!(header.preskip_samples(), 3840);
+        assert_eq!(header.get_output_gain().as_fixed_point(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn channel_mapping() -> Result<(), Error> {
+        let mut data = b"OpusHead".to_vec();
+        data.extend_from_slice(&[1, 2, 0x38, 0x01, 0x80, 0xbb, 0, 0, 0, 0, 0]);
+        let header = IdHeader::try_parse(&data)?.expect("Not an Opus header");
+        let expected = ChannelMapping { family: 0, stream_count: 1, coupled_count: 1, mapping: vec![0, 1] };
+        assert_eq!(header.channel_mapping()?, expected);
+
+        // 5.1 surround
+        let mut data = b"OpusHead".to_vec();
+        data.extend_from_slice(&[1, 6, 0x38, 0x01, 0x80, 0xbb, 0, 0, 0, 0, 1, 4, 2, 0, 4, 1, 2, 3, 5]);
+        let header = IdHeader::try_parse(&data)?.expect("Not an Opus header");
+        let mapping = header.channel_mapping()?;
+        assert_eq!((mapping.family, mapping.stream_count, mapping.coupled_count), (1, 4, 2));
+        assert_eq!(mapping.mapping, [0, 4, 1, 2, 3, 5]);
+
+        // Truncated mapping table
+        let header = IdHeader::try_parse(&data[..data.len() - 1])?.expect("Not an Opus header");
+        assert!(header.channel_mapping().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn builder() -> Result<(), Error> {
+        let header = IdHeader::builder()
+            .pre_skip(312)
+            .input_sample_rate(Some(44100))
+            .output_gain(FixedPointGain::from_fixed_point(-256))
+            .build()?;
+        let mut expected = b"OpusHead".to_vec();
+        expected.extend_from_slice(&[1, 2, 0x38, 0x01, 0x44, 0xac, 0, 0, 0x00, 0xff, 0]);
+        assert_eq!(header.into_vec(), expected);
+
+        let mapping = ChannelMapping { family: 1, stream_count: 4, coupled_count: 2, mapping: vec![0, 4, 1, 2, 3, 5] };
+        let header = IdHeader::builder().channels(6).channel_mapping(mapping.clone()).build()?;
+        assert_eq!(header.channel_mapping()?, mapping);
+        assert!(IdHeader::builder().channels(6).build().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn input_sample_rate_and_version() -> Result<(), Error> {
+        let mut data = b"OpusHead".to_vec();
+        data.extend_from_slice(&[1, 2, 0x38, 0x01, 0x80, 0xbb, 0, 0, 0, 0, 0]);
+        let mut header = IdHeader::try_parse(&data)?.expect("Not an Opus header");
+        assert_eq!(header.input_sample_rate(), Some(48000));
+        header.set_input_sample_rate(Some(44100));
+        assert_eq!(header.input_sample_rate(), Some(44100));
+        header.set_input_sample_rate(None);
+        assert_eq!(header.input_sample_rate(), None);
+        assert_eq!(header.version(), 1);
+        header.set_version(2);
+        assert!(IdHeader::try_parse(&header.into_vec()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn arbitrary_headers_round_trip() -> Result<(), Error> {
+        let mut rng = SmallRng::seed_from_u64(8513);
+        for _ in 0..256 {
+            let header: IdHeader = random_arbitrary(&mut rng);
+            let mapping = header.channel_mapping()?;
+            let parsed = IdHeader::try_parse(&header.clone().into_vec())?.expect("Not an Opus header");
+            assert_eq!(parsed.channel_mapping()?, mapping);
+            assert_eq!(parsed, header);
+        }
+        Ok(())
     }
 }
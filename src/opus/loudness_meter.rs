@@ -0,0 +1,223 @@
+use std::ops::Range;
+use std::time::Duration;
+
+use bs1770::{ChannelLoudnessMeter, Power, Windows100ms};
+use derivative::Derivative;
+
+use crate::opus::TrackAnalysis;
+use crate::{Decibels, Error};
+
+/// Measures the BS.1770 power of each 100ms window of interleaved PCM audio,
+/// along with its sample peak and duration
+pub(crate) struct Bs1770Meter {
+    meters: Vec<ChannelLoudnessMeter>,
+    sample_rate: u32,
+    peak: f32,
+    frames: u64,
+}
+
+impl Bs1770Meter {
+    /// Constructs a meter for mono or stereo audio
+    pub(crate) fn new(channel_count: usize, sample_rate: usize) -> Result<Bs1770Meter, Error> {
+        if !(1..=2).contains(&channel_count) {
+            return Err(Error::InvalidChannelCount(channel_count));
+        }
+        let sample_rate_u32 = match u32::try_from(sample_rate) {
+            Ok(0) | Err(_) => return Err(Error::InvalidSampleRate(sample_rate)),
+            Ok(rate) => rate,
+        };
+        let meters = (0..channel_count).map(|_| ChannelLoudnessMeter::new(sample_rate_u32)).collect();
+        Ok(Bs1770Meter { meters, sample_rate: sample_rate_u32, peak: 0.0, frames: 0 })
+    }
+
+    pub(crate) fn num_channels(&self) -> usize { self.meters.len() }
+
+    /// Meters the samples of each channel in `frames`, a range of the frames of
+    /// the interleaved `samples`
+    pub(crate) fn push(&mut self, samples: &[f32], frames: Range<usize>) {
+        let channel_count = self.meters.len();
+        for (channel_idx, meter) in self.meters.iter_mut().enumerate() {
+            let samples =
+                samples.iter().copied().skip(channel_idx).step_by(channel_count).skip(frames.start).take(frames.len());
+            meter.push(samples);
+        }
+        let kept = &samples[(frames.start * channel_count)..(frames.end * channel_count)];
+        self.peak = kept.iter().fold(self.peak, |peak, sample| peak.max(sample.abs()));
+        self.frames += frames.len() as u64;
+    }
+
+    /// Returns the power of each 100ms window metered so far, summed across
+    /// channels
+    pub(crate) fn window_powers(&self) -> Vec<f32> { self.windows().inner.into_iter().map(|power| power.0).collect() }
+
+    /// Returns the analysis of the audio metered so far
+    pub(crate) fn analysis(&self) -> TrackAnalysis {
+        let windows = self.windows();
+        let lufs = gated_mean_to_lufs(windows.as_ref());
+        let window_powers = windows.inner.into_iter().map(|power| power.0).collect();
+        TrackAnalysis::new(lufs, self.peak, self.duration(), window_powers)
+    }
+
+    /// The duration of the audio metered so far
+    fn duration(&self) -> Duration {
+        let rate = u64::from(self.sample_rate);
+        let nanos = (self.frames % rate) * 1_000_000_000 / rate;
+        Duration::new(self.frames / rate, u32::try_from(nanos).unwrap_or(0))
+    }
+
+    /// Returns the power of each 100ms window metered so far, summed across
+    /// channels
+    fn windows(&self) -> Windows100ms<Vec<Power>> {
+        let windows: Vec<_> = self.meters.iter().map(ChannelLoudnessMeter::as_100ms_windows).collect();
+        // See notes on `reduce_stero` in `bs1770` crate.
+        let power_scale_factor = if self.num_channels() == 1 {
+            2.0 // Since mono is still output to two devices
+        } else {
+            1.0
+        };
+        let num_windows = windows[0].len();
+        for channel_windows in &windows {
+            assert_eq!(num_windows, channel_windows.len(), "Channels had different amounts of audio");
+        }
+        let mut result_windows = Vec::with_capacity(num_windows);
+        for i in 0..num_windows {
+            let mut power = 0.0;
+            for channel_windows in &windows {
+                let channel_windows = &channel_windows.inner;
+                // It would be nice if `Power` implemented addition since this is a
+                // semantically-valid operation
+                power += channel_windows[i].0;
+            }
+            power *= power_scale_factor;
+            result_windows.push(Power(power));
+        }
+        Windows100ms { inner: result_windows }
+    }
+}
+
+/// Computes the gated mean loudness of a sequence of 100ms windows
+pub(crate) fn gated_mean_to_lufs(windows: Windows100ms<&[Power]>) -> Decibels {
+    let power = bs1770::gated_mean(windows.as_ref());
+    let lufs = if power.0.is_nan() {
+        // Near silence can result in a NaN result (https://github.com/ruuda/bs1770/issues/1).
+        // Returning a large negative value might result in the application of a massive
+        // gain and is therefore not a good idea. Instead we return zero,
+        // which indicates the audio is at peak volume.
+        0.0
+    } else {
+        power.loudness_lkfs().into()
+    };
+    Decibels::from(lufs)
+}
+
+/// Determines the BS.1770 loudness in LUFS of interleaved PCM audio. This
+/// allows audio decoded from any format, or generated synthetically, to be
+/// measured in the same way as Ogg Opus files are by `VolumeAnalyzer`.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct PcmVolumeAnalyzer {
+    #[derivative(Debug = "ignore")]
+    meter: Bs1770Meter,
+    sample_rate: usize,
+    partial_frame: Vec<f32>,
+}
+
+impl PcmVolumeAnalyzer {
+    /// Constructs an analyzer for mono or stereo audio sampled at
+    /// `sample_rate` Hz
+    pub fn new(channel_count: usize, sample_rate: usize) -> Result<PcmVolumeAnalyzer, Error> {
+        let meter = Bs1770Meter::new(channel_count, sample_rate)?;
+        Ok(PcmVolumeAnalyzer { meter, sample_rate, partial_frame: Vec::new() })
+    }
+
+    /// The number of channels of the audio
+    pub fn num_channels(&self) -> usize { self.meter.num_channels() }
+
+    /// The sample rate of the audio in Hz
+    pub fn sample_rate(&self) -> usize { self.sample_rate }
+
+    /// Submits interleaved floating-point samples in the range -1.0 to 1.0. The
+    /// samples need not contain a whole number of frames since any incomplete
+    /// frame at the end is completed by the next submission.
+    pub fn push_f32(&mut self, samples: &[f32]) {
+        let channel_count = self.num_channels();
+        let mut samples = samples;
+        if !self.partial_frame.is_empty() {
+            let needed = (channel_count - self.partial_frame.len()).min(samples.len());
+            self.partial_frame.extend_from_slice(&samples[..needed]);
+            samples = &samples[needed..];
+            if self.partial_frame.len() < channel_count {
+                return;
+            }
+            self.meter.push(&self.partial_frame, 0..1);
+            self.partial_frame.clear();
+        }
+        let num_frames = samples.len() / channel_count;
+        self.meter.push(samples, 0..num_frames);
+        self.partial_frame.extend_from_slice(&samples[(num_frames * channel_count)..]);
+    }
+
+    /// Submits interleaved 16-bit integer samples
+    pub fn push_i16(&mut self, samples: &[i16]) {
+        let samples: Vec<f32> = samples.iter().map(|&sample| f32::from(sample) / 32768.0).collect();
+        self.push_f32(&samples);
+    }
+
+    /// Completes the analysis, returning the loudness, peak and duration of
+    /// the submitted audio. Any incomplete frame is ignored.
+    pub fn finish(self) -> TrackAnalysis { self.meter.analysis() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    /// Generates `seconds` of a 997Hz sine wave with peak `amplitude`, with
+    /// the same signal in each channel
+    fn sine(channel_count: usize, amplitude: f32, seconds: usize) -> Vec<f32> {
+        (0..(SAMPLE_RATE * seconds))
+            .flat_map(|idx| {
+                #[allow(clippy::cast_precision_loss)]
+                let t = idx as f32 / SAMPLE_RATE as f32;
+                std::iter::repeat((t * 997.0 * std::f32::consts::TAU).sin() * amplitude).take(channel_count)
+            })
+            .collect()
+    }
+
+    fn assert_lufs(track: &TrackAnalysis, expected: f64) {
+        let lufs = track.lufs().as_f64();
+        assert!((lufs - expected).abs() < 0.05, "{} != {}", lufs, expected);
+    }
+
+    #[test]
+    fn sine_loudness_measured() -> Result<(), Error> {
+        // A sine at -20dBFS has a loudness of -20 LUFS when played on both
+        // stereo channels
+        let mut mono = PcmVolumeAnalyzer::new(1, SAMPLE_RATE)?;
+        mono.push_f32(&sine(1, 0.1, 5));
+        let track = mono.finish();
+        assert_lufs(&track, -20.0);
+        assert_eq!(track.window_powers().len(), 50);
+        assert_eq!(track.duration(), Duration::from_secs(5));
+        assert!((track.peak() - 0.1).abs() < 0.001);
+
+        let mut stereo = PcmVolumeAnalyzer::new(2, SAMPLE_RATE)?;
+        stereo.push_f32(&sine(2, 0.1, 5));
+        assert_lufs(&stereo.finish(), -20.0);
+
+        // Integer samples submitted in pieces which split frames
+        #[allow(clippy::cast_possible_truncation)]
+        let samples: Vec<i16> = sine(2, 0.5, 5).into_iter().map(|sample| (sample * 32768.0) as i16).collect();
+        let mut stereo = PcmVolumeAnalyzer::new(2, SAMPLE_RATE)?;
+        for chunk in samples.chunks(999) {
+            stereo.push_i16(chunk);
+        }
+        assert_lufs(&stereo.finish(), -6.02);
+
+        assert!(matches!(PcmVolumeAnalyzer::new(3, SAMPLE_RATE), Err(Error::InvalidChannelCount(3))));
+        assert!(matches!(PcmVolumeAnalyzer::new(2, 0), Err(Error::InvalidSampleRate(0))));
+        Ok(())
+    }
+}
@@ -1,9 +1,17 @@
 mod comment_header;
 mod id_header;
+#[cfg(feature = "analysis")]
+mod loudness_meter;
+mod packet;
+#[cfg(feature = "analysis")]
 mod volume_analyzer;
 
 pub use comment_header::{CommentHeader, Specifics as CommentHeaderSpecifics};
 pub use id_header::*;
+#[cfg(feature = "analysis")]
+pub use loudness_meter::PcmVolumeAnalyzer;
+pub use packet::*;
+#[cfg(feature = "analysis")]
 pub use volume_analyzer::*;
 
 pub use crate::constants::opus::*;
@@ -0,0 +1,234 @@
+use crate::Error;
+
+/// The maximum duration of an Opus packet in samples at 48kHz (RFC 6716,
+/// section 3.2.5)
+const MAX_PACKET_SAMPLES: usize = 5760;
+
+/// The maximum size of an Opus frame in bytes (RFC 6716, section 3.2.1)
+const MAX_FRAME_SIZE: usize = 1275;
+
+/// The coding mode of an Opus packet (RFC 6716, section 3.1)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PacketMode {
+    /// Linear prediction coding, used for speech
+    Silk,
+
+    /// Linear prediction coding for low frequencies combined with MDCT coding
+    /// for high frequencies
+    Hybrid,
+
+    /// MDCT coding, used for music and low-latency speech
+    Celt,
+}
+
+/// The audio bandwidth of an Opus packet (RFC 6716, section 2)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bandwidth {
+    /// 4kHz
+    Narrowband,
+
+    /// 6kHz
+    Mediumband,
+
+    /// 8kHz
+    Wideband,
+
+    /// 12kHz
+    SuperWideband,
+
+    /// 20kHz
+    Fullband,
+}
+
+impl Bandwidth {
+    /// The audio bandwidth in Hz
+    pub fn hz(self) -> usize {
+        match self {
+            Bandwidth::Narrowband => 4000,
+            Bandwidth::Mediumband => 6000,
+            Bandwidth::Wideband => 8000,
+            Bandwidth::SuperWideband => 12000,
+            Bandwidth::Fullband => 20000,
+        }
+    }
+}
+
+/// The structure of an Opus packet as described by its table-of-contents byte
+/// and frame lengths, which is available without decoding the audio
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PacketInfo {
+    /// The coding mode of the packet
+    pub mode: PacketMode,
+
+    /// The audio bandwidth of the packet
+    pub bandwidth: Bandwidth,
+
+    /// Whether the frames are coded in stereo
+    pub stereo: bool,
+
+    /// The duration of each frame in samples at 48kHz
+    pub frame_samples: usize,
+
+    /// The size in bytes of each compressed frame. A size of zero indicates a
+    /// frame which was not transmitted.
+    pub frame_sizes: Vec<usize>,
+
+    /// The number of padding bytes at the end of the packet
+    pub padding: usize,
+}
+
+impl PacketInfo {
+    /// Parses the table-of-contents byte and frame lengths of an Opus packet,
+    /// checking the packet against the requirements of RFC 6716, section 3.4
+    pub fn parse(data: &[u8]) -> Result<PacketInfo, Error> {
+        let (&toc, rest) = data.split_first().ok_or(Error::MalformedOpusPacket)?;
+        let config = toc >> 3;
+        let (mode, bandwidth, frame_samples) = match config {
+            0..=11 => (
+                PacketMode::Silk,
+                [Bandwidth::Narrowband, Bandwidth::Mediumband, Bandwidth::Wideband][usize::from(config / 4)],
+                [480, 960, 1920, 2880][usize::from(config % 4)],
+            ),
+            12..=15 => (
+                PacketMode::Hybrid,
+                if config < 14 { Bandwidth::SuperWideband } else { Bandwidth::Fullband },
+                [480, 960][usize::from(config % 2)],
+            ),
+            _ => (
+                PacketMode::Celt,
+                [Bandwidth::Narrowband, Bandwidth::Wideband, Bandwidth::SuperWideband, Bandwidth::Fullband]
+                    [usize::from((config - 16) / 4)],
+                [120, 240, 480, 960][usize::from(config % 4)],
+            ),
+        };
+        let stereo = toc & 0x4 != 0;
+        let mut padding = 0;
+        let frame_sizes = match toc & 0x3 {
+            0 => vec![rest.len()],
+            1 => {
+                if rest.len() % 2 != 0 {
+                    return Err(Error::MalformedOpusPacket);
+                }
+                vec![rest.len() / 2; 2]
+            }
+            2 => {
+                let (first, length_bytes) = read_frame_length(rest)?;
+                let second = rest.len().checked_sub(length_bytes + first).ok_or(Error::MalformedOpusPacket)?;
+                vec![first, second]
+            }
+            _ => {
+                let (&count_byte, mut rest) = rest.split_first().ok_or(Error::MalformedOpusPacket)?;
+                let vbr = count_byte & 0x80 != 0;
+                let count = usize::from(count_byte & 0x3f);
+                if count == 0 || count * frame_samples > MAX_PACKET_SAMPLES {
+                    return Err(Error::MalformedOpusPacket);
+                }
+                if count_byte & 0x40 != 0 {
+                    loop {
+                        let (&byte, remaining) = rest.split_first().ok_or(Error::MalformedOpusPacket)?;
+                        rest = remaining;
+                        padding += if byte == 255 { 254 } else { usize::from(byte) };
+                        if byte != 255 {
+                            break;
+                        }
+                    }
+                }
+                let mut data_len = rest.len().checked_sub(padding).ok_or(Error::MalformedOpusPacket)?;
+                if vbr {
+                    let mut sizes = Vec::with_capacity(count);
+                    for _ in 1..count {
+                        let (size, length_bytes) = read_frame_length(rest)?;
+                        rest = &rest[length_bytes..];
+                        data_len = data_len.checked_sub(length_bytes + size).ok_or(Error::MalformedOpusPacket)?;
+                        sizes.push(size);
+                    }
+                    sizes.push(data_len);
+                    sizes
+                } else {
+                    if data_len % count != 0 {
+                        return Err(Error::MalformedOpusPacket);
+                    }
+                    vec![data_len / count; count]
+                }
+            }
+        };
+        if frame_sizes.iter().any(|&size| size > MAX_FRAME_SIZE) {
+            return Err(Error::MalformedOpusPacket);
+        }
+        Ok(PacketInfo { mode, bandwidth, stereo, frame_samples, frame_sizes, padding })
+    }
+
+    /// The number of frames in the packet
+    pub fn frame_count(&self) -> usize { self.frame_sizes.len() }
+
+    /// The duration of the packet in samples at 48kHz
+    pub fn samples(&self) -> usize { self.frame_samples * self.frame_count() }
+}
+
+/// Reads a frame length coded in one or two bytes (RFC 6716, section 3.2.1),
+/// returning the length and the number of bytes used to code it
+fn read_frame_length(data: &[u8]) -> Result<(usize, usize), Error> {
+    match data {
+        [first @ 0..=251, ..] => Ok((usize::from(*first), 1)),
+        [first, second, ..] => Ok((usize::from(*second) * 4 + usize::from(*first), 2)),
+        _ => Err(Error::MalformedOpusPacket),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_frame_packets_parsed() -> Result<(), Error> {
+        // SILK wideband 20ms mono
+        let info = PacketInfo::parse(&[9 << 3, 1, 2, 3])?;
+        assert_eq!(info.mode, PacketMode::Silk);
+        assert_eq!(info.bandwidth, Bandwidth::Wideband);
+        assert!(!info.stereo);
+        assert_eq!((info.frame_sizes.as_slice(), info.samples()), (&[3][..], 960));
+
+        // Hybrid fullband 10ms stereo
+        let info = PacketInfo::parse(&[14 << 3 | 0x4, 1])?;
+        assert_eq!(
+            (info.mode, info.bandwidth, info.stereo, info.samples()),
+            (PacketMode::Hybrid, Bandwidth::Fullband, true, 480)
+        );
+
+        // CELT fullband 2.5ms
+        let info = PacketInfo::parse(&[28 << 3])?;
+        assert_eq!((info.mode, info.bandwidth, info.samples()), (PacketMode::Celt, Bandwidth::Fullband, 120));
+        assert_eq!(info.frame_sizes, vec![0]);
+
+        assert!(matches!(PacketInfo::parse(&[]), Err(Error::MalformedOpusPacket)));
+        Ok(())
+    }
+
+    #[test]
+    fn multiple_frame_packets_parsed() -> Result<(), Error> {
+        // Two frames of equal size
+        let info = PacketInfo::parse(&[31 << 3 | 1, 1, 2, 3, 4])?;
+        assert_eq!((info.samples(), info.frame_sizes), (1920, vec![2, 2]));
+        assert!(PacketInfo::parse(&[31 << 3 | 1, 1, 2, 3]).is_err());
+
+        // Two frames with the first length coded in two bytes
+        let mut packet = vec![31 << 3 | 2, 252, 1];
+        packet.extend([0; 256 + 10]);
+        assert_eq!(PacketInfo::parse(&packet)?.frame_sizes, vec![256, 10]);
+
+        // Three CBR frames with 254 + 2 bytes of padding
+        let mut packet = vec![31 << 3 | 3, 0x40 | 3, 255, 2];
+        packet.extend([0; 6 + 256]);
+        let info = PacketInfo::parse(&packet)?;
+        assert_eq!((info.samples(), info.padding, info.frame_sizes), (2880, 256, vec![2, 2, 2]));
+
+        // Two VBR frames
+        let info = PacketInfo::parse(&[31 << 3 | 3, 0x80 | 2, 1, 9, 8, 7])?;
+        assert_eq!(info.frame_sizes, vec![1, 2]);
+
+        // More than 120ms of audio
+        assert!(PacketInfo::parse(&[3 << 3 | 3, 3]).is_err());
+        assert!(PacketInfo::parse(&[31 << 3 | 3, 0]).is_err());
+        Ok(())
+    }
+}
@@ -1,15 +1,47 @@
-use bs1770::{ChannelLoudnessMeter, Power, Windows100ms};
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bs1770::{Power, Windows100ms};
 use derivative::Derivative;
-use ogg::Packet;
+use ogg::{Packet, PacketReader};
 use opus::{Channels, Decoder};
+use rayon::prelude::*;
+use rayon::ThreadPool;
 
 use crate::header::{CommentHeader as _, IdHeader as _};
+use crate::header_rewriter::Counted;
+use crate::import::take_id3v2_prefix;
+use crate::interrupt::{Interrupt, Never};
+#[cfg(feature = "async")]
+use crate::ogg_page::Page;
+use crate::operation::OperationHandle;
+use crate::opus::loudness_meter::{gated_mean_to_lufs, Bs1770Meter};
 use crate::opus::{CommentHeader as OpusCommentHeader, IdHeader as OpusIdHeader};
+use crate::progress::{self, AnalysisObserver, AnalysisProgress};
 use crate::{Codec, Decibels, Error};
 
 // Specified in RFC6716
 const OPUS_MAX_PACKET_DURATION_MS: usize = 120;
 
+/// The number of packets decoded by each worker when decoding in parallel
+const PARALLEL_CHUNK_PACKETS: usize = 500;
+
+/// The duration of silence inserted for a zero-length packet which is not
+/// preceded by any other packet. Elsewhere, such a packet is assumed to have
+/// the same duration as the packet before it.
+const DTX_DEFAULT_PACKET_MS: usize = 20;
+
+/// The duration of audio preceding a chunk that is decoded and discarded when
+/// decoding in parallel. RFC 7845 recommends at least 80ms for the decoder
+/// state to converge but we use more since the result is otherwise not
+/// identical to sequential decoding.
+const PARALLEL_PREROLL_MS: usize = 1000;
+
 #[derive(Clone, Copy, Debug)]
 enum State {
     AwaitingHeader,
@@ -18,36 +50,111 @@ enum State {
     Done,
 }
 
+/// How the packets of a stream are decoded
+#[derive(Clone, Debug, Default)]
+enum Decoding {
+    #[default]
+    Sequential,
+
+    /// In parallel using the specified thread pool, or the current Rayon
+    /// thread pool if none is specified
+    Parallel(Option<Arc<ThreadPool>>),
+}
+
+/// Packets buffered so that they can be decoded in parallel
+#[derive(Debug, Default)]
+struct PacketBatch {
+    /// Packets preceding the batch which are used to prime the decoder for the
+    /// first chunk
+    preroll: Vec<Vec<u8>>,
+
+    /// Packets yet to be decoded
+    packets: Vec<Vec<u8>>,
+}
+
+/// Tracks which decoded samples should be discarded from the start and end of
+/// a stream
+#[derive(Debug, Default)]
+struct Trim {
+    /// Samples remaining to be discarded from the start of the stream
+    preskip_remaining: usize,
+
+    /// Samples decoded from the stream so far, including pre-skip
+    position: u64,
+
+    /// The granule position of the end of the stream. RFC 7845 requires
+    /// decoded samples beyond this to be discarded.
+    end: Option<u64>,
+}
+
+impl Trim {
+    fn new(preskip: usize) -> Trim { Trim { preskip_remaining: preskip, ..Trim::default() } }
+
+    /// Given the number of samples decoded from a packet, returns the range of
+    /// those which should be kept
+    fn keep(&mut self, num_samples: usize) -> Range<usize> {
+        let start = std::cmp::min(self.preskip_remaining, num_samples);
+        self.preskip_remaining -= start;
+        let end = match self.end {
+            Some(end) => {
+                let available = end.saturating_sub(self.position);
+                usize::try_from(available).map_or(num_samples, |available| available.min(num_samples))
+            }
+            None => num_samples,
+        };
+        self.position += num_samples as u64;
+        start..std::cmp::max(start, end)
+    }
+}
+
 #[derive(Derivative)]
 #[derivative(Debug)]
 struct DecodeState {
     sample_rate: usize,
     decoder: Decoder,
     #[derivative(Debug = "ignore")]
-    meters: Vec<ChannelLoudnessMeter>,
+    meter: Bs1770Meter,
     sample_buffer: Vec<f32>,
-    preskip_remaining: usize,
+    trim: Trim,
+    batch: Option<PacketBatch>,
+    pool: Option<Arc<ThreadPool>>,
+    last_packet_samples: Option<usize>,
 }
 
 impl DecodeState {
-    pub fn new(channel_count: usize, sample_rate: usize, preskip: usize) -> Result<DecodeState, Error> {
-        let sample_rate_u32: u32 = sample_rate.try_into().expect("Unable to truncate sample rate");
+    pub fn new(
+        channel_count: usize, sample_rate: usize, preskip: usize, decoding: &Decoding,
+    ) -> Result<DecodeState, Error> {
+        let sample_rate_u32 = Self::sample_rate_u32(sample_rate)?;
         let decoder = Self::build_decoder(channel_count, sample_rate_u32)?;
-        let mut meters = Vec::with_capacity(channel_count);
-        for _ in 0..channel_count {
-            meters.push(ChannelLoudnessMeter::new(sample_rate_u32));
-        }
-        let ms_per_second: usize = 1000;
         let state = DecodeState {
             sample_rate,
             decoder,
-            meters,
-            sample_buffer: vec![0.0f32; channel_count * sample_rate * OPUS_MAX_PACKET_DURATION_MS / ms_per_second],
-            preskip_remaining: preskip,
+            meter: Bs1770Meter::new(channel_count, sample_rate)?,
+            sample_buffer: Self::new_sample_buffer(channel_count, sample_rate),
+            trim: Trim::new(preskip),
+            batch: match decoding {
+                Decoding::Sequential => None,
+                Decoding::Parallel(_) => Some(PacketBatch::default()),
+            },
+            pool: match decoding {
+                Decoding::Sequential | Decoding::Parallel(None) => None,
+                Decoding::Parallel(Some(pool)) => Some(Arc::clone(pool)),
+            },
+            last_packet_samples: None,
         };
         Ok(state)
     }
 
+    fn sample_rate_u32(sample_rate: usize) -> Result<u32, Error> {
+        sample_rate.try_into().map_err(|_| Error::InvalidSampleRate(sample_rate))
+    }
+
+    fn new_sample_buffer(channel_count: usize, sample_rate: usize) -> Vec<f32> {
+        let ms_per_second: usize = 1000;
+        vec![0.0f32; channel_count * sample_rate * OPUS_MAX_PACKET_DURATION_MS / ms_per_second]
+    }
+
     fn build_decoder(channel_count: usize, sample_rate: u32) -> Result<Decoder, Error> {
         let channel_count_typed = match channel_count {
             1 => Channels::Mono,
@@ -57,108 +164,308 @@ impl DecodeState {
         Decoder::new(sample_rate, channel_count_typed).map_err(Error::OpusError)
     }
 
-    pub fn reset_decoder(&mut self, channel_count: usize, sample_rate: usize, preskip: usize) -> Result<(), Error> {
-        if sample_rate != self.sample_rate || channel_count != self.num_channels() {
-            return Err(Error::UnexpectedAudioParametersChange);
-        }
-        let sample_rate_u32: u32 = sample_rate.try_into().expect("Unable to truncate sample rate");
-        let decoder = Self::build_decoder(channel_count, sample_rate_u32)?;
-        self.decoder = decoder;
-        self.preskip_remaining = preskip;
-        Ok(())
-    }
+    pub fn num_channels(&self) -> usize { self.meter.num_channels() }
 
-    pub fn num_channels(&self) -> usize { self.meters.len() }
+    /// Sets the granule position of the end of the stream, beyond which
+    /// decoded samples are discarded
+    pub fn set_end(&mut self, granule_position: u64) { self.trim.end = Some(granule_position); }
 
     pub fn push_packet(&mut self, packet: &[u8]) -> Result<(), Error> {
+        if let Some(batch) = self.batch.as_mut() {
+            batch.packets.push(packet.to_vec());
+            let num_threads =
+                self.pool.as_ref().map_or_else(rayon::current_num_threads, |pool| pool.current_num_threads());
+            if batch.packets.len() >= PARALLEL_CHUNK_PACKETS * num_threads {
+                self.flush()?;
+            }
+            return Ok(());
+        }
         // Decode to interleaved PCM
-        let decode_fec = false;
         let channel_count = self.num_channels();
-        let num_decoded_samples =
-            self.decoder.decode_float(packet, &mut self.sample_buffer, decode_fec).map_err(Error::OpusError)?;
+        let num_decoded_samples = Self::decode_packet(
+            &mut self.decoder,
+            packet,
+            &mut self.sample_buffer,
+            channel_count,
+            self.sample_rate,
+            &mut self.last_packet_samples,
+        )?;
         let decoded_samples = &self.sample_buffer[..(channel_count * num_decoded_samples)];
-        let to_skip = std::cmp::min(self.preskip_remaining, num_decoded_samples);
-        self.preskip_remaining -= to_skip;
-        for (channel_idx, meter) in self.meters.iter_mut().enumerate() {
-            let samples = decoded_samples.iter().copied().skip(channel_idx).step_by(channel_count).skip(to_skip);
-            meter.push(samples);
-        }
+        Self::meter_samples(&mut self.meter, &mut self.trim, decoded_samples);
         Ok(())
     }
 
-    pub fn get_windows(&self) -> Windows100ms<Vec<Power>> {
-        let windows: Vec<_> = self.meters.iter().map(ChannelLoudnessMeter::as_100ms_windows).collect();
-        // See notes on `reduce_stero` in `bs1770` crate.
-        let power_scale_factor = match self.num_channels() {
-            1 => 2.0, // Since mono is still output to two devices
-            2 => 1.0,
-            n => panic!("Calculating power for number of channels {} not yet supported", n),
-        };
-        let num_windows = windows[0].len();
-        for channel_windows in &windows {
-            assert_eq!(num_windows, channel_windows.len(), "Channels had different amounts of audio");
+    /// Decodes a packet to interleaved PCM in `sample_buffer`, returning the
+    /// number of samples per channel. Encoders using discontinuous
+    /// transmission (DTX) may write zero-length packets during silence, which
+    /// the decoder would otherwise treat as lost and conceal with up to 120ms
+    /// of audio. They are decoded as silence lasting as long as the previous
+    /// packet instead.
+    fn decode_packet(
+        decoder: &mut Decoder, packet: &[u8], sample_buffer: &mut [f32], channel_count: usize, sample_rate: usize,
+        last_packet_samples: &mut Option<usize>,
+    ) -> Result<usize, Error> {
+        if packet.is_empty() {
+            let ms_per_second: usize = 1000;
+            let num_samples = last_packet_samples.unwrap_or(sample_rate * DTX_DEFAULT_PACKET_MS / ms_per_second);
+            let len = (num_samples * channel_count).min(sample_buffer.len());
+            sample_buffer[..len].fill(0.0);
+            return Ok(len / channel_count);
         }
-        let mut result_windows = Vec::with_capacity(num_windows);
-        for i in 0..num_windows {
-            let mut power = 0.0;
-            for channel_windows in &windows {
-                let channel_windows = &channel_windows.inner;
-                // It would be nice if `Power` implemented addition since this is a
-                // semantically-valid operation
-                power += channel_windows[i].0;
+        let decode_fec = false;
+        let num_samples = decoder.decode_float(packet, sample_buffer, decode_fec).map_err(Error::OpusError)?;
+        *last_packet_samples = Some(num_samples);
+        Ok(num_samples)
+    }
+
+    /// Passes interleaved samples to the loudness meters, discarding any
+    /// remaining pre-skip and any samples beyond the end of the stream
+    fn meter_samples(meter: &mut Bs1770Meter, trim: &mut Trim, samples: &[f32]) {
+        let keep = trim.keep(samples.len() / meter.num_channels());
+        meter.push(samples, keep);
+    }
+
+    /// Finds the start of the packets at the end of `packets` which are used to
+    /// prime the decoder for the packets that follow them
+    fn preroll_start(packets: &[Vec<u8>], sample_rate: u32) -> usize {
+        let ms_per_second: usize = 1000;
+        let required = sample_rate as usize * PARALLEL_PREROLL_MS / ms_per_second;
+        let mut duration = 0;
+        for (idx, packet) in packets.iter().enumerate().rev() {
+            if duration >= required {
+                return idx + 1;
             }
-            power *= power_scale_factor;
-            result_windows.push(Power(power));
+            duration += opus::packet::get_nb_samples(packet, sample_rate).unwrap_or(0);
         }
-        Windows100ms { inner: result_windows }
+        0
     }
+
+    /// Decodes a chunk of packets using a new decoder primed with `preroll`,
+    /// returning interleaved PCM
+    fn decode_chunk(
+        channel_count: usize, sample_rate: usize, preroll: &[Vec<u8>], packets: &[Vec<u8>],
+    ) -> Result<Vec<f32>, Error> {
+        let sample_rate_u32 = Self::sample_rate_u32(sample_rate)?;
+        let mut decoder = Self::build_decoder(channel_count, sample_rate_u32)?;
+        let mut sample_buffer = Self::new_sample_buffer(channel_count, sample_rate);
+        let mut last_packet_samples = None;
+        let mut decode = |packet: &[u8], sample_buffer: &mut [f32]| {
+            Self::decode_packet(
+                &mut decoder,
+                packet,
+                sample_buffer,
+                channel_count,
+                sample_rate,
+                &mut last_packet_samples,
+            )
+        };
+        for packet in preroll {
+            decode(packet, &mut sample_buffer)?;
+        }
+        let mut pcm = Vec::new();
+        for packet in packets {
+            let num_decoded_samples = decode(packet, &mut sample_buffer)?;
+            pcm.extend_from_slice(&sample_buffer[..(channel_count * num_decoded_samples)]);
+        }
+        Ok(pcm)
+    }
+
+    /// Decodes any packets buffered for parallel decoding. Packets are split
+    /// into fixed-size chunks which are decoded concurrently, then metered in
+    /// order.
+    fn flush(&mut self) -> Result<(), Error> {
+        let Some(batch) = self.batch.as_mut() else { return Ok(()) };
+        if batch.packets.is_empty() {
+            return Ok(());
+        }
+        let packets = std::mem::take(&mut batch.packets);
+        let sample_rate_u32 = Self::sample_rate_u32(self.sample_rate)?;
+        let (channel_count, sample_rate) = (self.meter.num_channels(), self.sample_rate);
+        let chunks: Vec<_> = packets.chunks(PARALLEL_CHUNK_PACKETS).collect();
+        let first_preroll = batch.preroll.as_slice();
+        let decode = || -> Vec<_> {
+            (0..chunks.len())
+                .into_par_iter()
+                .map(|idx| {
+                    let preroll = if idx == 0 {
+                        first_preroll
+                    } else {
+                        let previous = chunks[idx - 1];
+                        &previous[Self::preroll_start(previous, sample_rate_u32)..]
+                    };
+                    Self::decode_chunk(channel_count, sample_rate, preroll, chunks[idx])
+                })
+                .collect()
+        };
+        let decoded = match self.pool.as_ref() {
+            Some(pool) => pool.install(decode),
+            None => decode(),
+        };
+        batch.preroll = packets[Self::preroll_start(&packets, sample_rate_u32)..].to_vec();
+        for samples in decoded {
+            Self::meter_samples(&mut self.meter, &mut self.trim, &samples?);
+        }
+        Ok(())
+    }
+}
+
+/// The duration of each window of audio whose power is measured
+const WINDOW_SECONDS: f64 = 0.1;
+
+/// The BS.1770 power of a 100ms window of audio, which may be used to plot
+/// loudness over time or to apply custom gating
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoudnessWindow {
+    /// The offset of the start of the window from the start of the track in
+    /// seconds
+    pub start_seconds: f64,
+
+    /// The mean square of the K-weighted samples of the window, summed across
+    /// channels
+    pub power: f64,
+}
+
+impl LoudnessWindow {
+    fn sequence<I: IntoIterator<Item = f32>>(powers: I) -> Vec<LoudnessWindow> {
+        #[allow(clippy::cast_precision_loss)]
+        let window = |(idx, power): (usize, f32)| LoudnessWindow {
+            start_seconds: idx as f64 * WINDOW_SECONDS,
+            power: f64::from(power),
+        };
+        powers.into_iter().enumerate().map(window).collect()
+    }
+
+    /// The momentary loudness of the window in LUFS
+    pub fn lufs(&self) -> f64 { -0.691 + 10.0 * self.power.log10() }
+}
+
+/// The loudness, peak and duration of a single track, along with the power of
+/// each of its 100ms windows which is needed to compute the loudness of an
+/// album containing it
+#[derive(Derivative)]
+#[derivative(Clone, Debug)]
+pub struct TrackAnalysis {
+    lufs: Decibels,
+    peak: f32,
+    duration: Duration,
+    #[derivative(Debug = "ignore")]
+    windows: Vec<Power>,
+}
+
+impl TrackAnalysis {
+    /// Constructs a track analysis from its LUFS value, sample peak, duration
+    /// and the BS.1770 power of each 100ms window
+    pub fn new(lufs: Decibels, peak: f32, duration: Duration, window_powers: Vec<f32>) -> TrackAnalysis {
+        TrackAnalysis { lufs, peak, duration, windows: window_powers.into_iter().map(Power).collect() }
+    }
+
+    /// The loudness of the track
+    pub fn lufs(&self) -> Decibels { self.lufs }
+
+    /// The largest absolute sample value of the decoded track, where 1.0 is
+    /// full scale
+    pub fn peak(&self) -> f32 { self.peak }
+
+    /// The duration of the decoded track, excluding pre-skip and end trimming
+    pub fn duration(&self) -> Duration { self.duration }
+
+    /// The BS.1770 power of each 100ms window of the track
+    pub fn window_powers(&self) -> impl ExactSizeIterator<Item = f32> + '_ { self.windows.iter().map(|p| p.0) }
+
+    /// Each 100ms window of the track with its start time
+    pub fn windows(&self) -> Vec<LoudnessWindow> { LoudnessWindow::sequence(self.window_powers()) }
 }
 
-/// Determines the BS.1770 loudness in LUFS of one or more Ogg Opus files
+/// The loudness, peak and duration of a group of tracks treated as an album,
+/// along with the analysis of each track
+#[derive(Clone, Debug)]
+pub struct AlbumAnalysis {
+    lufs: Decibels,
+    tracks: Vec<TrackAnalysis>,
+}
+
+impl AlbumAnalysis {
+    /// Combines the analyses of the tracks of an album, supplied in order
+    pub fn new(tracks: Vec<TrackAnalysis>) -> AlbumAnalysis {
+        let windows: Vec<_> = tracks.iter().flat_map(|track| track.windows.iter().copied()).collect();
+        let lufs = gated_mean_to_lufs(Windows100ms { inner: windows }.as_ref());
+        AlbumAnalysis { lufs, tracks }
+    }
+
+    /// The loudness of the album as a whole
+    pub fn lufs(&self) -> Decibels { self.lufs }
+
+    /// The largest sample peak of any track
+    pub fn peak(&self) -> f32 { self.tracks.iter().map(TrackAnalysis::peak).fold(0.0, f32::max) }
+
+    /// The total duration of the tracks
+    pub fn duration(&self) -> Duration { self.tracks.iter().map(TrackAnalysis::duration).sum() }
+
+    /// The analysis of each track, in the order supplied
+    pub fn tracks(&self) -> &[TrackAnalysis] { &self.tracks }
+}
+
+/// Determines the BS.1770 loudness in LUFS of a single Ogg Opus stream from
+/// its packets. To analyze entire files, use `AnalysisSession`.
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct VolumeAnalyzer {
     decode_state: Option<DecodeState>,
     state: State,
-    #[derivative(Debug = "ignore")]
-    windows: Windows100ms<Vec<Power>>,
-    track_loudness: Vec<Decibels>,
+    decoding: Decoding,
 }
 
 impl Default for VolumeAnalyzer {
     fn default() -> VolumeAnalyzer {
-        VolumeAnalyzer {
-            decode_state: None,
-            state: State::AwaitingHeader,
-            windows: Windows100ms::new(),
-            track_loudness: Vec::new(),
-        }
+        VolumeAnalyzer { decode_state: None, state: State::AwaitingHeader, decoding: Decoding::Sequential }
     }
 }
 
 impl VolumeAnalyzer {
+    /// Constructs an analyzer which decodes using the threads of the current
+    /// Rayon thread pool. This is intended for when a single large file is
+    /// being analyzed. Packets are decoded in chunks, each using a decoder
+    /// primed with the audio preceding the chunk, so the computed loudness may
+    /// differ very slightly from that of a sequential analysis.
+    pub fn parallel() -> VolumeAnalyzer {
+        VolumeAnalyzer { decoding: Decoding::Parallel(None), ..VolumeAnalyzer::default() }
+    }
+
+    /// Identical to `parallel` except that packets are decoded using the
+    /// threads of `pool` rather than the current thread pool
+    pub fn parallel_in(pool: Arc<ThreadPool>) -> VolumeAnalyzer {
+        VolumeAnalyzer { decoding: Decoding::Parallel(Some(pool)), ..VolumeAnalyzer::default() }
+    }
+
     /// Submits a new Ogg packet to the analyzer
     #[allow(clippy::needless_pass_by_value)]
     pub fn submit(&mut self, packet: Packet) -> Result<(), Error> {
-        let packet_serial = packet.stream_serial();
+        self.submit_data(packet.stream_serial(), &packet.data, packet.absgp_page(), packet.last_in_stream())
+    }
+
+    /// Submits the content of a packet belonging to the logical stream with
+    /// serial `packet_serial`. `granule_position` is that of the page on which
+    /// the packet ends and is used to discard the padding at the end of the
+    /// final packet.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn submit_data(
+        &mut self, packet_serial: u32, data: &[u8], granule_position: u64, last_in_stream: bool,
+    ) -> Result<(), Error> {
         match self.state {
             State::AwaitingHeader => {
-                let header = OpusIdHeader::try_parse(&packet.data)?.ok_or(Error::MissingStream(Codec::Opus))?;
+                let header = OpusIdHeader::try_parse(data)?.ok_or(Error::MissingStream(Codec::Opus))?;
                 let channel_count = header.num_output_channels();
                 let sample_rate = header.output_sample_rate();
                 let preskip = header.preskip_samples();
-                if let Some(ref mut decode_state) = self.decode_state {
-                    decode_state.reset_decoder(channel_count, sample_rate, preskip)?;
-                } else {
-                    self.decode_state = Some(DecodeState::new(channel_count, sample_rate, preskip)?);
-                }
+                tracing::debug!(serial = packet_serial, channel_count, preskip, "Started loudness analysis of stream");
+                self.decode_state = Some(DecodeState::new(channel_count, sample_rate, preskip, &self.decoding)?);
                 self.state = State::AwaitingComments { serial: packet_serial };
             }
             State::AwaitingComments { serial } => {
                 if serial == packet_serial {
                     // Check comment header is valid
-                    OpusCommentHeader::try_parse(&packet.data)?;
-                    self.state = if packet.last_in_stream() { State::Done } else { State::Analyzing { serial } };
+                    OpusCommentHeader::try_parse(data)?;
+                    self.state = if last_in_stream { State::Done } else { State::Analyzing { serial } };
                 } else {
                     return Err(Error::UnexpectedLogicalStream(packet_serial));
                 }
@@ -166,8 +473,12 @@ impl VolumeAnalyzer {
             State::Analyzing { serial } => {
                 if serial == packet_serial {
                     let decode_state = self.decode_state.as_mut().expect("Decode state unexpectedly missing");
-                    decode_state.push_packet(&packet.data)?;
-                    if packet.last_in_stream() {
+                    if last_in_stream {
+                        decode_state.set_end(granule_position);
+                    }
+                    decode_state.push_packet(data)?;
+                    if last_in_stream {
+                        decode_state.flush()?;
                         self.state = State::Done;
                     }
                 } else {
@@ -184,54 +495,386 @@ impl VolumeAnalyzer {
         Ok(())
     }
 
-    fn gated_mean_to_lufs(windows: Windows100ms<&[Power]>) -> Decibels {
-        let power = bs1770::gated_mean(windows.as_ref());
-        let lufs = if power.0.is_nan() {
-            // Near silence can result in a NaN result (https://github.com/ruuda/bs1770/issues/1).
-            // Returning a large negative value might result in the application of a massive
-            // gain and is therefore not a good idea. Instead we return zero,
-            // which indicates the audio is at peak volume.
-            0.0
-        } else {
-            power.loudness_lkfs().into()
-        };
-        Decibels::from(lufs)
+    /// Returns each 100ms window of the audio decoded so far with its start
+    /// time. Packets buffered for parallel decoding are not included until
+    /// they have been decoded.
+    pub fn windows(&self) -> Vec<LoudnessWindow> {
+        let powers = self.decode_state.as_ref().map(|decode_state| decode_state.meter.window_powers());
+        LoudnessWindow::sequence(powers.unwrap_or_default())
     }
 
-    /// This should be called after all packets from an Ogg Opus file have been
-    /// submitted. It is then possible to start calculating the volume of a
-    /// new file.
-    #[allow(clippy::missing_panics_doc)]
-    pub fn file_complete(&mut self) {
-        if let Some(decode_state) = self.decode_state.take() {
-            let windows = decode_state.get_windows();
-            let track_power = Self::gated_mean_to_lufs(windows.as_ref());
-            self.track_loudness.push(track_power);
-            self.windows.inner.extend(windows.inner);
+    /// Completes the analysis once all packets of the stream have been
+    /// submitted, returning the loudness, peak and duration of the track.
+    /// Fails with `Error::MissingStream` if no Opus stream was submitted.
+    pub fn finish(self) -> Result<TrackAnalysis, Error> {
+        let mut decode_state = self.decode_state.ok_or(Error::MissingStream(Codec::Opus))?;
+        decode_state.flush()?;
+        let track = decode_state.meter.analysis();
+        tracing::debug!(lufs = track.lufs().as_f64(), "Completed loudness analysis of track");
+        Ok(track)
+    }
+}
+
+/// Determines the BS.1770 loudness in LUFS of a sequence of Ogg Opus files,
+/// such as the tracks of an album
+#[derive(Debug, Default)]
+pub struct AnalysisSession {
+    decoding: Decoding,
+    tracks: Vec<TrackAnalysis>,
+}
+
+impl AnalysisSession {
+    /// Constructs a session which decodes each file in parallel, as described
+    /// for `VolumeAnalyzer::parallel`
+    pub fn parallel() -> AnalysisSession {
+        AnalysisSession { decoding: Decoding::Parallel(None), ..AnalysisSession::default() }
+    }
+
+    /// Constructs a session which decodes each file in parallel using the
+    /// threads of `pool`, as described for `VolumeAnalyzer::parallel_in`
+    pub fn parallel_in(pool: Arc<ThreadPool>) -> AnalysisSession {
+        AnalysisSession { decoding: Decoding::Parallel(Some(pool)), ..AnalysisSession::default() }
+    }
+
+    fn new_analyzer(&self) -> VolumeAnalyzer {
+        VolumeAnalyzer { decoding: self.decoding.clone(), ..VolumeAnalyzer::default() }
+    }
+
+    /// Reads an entire Ogg Opus file and returns the analysis of it, which is
+    /// also retained as the next track of the session. A leading ID3v2 tag is
+    /// skipped. Fails with `Error::Interrupted` if `interrupt` is set before
+    /// the file has been read.
+    pub fn analyze_file_with_interrupt<R: Read + Seek, I: Interrupt>(
+        &mut self, input: R, interrupt: &I,
+    ) -> Result<&TrackAnalysis, Error> {
+        self.analyze_file_with_progress(input, interrupt, &mut progress::Ignore::default())
+    }
+
+    /// Identical to `analyze_file_with_interrupt` except that `observer` is
+    /// updated with the progress of the analysis after each packet is
+    /// submitted
+    pub fn analyze_file_with_progress<R, I, O>(
+        &mut self, input: R, interrupt: &I, observer: &mut O,
+    ) -> Result<&TrackAnalysis, Error>
+    where
+        R: Read + Seek,
+        I: Interrupt,
+        O: AnalysisObserver,
+    {
+        let track = self.analyze_reader(input, interrupt, observer)?;
+        Ok(self.add_track(track))
+    }
+
+    /// Opens and analyzes the Ogg Opus file at `path` as described for
+    /// `analyze_file_with_progress`
+    pub fn analyze_path<P, I, O>(&mut self, path: P, interrupt: &I, observer: &mut O) -> Result<&TrackAnalysis, Error>
+    where
+        P: AsRef<Path>,
+        I: Interrupt,
+        O: AnalysisObserver,
+    {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+        self.analyze_file_with_progress(BufReader::new(file), interrupt, observer)
+    }
+
+    fn analyze_reader<R, I, O>(&self, input: R, interrupt: &I, observer: &mut O) -> Result<TrackAnalysis, Error>
+    where
+        R: Read + Seek,
+        I: Interrupt,
+        O: AnalysisObserver,
+    {
+        let bytes_read = Cell::new(0);
+        let mut input = Counted { inner: input, count: &bytes_read };
+        let mut progress = AnalysisProgress { total_bytes: stream_len(&mut input)?, ..AnalysisProgress::default() };
+        take_id3v2_prefix(&mut input)?;
+        let mut analyzer = self.new_analyzer();
+        let mut ogg_reader = PacketReader::new(input);
+        while !progress.complete {
+            if interrupt.is_set() {
+                return Err(Error::Interrupted);
+            }
+            match ogg_reader.read_packet().map_err(Error::OggDecode)? {
+                Some(packet) => {
+                    analyzer.submit(packet)?;
+                    progress.packets += 1;
+                }
+                None => progress.complete = true,
+            }
+            progress.bytes_read = bytes_read.get();
+            observer.update(&progress);
+        }
+        analyzer.finish()
+    }
+
+    /// Identical to `analyze_file_with_interrupt` except reading cannot be
+    /// interrupted
+    pub fn analyze_file<R: Read + Seek>(&mut self, input: R) -> Result<&TrackAnalysis, Error> {
+        self.analyze_file_with_interrupt(input, &Never::default())
+    }
+
+    /// Reads an entire Ogg Opus file from an asynchronous reader and returns
+    /// the analysis of it, which is also retained as the next track of the
+    /// session. Only reading is asynchronous; decoding takes place on the
+    /// calling task.
+    #[cfg(feature = "async")]
+    pub async fn analyze_file_async<R: tokio::io::AsyncRead + Unpin>(
+        &mut self, mut input: R,
+    ) -> Result<&TrackAnalysis, Error> {
+        let mut analyzer = self.new_analyzer();
+        let mut partial = Vec::new();
+        while let Some(page) = Page::read_async(&mut input).await? {
+            let fragments = page.packet_fragments();
+            let last_ending = fragments.iter().rposition(|&(_, ends)| ends);
+            for (idx, (fragment, ends)) in fragments.into_iter().enumerate() {
+                partial.extend_from_slice(fragment);
+                if ends {
+                    let last_in_stream = page.is_last_in_stream() && Some(idx) == last_ending;
+                    analyzer.submit_data(page.serial(), &partial, page.granule_position(), last_in_stream)?;
+                    partial.clear();
+                }
+            }
         }
-        assert!(self.decode_state.is_none());
-        self.state = State::AwaitingHeader;
+        Ok(self.add_track(analyzer.finish()?))
     }
 
-    /// Returns the mean LUFS of all completed files submitted to the volume
-    /// analyzer so far
-    pub fn mean_lufs(&self) -> Decibels { Self::gated_mean_to_lufs(self.windows.as_ref()) }
+    /// Adds a track whose analysis was computed previously, as if its file had
+    /// been analyzed by the session
+    pub fn add_track(&mut self, track: TrackAnalysis) -> &TrackAnalysis {
+        let idx = self.tracks.len();
+        self.tracks.push(track);
+        &self.tracks[idx]
+    }
 
-    /// Returns the LUFS of all tracks submitted ot the volume analyzer so far
-    pub fn track_lufs(&self) -> Vec<Decibels> { self.track_loudness.clone() }
+    /// The analysis of each track of the session so far
+    pub fn tracks(&self) -> &[TrackAnalysis] { &self.tracks }
 
-    /// Returns the volume of the most recent track submitted to the volume
-    /// analyzer
-    pub fn last_track_lufs(&self) -> Option<Decibels> { self.track_loudness.last().copied() }
+    /// Completes the session, returning the analysis of its tracks as an album
+    pub fn finish(self) -> AlbumAnalysis { AlbumAnalysis::new(self.tracks) }
+}
 
-    /// Returns the mean LUFS of all completed files submitted to the supplied
-    /// volume analyzers
-    pub fn mean_lufs_across_multiple<'a, I: IntoIterator<Item = &'a VolumeAnalyzer>>(analyzers: I) -> Decibels {
-        let mut windows: Vec<Power> = Vec::new();
-        for analyzer in analyzers {
-            windows.extend(analyzer.windows.inner.iter());
+/// Returns the length of a seekable stream, leaving its position unchanged
+fn stream_len<S: Seek>(stream: &mut S) -> Result<u64, Error> {
+    let position = stream.stream_position().map_err(Error::ReadError)?;
+    let len = stream.seek(SeekFrom::End(0)).map_err(Error::ReadError)?;
+    stream.seek(SeekFrom::Start(position)).map_err(Error::ReadError)?;
+    Ok(len)
+}
+
+/// Analyzes the Ogg Opus file at `path`, updating `observer` with the progress
+/// of the analysis after each packet is submitted. A leading ID3v2 tag is
+/// skipped. Fails with `Error::Interrupted` if `interrupt` is set before the
+/// file has been read.
+pub fn analyze_file<P, I, O>(path: P, interrupt: &I, observer: &mut O) -> Result<TrackAnalysis, Error>
+where
+    P: AsRef<Path>,
+    I: Interrupt,
+    O: AnalysisObserver,
+{
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    AnalysisSession::default().analyze_reader(BufReader::new(file), interrupt, observer)
+}
+
+/// Analyzes the Ogg Opus file at `path` as described for `analyze_file` on a
+/// new thread, returning a handle which can cancel the analysis, report its
+/// progress and wait for its result
+pub fn analyze_file_in_background<P: AsRef<Path>>(path: P) -> OperationHandle<AnalysisProgress, TrackAnalysis> {
+    let path = path.as_ref().to_path_buf();
+    OperationHandle::spawn(move |interrupt, observer| analyze_file(path, interrupt, observer))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+    use opus::{Application, Encoder};
+
+    use super::*;
+    use crate::testing::StreamBuilder;
+
+    const SAMPLE_RATE: usize = 48000;
+    const FRAME_SAMPLES: usize = 960;
+    const PRESKIP: usize = 0x138;
+
+    /// Encodes a stereo tone whose amplitude varies over time
+    fn encode_stream(num_frames: usize) -> Result<Vec<u8>, Error> { encode_stream_with_dtx(num_frames, |_| false) }
+
+    /// Encodes a stereo tone, replacing the frames for which `dtx` returns
+    /// `true` with zero-length packets
+    fn encode_stream_with_dtx(num_frames: usize, dtx: impl Fn(usize) -> bool) -> Result<Vec<u8>, Error> {
+        let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).map_err(Error::OpusError)?;
+        let mut output = Vec::new();
+        let mut writer = PacketWriter::new(&mut output);
+        let (id_header, comment_header) = StreamBuilder::default().header_packets()?;
+        for header in [id_header, comment_header] {
+            writer.write_packet(header, 1, PacketWriteEndInfo::EndPage, 0).map_err(Error::WriteError)?;
         }
-        let windows = Windows100ms { inner: windows };
-        Self::gated_mean_to_lufs(windows.as_ref())
+        let mut frame = vec![0.0f32; FRAME_SAMPLES * 2];
+        for frame_idx in 0..num_frames {
+            for (idx, sample) in frame.iter_mut().enumerate() {
+                #[allow(clippy::cast_precision_loss)]
+                let t = (frame_idx * FRAME_SAMPLES + idx / 2) as f32 / SAMPLE_RATE as f32;
+                *sample = (t * 440.0 * std::f32::consts::TAU).sin() * (0.3 + 0.2 * (t * 0.7).sin());
+            }
+            let packet = if dtx(frame_idx) {
+                Vec::new()
+            } else {
+                encoder.encode_vec_float(&frame, 4000).map_err(Error::OpusError)?
+            };
+            let end = if frame_idx + 1 == num_frames {
+                PacketWriteEndInfo::EndStream
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            let granule = (PRESKIP + (frame_idx + 1) * FRAME_SAMPLES) as u64;
+            writer.write_packet(packet, 1, end, granule).map_err(Error::WriteError)?;
+        }
+        drop(writer);
+        Ok(output)
+    }
+
+    fn analyze(mut session: AnalysisSession, stream: &[u8]) -> Result<Decibels, Error> {
+        Ok(session.analyze_file(Cursor::new(stream))?.lufs())
+    }
+
+    #[test]
+    fn parallel_decode_matches_sequential() -> Result<(), Error> {
+        let stream = encode_stream(PARALLEL_CHUNK_PACKETS * 5 / 2)?;
+        let sequential = analyze(AnalysisSession::default(), &stream)?;
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().expect("Failed to create thread pool");
+        let parallel = analyze(AnalysisSession::parallel_in(Arc::new(pool)), &stream)?;
+        assert!((sequential - parallel).as_f64().abs() < 0.01, "{:?} != {:?}", sequential, parallel);
+        Ok(())
+    }
+
+    #[test]
+    fn zero_length_packets_decoded_as_silence() -> Result<(), Error> {
+        let window_count = |stream: &[u8], parallel| -> Result<usize, Error> {
+            let mut session = if parallel { AnalysisSession::parallel() } else { AnalysisSession::default() };
+            let num_windows = session.analyze_file(Cursor::new(stream))?.window_powers().len();
+            Ok(num_windows)
+        };
+        let num_frames = PARALLEL_CHUNK_PACKETS * 2;
+        let expected = window_count(&encode_stream(num_frames)?, false)?;
+        // Gaps at the start of the stream and spanning a chunk boundary
+        let stream = encode_stream_with_dtx(num_frames, |idx| idx < 3 || (idx / 50) % 4 == 1)?;
+        assert_eq!(window_count(&stream, false)?, expected);
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().expect("Failed to create thread pool");
+        let sequential = analyze(AnalysisSession::default(), &stream)?;
+        let parallel = pool.install(|| analyze(AnalysisSession::parallel(), &stream))?;
+        assert!((sequential - parallel).as_f64().abs() < 0.01, "{:?} != {:?}", sequential, parallel);
+        assert_eq!(pool.install(|| window_count(&stream, true))?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn session_analyzes_tracks_and_album() -> Result<(), Error> {
+        // The encoder does not compensate for pre-skip so it is trimmed from
+        // the audio of each frame
+        let duration = |num_frames: usize| {
+            Duration::from_micros(((num_frames * FRAME_SAMPLES - PRESKIP) * 1000 / (SAMPLE_RATE / 1000)) as u64)
+        };
+        let mut session = AnalysisSession::default();
+        let short = session.analyze_file(Cursor::new(encode_stream(50)?))?.clone();
+        assert_eq!(short.duration(), duration(50));
+        assert!(short.peak() > 0.3 && short.peak() <= 1.0, "Unexpected peak {}", short.peak());
+        let long = session.analyze_file(Cursor::new(encode_stream(125)?))?.clone();
+        assert_eq!(long.duration(), duration(125));
+        assert_eq!(session.tracks().len(), 2);
+
+        let album = session.finish();
+        assert_eq!(album.duration(), duration(50) + duration(125));
+        assert!((album.peak() - short.peak().max(long.peak())).abs() < f32::EPSILON);
+        let (short_lufs, long_lufs) = (short.lufs().as_f64(), long.lufs().as_f64());
+        let album_lufs = album.lufs().as_f64();
+        assert!(album_lufs >= short_lufs.min(long_lufs) && album_lufs <= short_lufs.max(long_lufs));
+
+        assert!(matches!(VolumeAnalyzer::default().finish(), Err(Error::MissingStream(Codec::Opus))));
+        Ok(())
+    }
+
+    #[test]
+    fn windows_exposed_with_timestamps() -> Result<(), Error> {
+        let stream = encode_stream(50)?;
+        let mut analyzer = VolumeAnalyzer::default();
+        assert!(analyzer.windows().is_empty());
+        let mut ogg_reader = PacketReader::new(Cursor::new(&stream));
+        while let Some(packet) = ogg_reader.read_packet().map_err(Error::OggDecode)? {
+            analyzer.submit(packet)?;
+        }
+        let windows = analyzer.windows();
+        let track = analyzer.finish()?;
+        assert_eq!(windows, track.windows());
+        assert_eq!(windows.len(), 9);
+        assert!(windows[0].start_seconds.abs() < f64::EPSILON);
+        for pair in windows.windows(2) {
+            assert!((pair[1].start_seconds - pair[0].start_seconds - 0.1).abs() < 1e-9);
+        }
+        for window in &windows {
+            assert!(window.lufs() < 0.0 && window.lufs() > -30.0, "Unexpected loudness {}", window.lufs());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn analysis_progress_reported() -> Result<(), Error> {
+        let stream = encode_stream(100)?;
+        let mut updates = Vec::new();
+        let mut observer = |progress: &AnalysisProgress| updates.push(*progress);
+        AnalysisSession::default().analyze_file_with_progress(
+            Cursor::new(&stream),
+            &Never::default(),
+            &mut observer,
+        )?;
+        assert_eq!(updates.len(), 103);
+        assert!(updates.windows(2).all(|pair| pair[0].bytes_read <= pair[1].bytes_read));
+        let last = updates.last().expect("Missing progress update");
+        let len = stream.len() as u64;
+        assert_eq!(*last, AnalysisProgress { packets: 102, bytes_read: len, total_bytes: len, complete: true });
+        assert!(updates[..102].iter().all(|progress| !progress.complete));
+        Ok(())
+    }
+
+    #[test]
+    fn file_analyzed_by_path() -> Result<(), Error> {
+        let stream = encode_stream(100)?;
+        let expected = analyze(AnalysisSession::default(), &stream)?;
+        let dir = tempfile::tempdir().map_err(Error::ReadError)?;
+        let path = dir.path().join("track.opus");
+        std::fs::write(&path, &stream).map_err(Error::WriteError)?;
+        let mut observer = progress::Ignore::default();
+        let track = analyze_file(&path, &Never::default(), &mut observer)?;
+        assert!((track.lufs() - expected).as_f64().abs() < f64::EPSILON);
+        let missing = analyze_file(dir.path().join("missing.opus"), &Never::default(), &mut observer);
+        assert!(matches!(missing, Err(Error::FileOpenError(..))));
+
+        let handle = analyze_file_in_background(&path);
+        let track = handle.join()?;
+        assert!((track.lufs() - expected).as_f64().abs() < f64::EPSILON);
+        Ok(())
+    }
+
+    #[test]
+    fn end_trimmed_to_granule_position() {
+        let mut trim = Trim::new(312);
+        assert_eq!(trim.keep(960), 312..960);
+        assert_eq!(trim.keep(960), 0..960);
+        trim.end = Some(312 + 960 + 1500);
+        assert_eq!(trim.keep(960), 0..852);
+        assert_eq!(trim.keep(960), 0..0);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_analysis_matches_sync() -> Result<(), Error> {
+        let stream = encode_stream(100)?;
+        let expected = analyze(AnalysisSession::default(), &stream)?;
+        let runtime = tokio::runtime::Builder::new_current_thread().build().map_err(Error::ReadError)?;
+        let mut session = AnalysisSession::default();
+        let lufs = runtime.block_on(session.analyze_file_async(stream.as_slice()))?.lufs();
+        assert!((expected - lufs).as_f64().abs() < f64::EPSILON);
+        Ok(())
     }
 }
@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::io::{BufReader, Seek};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::header::IdHeader as _;
+use crate::header_rewriter::{read_headers, rewrite_file, CodecHeaders, HeaderRewrite};
+use crate::ogg_page::last_granule_position;
+use crate::volume_rewrite::OpusGains;
+use crate::{opus, Error};
+
+/// Replaces the codec headers with a fixed set
+#[derive(Debug)]
+struct ReplaceHeaders {
+    headers: CodecHeaders,
+}
+
+impl HeaderRewrite for ReplaceHeaders {
+    type Error = Error;
+
+    fn rewrite(&self, headers: &mut CodecHeaders) -> Result<(), Error> {
+        *headers = self.headers.clone();
+        Ok(())
+    }
+}
+
+/// An Ogg Opus file whose headers have been read into memory. The tags and
+/// gains can be inspected and modified, then written back with `save()`.
+#[derive(Clone, Debug)]
+pub struct OpusFile {
+    path: PathBuf,
+    id_header: opus::IdHeader,
+    comment_header: opus::CommentHeader,
+    duration: Duration,
+}
+
+impl OpusFile {
+    /// Reads the headers of the Ogg Opus file at `path` and determines its
+    /// duration. Only the first logical stream is considered.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<OpusFile, Error> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+        let mut reader = BufReader::new(file);
+        let (id_header, comment_header) = match read_headers(&mut reader)? {
+            CodecHeaders::Opus(id_header, comment_header) => (id_header, comment_header),
+            headers @ CodecHeaders::Vorbis(..) => return Err(Error::UnsupportedCodec(headers.codec())),
+        };
+
+        reader.rewind().map_err(|e| Error::FileReadError(path.to_path_buf(), e))?;
+        let granule_position = last_granule_position(&mut reader)?.unwrap_or(0);
+        let samples = granule_position.saturating_sub(id_header.preskip_samples() as u64);
+        let sample_rate = id_header.output_sample_rate() as u64;
+        let duration = Duration::from_secs(samples / sample_rate)
+            + Duration::from_nanos((samples % sample_rate) * 1_000_000_000 / sample_rate);
+        Ok(OpusFile { path: path.to_path_buf(), id_header, comment_header, duration })
+    }
+
+    /// The path the file was opened from and will be saved to
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// The comment header containing the tags of the file
+    pub fn tags(&self) -> &opus::CommentHeader { &self.comment_header }
+
+    /// Mutable access to the tags of the file. Changes are only written by
+    /// `save()`.
+    pub fn tags_mut(&mut self) -> &mut opus::CommentHeader { &mut self.comment_header }
+
+    /// The identification header of the file
+    pub fn id_header(&self) -> &opus::IdHeader { &self.id_header }
+
+    /// Mutable access to the identification header, e.g. to change the output
+    /// gain. Changes are only written by `save()`.
+    pub fn id_header_mut(&mut self) -> &mut opus::IdHeader { &mut self.id_header }
+
+    /// The output gain and R128 gain tags of the file
+    pub fn gains(&self) -> OpusGains { OpusGains::from_headers(&self.id_header, &self.comment_header) }
+
+    /// The duration of the decoded audio, excluding pre-skip
+    pub fn duration(&self) -> Duration { self.duration }
+
+    /// The number of decoded output channels
+    pub fn channels(&self) -> usize { self.id_header.num_output_channels() }
+
+    /// Writes the current headers back to the file. The file is only replaced
+    /// if the headers differ from those on disk, in which case `true` is
+    /// returned. Audio pages are copied verbatim and the file is replaced
+    /// atomically via a temporary file in the same directory.
+    pub fn save(&self) -> Result<bool, Error> {
+        let rewrite =
+            ReplaceHeaders { headers: CodecHeaders::Opus(self.id_header.clone(), self.comment_header.clone()) };
+        rewrite_file(&self.path, rewrite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::CommentList;
+    use crate::testing::StreamBuilder;
+
+    fn write_opus_file(path: &Path) -> Result<(), Error> {
+        let stream = StreamBuilder::default().duration(Duration::from_micros(993_500)).build()?;
+        std::fs::write(path, stream).map_err(Error::WriteError)
+    }
+
+    #[test]
+    fn open_modify_and_save() -> Result<(), Error> {
+        let dir = tempfile::tempdir().map_err(Error::ReadError)?;
+        let path = dir.path().join("test.opus");
+        write_opus_file(&path)?;
+
+        let mut file = OpusFile::open(&path)?;
+        assert_eq!(file.channels(), 2);
+        assert_eq!(file.duration(), Duration::from_micros(993_500));
+        assert!(file.gains().track_r128.is_none());
+        assert!(!file.save()?);
+
+        file.tags_mut().push("TITLE", "Example")?;
+        assert!(file.save()?);
+        let reopened = OpusFile::open(&path)?;
+        assert_eq!(reopened.tags().get_first("TITLE"), Some("Example"));
+        assert_eq!(reopened.duration(), file.duration());
+        Ok(())
+    }
+}
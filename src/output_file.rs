@@ -1,9 +1,106 @@
 use std::ffi::{OsStr, OsString};
-use std::io::{self, Write};
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use tempfile::NamedTempFile;
-use zoog::Error;
+use tempfile::{NamedTempFile, PersistError};
+
+use crate::Error;
+
+/// Space to leave free on a filesystem after writing a temporary file
+pub const FREE_SPACE_MARGIN: u64 = 64 * 1024 * 1024;
+
+/// The maximum length of a file name on common filesystems, in bytes on Unix
+/// and UTF-16 code units on Windows
+const MAX_FILE_NAME_LEN: usize = 255;
+
+/// The length of the random part of temporary file names
+const TEMP_RANDOM_LEN: usize = 6;
+
+/// How creating and renaming output files is retried after an error which is
+/// likely to be transient, such as a file being briefly locked by another
+/// process or a network share failing to respond. The delay before each retry
+/// is double that before the last.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The number of times to retry an operation before failing
+    pub retries: u32,
+
+    /// The delay before the first retry
+    pub initial_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Fails on the first error
+    pub const NEVER: RetryPolicy = RetryPolicy { retries: 0, initial_delay: Duration::ZERO };
+
+    /// The delays before each retry
+    fn delays(self) -> impl Iterator<Item = Duration> {
+        std::iter::successors(Some(self.initial_delay), |delay| Some(delay.saturating_mul(2)))
+            .take(self.retries as usize)
+    }
+
+    /// Performs `operation`, retrying it while it fails with a transient error
+    fn run<T>(self, mut operation: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut delays = self.delays();
+        loop {
+            match operation() {
+                Err(e) if is_transient(&e) => match delays.next() {
+                    Some(delay) => {
+                        tracing::debug!(error = %e, ?delay, "Retrying after transient error");
+                        std::thread::sleep(delay);
+                    }
+                    None => break Err(e),
+                },
+                result => break result,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Retries three times, waiting for a total of 700ms
+    fn default() -> RetryPolicy { RetryPolicy { retries: 3, initial_delay: Duration::from_millis(100) } }
+}
+
+/// Whether an error is one which may not recur if the operation is retried
+fn is_transient(error: &io::Error) -> bool {
+    if matches!(error.kind(), io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) {
+        return true;
+    }
+    #[cfg(unix)]
+    {
+        error.raw_os_error() == Some(libc::EBUSY)
+    }
+    #[cfg(windows)]
+    {
+        use winapi::shared::winerror::{ERROR_LOCK_VIOLATION, ERROR_SHARING_VIOLATION};
+        [ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION].iter().any(|&code| error.raw_os_error() == Some(code as i32))
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        false
+    }
+}
+
+/// Renames `temp` to `path`, retrying after transient errors
+fn persist_with_retry(mut temp: NamedTempFile, path: &Path, retry: RetryPolicy) -> Result<File, PersistError> {
+    let mut delays = retry.delays();
+    loop {
+        match temp.persist(path) {
+            Err(e) if is_transient(&e.error) => match delays.next() {
+                Some(delay) => {
+                    tracing::debug!(path = %path.display(), error = %e.error, ?delay, "Retrying rename");
+                    std::thread::sleep(delay);
+                    temp = e.file;
+                }
+                None => break Err(e),
+            },
+            result => break result,
+        }
+    }
+}
 
 #[derive(Debug)]
 enum FileEnum {
@@ -11,42 +108,92 @@ enum FileEnum {
     Sink,
 }
 
+/// A file which is written to a temporary, by default alongside its final
+/// path, and only replaces the file at that path once committed. The
+/// replacement keeps the
+/// permissions of the file it replaces and, where possible, its owner, group
+/// and extended attributes.
 #[derive(Debug)]
 pub struct OutputFile {
     file_enum: FileEnum,
+    bytes_written: u64,
+    durable: bool,
+    retry: RetryPolicy,
 }
 
-fn make_sibling_temporary_file(path: &Path, distinguisher: &OsStr) -> Result<NamedTempFile, Error> {
+fn make_sibling_temporary_file(path: &Path, distinguisher: &OsStr, retry: RetryPolicy) -> Result<NamedTempFile, Error> {
     let parent_dir = path.parent().ok_or_else(|| Error::NoParentError(path.to_path_buf()))?;
+    make_temporary_file_in(parent_dir, path, distinguisher, retry)
+}
+
+/// Creates a temporary file in `dir` with a name derived from that of `path`
+fn make_temporary_file_in(
+    dir: &Path, path: &Path, distinguisher: &OsStr, retry: RetryPolicy,
+) -> Result<NamedTempFile, Error> {
     let file_stem = path.file_stem().ok_or_else(|| Error::NotAFilePath(path.to_path_buf()))?;
     let file_ext = path.extension().map(|e| {
         let mut ext = OsString::from(".");
         ext.push(e);
         ext
     });
+    // The stem is shortened if necessary so that the name of the temporary is
+    // no longer than that of the file it replaces could be
+    let reserved = 1 + distinguisher.len() + TEMP_RANDOM_LEN + file_ext.as_ref().map_or(0, |ext| ext.len());
     let file_stem = {
-        let mut stem = file_stem.to_os_string();
+        let mut stem = truncate_file_name(file_stem, MAX_FILE_NAME_LEN.saturating_sub(reserved));
         stem.push("-");
         stem.push(distinguisher);
         stem
     };
     let mut builder = tempfile::Builder::new();
-    builder.prefix(&file_stem);
+    builder.prefix(&file_stem).rand_bytes(TEMP_RANDOM_LEN);
     if let Some(file_ext) = file_ext.as_ref() {
         builder.suffix(file_ext);
     }
-    let temp = builder.tempfile_in(parent_dir).map_err(|e| Error::TempFileOpenError(parent_dir.to_path_buf(), e))?;
+    let temp = retry.run(|| builder.tempfile_in(dir)).map_err(|e| Error::TempFileOpenError(dir.to_path_buf(), e))?;
     Ok(temp)
 }
 
+/// Shortens `name` to at most `max_len` bytes. Long names which are not valid
+/// Unicode are shortened lossily, since the result need only resemble the
+/// original.
+fn truncate_file_name(name: &OsStr, max_len: usize) -> OsString {
+    if name.len() <= max_len {
+        return name.to_os_string();
+    }
+    let name = name.to_string_lossy();
+    let end = name.char_indices().map(|(idx, c)| idx + c.len_utf8()).take_while(|&end| end <= max_len).last();
+    OsString::from(&name[..end.unwrap_or(0)])
+}
+
 impl OutputFile {
     /// Creates a new output that discards all data written
-    pub fn new_sink() -> OutputFile { OutputFile { file_enum: FileEnum::Sink } }
+    pub fn new_sink() -> OutputFile {
+        OutputFile { file_enum: FileEnum::Sink, bytes_written: 0, durable: false, retry: RetryPolicy::NEVER }
+    }
 
     /// Writes to a temporary that replaces the specified path on `commit()`.
-    pub fn new_target(path: &Path) -> Result<OutputFile, Error> {
-        let temp = make_sibling_temporary_file(path, OsStr::new("new"))?;
-        Ok(OutputFile { file_enum: FileEnum::Temp(temp, path.to_path_buf()) })
+    pub fn new_target(path: &Path) -> Result<OutputFile, Error> { Self::new_target_in(path, None) }
+
+    /// Like `new_target()`, but writes the temporary to `temp_dir` if
+    /// specified. If the temporary cannot be renamed over the target on
+    /// `commit()` because it is on a different filesystem, it is copied
+    /// alongside the target first.
+    pub fn new_target_in(path: &Path, temp_dir: Option<&Path>) -> Result<OutputFile, Error> {
+        Self::new_target_with_retry(path, temp_dir, RetryPolicy::default())
+    }
+
+    /// Like `new_target_in()`, but retries creating the temporary and
+    /// renaming it on `commit()` according to `retry` rather than the default
+    /// policy
+    pub fn new_target_with_retry(
+        path: &Path, temp_dir: Option<&Path>, retry: RetryPolicy,
+    ) -> Result<OutputFile, Error> {
+        let temp = match temp_dir {
+            Some(temp_dir) => make_temporary_file_in(temp_dir, path, OsStr::new("new"), retry)?,
+            None => make_sibling_temporary_file(path, OsStr::new("new"), retry)?,
+        };
+        Ok(OutputFile { file_enum: FileEnum::Temp(temp, path.to_path_buf()), bytes_written: 0, durable: false, retry })
     }
 
     /// Writes to a temporary that replaces the specified path on `commit()` if
@@ -59,12 +206,41 @@ impl OutputFile {
         }
     }
 
+    /// Sets whether `commit()` also syncs the directory containing the file
+    /// after the rename, so that the replacement survives a power loss. This
+    /// only has an effect on Unix.
+    pub fn set_durable(&mut self, durable: bool) { self.durable = durable; }
+
+    /// Checks that the filesystem the temporary is written to has room for
+    /// `size` bytes plus `FREE_SPACE_MARGIN`, as does that of the final path if
+    /// the temporary will need to be copied alongside it. This allows a
+    /// rewrite to fail with `Error::InsufficientSpace` before any data is
+    /// written, rather than part way through.
+    pub fn check_free_space(&self, size: u64) -> Result<(), Error> {
+        match &self.file_enum {
+            FileEnum::Sink => Ok(()),
+            FileEnum::Temp(temp, final_path) => {
+                let temp_dir = temp.path().parent().ok_or_else(|| Error::NoParentError(temp.path().to_path_buf()))?;
+                let final_dir = final_path.parent().ok_or_else(|| Error::NoParentError(final_path.clone()))?;
+                let final_dir = if final_dir.as_os_str().is_empty() { Path::new(".") } else { final_dir };
+                check_free_space(temp_dir, size)?;
+                if !same_filesystem(temp_dir, final_dir)? {
+                    check_free_space(final_dir, size)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The number of bytes written so far
+    pub fn bytes_written(&self) -> u64 { self.bytes_written }
+
     /// Deletes the underlying file.
-    #[allow(dead_code)]
     pub fn abort(self) -> Result<(), Error> {
         match self.file_enum {
             FileEnum::Sink => {}
-            FileEnum::Temp(temp, _) => {
+            FileEnum::Temp(temp, final_path) => {
+                tracing::debug!(path = %final_path.display(), "Discarded output file");
                 let temp_path = temp.path().to_path_buf();
                 temp.close().map_err(|e| Error::FileDelete(temp_path, e))?;
             }
@@ -80,25 +256,212 @@ impl OutputFile {
                 // How to write this code so that it minimizes the chance of
                 // data loss is an open question.
 
+                // Carry over the permissions and other attributes of the file being replaced
+                copy_file_attributes(&final_path, temp.as_file())?;
+
                 // Sync all data of the new file to disk
                 temp.as_file().sync_all().map_err(Error::WriteError)?;
 
-                // Persist the temporary to the final path
-                temp.persist(final_path)
-                    .map_err(Error::PersistError)
-                    .and_then(|f| f.sync_all().map_err(Error::WriteError))?;
+                // Persist the temporary to the final path. A rename is only
+                // possible within a filesystem, so a temporary elsewhere is
+                // first copied alongside the target.
+                let persisted = match persist_with_retry(temp, &final_path, self.retry) {
+                    Err(e) if is_cross_device(&e.error) => {
+                        tracing::debug!(path = %final_path.display(), "Copying temporary from another filesystem");
+                        let sibling = copy_to_sibling(e.file, &final_path, self.retry)?;
+                        copy_file_attributes(&final_path, sibling.as_file())?;
+                        sibling.as_file().sync_all().map_err(Error::WriteError)?;
+                        persist_with_retry(sibling, &final_path, self.retry)
+                    }
+                    result => result,
+                };
+                persisted.map_err(Error::PersistError).and_then(|f| f.sync_all().map_err(Error::WriteError))?;
+
+                // Sync the directory so that the rename itself is durable
+                if self.durable {
+                    sync_parent_directory(&final_path)?;
+                }
+                tracing::debug!(path = %final_path.display(), bytes = self.bytes_written, "Committed output file");
             }
         }
         Ok(())
     }
 }
 
+/// Whether a rename failed because the source and destination are on
+/// different filesystems
+fn is_cross_device(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        error.raw_os_error() == Some(libc::EXDEV)
+    }
+    #[cfg(windows)]
+    {
+        error.raw_os_error() == Some(winapi::shared::winerror::ERROR_NOT_SAME_DEVICE as i32)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = error;
+        false
+    }
+}
+
+/// Fails with `Error::InsufficientSpace` unless the filesystem containing `dir`
+/// has room for `size` bytes plus `FREE_SPACE_MARGIN`
+#[cfg(not(target_family = "wasm"))]
+fn check_free_space(dir: &Path, size: u64) -> Result<(), Error> {
+    let available = fs2::available_space(dir).map_err(|e| Error::FileReadError(dir.to_path_buf(), e))?;
+    let required = size.saturating_add(FREE_SPACE_MARGIN);
+    if available < required {
+        return Err(Error::InsufficientSpace(dir.to_path_buf(), required, available));
+    }
+    Ok(())
+}
+
+/// Free space cannot be queried on WebAssembly
+#[cfg(target_family = "wasm")]
+fn check_free_space(_dir: &Path, _size: u64) -> Result<(), Error> { Ok(()) }
+
+/// Whether `first` and `second` are directories on the same filesystem. Where
+/// this cannot be determined, they are only considered to be on the same
+/// filesystem if they are the same directory.
+fn same_filesystem(first: &Path, second: &Path) -> Result<bool, Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let device =
+            |dir: &Path| fs::metadata(dir).map(|m| m.dev()).map_err(|e| Error::FileReadError(dir.to_path_buf(), e));
+        Ok(device(first)? == device(second)?)
+    }
+    #[cfg(not(unix))]
+    {
+        let canonical = |dir: &Path| fs::canonicalize(dir).map_err(|e| Error::FileReadError(dir.to_path_buf(), e));
+        Ok(canonical(first)? == canonical(second)?)
+    }
+}
+
+/// Copies the content of `temp` to a new temporary alongside `path`, deleting
+/// `temp` afterwards
+fn copy_to_sibling(mut temp: NamedTempFile, path: &Path, retry: RetryPolicy) -> Result<NamedTempFile, Error> {
+    let mut sibling = make_sibling_temporary_file(path, OsStr::new("new"), retry)?;
+    temp.as_file_mut()
+        .seek(SeekFrom::Start(0))
+        .and_then(|_| io::copy(temp.as_file_mut(), sibling.as_file_mut()))
+        .map_err(|e| Error::FileCopy(temp.path().to_path_buf(), sibling.path().to_path_buf(), e))?;
+    let temp_path = temp.path().to_path_buf();
+    temp.close().map_err(|e| Error::FileDelete(temp_path, e))?;
+    Ok(sibling)
+}
+
+/// Copies the permissions of the file at `source`, if there is one, onto
+/// `target`. Where possible, the owner, group and extended attributes are also
+/// copied, but failing to do so is not an error since it may require
+/// privileges the user does not have.
+fn copy_file_attributes(source: &Path, target: &File) -> Result<(), Error> {
+    let metadata = match fs::metadata(source) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(Error::FileReadError(source.to_path_buf(), e)),
+    };
+
+    // Ownership must be changed first since doing so can clear the set-user-ID
+    // and set-group-ID bits
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        use std::os::unix::io::AsRawFd;
+
+        // SAFETY: the descriptor is owned by `target` and remains open for the
+        // duration of the call
+        if unsafe { libc::fchown(target.as_raw_fd(), metadata.uid(), metadata.gid()) } != 0 {
+            let error = io::Error::last_os_error();
+            tracing::debug!(path = %source.display(), %error, "Unable to preserve file ownership");
+        }
+    }
+
+    target.set_permissions(metadata.permissions()).map_err(|e| Error::FileWriteError(source.to_path_buf(), e))?;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Err(error) = File::open(source).and_then(|source_file| xattr::copy(&source_file, target)) {
+        tracing::debug!(path = %source.display(), %error, "Unable to preserve extended attributes");
+    }
+    Ok(())
+}
+
+/// Copying of extended attributes using the Linux system calls
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod xattr {
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    /// Calls `f` with a buffer of the size it requests when passed an empty
+    /// one, retrying if the required size grows in between
+    fn read_sized<F: FnMut(*mut libc::c_void, usize) -> libc::ssize_t>(mut f: F) -> io::Result<Vec<u8>> {
+        loop {
+            let size = f(std::ptr::null_mut(), 0);
+            let Ok(size) = usize::try_from(size) else { return Err(io::Error::last_os_error()) };
+            let mut buffer = vec![0u8; size];
+            let read = f(buffer.as_mut_ptr().cast(), buffer.len());
+            if let Ok(read) = usize::try_from(read) {
+                buffer.truncate(read);
+                return Ok(buffer);
+            }
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::ERANGE) {
+                return Err(error);
+            }
+        }
+    }
+
+    /// Copies the extended attributes of `source` onto `target`. Attributes
+    /// which cannot be copied are skipped and the first such error returned.
+    pub fn copy(source: &File, target: &File) -> io::Result<()> {
+        let (source, target) = (source.as_raw_fd(), target.as_raw_fd());
+        // SAFETY: the buffer pointer and length passed to each call describe
+        // a valid allocation, or are null and zero
+        let names = read_sized(|buf, len| unsafe { libc::flistxattr(source, buf.cast(), len) })?;
+        let mut result = Ok(());
+        for name in names.split_inclusive(|&b| b == 0) {
+            let Ok(name) = CStr::from_bytes_with_nul(name) else { continue };
+            let copied =
+                read_sized(|buf, len| unsafe { libc::fgetxattr(source, name.as_ptr(), buf, len) }).and_then(|value| {
+                    // SAFETY: `name` is NUL-terminated and `value` is a valid slice
+                    let status =
+                        unsafe { libc::fsetxattr(target, name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+                    if status == 0 {
+                        Ok(())
+                    } else {
+                        Err(io::Error::last_os_error())
+                    }
+                });
+            result = result.and(copied);
+        }
+        result
+    }
+}
+
+/// Syncs the directory containing `path` to disk
+#[cfg(unix)]
+fn sync_parent_directory(path: &Path) -> Result<(), Error> {
+    let parent = path.parent().ok_or_else(|| Error::NoParentError(path.to_path_buf()))?;
+    let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+    File::open(parent).and_then(|dir| dir.sync_all()).map_err(|e| Error::FileWriteError(parent.to_path_buf(), e))
+}
+
+/// Directories cannot be synced portably on other platforms
+#[cfg(not(unix))]
+fn sync_parent_directory(_path: &Path) -> Result<(), Error> { Ok(()) }
+
 impl Write for OutputFile {
     fn write(&mut self, data: &[u8]) -> Result<usize, io::Error> {
-        match &mut self.file_enum {
-            FileEnum::Sink => Ok(data.len()),
-            FileEnum::Temp(ref mut temp, _) => temp.write(data),
-        }
+        let written = match &mut self.file_enum {
+            FileEnum::Sink => data.len(),
+            FileEnum::Temp(ref mut temp, _) => temp.write(data)?,
+        };
+        self.bytes_written += written as u64;
+        Ok(written)
     }
 
     fn flush(&mut self) -> Result<(), io::Error> {
@@ -108,3 +471,101 @@ impl Write for OutputFile {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+
+    #[cfg(unix)]
+    #[test]
+    fn permissions_preserved() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.opus");
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        let mut output = OutputFile::new_target(&path).unwrap();
+        output.write_all(b"new").unwrap();
+        output.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+    }
+
+    #[test]
+    fn free_space_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.opus");
+        let output = OutputFile::new_target(&path).unwrap();
+        output.check_free_space(1).unwrap();
+        let error = output.check_free_space(u64::MAX / 2).unwrap_err();
+        assert!(matches!(error, Error::InsufficientSpace(_, required, available) if available < required));
+        assert_eq!(error.kind(), ErrorKind::Io);
+        OutputFile::new_sink().check_free_space(u64::MAX).unwrap();
+    }
+
+    #[test]
+    fn temporary_name_length_limited() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(format!("{}.opus", "é".repeat(125)));
+        fs::write(&path, b"old").unwrap();
+        let mut output = OutputFile::new_target(&path).unwrap();
+        output.write_all(b"new").unwrap();
+        output.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+
+        assert_eq!(truncate_file_name(OsStr::new("abcdef"), 10), OsStr::new("abcdef"));
+        assert_eq!(truncate_file_name(OsStr::new("aééé"), 4), OsStr::new("aé"));
+    }
+
+    #[test]
+    fn transient_errors_retried() {
+        let retry = RetryPolicy { retries: 2, initial_delay: Duration::from_millis(1) };
+        assert_eq!(retry.delays().collect::<Vec<_>>(), [Duration::from_millis(1), Duration::from_millis(2)]);
+
+        let mut attempts = 0;
+        let result = retry.run(|| {
+            attempts += 1;
+            if attempts < 3 {
+                Err(io::Error::from(io::ErrorKind::WouldBlock))
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        let mut attempts = 0;
+        let result: io::Result<()> = retry.run(|| {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+
+        let mut attempts = 0;
+        let result: io::Result<()> = RetryPolicy::NEVER.run(|| {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::Interrupted))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn temporary_in_separate_directory() {
+        let target_dir = tempfile::tempdir().unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = target_dir.path().join("file.opus");
+        fs::write(&path, b"old").unwrap();
+
+        let mut output = OutputFile::new_target_in(&path, Some(temp_dir.path())).unwrap();
+        output.write_all(b"new").unwrap();
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+        output.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(target_dir.path()).unwrap().count(), 1);
+    }
+}
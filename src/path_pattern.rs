@@ -0,0 +1,154 @@
+use std::path::{Component, Path};
+
+use zoog::header::validate_comment_field_name;
+
+/// Placeholder names which map to a tag with a different name
+const FIELD_ALIASES: [(&str, &str); 3] = [("track", "TRACKNUMBER"), ("disc", "DISCNUMBER"), ("year", "DATE")];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Part {
+    Literal(String),
+    Field(String),
+}
+
+/// A pattern such as `%artist%/%album%/%track% - %title%` which extracts tag
+/// values from the trailing components of a path. Each `%name%` placeholder
+/// matches one or more characters within a single path component and `%%`
+/// matches a literal `%`. The extension of the file name is ignored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PathPattern {
+    components: Vec<Vec<Part>>,
+}
+
+impl PathPattern {
+    pub fn new(pattern: &str) -> Result<PathPattern, String> {
+        let components = pattern.split('/').map(parse_component).collect::<Result<Vec<_>, _>>()?;
+        if components.iter().any(Vec::is_empty) {
+            return Err(format!("`{}` contains an empty path component", pattern));
+        }
+        Ok(PathPattern { components })
+    }
+
+    /// Returns the tag names and values extracted from `path`, or `None` if
+    /// it does not match
+    pub fn match_path(&self, path: &Path) -> Option<Vec<(String, String)>> {
+        let mut names: Vec<&str> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_str()),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        let file_stem = path.file_stem()?.to_str()?;
+        *names.last_mut()? = file_stem;
+        let names = names.get(names.len().checked_sub(self.components.len())?..)?;
+        let mut tags = Vec::new();
+        for (parts, name) in self.components.iter().zip(names) {
+            if !match_parts(parts, name, &mut tags) {
+                return None;
+            }
+        }
+        Some(tags)
+    }
+}
+
+fn parse_component(component: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = component;
+    while let Some(start) = rest.find('%') {
+        literal.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let end = after.find('%').ok_or_else(|| format!("unterminated placeholder in `{}`", component))?;
+        let name = &after[..end];
+        if name.is_empty() {
+            literal.push('%');
+        } else {
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(Part::Field(tag_for_placeholder(name)?));
+        }
+        rest = &after[end + 1..];
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(parts)
+}
+
+fn tag_for_placeholder(name: &str) -> Result<String, String> {
+    let lower = name.to_ascii_lowercase();
+    let tag = match FIELD_ALIASES.iter().find(|(alias, _)| *alias == lower) {
+        Some((_, tag)) => (*tag).to_string(),
+        None => name.to_ascii_uppercase(),
+    };
+    validate_comment_field_name(&tag).map_err(|e| e.to_string())?;
+    Ok(tag)
+}
+
+/// Matches `text` against `parts`, preferring shorter matches for earlier
+/// placeholders. A placeholder which occurs more than once must match the same
+/// text each time.
+fn match_parts(parts: &[Part], text: &str, tags: &mut Vec<(String, String)>) -> bool {
+    match parts.split_first() {
+        None => text.is_empty(),
+        Some((Part::Literal(literal), rest)) => {
+            text.strip_prefix(literal.as_str()).map_or(false, |text| match_parts(rest, text, tags))
+        }
+        Some((Part::Field(name), rest)) => {
+            for end in text.char_indices().map(|(start, c)| start + c.len_utf8()) {
+                let (value, remainder) = text.split_at(end);
+                let previous = tags.iter().find(|(existing, _)| existing == name).map(|(_, value)| value.as_str());
+                if previous.map_or(false, |previous| previous != value) {
+                    continue;
+                }
+                let added = previous.is_none();
+                if added {
+                    tags.push((name.clone(), value.to_string()));
+                }
+                if match_parts(rest, remainder, tags) {
+                    return true;
+                }
+                if added {
+                    tags.pop();
+                }
+            }
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| ((*k).to_string(), (*v).to_string())).collect()
+    }
+
+    #[test]
+    fn tags_extracted_from_path() -> Result<(), String> {
+        let pattern = PathPattern::new("%artist%/%album%/%track% - %title%")?;
+        let path = Path::new("/music/An Artist/An Album/01 - A Title - Live.opus");
+        let expected =
+            tags(&[("ARTIST", "An Artist"), ("ALBUM", "An Album"), ("TRACKNUMBER", "01"), ("TITLE", "A Title - Live")]);
+        assert_eq!(pattern.match_path(path), Some(expected));
+
+        assert_eq!(pattern.match_path(Path::new("An Album/01 - A Title.opus")), None);
+        assert_eq!(pattern.match_path(Path::new("a/b/No separator.opus")), None);
+
+        let pattern = PathPattern::new("%artist% - %title% (%artist%) 100%%")?;
+        let path = Path::new("A - B (A) 100%.opus");
+        assert_eq!(pattern.match_path(path), Some(tags(&[("ARTIST", "A"), ("TITLE", "B")])));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_patterns_rejected() {
+        assert!(PathPattern::new("%artist").is_err());
+        assert!(PathPattern::new("%artist%//%title%").is_err());
+        assert!(PathPattern::new("%ti=tle%").is_err());
+    }
+}
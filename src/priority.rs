@@ -0,0 +1,51 @@
+use std::io;
+
+/// The nice value used for low-priority processing
+#[cfg(unix)]
+const NICE_VALUE: libc::c_int = 19;
+
+/// The idle I/O scheduling class, which only receives disk time when no other
+/// process has asked for it (see `ioprio_set(2)`)
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+
+#[cfg(target_os = "linux")]
+const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+
+/// Lowers the CPU priority of the calling thread and, on Linux, places it in
+/// the idle I/O scheduling class. On Linux both priorities apply to individual
+/// threads, so this must be called before any worker threads are started in
+/// order for them to inherit it.
+pub fn lower_priority() -> Result<(), io::Error> {
+    #[cfg(unix)]
+    {
+        // SAFETY: `setpriority` has no memory-safety requirements
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, NICE_VALUE) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let ioprio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+        // SAFETY: `ioprio_set` takes only integer arguments
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    #[cfg(windows)]
+    {
+        use winapi::shared::minwindef::FALSE;
+        use winapi::um::processthreadsapi::{GetCurrentProcess, SetPriorityClass};
+        use winapi::um::winbase::PROCESS_MODE_BACKGROUND_BEGIN;
+
+        // Background mode lowers both the CPU and I/O priority of the process
+        // SAFETY: the pseudo-handle of the current process is always valid
+        if unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) } == FALSE {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
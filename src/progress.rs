@@ -0,0 +1,218 @@
+use std::sync::mpsc::Sender;
+
+/// The stage a stream rewrite has reached
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RewriteStage {
+    /// The codec headers are being read and rewritten
+    #[default]
+    Headers,
+
+    /// The packets following the codec headers are being copied
+    Audio,
+
+    /// The input has been exhausted and the output flushed
+    Complete,
+}
+
+/// The progress of a stream rewrite
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RewriteProgress {
+    /// The number of packets read from the input
+    pub packets: u64,
+
+    /// The number of bytes read from the input, including any ID3v2 tag
+    pub bytes_read: u64,
+
+    /// The number of bytes written to the output. Since the output is written
+    /// in pages, this may lag behind the packets read.
+    pub bytes_written: u64,
+
+    /// The stage the rewrite has reached
+    pub stage: RewriteStage,
+}
+
+/// Receives updates on the progress of a stream rewrite
+pub trait ProgressObserver {
+    /// Called after each packet is processed and once the rewrite is complete
+    fn update(&mut self, progress: &RewriteProgress);
+}
+
+impl<F: FnMut(&RewriteProgress)> ProgressObserver for F {
+    fn update(&mut self, progress: &RewriteProgress) { self(progress); }
+}
+
+/// An observer that ignores all updates
+#[derive(Debug, Default)]
+pub struct Ignore {}
+
+impl ProgressObserver for Ignore {
+    fn update(&mut self, _progress: &RewriteProgress) {}
+}
+
+impl AnalysisObserver for Ignore {
+    fn update(&mut self, _progress: &AnalysisProgress) {}
+}
+
+/// The progress of a loudness analysis
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AnalysisProgress {
+    /// The number of packets submitted to the analyzer
+    pub packets: u64,
+
+    /// The number of bytes read from the input, including any ID3v2 tag
+    pub bytes_read: u64,
+
+    /// The total length of the input in bytes
+    pub total_bytes: u64,
+
+    /// Whether the input has been exhausted. The analysis may still take some
+    /// time to complete if packets are being decoded in parallel.
+    pub complete: bool,
+}
+
+/// Receives updates on the progress of a loudness analysis
+pub trait AnalysisObserver {
+    /// Called after each packet is submitted and once the input is exhausted
+    fn update(&mut self, progress: &AnalysisProgress);
+}
+
+impl<F: FnMut(&AnalysisProgress)> AnalysisObserver for F {
+    fn update(&mut self, progress: &AnalysisProgress) { self(progress); }
+}
+
+/// An event emitted during a stream rewrite by an `EventSender`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RewriteEvent {
+    /// The rewrite has reached a new stage. This precedes the progress update
+    /// in which the new stage is first reported.
+    StageChanged(RewriteStage),
+
+    /// A packet has been processed or the rewrite has completed
+    Progress(RewriteProgress),
+}
+
+/// An event emitted during a loudness analysis by an `EventSender`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AnalysisEvent {
+    /// A packet has been submitted or the input has been exhausted
+    Progress(AnalysisProgress),
+
+    /// The input has been exhausted. This follows the final progress update.
+    InputExhausted,
+}
+
+/// An observer which sends `RewriteEvent`s or `AnalysisEvent`s over an `mpsc`
+/// channel, so that a GUI or other front-end can follow an operation running
+/// on another thread without polling. Events are discarded once the receiver
+/// has been dropped, so a closed window does not cause the operation to fail.
+#[derive(Debug)]
+pub struct EventSender<E> {
+    sender: Sender<E>,
+    stage: Option<RewriteStage>,
+    input_exhausted: bool,
+}
+
+impl<E> EventSender<E> {
+    /// Constructs an observer which sends events to `sender`
+    pub fn new(sender: Sender<E>) -> EventSender<E> { EventSender { sender, stage: None, input_exhausted: false } }
+
+    fn send(&self, event: E) {
+        // A disconnected receiver is not interested in further events
+        let _ = self.sender.send(event);
+    }
+}
+
+impl ProgressObserver for EventSender<RewriteEvent> {
+    fn update(&mut self, progress: &RewriteProgress) {
+        if self.stage != Some(progress.stage) {
+            self.stage = Some(progress.stage);
+            self.send(RewriteEvent::StageChanged(progress.stage));
+        }
+        self.send(RewriteEvent::Progress(*progress));
+    }
+}
+
+impl AnalysisObserver for EventSender<AnalysisEvent> {
+    fn update(&mut self, progress: &AnalysisProgress) {
+        self.send(AnalysisEvent::Progress(*progress));
+        if progress.complete && !self.input_exhausted {
+            self.input_exhausted = true;
+            self.send(AnalysisEvent::InputExhausted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::comment_rewrite::{
+        CommentHeaderRewrite, CommentHeaderSummary, CommentRewriterAction, CommentRewriterConfig,
+    };
+    use crate::header_rewriter::rewrite_stream_with_progress;
+    use crate::interrupt::Never;
+    use crate::testing::StreamBuilder;
+    use crate::Error;
+
+    #[test]
+    fn rewrite_events_sent() -> Result<(), Error> {
+        let input = StreamBuilder::default().build()?;
+        let (sender, receiver) = mpsc::channel();
+        let worker = std::thread::spawn(move || {
+            let rewrite = CommentHeaderRewrite::new(CommentRewriterConfig {
+                action: CommentRewriterAction::NoChange,
+                drop_invalid: false,
+                normalize_keys: None,
+            });
+            let mut observer = EventSender::new(sender);
+            let result: Result<_, Error> = rewrite_stream_with_progress(
+                rewrite,
+                CommentHeaderSummary::default(),
+                Cursor::new(input),
+                std::io::sink(),
+                false,
+                &Never::default(),
+                &mut observer,
+            );
+            result.map(|_| ())
+        });
+        let events: Vec<RewriteEvent> = receiver.iter().collect();
+        worker.join().expect("Rewrite thread panicked")?;
+
+        let stages: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                RewriteEvent::StageChanged(stage) => Some(*stage),
+                RewriteEvent::Progress(_) => None,
+            })
+            .collect();
+        assert_eq!(stages, [RewriteStage::Headers, RewriteStage::Audio, RewriteStage::Complete]);
+        let Some(RewriteEvent::Progress(last)) = events.last() else { panic!("No final progress update") };
+        assert_eq!(last.stage, RewriteStage::Complete);
+        assert_eq!(last.packets, 2 + 51);
+        Ok(())
+    }
+
+    #[test]
+    fn analysis_events_sent() {
+        let (sender, receiver) = mpsc::channel();
+        let mut observer = EventSender::new(sender);
+        let mut progress = AnalysisProgress { packets: 1, bytes_read: 10, total_bytes: 20, complete: false };
+        AnalysisObserver::update(&mut observer, &progress);
+        progress.complete = true;
+        AnalysisObserver::update(&mut observer, &progress);
+        AnalysisObserver::update(&mut observer, &progress);
+        drop(observer);
+        let events: Vec<_> = receiver.iter().collect();
+        assert_eq!(events.iter().filter(|event| **event == AnalysisEvent::InputExhausted).count(), 1);
+        assert_eq!(events[2], AnalysisEvent::InputExhausted);
+
+        // Updates after the receiver is dropped are discarded
+        let (sender, receiver) = mpsc::channel();
+        let mut observer = EventSender::new(sender);
+        drop(receiver);
+        AnalysisObserver::update(&mut observer, &progress);
+    }
+}
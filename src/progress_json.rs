@@ -0,0 +1,140 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::Serialize;
+use zoog::progress::AnalysisProgress;
+
+/// A state change reported when machine-readable progress output is enabled
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent<'a> {
+    /// Processing of a file has started
+    FileStarted { path: Cow<'a, str> },
+
+    /// A file was skipped because its gains already matched the target
+    FileSkipped { path: Cow<'a, str> },
+
+    /// The fraction of a file consumed by loudness analysis has changed
+    AnalysisProgress { path: Cow<'a, str>, percent: u8 },
+
+    /// Loudness analysis of a file has finished
+    AnalysisComplete { path: Cow<'a, str>, lufs: f64 },
+
+    /// A file has been rewritten (or would have been in dry-run mode)
+    RewriteComplete { path: Cow<'a, str>, changed: bool },
+
+    /// Processing of a file failed
+    Error { path: Cow<'a, str>, message: String },
+
+    /// Processing of all files has finished
+    Summary(RunSummary),
+}
+
+/// The outcome of processing a set of files. Files which were changed count
+/// towards both `processed` and `changed`, and `gain_change` is the sum of the
+/// changes to their output gains in decibels. In dry-run mode these describe
+/// the changes which would have been made.
+#[derive(Clone, Debug, Serialize)]
+pub struct RunSummary {
+    pub processed: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub elapsed_seconds: f64,
+    pub gain_change: f64,
+}
+
+impl ProgressEvent<'_> {
+    /// Converts a path to the representation used in events
+    pub fn path_string(path: &Path) -> Cow<'_, str> { path.to_string_lossy() }
+}
+
+/// Emits `ProgressEvent`s as JSON lines on standard error, if enabled
+#[derive(Debug, Default)]
+pub struct ProgressReporter {
+    enabled: bool,
+}
+
+impl ProgressReporter {
+    pub fn new(enabled: bool) -> ProgressReporter { ProgressReporter { enabled } }
+
+    /// Writes the event as a single line of JSON. Events are written with the
+    /// stream locked so that lines from different threads never interleave.
+    pub fn emit(&self, event: &ProgressEvent<'_>) -> Result<(), io::Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let line = serde_json::to_string(event)?;
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        writeln!(stderr, "{}", line)?;
+        stderr.flush()
+    }
+
+    /// Returns an observer which emits `AnalysisProgress` events as the file
+    /// at `path` is analyzed
+    pub fn analysis_observer<'a>(&'a self, path: &'a Path) -> impl FnMut(&AnalysisProgress) + 'a {
+        let mut last_percent = None;
+        move |progress: &AnalysisProgress| {
+            if !self.enabled {
+                return;
+            }
+            let percent = percentage(progress.bytes_read, progress.total_bytes);
+            if last_percent != Some(percent) {
+                last_percent = Some(percent);
+                let event = ProgressEvent::AnalysisProgress { path: ProgressEvent::path_string(path), percent };
+                // Failure to report progress should not cause the analysis itself to fail
+                drop(self.emit(&event));
+            }
+        }
+    }
+}
+
+/// Computes how far through a stream of `length` bytes `position` is, as a
+/// percentage
+fn percentage(position: u64, length: u64) -> u8 {
+    if length == 0 {
+        return 100;
+    }
+    let percent = u128::from(position.min(length)) * 100 / u128::from(length);
+    u8::try_from(percent).unwrap_or(100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentage_bounds() {
+        assert_eq!(percentage(0, 200), 0);
+        assert_eq!(percentage(100, 200), 50);
+        assert_eq!(percentage(200, 200), 100);
+        assert_eq!(percentage(400, 200), 100);
+        assert_eq!(percentage(0, 0), 100);
+        assert_eq!(percentage(u64::MAX - 1, u64::MAX), 99);
+    }
+
+    #[test]
+    fn event_serialization() {
+        let event = ProgressEvent::RewriteComplete { path: "a \"b\".opus".into(), changed: true };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(json, r#"{"event":"rewrite_complete","path":"a \"b\".opus","changed":true}"#);
+
+        let event = ProgressEvent::Summary(RunSummary {
+            processed: 3,
+            changed: 1,
+            unchanged: 1,
+            skipped: 0,
+            failed: 1,
+            elapsed_seconds: 1.5,
+            gain_change: -2.25,
+        });
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            r#"{"event":"summary","processed":3,"changed":1,"unchanged":1,"skipped":0,"failed":1,"elapsed_seconds":1.5,"gain_change":-2.25}"#
+        );
+    }
+}
@@ -0,0 +1,148 @@
+// Arguments converted from Python objects must be owned
+#![allow(clippy::needless_pass_by_value)]
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+
+use crate::header::CommentList;
+use crate::header_rewriter::rewrite_file;
+use crate::interrupt::Never;
+use crate::opus::{AlbumAnalysis, AnalysisSession, TrackAnalysis};
+use crate::volume_rewrite::{OutputGainMode, VolumeHeaderRewrite, VolumeProfile, VolumeRewriterConfig, VolumeTarget};
+use crate::{progress, Decibels, Error, OpusFile, TagEditor};
+
+create_exception!(zoog, ZoogError, PyException, "Raised when an operation on an Ogg file fails");
+
+impl From<Error> for PyErr {
+    fn from(error: Error) -> PyErr { ZoogError::new_err(error.to_string()) }
+}
+
+/// Converts an analysis to a dictionary with the loudness in LUFS, the sample
+/// peak relative to full scale and the duration in seconds
+fn analysis_dict(lufs: Decibels, peak: f32, duration_secs: f64) -> HashMap<&'static str, f64> {
+    HashMap::from([("lufs", lufs.as_f64()), ("peak", f64::from(peak)), ("duration", duration_secs)])
+}
+
+fn track_dict(track: &TrackAnalysis) -> HashMap<&'static str, f64> {
+    analysis_dict(track.lufs(), track.peak(), track.duration().as_secs_f64())
+}
+
+fn album_dict(album: &AlbumAnalysis) -> HashMap<&'static str, f64> {
+    analysis_dict(album.lufs(), album.peak(), album.duration().as_secs_f64())
+}
+
+fn analyze_paths(paths: &[PathBuf]) -> Result<AlbumAnalysis, Error> {
+    let mut session = AnalysisSession::parallel();
+    for path in paths {
+        session.analyze_path(path, &Never::default(), &mut progress::Ignore::default())?;
+    }
+    Ok(session.finish())
+}
+
+/// Returns the comments of an Ogg Opus or Vorbis file as a list of
+/// `(key, value)` pairs in the order they appear
+#[pyfunction]
+fn read_tags(py: Python<'_>, path: PathBuf) -> PyResult<Vec<(String, String)>> {
+    let editor = py.allow_threads(|| TagEditor::open(path))?;
+    Ok(editor.comments().iter().map(|(key, value)| (key.to_string(), value.to_string())).collect())
+}
+
+/// Replaces the comments of an Ogg Opus or Vorbis file with a list of
+/// `(key, value)` pairs, returning whether the file was rewritten
+#[pyfunction]
+fn write_tags(py: Python<'_>, path: PathBuf, tags: Vec<(String, String)>) -> PyResult<bool> {
+    py.allow_threads(|| {
+        let mut editor = TagEditor::open(path)?;
+        let comments = editor.comments_mut();
+        comments.clear();
+        for (key, value) in &tags {
+            comments.push(key, value)?;
+        }
+        editor.commit()
+    })
+    .map_err(PyErr::from)
+}
+
+/// Returns the output gain and R128 gain tags of an Ogg Opus file in decibels
+/// as a dictionary with the keys `output`, `track` and `album`. Absent tags
+/// are `None`.
+#[pyfunction]
+fn read_gains(py: Python<'_>, path: PathBuf) -> PyResult<HashMap<&'static str, Option<f64>>> {
+    let gains = py.allow_threads(|| OpusFile::open(path))?.gains();
+    Ok(HashMap::from([
+        ("output", Some(gains.output.as_f64())),
+        ("track", gains.track_r128.as_ref().map(Decibels::as_f64)),
+        ("album", gains.album_r128.as_ref().map(Decibels::as_f64)),
+    ]))
+}
+
+/// Measures the BS.1770 loudness of an Ogg Opus file, returning a dictionary
+/// with the keys `lufs`, `peak` and `duration`
+#[pyfunction]
+fn analyze(py: Python<'_>, path: PathBuf) -> PyResult<HashMap<&'static str, f64>> {
+    let album = py.allow_threads(|| analyze_paths(&[path]))?;
+    Ok(track_dict(&album.tracks()[0]))
+}
+
+/// Measures the loudness of several Ogg Opus files as an album, returning the
+/// analysis of the album and a list of the analysis of each track
+#[pyfunction]
+#[allow(clippy::type_complexity)]
+fn analyze_album(
+    py: Python<'_>, paths: Vec<PathBuf>,
+) -> PyResult<(HashMap<&'static str, f64>, Vec<HashMap<&'static str, f64>>)> {
+    let album = py.allow_threads(|| analyze_paths(&paths))?;
+    Ok((album_dict(&album), album.tracks().iter().map(track_dict).collect()))
+}
+
+/// Sets the output gain and R128 tags of an Ogg Opus file for one of the
+/// `opusgain` presets, returning whether the file was rewritten. The track is
+/// analyzed unless `track_lufs` is supplied. If `album_lufs` is supplied the
+/// output gain targets the album loudness and an album gain tag is written.
+#[pyfunction]
+#[pyo3(signature = (path, preset = "rg", track_lufs = None, album_lufs = None))]
+fn apply_gain(
+    py: Python<'_>, path: PathBuf, preset: &str, track_lufs: Option<f64>, album_lufs: Option<f64>,
+) -> PyResult<bool> {
+    let profile =
+        VolumeProfile::find(preset).ok_or_else(|| PyValueError::new_err(format!("Unknown preset `{}`", preset)))?;
+    py.allow_threads(|| apply_gain_to_path(&path, profile.target, track_lufs, album_lufs)).map_err(PyErr::from)
+}
+
+fn apply_gain_to_path(
+    path: &Path, target: VolumeTarget, track_lufs: Option<f64>, album_lufs: Option<f64>,
+) -> Result<bool, Error> {
+    let track_volume = match track_lufs {
+        Some(lufs) => Decibels::from(lufs),
+        None => analyze_paths(&[path.to_path_buf()])?.lufs(),
+    };
+    let config = VolumeRewriterConfig {
+        output_gain: target,
+        output_gain_mode: if album_lufs.is_some() { OutputGainMode::Album } else { OutputGainMode::Track },
+        track_volume: Some(track_volume),
+        album_volume: album_lufs.map(Decibels::from),
+        max_output_gain: None,
+        tag_compensation: true,
+        clear_track_gain: false,
+        clear_album_gain: false,
+    };
+    rewrite_file(path, VolumeHeaderRewrite::new(config))
+}
+
+/// Reading and writing of Ogg Opus and Vorbis tags, loudness analysis and
+/// gain rewriting
+#[pymodule]
+fn zoog(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add("ZoogError", module.py().get_type::<ZoogError>())?;
+    module.add_function(wrap_pyfunction!(read_tags, module)?)?;
+    module.add_function(wrap_pyfunction!(write_tags, module)?)?;
+    module.add_function(wrap_pyfunction!(read_gains, module)?)?;
+    module.add_function(wrap_pyfunction!(analyze, module)?)?;
+    module.add_function(wrap_pyfunction!(analyze_album, module)?)?;
+    module.add_function(wrap_pyfunction!(apply_gain, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,432 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use ogg::PacketReader;
+
+use crate::header::{
+    parse_comment, CommentHeaderGeneric, CommentHeaderSpecifics, CommentList, DiscreteCommentList, FixedPointGain,
+    IdHeader as _, InvalidComment, InvalidCommentReason,
+};
+use crate::header_rewriter::{rewrite_file, CodecHeaders, HeaderRewrite};
+use crate::opus::{TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
+use crate::{opus, vorbis, Error};
+
+/// The kinds of damage to a comment header which should be repaired. Damage
+/// which is not repaired causes parsing to fail as it normally would. By
+/// default everything is repaired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct RepairPolicy {
+    /// Correct a comment count which does not match the number of comments
+    /// present. Entries beyond the declared count are only taken to be
+    /// comments if they are complete and valid.
+    pub comment_count: bool,
+
+    /// Keep the part of a vendor string or final comment which extends past
+    /// the end of the header
+    pub truncated_comments: bool,
+
+    /// Replace missing or invalid data following the comments, such as the
+    /// Vorbis framing bit
+    pub suffix: bool,
+
+    /// Replace invalid UTF-8 sequences in the vendor string and comments with
+    /// U+FFFD rather than failing or preserving the comment unparsed
+    pub invalid_utf8: bool,
+
+    /// Clamp R128 gain tags outside the range of a fixed-point gain and remove
+    /// those which are not integers
+    pub gains: bool,
+}
+
+impl Default for RepairPolicy {
+    fn default() -> RepairPolicy {
+        RepairPolicy { comment_count: true, truncated_comments: true, suffix: true, invalid_utf8: true, gains: true }
+    }
+}
+
+impl RepairPolicy {
+    /// A policy which repairs nothing
+    pub fn none() -> RepairPolicy {
+        RepairPolicy {
+            comment_count: false,
+            truncated_comments: false,
+            suffix: false,
+            invalid_utf8: false,
+            gains: false,
+        }
+    }
+}
+
+/// A repair made to a comment header
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Repair {
+    /// The header declared `declared` comments but `found` were present
+    CommentCount { declared: u32, found: u32 },
+
+    /// The vendor string extended past the end of the header
+    TruncatedVendor,
+
+    /// The final comment extended past the end of the header
+    TruncatedComment,
+
+    /// The data following the comments was missing or invalid
+    Suffix,
+
+    /// The vendor string was not valid UTF-8
+    InvalidUtf8Vendor,
+
+    /// A comment was not valid UTF-8
+    InvalidUtf8Comment,
+
+    /// The specified gain tag was clamped to the range of a fixed-point gain
+    GainClamped(String),
+
+    /// The specified gain tag was not an integer and was removed
+    GainRemoved(String),
+}
+
+impl Display for Repair {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Repair::CommentCount { declared, found } => {
+                write!(formatter, "corrected comment count from {} to {}", declared, found)
+            }
+            Repair::TruncatedVendor => write!(formatter, "kept truncated vendor string"),
+            Repair::TruncatedComment => write!(formatter, "kept truncated final comment"),
+            Repair::Suffix => write!(formatter, "replaced data following the comments"),
+            Repair::InvalidUtf8Vendor => write!(formatter, "replaced invalid UTF-8 in vendor string"),
+            Repair::InvalidUtf8Comment => write!(formatter, "replaced invalid UTF-8 in comment"),
+            Repair::GainClamped(tag) => write!(formatter, "clamped out-of-range {} tag", tag),
+            Repair::GainRemoved(tag) => write!(formatter, "removed invalid {} tag", tag),
+        }
+    }
+}
+
+/// Reads a length-prefixed byte string, returning `None` if there is no room
+/// for the length. Otherwise the bytes present are returned along with whether
+/// the string is complete.
+fn read_entry(reader: &mut Cursor<&[u8]>) -> Option<(Vec<u8>, bool)> {
+    let len = reader.read_u32::<LittleEndian>().ok()?;
+    let mut data = Vec::new();
+    reader.take(u64::from(len)).read_to_end(&mut data).expect("Reading from a slice should not fail");
+    let complete = data.len() == len as usize;
+    Some((data, complete))
+}
+
+/// Whether a byte string is a valid comment
+fn is_valid_comment(entry: &[u8]) -> bool { std::str::from_utf8(entry).map_or(false, |c| parse_comment(c).is_ok()) }
+
+/// Adds a comment entry to `comments`, preserving it unparsed if it is invalid
+fn push_entry(
+    comments: &mut DiscreteCommentList, entry: Vec<u8>, policy: RepairPolicy, repairs: &mut Vec<Repair>,
+) -> Result<(), Error> {
+    let comment = match String::from_utf8(entry) {
+        Ok(comment) => comment,
+        Err(e) if policy.invalid_utf8 => {
+            repairs.push(Repair::InvalidUtf8Comment);
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
+        }
+        Err(e) => {
+            comments.push_invalid(InvalidComment::new(e.into_bytes(), InvalidCommentReason::InvalidUtf8));
+            return Ok(());
+        }
+    };
+    match parse_comment(&comment) {
+        Ok((key, value)) => comments.push(key, value),
+        Err(e) => {
+            let reason = match e {
+                Error::MissingCommentSeparator => InvalidCommentReason::MissingSeparator,
+                _ => InvalidCommentReason::InvalidFieldName,
+            };
+            comments.push_invalid(InvalidComment::new(comment.into_bytes(), reason));
+            Ok(())
+        }
+    }
+}
+
+/// Clamps or removes R128 gain tags which cannot be parsed
+fn repair_gains<C: CommentList>(comments: &mut C, repairs: &mut Vec<Repair>) -> Result<(), Error> {
+    for tag in [TAG_TRACK_GAIN, TAG_ALBUM_GAIN] {
+        let Some(value) = comments.get_first(tag) else { continue };
+        if value.parse::<FixedPointGain>().is_ok() {
+            continue;
+        }
+        if let Ok(value) = value.trim().parse::<i64>() {
+            let clamped = i16::try_from(value).unwrap_or(if value < 0 { i16::MIN } else { i16::MAX });
+            comments.set_tag_to_gain(tag, FixedPointGain::from_fixed_point(clamped))?;
+            repairs.push(Repair::GainClamped(tag.to_string()));
+        } else {
+            comments.remove_all(tag);
+            repairs.push(Repair::GainRemoved(tag.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Parses a comment header, repairing the kinds of damage permitted by
+/// `policy`. Returns the header and the repairs made, which are empty if the
+/// header was undamaged. An error is returned if the magic signature is absent
+/// or the header has damage which the policy does not permit repairing.
+pub fn repair_comment_header<S: CommentHeaderSpecifics + Default>(
+    data: &[u8], policy: RepairPolicy,
+) -> Result<(CommentHeaderGeneric<S>, Vec<Repair>), Error> {
+    let magic = S::get_magic();
+    if !data.starts_with(&magic) {
+        return Err(Error::MalformedCommentHeader);
+    }
+    let mut reader = Cursor::new(&data[magic.len()..]);
+    let mut repairs = Vec::new();
+
+    let (vendor, complete) = read_entry(&mut reader).ok_or(Error::MalformedCommentHeader)?;
+    if !complete {
+        if !policy.truncated_comments {
+            return Err(Error::MalformedCommentHeader);
+        }
+        repairs.push(Repair::TruncatedVendor);
+    }
+    let vendor = match String::from_utf8(vendor) {
+        Ok(vendor) => vendor,
+        Err(e) if policy.invalid_utf8 => {
+            repairs.push(Repair::InvalidUtf8Vendor);
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let declared = reader.read_u32::<LittleEndian>().ok();
+    let mut user_comments = DiscreteCommentList::default();
+    let mut found = 0;
+    loop {
+        let position = reader.position();
+        let expected = declared.map_or(false, |declared| found < declared);
+        let Some((entry, complete)) = read_entry(&mut reader) else { break };
+        if !expected {
+            // Data following the declared comments may be padding
+            if !policy.comment_count || !complete || !is_valid_comment(&entry) {
+                reader.set_position(position);
+                break;
+            }
+        } else if !complete {
+            if !policy.truncated_comments {
+                return Err(Error::MalformedCommentHeader);
+            }
+            repairs.push(Repair::TruncatedComment);
+        }
+        push_entry(&mut user_comments, entry, policy, &mut repairs)?;
+        found += 1;
+        if !complete {
+            break;
+        }
+    }
+    if declared != Some(found) {
+        if !policy.comment_count {
+            return Err(Error::MalformedCommentHeader);
+        }
+        repairs.push(Repair::CommentCount { declared: declared.unwrap_or(0), found });
+    }
+
+    let mut specifics = S::default();
+    if let Err(e) = specifics.read_suffix(&mut reader) {
+        if !policy.suffix {
+            return Err(e);
+        }
+        specifics = S::default();
+        repairs.push(Repair::Suffix);
+    }
+    let mut header = CommentHeaderGeneric::from_parts(vendor, user_comments, specifics);
+    if policy.gains {
+        repair_gains(&mut header, &mut repairs)?;
+    }
+    Ok((header, repairs))
+}
+
+/// Parses the identification and comment header packets of an Opus or Vorbis
+/// stream, repairing the comment header according to `policy`
+pub fn repair_headers(
+    identification: &[u8], comment: &[u8], policy: RepairPolicy,
+) -> Result<(CodecHeaders, Vec<Repair>), Error> {
+    if let Some(id_header) = opus::IdHeader::try_parse(identification)? {
+        let (comment_header, repairs) = repair_comment_header(comment, policy)?;
+        return Ok((CodecHeaders::Opus(id_header, comment_header), repairs));
+    }
+    if let Some(id_header) = vorbis::IdHeader::try_parse(identification)? {
+        let (comment_header, repairs) = repair_comment_header(comment, policy)?;
+        return Ok((CodecHeaders::Vorbis(id_header, comment_header), repairs));
+    }
+    Err(Error::UnknownCodec)
+}
+
+/// Parameterization struct for `HeaderRewriter` which repairs a damaged
+/// comment header according to a `RepairPolicy`. Streams with undamaged
+/// headers are left unchanged.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RepairHeaderRewrite {
+    policy: RepairPolicy,
+}
+
+impl RepairHeaderRewrite {
+    pub fn new(policy: RepairPolicy) -> RepairHeaderRewrite { RepairHeaderRewrite { policy } }
+}
+
+impl HeaderRewrite for RepairHeaderRewrite {
+    type Error = Error;
+
+    fn rewrite(&self, _headers: &mut CodecHeaders) -> Result<(), Error> { Ok(()) }
+
+    fn parse_headers(&self, identification: &[u8], comment: &[u8]) -> Result<(CodecHeaders, bool), Error> {
+        let (headers, repairs) = repair_headers(identification, comment, self.policy)?;
+        Ok((headers, !repairs.is_empty()))
+    }
+}
+
+/// Reads the identification and comment header packets of the first logical
+/// stream
+fn read_header_packets<R: Read + Seek>(input: R) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let mut reader = PacketReader::new(input);
+    let mut serial = None;
+    let mut packets = Vec::with_capacity(2);
+    while packets.len() < 2 {
+        let packet = reader.read_packet().map_err(Error::OggDecode)?.ok_or(Error::MalformedCommentHeader)?;
+        if *serial.get_or_insert(packet.stream_serial()) == packet.stream_serial() {
+            packets.push(packet.data);
+        }
+    }
+    let comment = packets.pop().expect("Comment header packet missing");
+    let identification = packets.pop().expect("Identification header packet missing");
+    Ok((identification, comment))
+}
+
+/// Repairs the comment header of the file at `path` in place according to
+/// `policy`, returning the repairs made. The file is left untouched if its
+/// header is undamaged.
+pub fn repair_file(path: &Path, policy: RepairPolicy) -> Result<Vec<Repair>, Error> {
+    let input = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+    let (identification, comment) = read_header_packets(BufReader::new(input))?;
+    let (_, repairs) = repair_headers(&identification, &comment, policy)?;
+    if !repairs.is_empty() {
+        rewrite_file(path, RepairHeaderRewrite::new(policy))?;
+    }
+    Ok(repairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::WriteBytesExt;
+    use ogg::{PacketWriteEndInfo, PacketWriter};
+
+    use super::*;
+    use crate::comment_rewrite::CommentHeaderSummary;
+    use crate::header::CommentHeader as _;
+    use crate::header_rewriter::{rewrite_stream, SubmitResult};
+    use crate::testing::StreamBuilder;
+
+    fn comment_header(magic: &[u8], declared: u32, entries: &[&[u8]], suffix: &[u8]) -> Vec<u8> {
+        let mut data = magic.to_vec();
+        let vendor = b"vendor";
+        data.write_u32::<LittleEndian>(6).unwrap();
+        data.extend(vendor);
+        data.write_u32::<LittleEndian>(declared).unwrap();
+        for entry in entries {
+            data.write_u32::<LittleEndian>(entry.len().try_into().unwrap()).unwrap();
+            data.extend(*entry);
+        }
+        data.extend(suffix);
+        data
+    }
+
+    #[test]
+    fn comment_count_corrected() -> Result<(), Error> {
+        let entries: [&[u8]; 2] = [b"ARTIST=Artist", b"TITLE=Title"];
+        for declared in [1, 3] {
+            let data = comment_header(b"OpusTags", declared, &entries, &[]);
+            // Without repair, the second comment is either lost or fails to parse
+            assert!(opus::CommentHeader::try_parse(&data).map_or(true, |header| header.len() == 1));
+            let (header, repairs) =
+                repair_comment_header::<opus::CommentHeaderSpecifics>(&data, RepairPolicy::default())?;
+            assert_eq!(header.get_first("TITLE"), Some("Title"));
+            assert_eq!(repairs, vec![Repair::CommentCount { declared, found: 2 }]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_comment_kept() -> Result<(), Error> {
+        let mut data = comment_header(b"OpusTags", 2, &[b"ARTIST=Artist", b"TITLE=Title"], &[]);
+        data.truncate(data.len() - 3);
+        assert!(matches!(opus::CommentHeader::try_parse(&data), Err(Error::MalformedCommentHeader)));
+        let (header, repairs) = repair_comment_header::<opus::CommentHeaderSpecifics>(&data, RepairPolicy::default())?;
+        assert_eq!(header.get_first("TITLE"), Some("Ti"));
+        assert_eq!(repairs, vec![Repair::TruncatedComment]);
+        let policy = RepairPolicy { truncated_comments: false, ..RepairPolicy::default() };
+        assert!(repair_comment_header::<opus::CommentHeaderSpecifics>(&data, policy).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn missing_framing_bit_replaced() -> Result<(), Error> {
+        let data = comment_header(b"\x03vorbis", 1, &[b"TITLE=Title"], &[]);
+        assert!(vorbis::CommentHeader::try_parse(&data).is_err());
+        let (header, repairs) =
+            repair_comment_header::<vorbis::CommentHeaderSpecifics>(&data, RepairPolicy::default())?;
+        assert_eq!(repairs, vec![Repair::Suffix]);
+        let mut repaired = Vec::new();
+        header.serialize_into(&mut repaired)?;
+        assert_eq!(vorbis::CommentHeader::try_parse(&repaired)?, header);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_utf8_replaced() -> Result<(), Error> {
+        let data = comment_header(b"OpusTags", 1, &[b"TITLE=Caf\xE9"], &[]);
+        let (header, repairs) = repair_comment_header::<opus::CommentHeaderSpecifics>(&data, RepairPolicy::default())?;
+        assert_eq!(header.get_first("TITLE"), Some("Caf\u{FFFD}"));
+        assert_eq!(repairs, vec![Repair::InvalidUtf8Comment]);
+        let (header, repairs) = repair_comment_header::<opus::CommentHeaderSpecifics>(&data, RepairPolicy::none())?;
+        assert_eq!(header.invalid_entries().len(), 1);
+        assert!(repairs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_gains_repaired() -> Result<(), Error> {
+        let data = comment_header(b"OpusTags", 2, &[b"R128_TRACK_GAIN=-40000", b"R128_ALBUM_GAIN=-3 dB"], &[]);
+        let (header, repairs) = repair_comment_header::<opus::CommentHeaderSpecifics>(&data, RepairPolicy::default())?;
+        assert_eq!(header.get_first(TAG_TRACK_GAIN), Some("-32768"));
+        assert_eq!(header.get_first(TAG_ALBUM_GAIN), None);
+        assert_eq!(
+            repairs,
+            vec![Repair::GainClamped(TAG_TRACK_GAIN.to_string()), Repair::GainRemoved(TAG_ALBUM_GAIN.to_string())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stream_repaired() -> Result<(), Error> {
+        let (id_header, _) = StreamBuilder::default().header_packets()?;
+        // The comment header claims more comments than it contains
+        let comment_header = comment_header(b"OpusTags", 5, &[b"TITLE=Title"], &[]);
+        let mut input = Vec::new();
+        let mut writer = PacketWriter::new(&mut input);
+        writer.write_packet(id_header, 1, PacketWriteEndInfo::EndPage, 0).map_err(Error::WriteError)?;
+        writer.write_packet(comment_header, 1, PacketWriteEndInfo::EndPage, 0).map_err(Error::WriteError)?;
+        writer.write_packet(vec![0xf8], 1, PacketWriteEndInfo::EndStream, 960).map_err(Error::WriteError)?;
+        drop(writer);
+
+        let summarize = CommentHeaderSummary::default();
+        let rewrite = RepairHeaderRewrite::default();
+        let mut output = Vec::new();
+        let abort_on_unchanged = true;
+        match rewrite_stream(rewrite, summarize, Cursor::new(&input), &mut output, abort_on_unchanged)? {
+            SubmitResult::HeadersChanged { to, .. } => assert_eq!(to.get_first("TITLE"), Some("Title")),
+            _ => panic!("Damaged header was not repaired"),
+        }
+        let (identification, comment) = read_header_packets(Cursor::new(&output))?;
+        let (_, repairs) = repair_headers(&identification, &comment, RepairPolicy::default())?;
+        assert!(repairs.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use parking_lot::{Condvar, Mutex};
+use rayon::ThreadPool;
+use zoog::output_file::FREE_SPACE_MARGIN;
+use zoog::Error;
+
+/// Identifies the filesystem a file will be written to
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum FilesystemId {
+    #[cfg(unix)]
+    Device(u64),
+    #[cfg(not(unix))]
+    Directory(std::path::PathBuf),
+}
+
+impl FilesystemId {
+    fn of(dir: &Path) -> Result<FilesystemId, Error> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let metadata = std::fs::metadata(dir).map_err(|e| Error::FileReadError(dir.to_path_buf(), e))?;
+            Ok(FilesystemId::Device(metadata.dev()))
+        }
+        #[cfg(not(unix))]
+        {
+            let canonical = std::fs::canonicalize(dir).map_err(|e| Error::FileReadError(dir.to_path_buf(), e))?;
+            Ok(FilesystemId::Directory(canonical))
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FilesystemUsage {
+    /// Number of rewrites in progress
+    active: usize,
+
+    /// Bytes reserved by rewrites in progress
+    reserved: u64,
+}
+
+/// Limits the number of concurrent rewrites so that the temporary files they
+/// create should not exhaust the free space on the filesystems they are
+/// written to. A rewrite waits until enough space is available that is not
+/// already reserved by others. A rewrite that is the only one in progress on
+/// its filesystem is always allowed to proceed, and should check the free
+/// space itself using `OutputFile::check_free_space()`.
+#[derive(Debug, Default)]
+pub struct RewriteLimiter {
+    usage: Mutex<HashMap<FilesystemId, FilesystemUsage>>,
+    released: Condvar,
+}
+
+/// Releases the space reserved for a rewrite when dropped
+#[derive(Debug)]
+pub struct RewriteReservation<'a> {
+    limiter: &'a RewriteLimiter,
+    filesystem: FilesystemId,
+    size: u64,
+}
+
+impl RewriteLimiter {
+    pub fn new() -> RewriteLimiter { RewriteLimiter::default() }
+
+    /// Blocks until a rewrite of the file at `path`, which requires `size`
+    /// bytes of temporary storage in `temp_dir` or otherwise alongside it, can
+    /// proceed
+    pub fn acquire(&self, path: &Path, temp_dir: Option<&Path>, size: u64) -> Result<RewriteReservation<'_>, Error> {
+        let dir = match temp_dir {
+            Some(temp_dir) => temp_dir,
+            None => path.parent().ok_or_else(|| Error::NoParentError(path.to_path_buf()))?,
+        };
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let filesystem = FilesystemId::of(dir)?;
+        let mut usage = self.usage.lock();
+        loop {
+            let current = usage.entry(filesystem.clone()).or_default();
+            let can_proceed = current.active == 0 || {
+                let available = fs2::available_space(dir).map_err(|e| Error::FileReadError(dir.to_path_buf(), e))?;
+                available.saturating_sub(current.reserved) >= size.saturating_add(FREE_SPACE_MARGIN)
+            };
+            if can_proceed {
+                current.active += 1;
+                current.reserved += size;
+                break;
+            }
+            self.released.wait(&mut usage);
+        }
+        Ok(RewriteReservation { limiter: self, filesystem, size })
+    }
+
+    /// Runs `op` on `pool` once a rewrite of the file at `path` can proceed, as
+    /// for `acquire()`. The space remains reserved until `op` returns.
+    ///
+    /// The reservation is held only on `pool`, never by the caller. A caller
+    /// running on another pool executes queued work from that pool while it
+    /// waits, and that work could otherwise block forever waiting for space
+    /// reserved by the suspended caller.
+    pub fn install<T, F>(
+        &self, pool: &ThreadPool, path: &Path, temp_dir: Option<&Path>, size: u64, op: F,
+    ) -> Result<T, Error>
+    where
+        T: Send,
+        F: FnOnce() -> Result<T, Error> + Send,
+    {
+        pool.install(|| {
+            let _reservation = self.acquire(path, temp_dir, size)?;
+            op()
+        })
+    }
+}
+
+impl Drop for RewriteReservation<'_> {
+    fn drop(&mut self) {
+        let mut usage = self.limiter.usage.lock();
+        if let Some(current) = usage.get_mut(&self.filesystem) {
+            current.active -= 1;
+            current.reserved -= self.size;
+        }
+        drop(usage);
+        self.limiter.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reservations_are_released() -> Result<(), Error> {
+        let dir = tempfile::tempdir().map_err(Error::ReadError)?;
+        let path = dir.path().join("a.opus");
+        let limiter = RewriteLimiter::new();
+        {
+            let _first = limiter.acquire(&path, None, 1)?;
+            let _second = limiter.acquire(&path, None, 1)?;
+            let usage = limiter.usage.lock();
+            let current = usage.values().next().expect("Missing filesystem usage");
+            assert_eq!((current.active, current.reserved), (2, 2));
+        }
+        let usage = limiter.usage.lock();
+        assert!(usage.values().all(|u| u.active == 0 && u.reserved == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn install_does_not_hold_reservations_while_waiting() -> Result<(), Error> {
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
+        use rayon::ThreadPoolBuilder;
+
+        let dir = tempfile::tempdir().map_err(Error::ReadError)?;
+        let path = dir.path().join("a.opus");
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let analysis_pool = ThreadPoolBuilder::new().num_threads(2).build().expect("Failed to build pool");
+            let rewrite_pool = ThreadPoolBuilder::new().num_threads(2).build().expect("Failed to build pool");
+            // No two reservations of this size can be held at once
+            let limiter = RewriteLimiter::new();
+            let result = analysis_pool.install(|| {
+                (0..16).into_par_iter().try_for_each(|_| {
+                    limiter.install(&rewrite_pool, &path, None, u64::MAX / 2, || {
+                        std::thread::sleep(Duration::from_millis(1));
+                        Ok(())
+                    })
+                })
+            });
+            let _ = sender.send(result.is_ok());
+        });
+        let succeeded = receiver.recv_timeout(Duration::from_secs(60)).expect("Rewrites deadlocked");
+        assert!(succeeded);
+        Ok(())
+    }
+
+    #[test]
+    fn single_rewrite_always_proceeds() -> Result<(), Error> {
+        let dir = tempfile::tempdir().map_err(Error::ReadError)?;
+        let limiter = RewriteLimiter::new();
+        let _reservation = limiter.acquire(&dir.path().join("a.opus"), None, u64::MAX / 2)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use zoog::output_file::OutputFile;
+use zoog::Error;
+
+/// Which of the files supplied to a run were completed, skipped or remain to
+/// be processed. Paths are recorded as they were supplied.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RunState {
+    /// Files which were analyzed and rewritten, or found to already be correct
+    pub completed: Vec<PathBuf>,
+
+    /// Files which were skipped since their gains already matched the target
+    pub skipped: Vec<PathBuf>,
+
+    /// Files which were not processed, or which failed
+    pub pending: Vec<PathBuf>,
+}
+
+impl RunState {
+    /// Reads a state previously written by `save()`
+    pub fn load(path: &Path) -> Result<RunState, Error> {
+        let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| Error::FileReadError(path.to_path_buf(), e.into()))
+    }
+
+    /// Atomically replaces the file at `path` with the state
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut output = OutputFile::new_target(path)?;
+        let write = |output: &mut OutputFile| -> io::Result<()> {
+            let mut writer = BufWriter::new(output);
+            serde_json::to_writer_pretty(&mut writer, self)?;
+            writeln!(writer)?;
+            writer.flush()
+        };
+        match write(&mut output) {
+            Ok(()) => output.commit(),
+            Err(e) => {
+                output.abort()?;
+                Err(Error::FileWriteError(path.to_path_buf(), e))
+            }
+        }
+    }
+
+    /// The files which do not need processing again
+    pub fn done(&self) -> HashSet<&Path> {
+        self.completed.iter().chain(self.skipped.iter()).map(PathBuf::as_path).collect()
+    }
+}
+
+/// Records the outcome of each file as a run progresses
+#[derive(Debug, Default)]
+pub struct RunTracker {
+    inputs: Mutex<Vec<PathBuf>>,
+    completed: Mutex<Vec<PathBuf>>,
+    skipped: Mutex<Vec<PathBuf>>,
+}
+
+impl RunTracker {
+    /// Records the files to be processed by the run
+    pub fn add_inputs<'a, I: IntoIterator<Item = &'a PathBuf>>(&self, paths: I) {
+        self.inputs.lock().extend(paths.into_iter().cloned());
+    }
+
+    pub fn completed(&self, path: &Path) { self.completed.lock().push(path.to_path_buf()); }
+
+    pub fn skipped(&self, path: &Path) { self.skipped.lock().push(path.to_path_buf()); }
+
+    /// The current state of the run. Any input not yet completed or skipped is
+    /// pending.
+    pub fn state(&self) -> RunState {
+        let completed = self.completed.lock().clone();
+        let skipped = self.skipped.lock().clone();
+        let done: HashSet<_> = completed.iter().chain(skipped.iter()).collect();
+        let pending = self.inputs.lock().iter().filter(|path| !done.contains(path)).cloned().collect();
+        RunState { completed, skipped, pending }
+    }
+}
@@ -0,0 +1,138 @@
+use std::io::Read;
+
+use crate::ogg_page::Page;
+use crate::opus::PacketInfo;
+use crate::{Codec, Error};
+
+/// Statistics of the pages and packets of a logical stream, accumulated one
+/// page at a time so that they can be collected alongside other processing.
+/// The stream is the one the first page added belongs to and pages of other
+/// logical streams are ignored.
+#[derive(Clone, Debug, Default)]
+pub struct StreamStats {
+    /// The number of pages
+    pub pages: u64,
+
+    /// The number of complete packets, including codec headers
+    pub packets: u64,
+
+    /// The number of packets which span more than one page
+    pub continued_packets: u64,
+
+    /// The size of the smallest page in bytes, including its header
+    pub min_page_size: Option<usize>,
+
+    /// The size of the largest page in bytes, including its header
+    pub max_page_size: usize,
+
+    /// The total size of all pages in bytes
+    pub total_page_size: u64,
+
+    serial: Option<u32>,
+    codec: Option<Codec>,
+    samples: u64,
+    partial: Vec<u8>,
+    spans_pages: bool,
+}
+
+impl StreamStats {
+    /// The number of header packets preceding the audio of each codec
+    fn header_packets(codec: Codec) -> u64 {
+        match codec {
+            Codec::Opus => 2,
+            Codec::Vorbis => 3,
+        }
+    }
+
+    /// Adds a page to the statistics. The durations of Opus audio packets are
+    /// read from their table-of-contents bytes, so an error is returned if one
+    /// is malformed.
+    pub fn add_page(&mut self, page: &Page) -> Result<(), Error> {
+        if *self.serial.get_or_insert(page.serial()) != page.serial() {
+            return Ok(());
+        }
+        self.pages += 1;
+        let size = page.size();
+        self.min_page_size = Some(self.min_page_size.map_or(size, |min| min.min(size)));
+        self.max_page_size = self.max_page_size.max(size);
+        self.total_page_size += size as u64;
+        for (fragment, ends) in page.packet_fragments() {
+            self.partial.extend_from_slice(fragment);
+            if !ends {
+                self.spans_pages = true;
+                continue;
+            }
+            let packet = std::mem::take(&mut self.partial);
+            if self.packets == 0 {
+                self.codec = Codec::identify(&packet);
+            }
+            if self.codec == Some(Codec::Opus) && self.packets >= StreamStats::header_packets(Codec::Opus) {
+                self.samples += PacketInfo::parse(&packet)?.samples() as u64;
+            }
+            self.packets += 1;
+            if std::mem::take(&mut self.spans_pages) {
+                self.continued_packets += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// The codec of the stream, if it was recognised
+    pub fn codec(&self) -> Option<Codec> { self.codec }
+
+    /// The mean size of a page in bytes, including its header
+    #[allow(clippy::cast_precision_loss)]
+    pub fn mean_page_size(&self) -> Option<f64> {
+        (self.pages > 0).then(|| self.total_page_size as f64 / self.pages as f64)
+    }
+
+    /// The total duration of the audio packets in samples at the decoding
+    /// sample rate, before pre-skip and end trimming are applied. This is only
+    /// available for Opus streams, since the durations of Vorbis packets
+    /// depend on the codec setup header.
+    pub fn samples(&self) -> Option<u64> { (self.codec == Some(Codec::Opus)).then_some(self.samples) }
+}
+
+/// Reads all pages from `reader` and returns the statistics of the first
+/// logical stream
+pub fn read_stream_stats<R: Read>(mut reader: R) -> Result<StreamStats, Error> {
+    let mut stats = StreamStats::default();
+    while let Some(page) = Page::read(&mut reader)? {
+        stats.add_page(&page)?;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::testing::StreamBuilder;
+
+    #[test]
+    fn stats_of_opus_stream() -> Result<(), Error> {
+        let (id_header, comment_header) = StreamBuilder::default().vendor("").header_packets()?;
+        // Two 20ms CELT packets, the second of which is padded to span two pages
+        let mut padded = vec![31 << 3 | 3, 0x40 | 1];
+        padded.extend([255; 256]);
+        padded.push(0);
+        padded.resize(padded.len() + 254 * 256, 0);
+        let audio = vec![vec![31 << 3, 0], padded];
+        let mut stream = Vec::new();
+        for page in Page::paginate(&[id_header], 1, 0, 0, true)
+            .into_iter()
+            .chain(Page::paginate(&[comment_header], 1, 1, 0, false))
+            .chain(Page::paginate(&audio, 1, 2, 1920, false))
+        {
+            page.write(&mut stream)?;
+        }
+        let stats = read_stream_stats(Cursor::new(stream))?;
+        assert_eq!(stats.codec(), Some(Codec::Opus));
+        assert_eq!((stats.pages, stats.packets, stats.continued_packets), (4, 4, 1));
+        assert_eq!(stats.samples(), Some(1920));
+        assert_eq!(stats.min_page_size, Some(27 + 1 + 16));
+        assert_eq!(stats.max_page_size, 27 + 255 + 2 + 254 * 255);
+        Ok(())
+    }
+}
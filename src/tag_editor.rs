@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use crate::comment_rewrite::{CommentHeaderRewrite, CommentRewriterAction, CommentRewriterConfig};
+use crate::header::{CommentHeader as _, DiscreteCommentList};
+use crate::header_rewriter::{read_headers, rewrite_file, CodecHeaders};
+use crate::Error;
+
+/// Edits the comments of an Ogg Opus or Vorbis file. The comments are read
+/// when the editor is opened and written back by `commit()`, which replaces
+/// the file atomically so it is never left partially written.
+///
+/// ```no_run
+/// # use zoog::header::CommentList;
+/// let mut editor = zoog::TagEditor::open("track.opus")?;
+/// editor.comments_mut().replace("TITLE", "A New Title")?;
+/// editor.comments_mut().remove_all("COMMENT");
+/// editor.commit()?;
+/// # Ok::<(), zoog::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct TagEditor {
+    path: PathBuf,
+    original: DiscreteCommentList,
+    comments: DiscreteCommentList,
+}
+
+impl TagEditor {
+    /// Reads the comments of the file at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<TagEditor, Error> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| Error::FileOpenError(path.to_path_buf(), e))?;
+        let comments = match read_headers(BufReader::new(file))? {
+            CodecHeaders::Opus(_, comment_header) => comment_header.to_discrete_comment_list(),
+            CodecHeaders::Vorbis(_, comment_header) => comment_header.to_discrete_comment_list(),
+        };
+        Ok(TagEditor { path: path.to_path_buf(), original: comments.clone(), comments })
+    }
+
+    /// The path of the file being edited
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// The comments as they will be written
+    pub fn comments(&self) -> &DiscreteCommentList { &self.comments }
+
+    /// Mutable access to the comments. Changes are only written by `commit()`.
+    pub fn comments_mut(&mut self) -> &mut DiscreteCommentList { &mut self.comments }
+
+    /// Have the comments been changed since the file was opened
+    pub fn is_modified(&self) -> bool { self.comments != self.original }
+
+    /// Writes the comments back to the file if they have been changed,
+    /// returning whether the file was replaced. Entries which could not be
+    /// parsed are preserved unless they were removed from the comment list.
+    pub fn commit(self) -> Result<bool, Error> {
+        if !self.is_modified() {
+            return Ok(false);
+        }
+        let action = CommentRewriterAction::Replace(self.comments);
+        let config = CommentRewriterConfig { action, drop_invalid: false, normalize_keys: None };
+        rewrite_file(&self.path, CommentHeaderRewrite::new(config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+    use super::*;
+    use crate::header::{CommentList, InvalidComment, InvalidCommentReason};
+    use crate::testing::StreamBuilder;
+
+    fn write_vorbis_file(path: &Path) -> Result<(), Error> {
+        let mut id_header = b"\x01vorbis".to_vec();
+        id_header.extend_from_slice(&[
+            0, 0, 0, 0, 2, 0x44, 0xac, 0, 0, 0, 0, 0, 0, 0, 0xee, 0x02, 0, 0, 0, 0, 0, 0, 0xb8, 1,
+        ]);
+        let mut comment_header = b"\x03vorbis".to_vec();
+        comment_header.extend_from_slice(&[4, 0, 0, 0]);
+        comment_header.extend_from_slice(b"test");
+        comment_header.extend_from_slice(&[1, 0, 0, 0, 11, 0, 0, 0]);
+        comment_header.extend_from_slice(b"TITLE=Title");
+        comment_header.push(1);
+
+        let mut data = Vec::new();
+        let mut writer = PacketWriter::new(&mut data);
+        let serial = 1;
+        writer.write_packet(id_header, serial, PacketWriteEndInfo::EndPage, 0).map_err(Error::WriteError)?;
+        writer.write_packet(comment_header, serial, PacketWriteEndInfo::NormalPacket, 0).map_err(Error::WriteError)?;
+        writer.write_packet(vec![5; 20], serial, PacketWriteEndInfo::EndPage, 0).map_err(Error::WriteError)?;
+        writer.write_packet(vec![0; 10], serial, PacketWriteEndInfo::EndStream, 1024).map_err(Error::WriteError)?;
+        drop(writer);
+        File::create(path).and_then(|mut f| f.write_all(&data)).map_err(Error::WriteError)
+    }
+
+    #[test]
+    fn edit_and_commit() -> Result<(), Error> {
+        let dir = tempfile::tempdir().map_err(Error::ReadError)?;
+        let path = dir.path().join("test.ogg");
+        write_vorbis_file(&path)?;
+
+        let editor = TagEditor::open(&path)?;
+        assert_eq!(editor.comments().get_first("TITLE"), Some("Title"));
+        assert!(!editor.commit()?);
+
+        let mut editor = TagEditor::open(&path)?;
+        editor.comments_mut().replace("TITLE", "New Title")?;
+        editor.comments_mut().push("ARTIST", "Artist")?;
+        assert_eq!(editor.comments().vendor(), Some("test"));
+        editor.comments_mut().set_vendor(Some("zoog".into()));
+        assert!(editor.is_modified());
+        assert!(editor.commit()?);
+
+        let editor = TagEditor::open(&path)?;
+        let comments: Vec<_> = editor.comments().iter().collect();
+        assert_eq!(comments, [("TITLE", "New Title"), ("ARTIST", "Artist")]);
+        assert_eq!(editor.comments().vendor(), Some("zoog"));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_entries_committed() -> Result<(), Error> {
+        let dir = tempfile::tempdir().map_err(Error::ReadError)?;
+        let path = dir.path().join("test.opus");
+        let stream = StreamBuilder::default().comment("TITLE", "Title").build()?;
+        std::fs::write(&path, stream).map_err(Error::WriteError)?;
+
+        let mut editor = TagEditor::open(&path)?;
+        editor.comments_mut().push_raw("ARTIST", b"Artist \xff")?;
+        let entry = InvalidComment::new(b"no separator".to_vec(), InvalidCommentReason::MissingSeparator);
+        editor.comments_mut().push_invalid(entry);
+        assert!(editor.commit()?);
+
+        let mut editor = TagEditor::open(&path)?;
+        assert_eq!(editor.comments().get_first_raw("ARTIST"), Some(&b"Artist \xff"[..]));
+        assert_eq!(editor.comments().invalid_entries().len(), 2);
+
+        // Remove only the entry which is not valid UTF-8
+        let comments = editor.comments_mut();
+        let retained: Vec<_> = comments
+            .invalid_entries()
+            .iter()
+            .filter(|entry| entry.reason() != InvalidCommentReason::InvalidUtf8)
+            .cloned()
+            .collect();
+        comments.remove_invalid_entries();
+        for entry in retained {
+            comments.push_invalid(entry);
+        }
+        assert!(editor.commit()?);
+
+        let editor = TagEditor::open(&path)?;
+        assert_eq!(editor.comments().get_first_raw("ARTIST"), None);
+        let invalid: Vec<_> = editor.comments().invalid_entries().iter().map(InvalidComment::as_bytes).collect();
+        assert_eq!(invalid, [b"no separator"]);
+        assert_eq!(editor.comments().get_first("TITLE"), Some("Title"));
+        Ok(())
+    }
+}
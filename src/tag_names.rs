@@ -0,0 +1,181 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use crate::header::CommentList;
+use crate::Error;
+
+/// A convention for naming the tags which applications disagree about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagConvention {
+    /// The names written by MusicBrainz Picard, e.g. `ALBUMARTIST` and
+    /// `TOTALTRACKS`
+    Picard,
+
+    /// The names written by foobar2000, e.g. `ALBUM ARTIST` and `TOTALTRACKS`
+    Foobar2000,
+
+    /// The names proposed in the Xiph.Org field recommendations, e.g.
+    /// `ALBUMARTIST` and `TRACKTOTAL`
+    Xiph,
+}
+
+/// A tag known by different names, with the name used by each convention
+#[derive(Debug)]
+struct Field {
+    picard: &'static str,
+    foobar2000: &'static str,
+    xiph: &'static str,
+    aliases: &'static [&'static str],
+}
+
+const TRACK_NUMBER: Field =
+    Field { picard: "TRACKNUMBER", foobar2000: "TRACKNUMBER", xiph: "TRACKNUMBER", aliases: &["TRACK"] };
+
+const TOTAL_TRACKS: Field =
+    Field { picard: "TOTALTRACKS", foobar2000: "TOTALTRACKS", xiph: "TRACKTOTAL", aliases: &["TRACKSTOTAL"] };
+
+const DISC_NUMBER: Field =
+    Field { picard: "DISCNUMBER", foobar2000: "DISCNUMBER", xiph: "DISCNUMBER", aliases: &["DISC"] };
+
+const TOTAL_DISCS: Field =
+    Field { picard: "TOTALDISCS", foobar2000: "TOTALDISCS", xiph: "DISCTOTAL", aliases: &["DISCSTOTAL"] };
+
+const FIELDS: [Field; 6] = [
+    Field { picard: "ALBUMARTIST", foobar2000: "ALBUM ARTIST", xiph: "ALBUMARTIST", aliases: &["ALBUM_ARTIST"] },
+    TRACK_NUMBER,
+    TOTAL_TRACKS,
+    DISC_NUMBER,
+    TOTAL_DISCS,
+    Field { picard: "DATE", foobar2000: "DATE", xiph: "DATE", aliases: &["YEAR"] },
+];
+
+impl Field {
+    fn name(&self, convention: TagConvention) -> &'static str {
+        match convention {
+            TagConvention::Picard => self.picard,
+            TagConvention::Foobar2000 => self.foobar2000,
+            TagConvention::Xiph => self.xiph,
+        }
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        [self.picard, self.foobar2000, self.xiph].iter().chain(self.aliases).any(|name| name.eq_ignore_ascii_case(key))
+    }
+}
+
+impl TagConvention {
+    /// All supported conventions
+    pub const ALL: [TagConvention; 3] = [TagConvention::Picard, TagConvention::Foobar2000, TagConvention::Xiph];
+
+    /// The name of the convention as accepted by `from_str()`
+    pub fn name(self) -> &'static str {
+        match self {
+            TagConvention::Picard => "picard",
+            TagConvention::Foobar2000 => "foobar2000",
+            TagConvention::Xiph => "xiph",
+        }
+    }
+
+    /// The name this convention uses for the tag named `key` in any
+    /// convention, or `None` if the tag is not one whose name differs
+    /// between conventions
+    pub fn tag_name(self, key: &str) -> Option<&'static str> {
+        FIELDS.iter().find(|field| field.matches(key)).map(|field| field.name(self))
+    }
+
+    /// Renames the tags in `comments` to the names used by this convention,
+    /// preserving their order. A track or disc number of the form `N/M` is
+    /// split into the number and a total tag unless a total is already
+    /// present. Entries which could not be parsed are left untouched.
+    pub fn normalize<C: CommentList>(self, comments: &mut C) -> Result<(), Error> {
+        let has_tag = |field: &Field| comments.iter().any(|(key, _)| field.matches(key));
+        let split_totals = [(TRACK_NUMBER, TOTAL_TRACKS), (DISC_NUMBER, TOTAL_DISCS)]
+            .into_iter()
+            .filter(|(_, total)| !has_tag(total))
+            .collect::<Vec<_>>();
+        let mut normalized = Vec::with_capacity(comments.len());
+        for (key, value) in comments.iter() {
+            let key = self.tag_name(key).unwrap_or(key);
+            let split = split_totals.iter().find(|(number, _)| number.matches(key)).and_then(|(_, total)| {
+                let (number, count) = value.split_once('/')?;
+                Some((number.trim(), total.name(self), count.trim()))
+            });
+            match split {
+                Some((number, total_key, count)) => {
+                    normalized.push((key.to_string(), number.to_string()));
+                    normalized.push((total_key.to_string(), count.to_string()));
+                }
+                None => normalized.push((key.to_string(), value.to_string())),
+            }
+        }
+        comments.retain(|_, _| false);
+        comments.extend(normalized)
+    }
+}
+
+impl Display for TagConvention {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), fmt::Error> { write!(formatter, "{}", self.name()) }
+}
+
+impl FromStr for TagConvention {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<TagConvention, String> {
+        let found = TagConvention::ALL.into_iter().find(|convention| convention.name().eq_ignore_ascii_case(name));
+        found.ok_or_else(|| {
+            let names: Vec<_> = TagConvention::ALL.iter().map(|convention| convention.name()).collect();
+            format!("unknown tag convention `{}` (expected one of {})", name, names.join(", "))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::DiscreteCommentList;
+
+    fn comment_list(entries: &[(&str, &str)]) -> Result<DiscreteCommentList, Error> {
+        let mut comments = DiscreteCommentList::default();
+        comments.extend(entries.iter().copied())?;
+        Ok(comments)
+    }
+
+    #[test]
+    fn tags_renamed_to_convention() -> Result<(), Error> {
+        let mut comments = comment_list(&[
+            ("Album Artist", "Someone"),
+            ("TITLE", "A Title"),
+            ("TRACKNUMBER", "3/12"),
+            ("year", "2001"),
+            ("DISCTOTAL", "2"),
+            ("DISCNUMBER", "1/2"),
+        ])?;
+        TagConvention::Picard.normalize(&mut comments)?;
+        let expected = comment_list(&[
+            ("ALBUMARTIST", "Someone"),
+            ("TITLE", "A Title"),
+            ("TRACKNUMBER", "3"),
+            ("TOTALTRACKS", "12"),
+            ("DATE", "2001"),
+            ("TOTALDISCS", "2"),
+            ("DISCNUMBER", "1/2"),
+        ])?;
+        assert_eq!(comments, expected);
+
+        TagConvention::Foobar2000.normalize(&mut comments)?;
+        assert_eq!(comments.get_first("ALBUM ARTIST"), Some("Someone"));
+        TagConvention::Xiph.normalize(&mut comments)?;
+        assert_eq!(comments.get_first("ALBUMARTIST"), Some("Someone"));
+        assert_eq!(comments.get_first("TRACKTOTAL"), Some("12"));
+        assert_eq!(comments.get_first("DISCTOTAL"), Some("2"));
+        Ok(())
+    }
+
+    #[test]
+    fn conventions_parsed_by_name() {
+        for convention in TagConvention::ALL {
+            assert_eq!(convention.name().parse(), Ok(convention));
+        }
+        assert!("itunes".parse::<TagConvention>().is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use serde::Deserialize;
+use zoog::header::validate_comment_field_name;
+use zoog::Error;
+
+/// The name of the CSV column containing the path of each file
+const PATH_COLUMN: &str = "path";
+
+/// The tags to be applied to a single file
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TagTableRow {
+    /// The file to be tagged
+    pub path: PathBuf,
+
+    /// Each tag name with its values, in the order they should be written
+    pub tags: Vec<(String, Vec<String>)>,
+}
+
+/// A tag value in a JSON table, which may be a single value or a list of
+/// values for tags which occur more than once
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonValues {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonRow {
+    path: PathBuf,
+    tags: BTreeMap<String, JsonValues>,
+}
+
+fn invalid_table(path: &Path, message: String) -> Error {
+    Error::FileReadError(path.to_path_buf(), io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+/// Reads a table of files and the tags to apply to them. Files ending in
+/// `.json` contain an array of objects with `path` and `tags` fields, and all
+/// others are treated as CSV with a header row naming a `path` column and the
+/// tag in each other column. Relative paths are resolved against the
+/// directory containing the table.
+pub fn read_tag_table(path: &Path) -> Result<Vec<TagTableRow>, Error> {
+    let text = fs::read_to_string(path).map_err(|e| Error::FileReadError(path.to_path_buf(), e))?;
+    let is_json = path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case("json"));
+    let mut rows = if is_json {
+        let rows: Vec<JsonRow> = serde_json::from_str(&text).map_err(|e| invalid_table(path, e.to_string()))?;
+        rows.into_iter()
+            .map(|row| {
+                let tags = row
+                    .tags
+                    .into_iter()
+                    .map(|(name, values)| match values {
+                        JsonValues::Single(value) => (name, vec![value]),
+                        JsonValues::Multiple(values) => (name, values),
+                    })
+                    .collect();
+                TagTableRow { path: row.path, tags }
+            })
+            .collect()
+    } else {
+        rows_from_csv(&parse_csv(&text).map_err(|e| invalid_table(path, e))?).map_err(|e| invalid_table(path, e))?
+    };
+    for row in &rows {
+        for (name, _) in &row.tags {
+            validate_comment_field_name(name)?;
+        }
+    }
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    for row in &mut rows {
+        row.path = base.join(&row.path);
+    }
+    Ok(rows)
+}
+
+/// Converts CSV records to rows. Empty cells are ignored, and a tag may be
+/// given more than one value by naming it in more than one column.
+fn rows_from_csv(records: &[Vec<String>]) -> Result<Vec<TagTableRow>, String> {
+    let (header, records) = records.split_first().ok_or_else(|| String::from("the table is empty"))?;
+    let path_column = header
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(PATH_COLUMN))
+        .ok_or_else(|| format!("the header row has no `{}` column", PATH_COLUMN))?;
+    let mut rows = Vec::with_capacity(records.len());
+    for (index, record) in records.iter().enumerate() {
+        if record.len() != header.len() {
+            return Err(format!("row {} has {} fields but the header has {}", index + 2, record.len(), header.len()));
+        }
+        if record[path_column].is_empty() {
+            return Err(format!("row {} has no path", index + 2));
+        }
+        let mut row = TagTableRow { path: PathBuf::from(&record[path_column]), tags: Vec::new() };
+        for (column, (name, value)) in header.iter().zip(record).enumerate() {
+            if column == path_column || value.is_empty() {
+                continue;
+            }
+            match row.tags.iter_mut().find(|(existing, _)| existing.eq_ignore_ascii_case(name)) {
+                Some((_, values)) => values.push(value.clone()),
+                None => row.tags.push((name.clone(), vec![value.clone()])),
+            }
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Splits CSV text as described by RFC 4180 into records of fields. Blank
+/// lines are skipped.
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut in_quotes = false;
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            line += 1;
+        }
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                c => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            '"' => return Err(format!("line {}: unexpected quote within a field", line)),
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                let finished = std::mem::take(&mut record);
+                if finished != [""] {
+                    records.push(finished);
+                }
+            }
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(String::from("unterminated quoted field"));
+    }
+    record.push(field);
+    if record != [""] {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rows_parsed() -> Result<(), String> {
+        let text = "Path,TITLE,ARTIST,ARTIST\r\n\
+                    a.opus,\"Title, with comma\",One,Two\r\n\
+                    \r\n\
+                    b.opus,\"Quoted \"\"title\"\"\nover lines\",,Three\n";
+        let rows = rows_from_csv(&parse_csv(text)?)?;
+        assert_eq!(
+            rows,
+            vec![
+                TagTableRow {
+                    path: PathBuf::from("a.opus"),
+                    tags: vec![
+                        (String::from("TITLE"), vec![String::from("Title, with comma")]),
+                        (String::from("ARTIST"), vec![String::from("One"), String::from("Two")]),
+                    ],
+                },
+                TagTableRow {
+                    path: PathBuf::from("b.opus"),
+                    tags: vec![
+                        (String::from("TITLE"), vec![String::from("Quoted \"title\"\nover lines")]),
+                        (String::from("ARTIST"), vec![String::from("Three")]),
+                    ],
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_csv_rejected() {
+        assert!(parse_csv("path,TITLE\na.opus,\"unterminated").is_err());
+        assert!(parse_csv("path,TITLE\na.opus,un\"quoted").is_err());
+        assert!(parse_csv("TITLE\nTitle").and_then(|records| rows_from_csv(&records)).is_err());
+        assert!(parse_csv("path,TITLE\na.opus").and_then(|records| rows_from_csv(&records)).is_err());
+    }
+}
@@ -0,0 +1,407 @@
+use std::time::Duration;
+
+use crate::header::{CommentHeader as _, CommentList as _, FixedPointGain, IdHeader as _};
+use crate::ogg_page::{Page, FLAG_CONTINUED, FLAG_LAST, NO_GRANULE_POSITION};
+use crate::{opus, Error};
+
+/// The sample rate of Opus granule positions
+const SAMPLE_RATE: u32 = 48000;
+
+/// The duration of each generated packet in samples at 48kHz
+const PACKET_SAMPLES: u64 = 960;
+
+/// The configuration number of 20ms fullband CELT frames (RFC 6716, section
+/// 3.1)
+const CELT_FULLBAND_20MS: u8 = 31;
+
+/// The lacing value of a segment which does not end a packet
+const MAX_LACING_VALUE: u8 = 255;
+
+/// The audio coded in a generated stream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Content {
+    /// Silence, coded as packets containing only a table-of-contents byte
+    Silence,
+
+    /// A sine wave of the specified frequency in Hz and peak amplitude relative
+    /// to full scale in each channel, encoded with libopus
+    #[cfg(feature = "analysis")]
+    Sine { frequency: f32, amplitude: f32 },
+}
+
+/// How the audio packets of a generated stream are divided into pages
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pagination {
+    /// Each page ends after the specified number of packets
+    PacketsPerPage(usize),
+
+    /// Each page ends once it contains the specified number of segments, so
+    /// packets of 255 bytes or more may continue onto the next page
+    SegmentsPerPage(u8),
+}
+
+/// Builds an Ogg Opus stream in memory, for testing code which reads or
+/// rewrites streams without shipping binary files. This defaults to one
+/// second of stereo silence with a typical pre-skip, no output gain or
+/// comments, and 50 packets per page.
+#[derive(Clone, Debug)]
+pub struct StreamBuilder {
+    channels: u8,
+    duration: Duration,
+    pre_skip: u16,
+    output_gain: FixedPointGain,
+    vendor: String,
+    comments: Vec<(String, String)>,
+    serial: u32,
+    content: Content,
+    pagination: Pagination,
+}
+
+impl Default for StreamBuilder {
+    fn default() -> StreamBuilder {
+        StreamBuilder {
+            channels: 2,
+            duration: Duration::from_secs(1),
+            pre_skip: 312,
+            output_gain: FixedPointGain::default(),
+            vendor: String::from("zoog"),
+            comments: Vec::new(),
+            serial: 0x5eed,
+            content: Content::Silence,
+            pagination: Pagination::PacketsPerPage(50),
+        }
+    }
+}
+
+impl StreamBuilder {
+    /// Sets the number of channels, which must be one or two
+    #[must_use]
+    pub fn channels(mut self, channels: u8) -> StreamBuilder {
+        self.channels = channels;
+        self
+    }
+
+    /// Sets the duration of the audio following the pre-skip. The final
+    /// granule position is set so that exactly this duration is decoded.
+    #[must_use]
+    pub fn duration(mut self, duration: Duration) -> StreamBuilder {
+        self.duration = duration;
+        self
+    }
+
+    /// Sets the number of samples at 48kHz to discard from the start of the
+    /// decoded output
+    #[must_use]
+    pub fn pre_skip(mut self, samples: u16) -> StreamBuilder {
+        self.pre_skip = samples;
+        self
+    }
+
+    /// Sets the output gain
+    #[must_use]
+    pub fn output_gain(mut self, gain: FixedPointGain) -> StreamBuilder {
+        self.output_gain = gain;
+        self
+    }
+
+    /// Sets the vendor string of the comment header
+    #[must_use]
+    pub fn vendor(mut self, vendor: &str) -> StreamBuilder {
+        self.vendor = vendor.to_string();
+        self
+    }
+
+    /// Appends a comment to the comment header
+    #[must_use]
+    pub fn comment(mut self, key: &str, value: &str) -> StreamBuilder {
+        self.comments.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the serial number of the logical stream
+    #[must_use]
+    pub fn serial(mut self, serial: u32) -> StreamBuilder {
+        self.serial = serial;
+        self
+    }
+
+    /// Sets the audio coded in the stream
+    #[must_use]
+    pub fn content(mut self, content: Content) -> StreamBuilder {
+        self.content = content;
+        self
+    }
+
+    /// Sets how the audio packets are divided into pages
+    #[must_use]
+    pub fn pagination(mut self, pagination: Pagination) -> StreamBuilder {
+        self.pagination = pagination;
+        self
+    }
+
+    /// The number of samples at 48kHz decoded from the stream, including the
+    /// pre-skip
+    fn total_samples(&self) -> u64 {
+        let duration_samples = self.duration.as_nanos() * u128::from(SAMPLE_RATE) / 1_000_000_000;
+        u64::try_from(duration_samples).unwrap_or(u64::MAX).saturating_add(u64::from(self.pre_skip))
+    }
+
+    /// Generates the identification and comment header packets of the stream,
+    /// for tests which arrange the packets of a stream themselves
+    pub fn header_packets(&self) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        if !(1..=2).contains(&self.channels) {
+            return Err(Error::InvalidChannelCount(self.channels.into()));
+        }
+        let id_header = opus::IdHeader::builder()
+            .channels(self.channels)
+            .pre_skip(self.pre_skip)
+            .input_sample_rate(Some(SAMPLE_RATE))
+            .output_gain(self.output_gain)
+            .build()?;
+        let mut comment_header = opus::CommentHeader::empty(&self.vendor);
+        for (key, value) in &self.comments {
+            comment_header.push(key, value)?;
+        }
+        let mut id_data = Vec::new();
+        id_header.serialize_into(&mut id_data)?;
+        let mut comment_data = Vec::new();
+        comment_header.serialize_into(&mut comment_data)?;
+        Ok((id_data, comment_data))
+    }
+
+    /// Generates the stream, failing if the channel count is unsupported or a
+    /// comment key is invalid
+    pub fn build(&self) -> Result<Vec<u8>, Error> {
+        let (id_data, comment_data) = self.header_packets()?;
+        let mut pages = Page::paginate(&[id_data], self.serial, 0, 0, true);
+        let next_sequence = u32::try_from(pages.len()).unwrap_or(u32::MAX);
+        pages.extend(Page::paginate(&[comment_data], self.serial, next_sequence, 0, false));
+
+        let total_samples = self.total_samples();
+        let num_packets = (total_samples + PACKET_SAMPLES - 1) / PACKET_SAMPLES;
+        let mut source = PacketSource::new(self)?;
+        let next_sequence = u32::try_from(pages.len()).unwrap_or(u32::MAX);
+        let mut paginator = Paginator::new(self.serial, next_sequence, self.pagination);
+        for idx in 0..num_packets {
+            let packet = source.packet(idx)?;
+            paginator.push(&packet, ((idx + 1) * PACKET_SAMPLES).min(total_samples));
+        }
+        pages.extend(paginator.finish());
+
+        let mut output = Vec::new();
+        for page in &pages {
+            page.write(&mut output)?;
+        }
+        Ok(output)
+    }
+}
+
+/// Generates the audio packets of a stream
+enum PacketSource {
+    /// Packets consisting of the specified table-of-contents byte
+    Silence(u8),
+
+    /// Packets encoding a sine wave, one 20ms frame at a time
+    #[cfg(feature = "analysis")]
+    Sine { encoder: ::opus::Encoder, channels: usize, frequency: f32, amplitude: f32 },
+}
+
+impl PacketSource {
+    #[cfg_attr(not(feature = "analysis"), allow(clippy::unnecessary_wraps))]
+    fn new(builder: &StreamBuilder) -> Result<PacketSource, Error> {
+        match builder.content {
+            Content::Silence => {
+                // A single frame with no data, which is decoded as silence
+                let stereo = if builder.channels == 2 { 0x4 } else { 0 };
+                Ok(PacketSource::Silence(CELT_FULLBAND_20MS << 3 | stereo))
+            }
+            #[cfg(feature = "analysis")]
+            Content::Sine { frequency, amplitude } => {
+                use ::opus::{Application, Channels, Encoder};
+
+                let channels = if builder.channels == 1 { Channels::Mono } else { Channels::Stereo };
+                let encoder = Encoder::new(SAMPLE_RATE, channels, Application::Audio).map_err(Error::OpusError)?;
+                Ok(PacketSource::Sine { encoder, channels: builder.channels.into(), frequency, amplitude })
+            }
+        }
+    }
+
+    /// Generates the packet with index `idx`
+    #[cfg_attr(not(feature = "analysis"), allow(unused_variables, clippy::unnecessary_wraps))]
+    fn packet(&mut self, idx: u64) -> Result<Vec<u8>, Error> {
+        match self {
+            PacketSource::Silence(toc) => Ok(vec![*toc]),
+            #[cfg(feature = "analysis")]
+            PacketSource::Sine { encoder, channels, frequency, amplitude } => {
+                let mut samples = Vec::with_capacity(*channels * 960);
+                for position in (idx * PACKET_SAMPLES)..((idx + 1) * PACKET_SAMPLES) {
+                    #[allow(clippy::cast_precision_loss)]
+                    let t = position as f32 / SAMPLE_RATE as f32;
+                    let value = (t * *frequency * std::f32::consts::TAU).sin() * *amplitude;
+                    samples.extend(std::iter::repeat(value).take(*channels));
+                }
+                encoder.encode_vec_float(&samples, 4000).map_err(Error::OpusError)
+            }
+        }
+    }
+}
+
+/// Divides audio packets into pages according to a `Pagination`
+struct Paginator {
+    serial: u32,
+    sequence: u32,
+    pagination: Pagination,
+    pages: Vec<Page>,
+    lacing: Vec<u8>,
+    data: Vec<u8>,
+    continued: bool,
+    granule_position: u64,
+    packets_on_page: usize,
+}
+
+impl Paginator {
+    fn new(serial: u32, first_sequence: u32, pagination: Pagination) -> Paginator {
+        Paginator {
+            serial,
+            sequence: first_sequence,
+            pagination,
+            pages: Vec::new(),
+            lacing: Vec::new(),
+            data: Vec::new(),
+            continued: false,
+            granule_position: NO_GRANULE_POSITION,
+            packets_on_page: 0,
+        }
+    }
+
+    fn max_segments(&self) -> usize {
+        match self.pagination {
+            Pagination::PacketsPerPage(_) => usize::from(MAX_LACING_VALUE),
+            Pagination::SegmentsPerPage(segments) => usize::from(segments.max(1)),
+        }
+    }
+
+    /// Adds a packet after which `granule_position` samples have been decoded
+    fn push(&mut self, packet: &[u8], granule_position: u64) {
+        let mut remaining = packet;
+        loop {
+            if self.lacing.len() >= self.max_segments() {
+                self.end_page(0);
+            }
+            let len = remaining.len().min(usize::from(MAX_LACING_VALUE));
+            self.lacing.push(u8::try_from(len).expect("Segment length exceeds lacing value"));
+            self.data.extend_from_slice(&remaining[..len]);
+            remaining = &remaining[len..];
+            if len < usize::from(MAX_LACING_VALUE) {
+                break;
+            }
+        }
+        self.granule_position = granule_position;
+        self.packets_on_page += 1;
+        if let Pagination::PacketsPerPage(packets) = self.pagination {
+            if self.packets_on_page >= packets.max(1) {
+                self.end_page(0);
+            }
+        }
+    }
+
+    fn end_page(&mut self, flags: u8) {
+        let header_type = flags | if self.continued { FLAG_CONTINUED } else { 0 };
+        self.continued = self.lacing.last() == Some(&MAX_LACING_VALUE);
+        let page = Page::from_segments(
+            self.serial,
+            self.sequence,
+            header_type,
+            std::mem::replace(&mut self.granule_position, NO_GRANULE_POSITION),
+            std::mem::take(&mut self.lacing),
+            std::mem::take(&mut self.data),
+        );
+        self.pages.push(page);
+        self.sequence = self.sequence.wrapping_add(1);
+        self.packets_on_page = 0;
+    }
+
+    /// Returns the pages, the last of which is marked as ending the stream
+    fn finish(mut self) -> Vec<Page> {
+        if self.lacing.is_empty() {
+            // The stream must end on a page which ends a packet
+            if let Some(last) = self.pages.pop() {
+                self.sequence = last.sequence();
+                self.continued = last.is_continuation();
+                self.granule_position = last.granule_position();
+                self.lacing = last.lacing().to_vec();
+                self.data = last.data().to_vec();
+            }
+        }
+        self.end_page(FLAG_LAST);
+        self.pages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::header::CommentList;
+    use crate::header_rewriter::{read_headers, CodecHeaders};
+    use crate::ogg_page::last_granule_position;
+    use crate::validate::validate_stream;
+    use crate::Decibels;
+
+    #[test]
+    fn generated_stream_valid() -> Result<(), Error> {
+        let output_gain = FixedPointGain::try_from(Decibels::from(-3.0))?;
+        let stream = StreamBuilder::default()
+            .channels(1)
+            .duration(Duration::from_millis(1010))
+            .pre_skip(100)
+            .output_gain(output_gain)
+            .vendor("test vendor")
+            .comment("TITLE", "Generated")
+            .build()?;
+        assert_eq!(validate_stream(Cursor::new(&stream))?, Vec::new());
+        assert_eq!(last_granule_position(Cursor::new(&stream))?, Some(100 + 48480));
+
+        let CodecHeaders::Opus(id_header, comment_header) = read_headers(Cursor::new(&stream))? else {
+            panic!("Expected Opus headers");
+        };
+        assert_eq!(id_header.num_output_channels(), 1);
+        assert_eq!(id_header.pre_skip(), 100);
+        assert_eq!(id_header.get_output_gain(), output_gain);
+        assert_eq!(comment_header.get_vendor(), "test vendor");
+        assert_eq!(comment_header.get_first("TITLE"), Some("Generated"));
+
+        assert!(matches!(StreamBuilder::default().channels(3).build(), Err(Error::InvalidChannelCount(3))));
+        Ok(())
+    }
+
+    #[test]
+    fn packets_continued_across_pages() -> Result<(), Error> {
+        let builder = StreamBuilder::default().pagination(Pagination::SegmentsPerPage(1));
+        #[cfg(feature = "analysis")]
+        let builder = builder.content(Content::Sine { frequency: 997.0, amplitude: 0.5 });
+        let stream = builder.build()?;
+        assert_eq!(validate_stream(Cursor::new(&stream))?, Vec::new());
+        assert_eq!(last_granule_position(Cursor::new(&stream))?, Some(312 + 48000));
+
+        let mut reader = Cursor::new(&stream);
+        let mut pages = Vec::new();
+        while let Some(page) = Page::read(&mut reader)? {
+            pages.push(page);
+        }
+        assert!(pages.iter().all(|page| page.lacing().len() == 1));
+        #[cfg(feature = "analysis")]
+        assert!(pages.iter().any(Page::is_continuation));
+        assert!(pages.last().expect("No pages").is_last_in_stream());
+
+        #[cfg(feature = "analysis")]
+        {
+            let mut session = crate::opus::AnalysisSession::default();
+            let track = session.analyze_file(Cursor::new(&stream))?;
+            assert!((track.lufs().as_f64() + 6.02).abs() < 0.5, "Unexpected loudness {}", track.lufs());
+            assert_eq!(track.duration(), Duration::from_secs(1));
+        }
+        Ok(())
+    }
+}
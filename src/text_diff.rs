@@ -0,0 +1,72 @@
+use std::io::{self, Write};
+
+/// A line in a diff between two sequences of lines
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum DiffLine<'a> {
+    Removed(&'a str),
+    Added(&'a str),
+    Unchanged(&'a str),
+}
+
+/// Computes a minimal diff between `old` and `new` from their longest common
+/// subsequence. Removed lines are placed before added lines where the two
+/// are interleaved.
+fn diff<'a, S: AsRef<str>>(old: &'a [S], new: &'a [S]) -> Vec<DiffLine<'a>> {
+    // common[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i].as_ref() == new[j].as_ref() {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut result = Vec::with_capacity(old.len().max(new.len()));
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i].as_ref() == new[j].as_ref() {
+            result.push(DiffLine::Unchanged(old[i].as_ref()));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            result.push(DiffLine::Removed(old[i].as_ref()));
+            i += 1;
+        } else {
+            result.push(DiffLine::Added(new[j].as_ref()));
+            j += 1;
+        }
+    }
+    result
+}
+
+/// Writes a diff of `old` and `new` with lines only in `old` prefixed by `-`,
+/// lines only in `new` prefixed by `+` and lines in both prefixed by a space
+pub fn write_diff<W: Write, S: AsRef<str>>(mut writer: W, old: &[S], new: &[S]) -> io::Result<()> {
+    for line in diff(old, new) {
+        match line {
+            DiffLine::Removed(line) => writeln!(writer, "-{}", line)?,
+            DiffLine::Added(line) => writeln!(writer, "+{}", line)?,
+            DiffLine::Unchanged(line) => writeln!(writer, " {}", line)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_of_changed_lines() -> io::Result<()> {
+        let old = ["ARTIST=A", "TITLE=Old", "R128_TRACK_GAIN=0"];
+        let new = ["ARTIST=A", "TITLE=New", "R128_TRACK_GAIN=0", "ALBUM=B"];
+        let mut output = Vec::new();
+        write_diff(&mut output, &old, &new)?;
+        let expected = " ARTIST=A\n-TITLE=Old\n+TITLE=New\n R128_TRACK_GAIN=0\n+ALBUM=B\n";
+        assert_eq!(String::from_utf8_lossy(&output), expected);
+        Ok(())
+    }
+}
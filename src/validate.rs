@@ -0,0 +1,427 @@
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+
+use crate::header::{CommentHeader as _, IdHeader as _};
+use crate::ogg_page::{Page, NO_GRANULE_POSITION};
+use crate::{opus, vorbis, Codec, Error};
+
+/// The magic signature at the start of an Opus identification header
+const OPUS_MAGIC: &[u8] = b"OpusHead";
+
+/// Offset of the version within the Opus identification header
+const VERSION_OFFSET: usize = 8;
+
+/// Offset of the output channel count within the Opus identification header
+const CHANNELS_OFFSET: usize = 9;
+
+/// The minimum length of an Opus identification header
+const MIN_ID_HEADER_LEN: usize = 19;
+
+/// A way in which an Ogg Opus stream does not conform to RFC 7845. Pages are
+/// identified by their sequence numbers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// The first page of the stream does not have the beginning-of-stream flag
+    MissingBeginningOfStream,
+
+    /// The identification header is not the only packet on the first page
+    IdHeaderNotAlone,
+
+    /// The identification header is too short to contain the required fields
+    TruncatedIdHeader,
+
+    /// The identification header version is not 1
+    UnsupportedVersion(u8),
+
+    /// The identification header specifies zero output channels
+    ZeroChannels,
+
+    /// The channel mapping family, stream counts or mapping table are
+    /// inconsistent with each other or the channel count
+    InvalidChannelMapping,
+
+    /// The comment header could not be parsed
+    MalformedCommentHeader,
+
+    /// The comment header does not start on the second page, or the page it
+    /// ends on contains other packets
+    CommentHeaderNotOnOwnPages,
+
+    /// A page containing header packets has a non-zero granule position
+    NonZeroHeaderGranule { sequence: u32 },
+
+    /// A page on which a packet ends has no granule position
+    MissingGranule { sequence: u32 },
+
+    /// A page has a granule position smaller than that of an earlier page
+    DecreasingGranule { sequence: u32 },
+
+    /// The granule position of a page is not that of the previous page with a
+    /// granule position plus the duration of the packets completed since. Only
+    /// the final page may have a smaller granule position, to trim the end of
+    /// the stream.
+    InconsistentGranule { sequence: u32, expected: u64, found: u64 },
+
+    /// The granule position of the first audio page is smaller than the
+    /// duration of the packets it completes, which is only permitted if it is
+    /// also the final page
+    FirstGranuleTooSmall { sequence: u32, granule_position: u64, samples: u64 },
+
+    /// An audio packet completed on a page has a malformed table-of-contents
+    /// byte or frame lengths, so its duration is unknown
+    MalformedAudioPacket { sequence: u32 },
+
+    /// The sequence number of a page does not follow that of the preceding
+    /// page
+    SequenceGap { expected: u32, found: u32 },
+
+    /// The final granule position is smaller than the pre-skip
+    FinalGranuleBeforePreSkip { granule_position: u64, pre_skip: u16 },
+
+    /// The stream ends without a page with the end-of-stream flag
+    MissingEndOfStream,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Violation::MissingBeginningOfStream => write!(f, "First page lacks the beginning-of-stream flag"),
+            Violation::IdHeaderNotAlone => write!(f, "Identification header is not alone on the first page"),
+            Violation::TruncatedIdHeader => write!(f, "Identification header is truncated"),
+            Violation::UnsupportedVersion(version) => write!(f, "Identification header has version {}", version),
+            Violation::ZeroChannels => write!(f, "Identification header specifies zero channels"),
+            Violation::InvalidChannelMapping => write!(f, "Channel mapping is invalid"),
+            Violation::MalformedCommentHeader => write!(f, "Comment header is malformed"),
+            Violation::CommentHeaderNotOnOwnPages => write!(f, "Comment header does not occupy its own pages"),
+            Violation::NonZeroHeaderGranule { sequence } => {
+                write!(f, "Header page {} has a non-zero granule position", sequence)
+            }
+            Violation::MissingGranule { sequence } => {
+                write!(f, "Page {} completes a packet but has no granule position", sequence)
+            }
+            Violation::DecreasingGranule { sequence } => {
+                write!(f, "Page {} has a smaller granule position than an earlier page", sequence)
+            }
+            Violation::InconsistentGranule { sequence, expected, found } => {
+                write!(f, "Page {} has granule position {} but its packets imply {}", sequence, found, expected)
+            }
+            Violation::FirstGranuleTooSmall { sequence, granule_position, samples } => write!(
+                f,
+                "First audio page {} has granule position {} but completes packets of {} samples",
+                sequence, granule_position, samples
+            ),
+            Violation::MalformedAudioPacket { sequence } => {
+                write!(f, "Page {} completes an audio packet which is malformed", sequence)
+            }
+            Violation::SequenceGap { expected, found } => {
+                write!(f, "Expected page sequence number {} but found {}", expected, found)
+            }
+            Violation::FinalGranuleBeforePreSkip { granule_position, pre_skip } => {
+                write!(f, "Final granule position {} is smaller than the pre-skip of {}", granule_position, pre_skip)
+            }
+            Violation::MissingEndOfStream => write!(f, "Stream lacks an end-of-stream page"),
+        }
+    }
+}
+
+/// Checks the identification header, appending any violations. Returns the
+/// pre-skip if the header could be parsed.
+fn check_id_header(data: &[u8], violations: &mut Vec<Violation>) -> Result<Option<u16>, Error> {
+    if !data.starts_with(OPUS_MAGIC) {
+        return match vorbis::IdHeader::try_parse(data) {
+            Ok(Some(_)) | Err(Error::UnsupportedCodecVersion(Codec::Vorbis, _)) => {
+                Err(Error::UnsupportedCodec(Codec::Vorbis))
+            }
+            _ => Err(Error::UnknownCodec),
+        };
+    }
+    if data.len() < MIN_ID_HEADER_LEN {
+        violations.push(Violation::TruncatedIdHeader);
+        return Ok(None);
+    }
+    let version = data[VERSION_OFFSET];
+    if version != 1 {
+        violations.push(Violation::UnsupportedVersion(version));
+        return Ok(None);
+    }
+    if data[CHANNELS_OFFSET] == 0 {
+        violations.push(Violation::ZeroChannels);
+        return Ok(None);
+    }
+    let header = opus::IdHeader::try_parse(data)?.ok_or(Error::MalformedIdentificationHeader)?;
+    if header.channel_mapping().is_err() {
+        violations.push(Violation::InvalidChannelMapping);
+    }
+    Ok(Some(header.pre_skip()))
+}
+
+/// Progress through the header packets of the stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    IdHeader,
+    CommentHeader,
+    Audio,
+}
+
+/// Tracks the granule positions of audio pages and the durations of the
+/// packets completed on them
+#[derive(Debug)]
+struct GranuleCheck {
+    /// The granule position of the last page which had one
+    last_granule: Option<u64>,
+
+    /// The duration of the audio packets completed since the last page with a
+    /// granule position, or `None` if it is unknown
+    pending_samples: Option<u64>,
+
+    /// The data of a packet continued on the next page
+    packet: Vec<u8>,
+}
+
+impl Default for GranuleCheck {
+    fn default() -> GranuleCheck { GranuleCheck { last_granule: None, pending_samples: Some(0), packet: Vec::new() } }
+}
+
+impl GranuleCheck {
+    fn check_page(&mut self, page: &Page, fragments: &[(&[u8], bool)], violations: &mut Vec<Violation>) {
+        let sequence = page.sequence();
+        for &(data, ends) in fragments {
+            self.packet.extend_from_slice(data);
+            if !ends {
+                continue;
+            }
+            let packet = std::mem::take(&mut self.packet);
+            match (opus::PacketInfo::parse(&packet), self.pending_samples.as_mut()) {
+                (Ok(info), Some(samples)) => *samples += info.samples() as u64,
+                (Ok(_), None) => {}
+                (Err(_), _) => {
+                    violations.push(Violation::MalformedAudioPacket { sequence });
+                    self.pending_samples = None;
+                    break;
+                }
+            }
+        }
+        let granule_position = page.granule_position();
+        if granule_position == NO_GRANULE_POSITION {
+            if fragments.iter().any(|&(_, ends)| ends) {
+                violations.push(Violation::MissingGranule { sequence });
+            }
+            return;
+        }
+        match (self.last_granule, self.pending_samples) {
+            (Some(last), _) if granule_position < last => {
+                violations.push(Violation::DecreasingGranule { sequence });
+            }
+            (Some(last), Some(samples)) => {
+                let expected = last + samples;
+                let trimmed = page.is_last_in_stream() && granule_position < expected;
+                if granule_position != expected && !trimmed {
+                    violations.push(Violation::InconsistentGranule { sequence, expected, found: granule_position });
+                }
+            }
+            (None, Some(samples)) if granule_position < samples && !page.is_last_in_stream() => {
+                violations.push(Violation::FirstGranuleTooSmall { sequence, granule_position, samples });
+            }
+            _ => {}
+        }
+        self.last_granule = Some(granule_position);
+        self.pending_samples = Some(0);
+    }
+}
+
+/// Checks the first logical stream of an Ogg Opus file against the
+/// requirements of RFC 7845 and returns any violations found. This includes
+/// checking that granule positions never decrease and agree with the durations
+/// of the audio packets, which players rely on for seeking. Pages of other
+/// logical streams are ignored. An error is returned if the stream cannot be
+/// read, including if a page checksum is incorrect, or is not Ogg Opus.
+pub fn validate_stream<R: Read>(mut reader: R) -> Result<Vec<Violation>, Error> {
+    let mut violations = Vec::new();
+    let mut serial = None;
+    let mut stage = Stage::IdHeader;
+    let mut comment_header = Vec::new();
+    let mut pre_skip = None;
+    let mut last_sequence: Option<u32> = None;
+    let mut granules = GranuleCheck::default();
+    let mut ended = false;
+
+    while let Some(page) = Page::read(&mut reader)? {
+        if *serial.get_or_insert(page.serial()) != page.serial() {
+            continue;
+        }
+        if let Some(last_sequence) = last_sequence {
+            let expected = last_sequence.wrapping_add(1);
+            if page.sequence() != expected {
+                violations.push(Violation::SequenceGap { expected, found: page.sequence() });
+            }
+        }
+        last_sequence = Some(page.sequence());
+
+        let fragments = page.packet_fragments();
+        match stage {
+            Stage::IdHeader => {
+                if !page.is_first_in_stream() {
+                    violations.push(Violation::MissingBeginningOfStream);
+                }
+                let [(data, true)] = fragments.as_slice() else {
+                    violations.push(Violation::IdHeaderNotAlone);
+                    return Ok(violations);
+                };
+                pre_skip = check_id_header(data, &mut violations)?;
+                stage = Stage::CommentHeader;
+            }
+            Stage::CommentHeader => {
+                if comment_header.is_empty() && page.is_continuation() {
+                    violations.push(Violation::CommentHeaderNotOnOwnPages);
+                }
+                if let Some(&(data, ends)) = fragments.first() {
+                    comment_header.extend_from_slice(data);
+                    if ends {
+                        if fragments.len() > 1 {
+                            violations.push(Violation::CommentHeaderNotOnOwnPages);
+                        }
+                        if opus::CommentHeader::try_parse(&comment_header).is_err() {
+                            violations.push(Violation::MalformedCommentHeader);
+                        }
+                        stage = Stage::Audio;
+                    }
+                }
+                if page.granule_position() != 0 {
+                    violations.push(Violation::NonZeroHeaderGranule { sequence: page.sequence() });
+                }
+            }
+            Stage::Audio => granules.check_page(&page, &fragments, &mut violations),
+        }
+        if page.is_first_in_stream() && page.granule_position() != 0 {
+            violations.push(Violation::NonZeroHeaderGranule { sequence: page.sequence() });
+        }
+        if page.is_last_in_stream() {
+            ended = true;
+            break;
+        }
+    }
+
+    if serial.is_none() {
+        return Err(Error::MissingStream(Codec::Opus));
+    }
+    if stage != Stage::Audio {
+        violations.push(Violation::MalformedCommentHeader);
+    }
+    if !ended {
+        violations.push(Violation::MissingEndOfStream);
+    }
+    if let (Some(pre_skip), Some(granule_position)) = (pre_skip, granules.last_granule) {
+        if granule_position < u64::from(pre_skip) {
+            violations.push(Violation::FinalGranuleBeforePreSkip { granule_position, pre_skip });
+        }
+    }
+    Ok(violations)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+    use super::*;
+    use crate::testing::StreamBuilder;
+
+    const SERIAL: u32 = 0x5eed;
+
+    fn id_header() -> Vec<u8> { StreamBuilder::default().header_packets().expect("Failed to generate headers").0 }
+
+    fn comment_header() -> Vec<u8> { StreamBuilder::default().header_packets().expect("Failed to generate headers").1 }
+
+    fn write_stream(packets: Vec<(Vec<u8>, PacketWriteEndInfo, u64)>) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::new();
+        let mut writer = PacketWriter::new(&mut output);
+        for (data, end, granule) in packets {
+            writer.write_packet(data, SERIAL, end, granule).map_err(Error::WriteError)?;
+        }
+        drop(writer);
+        Ok(output)
+    }
+
+    #[test]
+    fn valid_stream() -> Result<(), Error> {
+        let stream = StreamBuilder::default().build()?;
+        assert_eq!(validate_stream(Cursor::new(stream))?, []);
+        Ok(())
+    }
+
+    #[test]
+    fn comment_header_sharing_page() -> Result<(), Error> {
+        let packets = vec![
+            (id_header(), PacketWriteEndInfo::EndPage, 0),
+            (comment_header(), PacketWriteEndInfo::NormalPacket, 0),
+            (vec![0xfc; 10], PacketWriteEndInfo::EndStream, 960),
+        ];
+        let stream = write_stream(packets)?;
+        let violations = validate_stream(Cursor::new(stream))?;
+        assert!(violations.contains(&Violation::CommentHeaderNotOnOwnPages));
+        Ok(())
+    }
+
+    #[test]
+    fn decreasing_granule() -> Result<(), Error> {
+        let packets = vec![
+            (id_header(), PacketWriteEndInfo::EndPage, 0),
+            (comment_header(), PacketWriteEndInfo::EndPage, 0),
+            (vec![0xfc; 10], PacketWriteEndInfo::EndPage, 1920),
+            (vec![0xfc; 10], PacketWriteEndInfo::EndStream, 960),
+        ];
+        let stream = write_stream(packets)?;
+        assert_eq!(validate_stream(Cursor::new(stream))?, [Violation::DecreasingGranule { sequence: 3 }]);
+        Ok(())
+    }
+
+    #[test]
+    fn granule_inconsistent_with_packet_durations() -> Result<(), Error> {
+        // A first granule position larger than the packet durations offsets
+        // the start of the stream, and the final page may trim the end
+        let packets = vec![
+            (id_header(), PacketWriteEndInfo::EndPage, 0),
+            (comment_header(), PacketWriteEndInfo::EndPage, 0),
+            (vec![0xfc; 10], PacketWriteEndInfo::EndPage, 2880),
+            (vec![0xfc; 10], PacketWriteEndInfo::EndPage, 3840),
+            (vec![0xfc; 10], PacketWriteEndInfo::EndStream, 4000),
+        ];
+        let stream = write_stream(packets)?;
+        assert_eq!(validate_stream(Cursor::new(stream))?, []);
+
+        let packets = vec![
+            (id_header(), PacketWriteEndInfo::EndPage, 0),
+            (comment_header(), PacketWriteEndInfo::EndPage, 0),
+            (vec![0xfc; 10], PacketWriteEndInfo::NormalPacket, 960),
+            (vec![0xfc; 10], PacketWriteEndInfo::EndPage, 1000),
+            (vec![0xfc; 10], PacketWriteEndInfo::EndPage, 1920),
+            (vec![0xfc; 10], PacketWriteEndInfo::EndStream, 4000),
+        ];
+        let stream = write_stream(packets)?;
+        assert_eq!(
+            validate_stream(Cursor::new(stream))?,
+            [
+                Violation::FirstGranuleTooSmall { sequence: 2, granule_position: 1000, samples: 1920 },
+                Violation::InconsistentGranule { sequence: 3, expected: 1960, found: 1920 },
+                Violation::InconsistentGranule { sequence: 4, expected: 2880, found: 4000 },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bad_version_and_missing_end() -> Result<(), Error> {
+        let mut header = id_header();
+        header[VERSION_OFFSET] = 2;
+        let packets = vec![
+            (header, PacketWriteEndInfo::EndPage, 0),
+            (comment_header(), PacketWriteEndInfo::EndPage, 0),
+            (vec![0xfc; 10], PacketWriteEndInfo::EndPage, 960),
+        ];
+        let stream = write_stream(packets)?;
+        let violations = validate_stream(Cursor::new(stream))?;
+        assert_eq!(violations, [Violation::UnsupportedVersion(2), Violation::MissingEndOfStream]);
+        Ok(())
+    }
+}
@@ -2,8 +2,8 @@ use std::convert::{Into, TryFrom};
 
 use crate::header::{CommentList, FixedPointGain};
 use crate::header_rewriter::{CodecHeaders, HeaderRewrite, HeaderSummarize};
-use crate::opus::{TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
-use crate::{Decibels, Error, R128_LUFS};
+use crate::opus::{self, TAG_ALBUM_GAIN, TAG_TRACK_GAIN};
+use crate::{Decibels, Error, PODCAST_LUFS, R128_LUFS, REPLAY_GAIN_LUFS, STREAMING_LUFS};
 
 /// Represents a target gain for an audio stream
 #[derive(Clone, Copy, Debug)]
@@ -16,6 +16,65 @@ pub enum VolumeTarget {
 
     /// The gain should remain the same as it already is
     NoChange,
+
+    /// The output gain should be set to the specified value, regardless of
+    /// the volume of the stream
+    Absolute(FixedPointGain),
+}
+
+/// A volume target which can be selected by name
+#[derive(Clone, Copy, Debug)]
+pub struct VolumeProfile {
+    /// The name used to select the profile
+    pub name: &'static str,
+
+    /// A short description of the profile
+    pub description: &'static str,
+
+    /// The volume targeted by the profile
+    pub target: VolumeTarget,
+}
+
+/// The built-in volume profiles
+pub const VOLUME_PROFILES: &[VolumeProfile] = &[
+    VolumeProfile {
+        name: "rg",
+        description: "ReplayGain (normalize to -18 LUFS)",
+        target: VolumeTarget::LUFS(REPLAY_GAIN_LUFS),
+    },
+    VolumeProfile {
+        name: "r128",
+        description: "EBU R 128 (normalize -23 LUFS)",
+        target: VolumeTarget::LUFS(R128_LUFS),
+    },
+    VolumeProfile {
+        name: "podcast",
+        description: "spoken word (normalize to -16 LUFS)",
+        target: VolumeTarget::LUFS(PODCAST_LUFS),
+    },
+    VolumeProfile {
+        name: "streaming",
+        description: "music streaming services (normalize to -14 LUFS)",
+        target: VolumeTarget::LUFS(STREAMING_LUFS),
+    },
+    VolumeProfile {
+        name: "broadcast",
+        description: "broadcast loudness (normalize to -23 LUFS)",
+        target: VolumeTarget::LUFS(R128_LUFS),
+    },
+    VolumeProfile {
+        name: "original",
+        description: "original source volume (set output gain to 0dB)",
+        target: VolumeTarget::ZeroGain,
+    },
+    VolumeProfile { name: "no-change", description: "leave the output gain unchanged", target: VolumeTarget::NoChange },
+];
+
+impl VolumeProfile {
+    /// Finds the built-in profile with the specified name
+    pub fn find(name: &str) -> Option<&'static VolumeProfile> {
+        VOLUME_PROFILES.iter().find(|profile| profile.name == name)
+    }
 }
 
 /// Represents whether output gain relative to full scale should be targetted to
@@ -40,6 +99,27 @@ pub struct VolumeRewriterConfig {
 
     /// The pre-computed volume of the album the track belongs to (if available)
     pub album_volume: Option<Decibels>,
+
+    /// The largest output gain to use when targeting a LUFS value, for example
+    /// to keep the sample peak below a ceiling. R128 tags are computed relative
+    /// to the limited output gain.
+    pub max_output_gain: Option<Decibels>,
+
+    /// Whether existing R128 tags should be adjusted to compensate for a
+    /// change in output gain when no volume is available, so that players
+    /// which apply them reach the same volume. This only applies when neither
+    /// the track nor the album volume is set, since a tag which could not be
+    /// recomputed from analyzed volumes is stale. Otherwise such tags are
+    /// removed.
+    pub tag_compensation: bool,
+
+    /// Whether the `R128_TRACK_GAIN` tag should be removed regardless of the
+    /// track volume
+    pub clear_track_gain: bool,
+
+    /// Whether the `R128_ALBUM_GAIN` tag should be removed regardless of the
+    /// album volume
+    pub clear_album_gain: bool,
 }
 
 impl VolumeRewriterConfig {
@@ -60,6 +140,7 @@ impl VolumeTarget {
             VolumeTarget::ZeroGain => String::from("original input"),
             VolumeTarget::LUFS(lufs) => format!("{:.2} LUFS", lufs.as_f64()),
             VolumeTarget::NoChange => String::from("existing gain value"),
+            VolumeTarget::Absolute(gain) => format!("fixed output gain of {}", gain),
         }
     }
 }
@@ -77,6 +158,75 @@ pub struct OpusGains {
     pub album_r128: Option<Decibels>,
 }
 
+impl OpusGains {
+    /// Reads the gains from Opus identification and comment headers. Gain tags
+    /// which cannot be parsed are treated as absent.
+    pub fn from_headers(id_header: &opus::IdHeader, comment_header: &opus::CommentHeader) -> OpusGains {
+        OpusGains {
+            output: id_header.get_output_gain().into(),
+            track_r128: comment_header.get_gain_from_tag(TAG_TRACK_GAIN).unwrap_or(None).map(Into::into),
+            album_r128: comment_header.get_gain_from_tag(TAG_ALBUM_GAIN).unwrap_or(None).map(Into::into),
+        }
+    }
+
+    /// The track volume implied by the output gain and track gain tag, i.e. the
+    /// volume they would have been computed from
+    pub fn implied_track_volume(&self) -> Option<Decibels> {
+        self.track_r128.map(|gain| R128_LUFS - gain - self.output)
+    }
+
+    /// The album volume implied by the output gain and album gain tag
+    pub fn implied_album_volume(&self) -> Option<Decibels> {
+        self.album_r128.map(|gain| R128_LUFS - gain - self.output)
+    }
+}
+
+/// The largest difference between two gains which is attributed to rounding
+/// them to fixed-point
+const GAIN_TOLERANCE: f64 = 1.5 / 256.0;
+
+/// Whether two gains or volumes differ by no more than can be attributed to
+/// rounding them to fixed-point
+pub fn gains_close(a: Decibels, b: Decibels) -> bool { (a - b).as_f64().abs() <= GAIN_TOLERANCE }
+
+/// Determines whether the gains of a set of tracks are consistent with them
+/// having already been rewritten for the specified target and output gain
+/// mode. If `album` is set the tracks are treated as a single album and must
+/// have album gain tags implying the same album volume, otherwise they must not
+/// have album gain tags. Only the headers are needed to make this decision so
+/// it can be used to avoid analyzing tracks which are already tagged.
+pub fn gains_match_target(gains: &[OpusGains], target: VolumeTarget, mode: OutputGainMode, album: bool) -> bool {
+    let mut album_volume = None;
+    for track_gains in gains {
+        let Some(track_volume) = track_gains.implied_track_volume() else { return false };
+        let track_album_volume = track_gains.implied_album_volume();
+        if album {
+            let Some(track_album_volume) = track_album_volume else { return false };
+            match album_volume {
+                None => album_volume = Some(track_album_volume),
+                Some(album_volume) if !gains_close(album_volume, track_album_volume) => return false,
+                Some(_) => {}
+            }
+        } else if track_album_volume.is_some() {
+            return false;
+        }
+        let expected_output_gain = match (target, mode) {
+            (VolumeTarget::NoChange, _) => continue,
+            (VolumeTarget::ZeroGain, _) => Decibels::default(),
+            (VolumeTarget::Absolute(gain), _) => gain.into(),
+            (VolumeTarget::LUFS(lufs), OutputGainMode::Track) => lufs - track_volume,
+            (VolumeTarget::LUFS(lufs), OutputGainMode::Album) => match track_album_volume {
+                Some(volume) => lufs - volume,
+                None => return false,
+            },
+        };
+        if !gains_close(expected_output_gain, track_gains.output) {
+            return false;
+        }
+    }
+    true
+}
+
 /// Returns the gains from the codec headers
 #[derive(Debug, Default)]
 pub struct GainsSummary {}
@@ -87,14 +237,7 @@ impl HeaderSummarize for GainsSummary {
 
     fn summarize(&self, headers: &CodecHeaders) -> Result<OpusGains, Error> {
         match headers {
-            CodecHeaders::Opus(opus_header, comment_header) => {
-                let gains = OpusGains {
-                    output: opus_header.get_output_gain().into(),
-                    track_r128: comment_header.get_gain_from_tag(TAG_TRACK_GAIN).unwrap_or(None).map(Into::into),
-                    album_r128: comment_header.get_gain_from_tag(TAG_ALBUM_GAIN).unwrap_or(None).map(Into::into),
-                };
-                Ok(gains)
-            }
+            CodecHeaders::Opus(opus_header, comment_header) => Ok(OpusGains::from_headers(opus_header, comment_header)),
             CodecHeaders::Vorbis(_, _) => Err(Error::UnsupportedCodec(headers.codec())),
         }
     }
@@ -120,24 +263,43 @@ impl HeaderRewrite for VolumeHeaderRewrite {
                 let new_header_gain = match self.config.output_gain {
                     VolumeTarget::ZeroGain => FixedPointGain::default(),
                     VolumeTarget::LUFS(target_lufs) => {
-                        let volume_for_output_gain = self
-                            .config
-                            .volume_for_output_gain_calculation()
-                            .expect("Precomputed volume unexpectedly missing");
-                        FixedPointGain::try_from(target_lufs - volume_for_output_gain)?
+                        let volume_for_output_gain =
+                            self.config.volume_for_output_gain_calculation().ok_or(Error::MissingVolume)?;
+                        let gain = target_lufs - volume_for_output_gain;
+                        let gain = match self.config.max_output_gain {
+                            Some(max) if max.as_f64() < gain.as_f64() => max,
+                            _ => gain,
+                        };
+                        FixedPointGain::try_from(gain)?
                     }
                     VolumeTarget::NoChange => opus_header.get_output_gain(),
+                    VolumeTarget::Absolute(gain) => gain,
                 };
+                let old_header_gain = opus_header.get_output_gain();
                 opus_header.set_output_gain(new_header_gain);
-                let compute_gain = |volume| -> Result<Option<FixedPointGain>, Error> {
-                    if let Some(volume) = volume {
-                        FixedPointGain::try_from(R128_LUFS - volume - new_header_gain.into()).map(Some)
-                    } else {
-                        Ok(None)
+                let analyzed = self.config.track_volume.is_some() || self.config.album_volume.is_some();
+                let compute_gain = |volume, tag| -> Result<Option<FixedPointGain>, Error> {
+                    match volume {
+                        Some(volume) => FixedPointGain::try_from(R128_LUFS - volume - new_header_gain.into()).map(Some),
+                        None if self.config.tag_compensation && !analyzed => {
+                            // Tags which cannot be parsed are removed
+                            let existing = comment_header.get_gain_from_tag(tag).unwrap_or(None);
+                            let delta = Decibels::from(old_header_gain) - Decibels::from(new_header_gain);
+                            existing.map(|gain| FixedPointGain::try_from(Decibels::from(gain) + delta)).transpose()
+                        }
+                        None => Ok(None),
                     }
                 };
-                let track_gain_r128 = compute_gain(self.config.track_volume)?;
-                let album_gain_r128 = compute_gain(self.config.album_volume)?;
+                let track_gain_r128 = if self.config.clear_track_gain {
+                    None
+                } else {
+                    compute_gain(self.config.track_volume, TAG_TRACK_GAIN)?
+                };
+                let album_gain_r128 = if self.config.clear_album_gain {
+                    None
+                } else {
+                    compute_gain(self.config.album_volume, TAG_ALBUM_GAIN)?
+                };
                 for (tag, gain) in [(TAG_TRACK_GAIN, track_gain_r128), (TAG_ALBUM_GAIN, album_gain_r128)] {
                     if let Some(gain) = gain {
                         comment_header.set_tag_to_gain(tag, gain)?;
@@ -151,3 +313,205 @@ impl HeaderRewrite for VolumeHeaderRewrite {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::REPLAY_GAIN_LUFS;
+
+    fn gains(output: f64, track_r128: Option<f64>, album_r128: Option<f64>) -> OpusGains {
+        OpusGains {
+            output: Decibels::from(output),
+            track_r128: track_r128.map(Decibels::from),
+            album_r128: album_r128.map(Decibels::from),
+        }
+    }
+
+    #[test]
+    fn track_gains_match_target() {
+        let target = VolumeTarget::LUFS(REPLAY_GAIN_LUFS);
+        // A track at -30 LUFS normalized to -18 LUFS
+        let tagged = [gains(12.0, Some(-5.0), None)];
+        assert!(gains_match_target(&tagged, target, OutputGainMode::Track, false));
+        assert!(!gains_match_target(&tagged, VolumeTarget::LUFS(R128_LUFS), OutputGainMode::Track, false));
+        assert!(!gains_match_target(&tagged, target, OutputGainMode::Track, true));
+        assert!(gains_match_target(&tagged, VolumeTarget::NoChange, OutputGainMode::Track, false));
+
+        let untagged = [gains(12.0, None, None)];
+        assert!(!gains_match_target(&untagged, target, OutputGainMode::Track, false));
+
+        let stale_album_tag = [gains(12.0, Some(-5.0), Some(-4.0))];
+        assert!(!gains_match_target(&stale_album_tag, target, OutputGainMode::Track, false));
+
+        let zero_gain = [gains(0.0, Some(7.0), None)];
+        assert!(gains_match_target(&zero_gain, VolumeTarget::ZeroGain, OutputGainMode::Track, false));
+        assert!(!gains_match_target(&zero_gain, target, OutputGainMode::Track, false));
+    }
+
+    #[test]
+    fn album_gains_match_target() {
+        let target = VolumeTarget::LUFS(REPLAY_GAIN_LUFS);
+        // Tracks at -30 and -26 LUFS in an album at -28 LUFS
+        let tagged = [gains(10.0, Some(-3.0), Some(-5.0)), gains(10.0, Some(-7.0), Some(-5.0 + 1.0 / 256.0))];
+        assert!(gains_match_target(&tagged, target, OutputGainMode::Album, true));
+        assert!(!gains_match_target(&tagged, target, OutputGainMode::Album, false));
+        assert!(!gains_match_target(&tagged, target, OutputGainMode::Track, true));
+
+        let inconsistent = [gains(10.0, Some(-3.0), Some(-5.0)), gains(10.0, Some(-7.0), Some(-4.0))];
+        assert!(!gains_match_target(&inconsistent, target, OutputGainMode::Album, true));
+    }
+
+    #[test]
+    fn profiles_found_by_name() {
+        assert!(VOLUME_PROFILES.iter().all(|profile| VolumeProfile::find(profile.name).is_some()));
+        let podcast = VolumeProfile::find("podcast").expect("podcast profile should exist");
+        assert!(matches!(podcast.target, VolumeTarget::LUFS(lufs) if gains_close(lufs, PODCAST_LUFS)));
+        assert!(VolumeProfile::find("unknown").is_none());
+    }
+
+    #[test]
+    fn absolute_gain_applied_without_volume() -> Result<(), Error> {
+        let gain = FixedPointGain::try_from(Decibels::from(-3.5))?;
+        let config = VolumeRewriterConfig {
+            output_gain: VolumeTarget::Absolute(gain),
+            output_gain_mode: OutputGainMode::Track,
+            track_volume: None,
+            album_volume: None,
+            max_output_gain: None,
+            tag_compensation: false,
+            clear_track_gain: false,
+            clear_album_gain: false,
+        };
+        let id_header = opus::IdHeader::builder().channels(2).build()?;
+        let mut headers = CodecHeaders::Opus(id_header, opus::CommentHeader::empty("test"));
+        VolumeHeaderRewrite::new(config).rewrite(&mut headers)?;
+        let CodecHeaders::Opus(id_header, _) = headers else { unreachable!() };
+        assert_eq!(id_header.get_output_gain(), gain);
+        Ok(())
+    }
+
+    #[test]
+    fn tags_compensate_for_output_gain_change() -> Result<(), Error> {
+        let rewrite_with_compensation = |tag_compensation| -> Result<OpusGains, Error> {
+            let config = VolumeRewriterConfig {
+                output_gain: VolumeTarget::Absolute(FixedPointGain::try_from(Decibels::from(-3.0))?),
+                output_gain_mode: OutputGainMode::Track,
+                track_volume: None,
+                album_volume: None,
+                max_output_gain: None,
+                tag_compensation,
+                clear_track_gain: false,
+                clear_album_gain: false,
+            };
+            let mut id_header = opus::IdHeader::builder().channels(2).build()?;
+            id_header.set_output_gain(FixedPointGain::try_from(Decibels::from(2.0))?);
+            let mut comment_header = opus::CommentHeader::empty("test");
+            comment_header.set_tag_to_gain(TAG_TRACK_GAIN, FixedPointGain::try_from(Decibels::from(-4.0))?)?;
+            let mut headers = CodecHeaders::Opus(id_header, comment_header);
+            VolumeHeaderRewrite::new(config).rewrite(&mut headers)?;
+            GainsSummary::default().summarize(&headers)
+        };
+        let compensated = rewrite_with_compensation(true)?;
+        assert_eq!(compensated.track_r128.map(|gain| gain.as_f64()), Some(1.0));
+        assert_eq!(compensated.implied_track_volume().map(|v| v.as_f64()), Some(-21.0));
+        assert!(rewrite_with_compensation(false)?.track_r128.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn stale_album_gain_removed_in_track_mode() -> Result<(), Error> {
+        let config = VolumeRewriterConfig {
+            output_gain: VolumeTarget::LUFS(REPLAY_GAIN_LUFS),
+            output_gain_mode: OutputGainMode::Track,
+            track_volume: Some(Decibels::from(-30.0)),
+            album_volume: None,
+            max_output_gain: None,
+            tag_compensation: true,
+            clear_track_gain: false,
+            clear_album_gain: false,
+        };
+        let mut comment_header = opus::CommentHeader::empty("test");
+        comment_header.set_tag_to_gain(TAG_ALBUM_GAIN, FixedPointGain::try_from(Decibels::from(-5.0))?)?;
+        let mut headers = CodecHeaders::Opus(opus::IdHeader::builder().channels(2).build()?, comment_header);
+        VolumeHeaderRewrite::new(config).rewrite(&mut headers)?;
+        let gains = GainsSummary::default().summarize(&headers)?;
+        assert!(gains_close(gains.output, Decibels::from(12.0)));
+        assert_eq!(gains.track_r128.map(|gain| gain.as_f64()), Some(-5.0));
+        assert!(gains.album_r128.is_none());
+        assert!(gains_match_target(&[gains], VolumeTarget::LUFS(REPLAY_GAIN_LUFS), OutputGainMode::Track, false));
+        Ok(())
+    }
+
+    #[test]
+    fn output_gain_limited() -> Result<(), Error> {
+        let rewrite = |max_output_gain| -> Result<OpusGains, Error> {
+            let config = VolumeRewriterConfig {
+                output_gain: VolumeTarget::LUFS(REPLAY_GAIN_LUFS),
+                output_gain_mode: OutputGainMode::Track,
+                track_volume: Some(Decibels::from(-30.0)),
+                album_volume: None,
+                max_output_gain,
+                tag_compensation: false,
+                clear_track_gain: false,
+                clear_album_gain: false,
+            };
+            let mut headers =
+                CodecHeaders::Opus(opus::IdHeader::builder().channels(2).build()?, opus::CommentHeader::empty("test"));
+            VolumeHeaderRewrite::new(config).rewrite(&mut headers)?;
+            GainsSummary::default().summarize(&headers)
+        };
+        let limited = rewrite(Some(Decibels::from(6.0)))?;
+        assert!(gains_close(limited.output, Decibels::from(6.0)));
+        // The track gain tag still reaches -23 LUFS from the limited output gain
+        assert_eq!(limited.track_r128.map(|gain| gain.as_f64()), Some(1.0));
+        let unlimited = rewrite(Some(Decibels::from(20.0)))?;
+        assert!(gains_close(unlimited.output, Decibels::from(12.0)));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_volume_is_error() -> Result<(), Error> {
+        let config = VolumeRewriterConfig {
+            output_gain: VolumeTarget::LUFS(REPLAY_GAIN_LUFS),
+            output_gain_mode: OutputGainMode::Album,
+            track_volume: Some(Decibels::from(-30.0)),
+            album_volume: None,
+            max_output_gain: None,
+            tag_compensation: false,
+            clear_track_gain: false,
+            clear_album_gain: false,
+        };
+        let id_header = opus::IdHeader::builder().channels(2).build()?;
+        let mut headers = CodecHeaders::Opus(id_header, opus::CommentHeader::empty("test"));
+        let result = VolumeHeaderRewrite::new(config).rewrite(&mut headers);
+        assert!(matches!(result, Err(Error::MissingVolume)));
+        Ok(())
+    }
+
+    #[test]
+    fn selected_tags_cleared() -> Result<(), Error> {
+        let clear = |clear_track_gain, clear_album_gain| -> Result<OpusGains, Error> {
+            let config = VolumeRewriterConfig {
+                output_gain: VolumeTarget::NoChange,
+                output_gain_mode: OutputGainMode::Track,
+                track_volume: None,
+                album_volume: None,
+                max_output_gain: None,
+                tag_compensation: true,
+                clear_track_gain,
+                clear_album_gain,
+            };
+            let mut comment_header = opus::CommentHeader::empty("test");
+            comment_header.set_tag_to_gain(TAG_TRACK_GAIN, FixedPointGain::try_from(Decibels::from(-4.0))?)?;
+            comment_header.set_tag_to_gain(TAG_ALBUM_GAIN, FixedPointGain::try_from(Decibels::from(-5.0))?)?;
+            let mut headers = CodecHeaders::Opus(opus::IdHeader::builder().channels(2).build()?, comment_header);
+            VolumeHeaderRewrite::new(config).rewrite(&mut headers)?;
+            GainsSummary::default().summarize(&headers)
+        };
+        let as_f64 = |gains: OpusGains| (gains.track_r128.map(|g| g.as_f64()), gains.album_r128.map(|g| g.as_f64()));
+        assert_eq!(as_f64(clear(false, true)?), (Some(-4.0), None));
+        assert_eq!(as_f64(clear(true, false)?), (None, Some(-5.0)));
+        assert_eq!(as_f64(clear(true, true)?), (None, None));
+        Ok(())
+    }
+}
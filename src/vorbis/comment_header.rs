@@ -29,6 +29,11 @@ impl header::CommentHeaderSpecifics for Specifics {
     }
 }
 
+#[cfg(any(test, feature = "arbitrary"))]
+impl<'a> arbitrary::Arbitrary<'a> for Specifics {
+    fn arbitrary(_u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Specifics> { Ok(Specifics {}) }
+}
+
 /// Manipulates an Ogg Vorbis comment header
 pub type CommentHeader = CommentHeaderGeneric<Specifics>;
 
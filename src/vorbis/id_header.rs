@@ -1,6 +1,4 @@
-use std::io::{Cursor, Write};
-
-use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::Write;
 
 use crate::{header, Codec, Error};
 
@@ -43,18 +41,13 @@ impl header::IdHeader for IdHeader {
         writer.write_all(&self.data).map_err(Error::WriteError)
     }
 
-    fn num_output_channels(&self) -> usize {
-        let mut reader = Cursor::new(&self.data[11..12]);
-        let value = reader.read_u8().expect("Error reading output channel count");
-        value.into()
-    }
+    fn num_output_channels(&self) -> usize { self.data[11].into() }
 
     fn input_sample_rate(&self) -> Option<usize> { Some(self.output_sample_rate()) }
 
     fn output_sample_rate(&self) -> usize {
-        let mut reader = Cursor::new(&self.data[12..16]);
-        let value = reader.read_u32::<LittleEndian>().expect("Error reading sample rate");
-        value.try_into().expect("Could not convert sample rate to usize")
+        let value = u32::from_le_bytes([self.data[12], self.data[13], self.data[14], self.data[15]]);
+        value as usize
     }
 
     fn preskip_samples(&self) -> usize { 0 }
@@ -62,8 +55,5 @@ impl header::IdHeader for IdHeader {
 
 impl IdHeader {
     /// The Vorbis version
-    pub fn version(&self) -> u32 {
-        let mut reader = Cursor::new(&self.data[7..11]);
-        reader.read_u32::<LittleEndian>().expect("Error reading version")
-    }
+    pub fn version(&self) -> u32 { u32::from_le_bytes([self.data[7], self.data[8], self.data[9], self.data[10]]) }
 }
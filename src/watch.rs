@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, SystemTime};
+use std::{fs, io};
+
+use notify::{Config, Event, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use zoog::Error;
+
+/// The properties of a file used to detect that it has changed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct FileState {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileState {
+    fn of(metadata: &fs::Metadata) -> FileState {
+        FileState { len: metadata.len(), modified: metadata.modified().ok() }
+    }
+}
+
+/// Identifies a directory, so that symbolic links which lead back to a
+/// directory already scanned are not followed
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum DirectoryId {
+    #[cfg(unix)]
+    Inode(u64, u64),
+    #[cfg(not(unix))]
+    Path(PathBuf),
+}
+
+impl DirectoryId {
+    // Each platform uses only one of the arguments
+    #[allow(unused_variables)]
+    #[cfg_attr(unix, allow(clippy::unnecessary_wraps))]
+    fn of(dir: &Path, metadata: &fs::Metadata) -> io::Result<DirectoryId> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            Ok(DirectoryId::Inode(metadata.dev(), metadata.ino()))
+        }
+        #[cfg(not(unix))]
+        {
+            fs::canonicalize(dir).map(DirectoryId::Path)
+        }
+    }
+}
+
+/// Detects files which are created or modified within a directory tree. Change
+/// notifications from the operating system determine which files are checked,
+/// falling back to scanning the tree periodically where notifications are
+/// unavailable. A file is only reported once it is unchanged between two
+/// consecutive polls, so that files which are still being written are not
+/// reported early.
+pub struct DirectoryWatcher {
+    root: PathBuf,
+    _watcher: Box<dyn Watcher>,
+    events: Receiver<notify::Result<Event>>,
+    rescan: bool,
+    pending: HashMap<PathBuf, FileState>,
+    reported: HashMap<PathBuf, FileState>,
+}
+
+impl DirectoryWatcher {
+    /// Starts watching `root`. If change notifications are unavailable, the
+    /// tree is scanned every `poll_interval` instead.
+    pub fn new(root: &Path, poll_interval: Duration) -> Result<DirectoryWatcher, Error> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher: Box<dyn Watcher> = match RecommendedWatcher::new(sender.clone(), Config::default()) {
+            Ok(watcher) => Box::new(watcher),
+            Err(_) => Box::new(
+                PollWatcher::new(sender, Config::default().with_poll_interval(poll_interval))
+                    .map_err(|e| watch_error(root, e))?,
+            ),
+        };
+        watcher.watch(root, RecursiveMode::Recursive).map_err(|e| watch_error(root, e))?;
+        Ok(DirectoryWatcher {
+            root: root.to_path_buf(),
+            _watcher: watcher,
+            events,
+            rescan: true,
+            pending: HashMap::new(),
+            reported: HashMap::new(),
+        })
+    }
+
+    /// Returns the files which have changed since they were last reported but
+    /// not since the previous poll, along with any errors encountered while
+    /// checking for changes. The whole tree is only scanned on the first poll
+    /// and when change notifications may have been missed.
+    pub fn poll(&mut self) -> (Vec<PathBuf>, Vec<Error>) {
+        let mut errors = Vec::new();
+        let mut changed = HashSet::new();
+        loop {
+            match self.events.try_recv() {
+                Ok(Ok(event)) if event.need_rescan() => self.rescan = true,
+                Ok(Ok(event)) => changed.extend(event.paths),
+                Ok(Err(e)) => {
+                    errors.push(watch_error(&self.root, e));
+                    self.rescan = true;
+                }
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+            }
+        }
+        if std::mem::take(&mut self.rescan) {
+            changed.insert(self.root.clone());
+        }
+        changed.extend(self.pending.keys().cloned());
+
+        let mut current = HashMap::new();
+        let mut visited = HashSet::new();
+        for path in changed {
+            match fs::metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => {
+                    scan_directory(&path, &metadata, &mut current, &mut visited, &mut errors);
+                }
+                Ok(metadata) if metadata.is_file() => {
+                    current.insert(path, FileState::of(&metadata));
+                }
+                Err(e) if e.kind() != io::ErrorKind::NotFound => errors.push(Error::FileReadError(path, e)),
+                // The file was removed or replaced by something else
+                _ => {
+                    self.pending.remove(&path);
+                    self.reported.remove(&path);
+                }
+            }
+        }
+
+        let mut ready = Vec::new();
+        for (path, state) in current {
+            if self.reported.get(&path) == Some(&state) {
+                self.pending.remove(&path);
+            } else if self.pending.get(&path) == Some(&state) {
+                self.pending.remove(&path);
+                self.reported.insert(path.clone(), state);
+                ready.push(path);
+            } else {
+                self.pending.insert(path, state);
+            }
+        }
+        ready.sort();
+        (ready, errors)
+    }
+
+    /// Records the current state of `path` as reported, so that changes made
+    /// while processing it are not reported by later polls
+    pub fn mark_reported(&mut self, path: &Path) {
+        if let Ok(metadata) = fs::metadata(path) {
+            self.pending.remove(path);
+            self.reported.insert(path.to_path_buf(), FileState::of(&metadata));
+        }
+    }
+}
+
+impl std::fmt::Debug for DirectoryWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DirectoryWatcher").field("root", &self.root).finish_non_exhaustive()
+    }
+}
+
+fn watch_error(path: &Path, error: notify::Error) -> Error {
+    let error = match error.kind {
+        notify::ErrorKind::Io(e) => e,
+        kind => io::Error::new(io::ErrorKind::Other, notify::Error::new(kind)),
+    };
+    Error::FileReadError(path.to_path_buf(), error)
+}
+
+/// Adds the state of each file in the tree rooted at `dir` to `files`. Entries
+/// which cannot be read are added to `errors` and skipped, and directories in
+/// `visited` are not scanned again.
+fn scan_directory(
+    dir: &Path, metadata: &fs::Metadata, files: &mut HashMap<PathBuf, FileState>, visited: &mut HashSet<DirectoryId>,
+    errors: &mut Vec<Error>,
+) {
+    match DirectoryId::of(dir, metadata) {
+        Ok(id) => {
+            if !visited.insert(id) {
+                return;
+            }
+        }
+        Err(e) => {
+            errors.push(Error::FileReadError(dir.to_path_buf(), e));
+            return;
+        }
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // The directory was removed since it was found
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            errors.push(Error::FileReadError(dir.to_path_buf(), e));
+            return;
+        }
+    };
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                errors.push(Error::FileReadError(dir.to_path_buf(), e));
+                continue;
+            }
+        };
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => scan_directory(&path, &metadata, files, visited, errors),
+            Ok(metadata) if metadata.is_file() => {
+                files.insert(path, FileState::of(&metadata));
+            }
+            Ok(_) => {}
+            // The entry was removed since the directory was read
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => errors.push(Error::FileReadError(path, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Polls until files are reported or a timeout expires, failing on any
+    /// error
+    fn poll_until_ready(watcher: &mut DirectoryWatcher) -> Result<Vec<PathBuf>, Error> {
+        let timeout = Instant::now() + Duration::from_secs(10);
+        loop {
+            let (ready, mut errors) = watcher.poll();
+            if let Some(e) = errors.pop() {
+                return Err(e);
+            }
+            if !ready.is_empty() || Instant::now() >= timeout {
+                return Ok(ready);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    #[test]
+    fn files_reported_once_stable() -> Result<(), Error> {
+        let dir = tempfile::tempdir().map_err(Error::ReadError)?;
+        let existing = dir.path().join("existing.opus");
+        fs::write(&existing, b"a").map_err(Error::WriteError)?;
+        let mut watcher = DirectoryWatcher::new(dir.path(), POLL_INTERVAL)?;
+        let (ready, errors) = watcher.poll();
+        assert!(ready.is_empty() && errors.is_empty());
+        assert_eq!(poll_until_ready(&mut watcher)?, vec![existing]);
+
+        let path = dir.path().join("sub").join("a.opus");
+        fs::create_dir(dir.path().join("sub")).map_err(Error::WriteError)?;
+        fs::write(&path, b"a").map_err(Error::WriteError)?;
+        assert!(watcher.poll().0.is_empty());
+        assert_eq!(poll_until_ready(&mut watcher)?, vec![path.clone()]);
+        assert!(watcher.poll().0.is_empty());
+
+        fs::write(&path, b"ab").map_err(Error::WriteError)?;
+        assert_eq!(poll_until_ready(&mut watcher)?, vec![path.clone()]);
+
+        fs::write(&path, b"abc").map_err(Error::WriteError)?;
+        watcher.mark_reported(&path);
+        std::thread::sleep(POLL_INTERVAL);
+        assert!(watcher.poll().0.is_empty());
+        assert!(watcher.poll().0.is_empty());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_cycles_not_followed() -> Result<(), Error> {
+        let dir = tempfile::tempdir().map_err(Error::ReadError)?;
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).map_err(Error::WriteError)?;
+        std::os::unix::fs::symlink(dir.path(), sub.join("loop")).map_err(Error::WriteError)?;
+        let path = sub.join("a.opus");
+        fs::write(&path, b"a").map_err(Error::WriteError)?;
+
+        let mut watcher = DirectoryWatcher::new(dir.path(), POLL_INTERVAL)?;
+        let (ready, errors) = watcher.poll();
+        assert!(ready.is_empty());
+        assert!(errors.is_empty(), "Unexpected errors {:?}", errors);
+        assert_eq!(poll_until_ready(&mut watcher)?, vec![path]);
+        Ok(())
+    }
+}